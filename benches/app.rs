@@ -3,12 +3,22 @@ use std::path::Path;
 use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
 use gurk::app::App;
 use gurk::config::{Config, NotificationConfig, User};
+use gurk::data::{Channel, ChannelId, Message, TypingSet};
+use gurk::passphrase::Passphrase;
 use gurk::signal::test::SignalManagerMock;
-use gurk::storage::{ForgetfulStorage, MemCache};
+use gurk::storage::{ForgetfulStorage, MemCache, SqliteStorage, Storage};
 use presage::libsignal_service::content::Content;
 use tracing::info;
+use uuid::Uuid;
+
+/// Number of channels and of messages per channel in a large history
+const LARGE_HISTORY: (u128, u64) = (20, 2_500);
 
 fn test_app() -> App {
+    app_with_storage(Box::new(MemCache::new(ForgetfulStorage)))
+}
+
+fn app_with_storage(storage: Box<dyn Storage>) -> App {
     let (app, _) = App::try_new(
         Config {
             notifications: NotificationConfig {
@@ -20,7 +30,7 @@ fn test_app() -> App {
             })
         },
         Box::new(SignalManagerMock::new()),
-        Box::new(MemCache::new(ForgetfulStorage)),
+        storage,
     )
     .unwrap();
     app
@@ -46,6 +56,73 @@ pub fn bench_on_message(c: &mut Criterion) {
     });
 }
 
+async fn large_history_storage() -> SqliteStorage {
+    let url = "sqlite::memory:".parse().unwrap();
+    let passphrase = Passphrase::new("secret").unwrap();
+    let mut storage = SqliteStorage::open(&url, &passphrase).await.unwrap();
+    let (num_channels, num_messages) = LARGE_HISTORY;
+    for idx in 0..num_channels {
+        let user_id = Uuid::from_u128(idx + 1);
+        let channel_id = ChannelId::User(user_id);
+        storage.store_channel(Channel {
+            id: channel_id,
+            name: format!("channel {idx}"),
+            group_data: None,
+            unread_messages: 0,
+            muted: false,
+            typing: TypingSet::new(false),
+            expire_timer: None,
+            draft: None,
+        });
+        for n in 0..num_messages {
+            let arrived_at = 1_700_000_000_000 + n * 1000 + idx as u64;
+            let message = Message::text(user_id, arrived_at, format!("message {n}"));
+            storage.store_message(channel_id, message);
+        }
+    }
+    storage
+}
+
+/// Startup of the app on a large history stored in sqlite
+pub fn bench_try_new(c: &mut Criterion) {
+    let _ = tracing_subscriber::fmt::try_init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let storage = runtime.block_on(async { tokio::spawn(large_history_storage()).await.unwrap() });
+    c.bench_function("try_new_large_history", |b| {
+        b.to_async(&runtime).iter_batched(
+            || storage.clone(),
+            |storage| async move { app_with_storage(Box::new(MemCache::new(storage))) },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+/// Startup of the app on a large history stored in sqlite, with all messages loaded upfront as
+/// before the messages were loaded page-wise
+pub fn bench_try_new_full_load(c: &mut Criterion) {
+    let _ = tracing_subscriber::fmt::try_init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let storage = runtime.block_on(async { tokio::spawn(large_history_storage()).await.unwrap() });
+    c.bench_function("try_new_large_history_full_load", |b| {
+        b.to_async(&runtime).iter_batched(
+            || storage.clone(),
+            |storage| async move {
+                let messages: Vec<Vec<Message>> = storage
+                    .channels()
+                    .map(|channel| {
+                        storage
+                            .messages(channel.id)
+                            .map(|message| message.into_owned())
+                            .collect()
+                    })
+                    .collect();
+                (app_with_storage(Box::new(MemCache::new(storage))), messages)
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
 #[allow(unused_variables)]
 fn read_input_data(path: impl AsRef<Path>) -> anyhow::Result<Vec<Content>> {
     #[cfg(feature = "dev")]
@@ -69,5 +146,10 @@ fn read_input_data(path: impl AsRef<Path>) -> anyhow::Result<Vec<Content>> {
     }
 }
 
-criterion_group!(benches, bench_on_message);
+criterion_group!(
+    benches,
+    bench_try_new,
+    bench_try_new_full_load,
+    bench_on_message
+);
 criterion_main!(benches);
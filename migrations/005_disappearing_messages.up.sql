@@ -0,0 +1,6 @@
+ALTER TABLE channels ADD COLUMN expire_timer INTEGER; -- u32, in seconds
+ALTER TABLE messages ADD COLUMN expire_timer INTEGER; -- u32, in seconds
+ALTER TABLE messages ADD COLUMN expires_at INTEGER; -- in milliseconds since epoch
+
+CREATE INDEX idx_messages_expires_at
+ON messages (expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,5 @@
+CREATE TABLE outbox (
+    arrived_at INTEGER PRIMARY KEY NOT NULL, -- reference into messages
+    channel_id BLOB NOT NULL, -- uuid or group id
+    state BLOB NOT NULL -- encoded SendState
+);
@@ -0,0 +1,6 @@
+-- full-text index of the message texts; rowid is arrived_at of the message
+CREATE VIRTUAL TABLE messages_fts USING fts5(message);
+
+INSERT INTO messages_fts(rowid, message)
+SELECT arrived_at, message FROM messages
+WHERE message IS NOT NULL AND edit IS NULL;
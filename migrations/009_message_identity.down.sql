@@ -0,0 +1,87 @@
+-- messages with colliding sent timestamps are dropped
+CREATE TABLE messages_old (
+    arrived_at INTEGER PRIMARY KEY NOT NULL,
+    channel_id BLOB NOT NULL, -- uuid or group id
+    from_id BLOB NOT NULL,
+    message TEXT,
+    quote INTEGER, -- reference into messages to arrived_at
+    receipt BLOB, -- encoded Receipt
+    body_ranges BLOB, -- encoded Vec<BodyRange>
+    attachments BLOB, -- encoded Vec<Attachment>
+    reactions BLOB, -- encoded Vec<(Uuid, String)>
+    edit INTEGER,
+    edited BOOLEAN NOT NULL DEFAULT FALSE,
+    deleted BOOLEAN NOT NULL DEFAULT FALSE,
+    expire_timer INTEGER,
+    expires_at INTEGER
+);
+
+INSERT OR IGNORE INTO messages_old(
+    arrived_at,
+    channel_id,
+    from_id,
+    message,
+    quote,
+    receipt,
+    body_ranges,
+    attachments,
+    reactions,
+    edit,
+    edited,
+    deleted,
+    expire_timer,
+    expires_at
+)
+SELECT
+    sent_at,
+    channel_id,
+    from_id,
+    message,
+    quote_sent_at,
+    receipt,
+    body_ranges,
+    attachments,
+    reactions,
+    edit,
+    edited,
+    deleted,
+    expire_timer,
+    expires_at
+FROM messages
+ORDER BY id ASC;
+
+CREATE TABLE outbox_old (
+    arrived_at INTEGER PRIMARY KEY NOT NULL, -- reference into messages
+    channel_id BLOB NOT NULL, -- uuid or group id
+    state BLOB NOT NULL -- encoded SendState
+);
+
+INSERT OR IGNORE INTO outbox_old(arrived_at, channel_id, state)
+SELECT m.sent_at, m.channel_id, o.state
+FROM outbox AS o
+JOIN messages AS m ON m.id = o.message_id;
+
+DROP TABLE outbox;
+ALTER TABLE outbox_old RENAME TO outbox;
+
+DROP TABLE messages;
+ALTER TABLE messages_old RENAME TO messages;
+
+CREATE INDEX idx_messages_channel_id
+ON messages (channel_id);
+
+CREATE INDEX idx_messages_quote
+ON messages (quote);
+
+CREATE INDEX idx_messages_edit
+ON messages (edit);
+
+CREATE INDEX idx_messages_expires_at
+ON messages (expires_at) WHERE expires_at IS NOT NULL;
+
+DROP TABLE messages_fts;
+CREATE VIRTUAL TABLE messages_fts USING fts5(message);
+
+INSERT INTO messages_fts(rowid, message)
+SELECT arrived_at, message FROM messages
+WHERE message IS NOT NULL AND edit IS NULL;
@@ -0,0 +1,96 @@
+-- messages are identified by channel, sender and sent timestamp instead of by the timestamp only,
+-- which collides between messages sent at the same millisecond in different channels
+CREATE TABLE messages_new (
+    id INTEGER PRIMARY KEY NOT NULL,
+    channel_id BLOB NOT NULL, -- uuid or group id
+    from_id BLOB NOT NULL,
+    sent_at INTEGER NOT NULL, -- arrived_at of the message
+    message TEXT,
+    quote_from_id BLOB, -- sender of the quoted message in the same channel
+    quote_sent_at INTEGER, -- sent_at of the quoted message
+    receipt BLOB, -- encoded Receipt
+    body_ranges BLOB, -- encoded Vec<BodyRange>
+    attachments BLOB, -- encoded Vec<Attachment>
+    reactions BLOB, -- encoded Vec<(Uuid, String)>
+    edit INTEGER, -- sent_at of the original message of the same sender in the same channel
+    edited BOOLEAN NOT NULL DEFAULT FALSE,
+    deleted BOOLEAN NOT NULL DEFAULT FALSE,
+    expire_timer INTEGER, -- u32, in seconds
+    expires_at INTEGER, -- in milliseconds since epoch
+    UNIQUE (channel_id, from_id, sent_at)
+);
+
+INSERT INTO messages_new(
+    channel_id,
+    from_id,
+    sent_at,
+    message,
+    quote_from_id,
+    quote_sent_at,
+    receipt,
+    body_ranges,
+    attachments,
+    reactions,
+    edit,
+    edited,
+    deleted,
+    expire_timer,
+    expires_at
+)
+SELECT
+    m.channel_id,
+    m.from_id,
+    m.arrived_at,
+    m.message,
+    q.from_id,
+    q.arrived_at,
+    m.receipt,
+    m.body_ranges,
+    m.attachments,
+    m.reactions,
+    m.edit,
+    m.edited,
+    m.deleted,
+    m.expire_timer,
+    m.expires_at
+FROM messages AS m
+LEFT JOIN messages AS q ON q.arrived_at = m.quote AND q.channel_id = m.channel_id
+ORDER BY m.arrived_at ASC;
+
+-- outbox references the messages by id
+CREATE TABLE outbox_new (
+    message_id INTEGER PRIMARY KEY NOT NULL, -- reference into messages
+    state BLOB NOT NULL -- encoded SendState
+);
+
+INSERT INTO outbox_new(message_id, state)
+SELECT m.id, o.state
+FROM outbox AS o
+JOIN messages_new AS m ON m.channel_id = o.channel_id AND m.sent_at = o.arrived_at;
+
+DROP TABLE outbox;
+ALTER TABLE outbox_new RENAME TO outbox;
+
+DROP TABLE messages;
+ALTER TABLE messages_new RENAME TO messages;
+
+CREATE INDEX idx_messages_channel_id_sent_at
+ON messages (channel_id, sent_at);
+
+-- channel of a message by its sender and sent timestamp
+CREATE INDEX idx_messages_from_id_sent_at
+ON messages (from_id, sent_at);
+
+CREATE INDEX idx_messages_edit
+ON messages (channel_id, from_id, edit) WHERE edit IS NOT NULL;
+
+CREATE INDEX idx_messages_expires_at
+ON messages (expires_at) WHERE expires_at IS NOT NULL;
+
+-- full-text index of the message texts; rowid is the id of the message
+DROP TABLE messages_fts;
+CREATE VIRTUAL TABLE messages_fts USING fts5(message);
+
+INSERT INTO messages_fts(rowid, message)
+SELECT id, message FROM messages
+WHERE message IS NOT NULL AND edit IS NULL;
@@ -0,0 +1,5 @@
+-- attachments which are not downloaded yet
+CREATE TABLE pending_attachments (
+    message_id INTEGER PRIMARY KEY NOT NULL, -- reference into messages
+    attachments BLOB NOT NULL -- encoded Vec<PendingAttachment>
+);
@@ -0,0 +1,3 @@
+DROP TABLE message_attachments_backfill;
+DROP INDEX idx_message_attachments_message_id;
+DROP TABLE message_attachments;
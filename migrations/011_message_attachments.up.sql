@@ -0,0 +1,19 @@
+-- files of the attachments of the messages and edits, such that the messages sharing an
+-- attachment are found without decoding the attachments of all messages
+CREATE TABLE message_attachments (
+    attachment_id TEXT NOT NULL, -- id of the attachment, i.e. its digest
+    message_id INTEGER NOT NULL, -- reference into messages
+    filename TEXT NOT NULL, -- path of the attachment file
+    PRIMARY KEY (attachment_id, message_id, filename)
+);
+
+CREATE INDEX idx_message_attachments_message_id
+ON message_attachments (message_id);
+
+-- contains a row until the attachments of the existing messages are added to
+-- message_attachments, which needs decoding them and is done when the storage is opened
+CREATE TABLE message_attachments_backfill (
+    id INTEGER PRIMARY KEY NOT NULL
+);
+
+INSERT INTO message_attachments_backfill(id) VALUES (0);
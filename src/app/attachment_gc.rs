@@ -0,0 +1,148 @@
+//! Periodic garbage collection of attachment files
+//!
+//! The garbage is found in the background with another handle of the storage. Afterwards, the
+//! messages are updated in the storage of the app, such that its cache stays up to date, and the
+//! files are removed in the background again.
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use tracing::{error, info};
+
+use crate::attachment_gc::{Garbage, GcReport, find_garbage};
+use crate::event::Event;
+use crate::storage::Storage;
+
+use super::App;
+
+/// Storage used in the background, sharing its data with the storage of the app
+pub(super) type GcStorage = Arc<Mutex<dyn Storage + Send>>;
+
+impl App {
+    /// Sets the storage used to collect the garbage of attachments in the background
+    ///
+    /// It must share its data with the storage of the app. Without it, no garbage is collected.
+    pub fn set_attachments_gc_storage(&mut self, storage: impl Storage + Send + 'static) {
+        self.attachments_gc_storage = Some(Arc::new(Mutex::new(storage)));
+    }
+
+    /// Collects the garbage of attachment files if enabled and the configured interval elapsed
+    pub fn step_attachments_gc(&mut self) {
+        self.gc_attachments_due(Instant::now());
+    }
+
+    pub(super) fn gc_attachments_due(&mut self, now: Instant) {
+        let Some(hours) = self
+            .config
+            .gc_attachments_interval
+            .filter(|&hours| hours > 0)
+        else {
+            return;
+        };
+        let Some(storage) = self.attachments_gc_storage.clone() else {
+            return;
+        };
+        // the first collection is done one interval after the start
+        let last = *self.attachments_gc_at.get_or_insert(now);
+        if now.duration_since(last) < Duration::from_secs(u64::from(hours) * 60 * 60) {
+            return;
+        }
+        self.attachments_gc_at = Some(now);
+
+        let data_dir = self.config.data_dir.clone();
+        let tx = self.event_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = find_garbage(&*lock(&storage), &data_dir);
+            // the app is gone if the receiver is dropped
+            let _ = tx.send(Event::FoundAttachmentsGarbage {
+                result: result.map(Box::new),
+            });
+        });
+    }
+
+    pub(super) fn handle_attachments_garbage(&mut self, result: anyhow::Result<Box<Garbage>>) {
+        let garbage = match result {
+            Ok(garbage) => garbage,
+            Err(error) => {
+                error!(%error, "failed to collect garbage of attachments");
+                return;
+            }
+        };
+        let Some(storage) = self.attachments_gc_storage.clone() else {
+            return;
+        };
+        garbage.update_messages(&mut *self.storage);
+        let tx = self.event_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            // the messages are updated before their files are removed
+            lock(&storage).save();
+            garbage.remove_files();
+            // the app is gone if the receiver is dropped
+            let _ = tx.send(Event::CollectedAttachmentsGarbage {
+                report: garbage.report,
+            });
+        });
+    }
+
+    pub(super) fn handle_collected_attachments_garbage(&self, report: GcReport) {
+        info!(
+            unreferenced = report.unreferenced.len(),
+            duplicates = report.duplicates.len(),
+            reclaimed_bytes = report.reclaimable_bytes,
+            "collected garbage of attachments"
+        );
+    }
+}
+
+fn lock(storage: &GcStorage) -> MutexGuard<'_, dyn Storage + Send + 'static> {
+    // the storage is consistent even if a thread panicked while holding the lock
+    storage.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::SystemTime;
+
+    use crate::app::tests::test_app;
+    use crate::storage::ForgetfulStorage;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_gc_attachments_due() {
+        let (mut app, mut events, _sent_messages) = test_app();
+        let dir = tempfile::tempdir().unwrap();
+        app.config.data_dir = dir.path().to_owned();
+        app.config.gc_attachments_interval = Some(1);
+        app.set_attachments_gc_storage(ForgetfulStorage);
+
+        let date_dir = dir.path().join("files").join("2024-01-01");
+        std::fs::create_dir_all(&date_dir).unwrap();
+        let orphan = date_dir.join("orphan.jpeg");
+        std::fs::write(&orphan, [42]).unwrap();
+        File::options()
+            .write(true)
+            .open(&orphan)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(2 * 60 * 60))
+            .unwrap();
+
+        let now = Instant::now();
+        app.gc_attachments_due(now);
+        app.gc_attachments_due(now + Duration::from_secs(30 * 60));
+        assert!(events.try_recv().is_err());
+
+        app.gc_attachments_due(now + Duration::from_secs(60 * 60));
+        let event = events.recv().await.unwrap();
+        assert!(matches!(
+            event,
+            Event::FoundAttachmentsGarbage { result: Ok(_) }
+        ));
+        assert!(orphan.exists());
+        app.handle_event(event).unwrap();
+        let event = events.recv().await.unwrap();
+        assert!(matches!(event, Event::CollectedAttachmentsGarbage { .. }));
+        assert!(!orphan.exists());
+    }
+}
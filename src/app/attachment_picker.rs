@@ -0,0 +1,225 @@
+//! Opening, saving and copying the attachments of the selected message
+
+use std::fs::OpenOptions;
+use std::io::{Cursor, ErrorKind, Write as _};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, anyhow, bail};
+use arboard::ImageData;
+use image::ImageReader;
+use tracing::{error, info};
+
+use crate::command::AttachmentAction;
+use crate::signal::Attachment;
+use crate::util::expand_home;
+
+use super::App;
+
+impl App {
+    /// Shows the attachments of the selected message, or closes the save prompt or the popup
+    pub(super) fn toggle_attachments(&mut self) -> Option<()> {
+        if self.attachment_picker.is_saving {
+            self.attachment_picker.stop_saving();
+            return Some(());
+        }
+        if self.attachment_picker.is_shown {
+            self.attachment_picker.hide();
+            return Some(());
+        }
+        let message_id = self.selected_message_id()?;
+        let message = self.storage.message(message_id)?;
+        if message.attachments.is_empty() {
+            return None;
+        }
+        let attachments = message.attachments.clone();
+        self.attachment_picker.show(attachments);
+        Some(())
+    }
+
+    /// Saves the selected attachment if the save prompt is shown, otherwise opens it
+    pub(super) fn on_attachments_enter(&mut self) -> Option<()> {
+        if self.attachment_picker.is_saving {
+            self.save_selected_attachment()
+        } else {
+            self.attachment_action(AttachmentAction::Open)
+        }
+    }
+
+    pub(super) fn attachment_action(&mut self, action: AttachmentAction) -> Option<()> {
+        let attachment = self.attachment_picker.selected()?.clone();
+        let status = match action {
+            AttachmentAction::Open => self
+                .decrypted_path(&attachment)
+                .and_then(|path| open(&path)),
+            AttachmentAction::Save => {
+                let path = default_save_path(&attachment);
+                self.attachment_picker
+                    .start_saving(path.display().to_string());
+                return Some(());
+            }
+            AttachmentAction::CopyPath => self.copy_attachment_path(&attachment),
+            AttachmentAction::CopyImage => self.copy_attachment_image(&attachment),
+            AttachmentAction::Reveal => self.decrypted_path(&attachment).and_then(|path| {
+                let dir = path.parent().context("attachment has no directory")?;
+                open(dir)
+            }),
+        };
+        self.set_attachment_status(status);
+        Some(())
+    }
+
+    /// Saves the decrypted selected attachment to the path entered in the save prompt
+    ///
+    /// An existing file is never overwritten.
+    fn save_selected_attachment(&mut self) -> Option<()> {
+        let attachment = self.attachment_picker.selected()?.clone();
+        let dest = expand_home(self.attachment_picker.input.data.trim());
+        let result = self
+            .files
+            .read(&attachment.filename)
+            .and_then(|data| write_new(&dest, &data))
+            .map(|_| format!("Saved to {}", dest.display()));
+        if result.is_ok() {
+            self.attachment_picker.stop_saving();
+        }
+        self.set_attachment_status(result);
+        Some(())
+    }
+
+    fn copy_attachment_path(&mut self, attachment: &Attachment) -> anyhow::Result<String> {
+        let path = self.decrypted_path(attachment)?;
+        let clipboard = self.clipboard.as_mut().context("clipboard is disabled")?;
+        clipboard
+            .set_text(path.display().to_string())
+            .context("failed to copy path to clipboard")?;
+        Ok("Copied path to clipboard".to_owned())
+    }
+
+    fn copy_attachment_image(&mut self, attachment: &Attachment) -> anyhow::Result<String> {
+        if !attachment.content_type.starts_with("image/") {
+            bail!("attachment is not an image");
+        }
+        let data = self.files.read(&attachment.filename)?;
+        let image = ImageReader::new(Cursor::new(data))
+            .with_guessed_format()
+            .context("failed to read image")?
+            .decode()
+            .context("failed to decode image")?
+            .into_rgba8();
+        let image = ImageData {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            bytes: image.into_raw().into(),
+        };
+        let clipboard = self.clipboard.as_mut().context("clipboard is disabled")?;
+        clipboard
+            .set_image(image)
+            .context("failed to copy image to clipboard")?;
+        Ok("Copied image to clipboard".to_owned())
+    }
+
+    fn set_attachment_status(&mut self, status: anyhow::Result<String>) {
+        let status = status
+            .inspect(|status| info!(%status, "attachment action"))
+            .map_err(|error| {
+                error!(%error, "attachment action failed");
+                format!("{error:#}")
+            });
+        self.attachment_picker.set_status(status);
+    }
+}
+
+fn open(path: &Path) -> anyhow::Result<String> {
+    opener::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    Ok(format!("Opened {}", path.display()))
+}
+
+/// Writes the data to a new file, failing if the file exists
+fn write_new(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+            return Err(anyhow!("file exists: {}", path.display()));
+        }
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("failed to save attachment to {}", path.display()));
+        }
+    };
+    file.write_all(data)
+        .with_context(|| format!("failed to save attachment to {}", path.display()))
+}
+
+/// File with the name of the attachment in the downloads directory of the user
+fn default_save_path(attachment: &Attachment) -> PathBuf {
+    let dir = dirs::download_dir()
+        .or_else(dirs::home_dir)
+        .unwrap_or_default();
+    dir.join(attachment.filename.file_name().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::tests::test_app;
+    use crate::data::Message;
+
+    use super::*;
+
+    #[test]
+    fn test_save_attachment() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("photo.jpeg");
+        std::fs::write(&filename, [42]).unwrap();
+
+        let attachments = ["first.jpeg", "photo.jpeg"].map(|name| Attachment {
+            id: name.to_owned(),
+            content_type: "image/jpeg".to_owned(),
+            filename: dir.path().join(name),
+            size: 1,
+        });
+        let message = Message {
+            attachments: attachments.to_vec(),
+            ..Message::text(app.user_id, 1, "photos".to_string())
+        };
+        app.add_message_to_channel(0, message);
+        app.messages
+            .get_mut(&channel_id)
+            .unwrap()
+            .state
+            .select(Some(0));
+
+        assert_eq!(app.toggle_attachments(), Some(()));
+        assert_eq!(app.attachment_picker.attachments(), attachments);
+        app.attachment_picker.next();
+
+        app.attachment_action(AttachmentAction::Save);
+        assert!(app.attachment_picker.is_saving);
+        let dest = dir.path().join("saved.jpeg");
+        app.attachment_picker.input.data = dest.display().to_string();
+        app.on_attachments_enter();
+
+        assert!(!app.attachment_picker.is_saving);
+        assert!(app.attachment_picker.status().unwrap().is_ok());
+        assert_eq!(std::fs::read(&dest).unwrap(), [42]);
+
+        // existing files are not overwritten
+        std::fs::write(&dest, [1]).unwrap();
+        app.attachment_action(AttachmentAction::Save);
+        app.attachment_picker.input.data = dest.display().to_string();
+        app.on_attachments_enter();
+        assert!(app.attachment_picker.is_saving);
+        let status = app.attachment_picker.status().unwrap().unwrap_err();
+        assert!(status.starts_with("file exists"), "{status}");
+        assert_eq!(std::fs::read(&dest).unwrap(), [1]);
+        app.toggle_attachments();
+
+        // the first press of esc closes the prompt, the second one the popup
+        app.attachment_action(AttachmentAction::Save);
+        app.toggle_attachments();
+        assert!(app.attachment_picker.is_shown);
+        app.toggle_attachments();
+        assert!(!app.attachment_picker.is_shown);
+    }
+}
@@ -0,0 +1,86 @@
+//! Adding and removing the attachments of the next sent message
+
+use tracing::warn;
+
+use crate::attachment_tray::TrayAttachment;
+use crate::util::expand_home;
+
+use super::App;
+
+impl App {
+    pub(super) fn toggle_add_attachment(&mut self) {
+        if self.add_attachment.is_shown {
+            self.add_attachment.hide();
+        } else {
+            self.add_attachment.show();
+        }
+    }
+
+    /// Adds the typed file to the tray, or descends into the typed directory
+    pub(super) fn on_add_attachment_enter(&mut self) {
+        let path = expand_home(self.add_attachment.input.data.trim());
+        if path.is_dir() {
+            self.add_attachment.complete();
+            return;
+        }
+        match TrayAttachment::new(path) {
+            Ok(attachment) => {
+                self.attachment_tray.add(attachment);
+                self.add_attachment.hide();
+            }
+            Err(error) => {
+                warn!(%error, "failed to add attachment");
+                self.add_attachment.set_error(format!("{error:#}"));
+            }
+        }
+    }
+
+    pub(super) fn remove_attachment(&mut self) -> Option<()> {
+        self.attachment_tray.remove_selected()?;
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::tests::test_app;
+    use crate::event::Event;
+
+    #[tokio::test]
+    async fn test_send_with_tray_attachments() {
+        let (mut app, mut events, sent_messages) = test_app();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.jpeg");
+        std::fs::write(&path, [42]).unwrap();
+
+        app.toggle_add_attachment();
+        app.add_attachment.input.data = dir.path().join("missing.jpeg").display().to_string();
+        app.on_add_attachment_enter();
+        assert!(app.add_attachment.is_shown);
+        assert!(app.add_attachment.error().is_some());
+
+        app.add_attachment.input.data = path.display().to_string();
+        app.on_add_attachment_enter();
+        assert!(!app.add_attachment.is_shown);
+        assert_eq!(app.attachment_tray.attachments().len(), 1);
+
+        // the message is not sent while an attachment is missing
+        std::fs::remove_file(&path).unwrap();
+        app.input.data = "photo".to_owned();
+        app.send_input(0);
+        assert!(sent_messages.borrow().is_empty());
+        assert_eq!(app.input.data, "photo");
+        assert!(app.attachment_tray.error().is_some());
+
+        std::fs::write(&path, [42]).unwrap();
+        app.send_input(0);
+        assert!(app.attachment_tray.is_empty());
+        assert!(app.input.data.is_empty());
+        // sent after the attachments are prepared
+        assert!(sent_messages.borrow().is_empty());
+        let event = events.recv().await.unwrap();
+        assert!(matches!(event, Event::PreparedAttachments { .. }));
+        app.handle_event(event).unwrap();
+        assert_eq!(sent_messages.borrow().len(), 1);
+    }
+}
@@ -1,10 +1,12 @@
+use tracing::debug;
 use uuid::Uuid;
 
 use crate::data::{Channel, ChannelId, Message, TypingSet};
 use crate::signal::{GroupMasterKeyBytes, ProfileKeyBytes, ResolvedGroup};
-use crate::util;
+use crate::storage::{MessageId, MessagePosition};
+use crate::util::{self, StatefulList};
 
-use super::App;
+use super::{App, MESSAGES_PAGE_SIZE};
 
 impl App {
     pub(super) fn reset_message_selection(&mut self) {
@@ -18,12 +20,16 @@ impl App {
 
     pub fn select_previous_channel(&mut self) {
         self.reset_unread_messages();
-        self.channels.previous();
+        self.switch_channel(StatefulList::previous);
     }
 
     pub fn select_next_channel(&mut self) {
         self.reset_unread_messages();
-        self.channels.next();
+        self.switch_channel(StatefulList::next);
+    }
+
+    pub fn select_channel_idx(&mut self, channel_idx: usize) {
+        self.switch_channel(|channels| channels.state.select(Some(channel_idx)));
     }
 
     pub fn on_pgup(&mut self) {
@@ -33,6 +39,7 @@ impl App {
                 .expect("non-existent channel")
                 .next();
             self.selected_message();
+            self.load_older_messages_if_needed();
         }
     }
 
@@ -45,6 +52,68 @@ impl App {
         }
     }
 
+    /// Loads older messages when the selection approaches the oldest loaded message
+    fn load_older_messages_if_needed(&mut self) {
+        let Some(&channel_id) = self.channels.selected_item() else {
+            return;
+        };
+        let Some(messages) = self.messages.get(&channel_id) else {
+            return;
+        };
+        // messages are selected from the end of the list
+        let selected = messages.state.selected().unwrap_or(0);
+        if messages.items.len().saturating_sub(selected) <= MESSAGES_PAGE_SIZE / 2 {
+            self.load_older_messages(channel_id);
+        }
+    }
+
+    /// Loads the next page of older messages of the channel into its list
+    ///
+    /// Returns whether any messages were loaded.
+    pub(super) fn load_older_messages(&mut self, channel_id: ChannelId) -> bool {
+        if !self.older_messages.contains(&channel_id) {
+            return false;
+        }
+        let messages = self.messages.entry(channel_id).or_default();
+        let before = messages
+            .items
+            .first()
+            .map_or(MessagePosition::LATEST, |&message_id| message_id.into());
+        let page = self
+            .storage
+            .load_messages_before(channel_id, before, MESSAGES_PAGE_SIZE);
+        debug!(?channel_id, loaded = page.len(), "loaded older messages");
+        if page.len() < MESSAGES_PAGE_SIZE {
+            self.older_messages.remove(&channel_id);
+        }
+        // the selection counts from the end of the list, so it is not affected
+        let is_loaded = !page.is_empty();
+        messages.items.splice(0..0, page);
+        is_loaded
+    }
+
+    /// Selects the message in the list of messages of its channel
+    ///
+    /// Older messages are loaded until the list contains the message.
+    pub(super) fn select_message(&mut self, message_id: MessageId) -> Option<()> {
+        while self
+            .messages
+            .get(&message_id.channel_id)?
+            .items
+            .first()
+            .is_none_or(|first| MessagePosition::from(message_id) < MessagePosition::from(*first))
+        {
+            if !self.load_older_messages(message_id.channel_id) {
+                break;
+            }
+        }
+        let messages = self.messages.get_mut(&message_id.channel_id)?;
+        let pos = messages.items.iter().position(|&id| id == message_id)?;
+        // messages are selected from the end of the list
+        messages.state.select(Some(messages.items.len() - 1 - pos));
+        Some(())
+    }
+
     pub fn reset_unread_messages(&mut self) {
         if let Some(channel_id) = self.channels.selected_item()
             && let Some(channel) = self.storage.channel(*channel_id)
@@ -120,6 +189,8 @@ impl App {
                 unread_messages: 0,
                 muted: false,
                 typing: TypingSet::GroupTyping(Default::default()),
+                expire_timer: None,
+                draft: None,
             };
             self.storage.store_channel(channel);
 
@@ -198,6 +269,8 @@ impl App {
                 unread_messages: 0,
                 muted: false,
                 typing: TypingSet::SingleTyping(false),
+                expire_timer: None,
+                draft: None,
             };
             let channel = self.storage.store_channel(channel);
 
@@ -233,6 +306,8 @@ impl App {
                 unread_messages: 0,
                 muted: false,
                 typing: TypingSet::SingleTyping(false),
+                expire_timer: None,
+                draft: None,
             };
             let channel = self.storage.store_channel(channel);
 
@@ -250,7 +325,7 @@ impl App {
         let from_current_user = self.user_id == message.from_id;
 
         let messages = self.messages.entry(channel_id).or_default();
-        messages.items.push(message.arrived_at);
+        messages.items.push(MessageId::of(channel_id, &message));
 
         if let Some(idx) = messages.state.selected() {
             // keep selection on the old message
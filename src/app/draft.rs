@@ -0,0 +1,151 @@
+//! Unsent input and attachments kept per channel
+
+use tracing::debug;
+
+use crate::cursor::Cursor;
+use crate::data::{Channel, ChannelId};
+use crate::input::Input;
+use crate::util::StatefulList;
+
+use super::App;
+
+impl App {
+    /// Changes the selected channel and swaps the input with the draft of the selected channel
+    ///
+    /// The attachment tray is swapped as well. Unlike the draft, it is only kept in memory.
+    pub(super) fn switch_channel(&mut self, select: impl FnOnce(&mut StatefulList<ChannelId>)) {
+        let previous_channel_id = self.channels.selected_item().copied();
+        select(&mut self.channels);
+        if self.channels.selected_item().copied() == previous_channel_id {
+            return;
+        }
+
+        if let Some(channel_id) = previous_channel_id {
+            // an interrupted edit of a message is not kept as draft
+            let draft = if self.reset_editing() {
+                None
+            } else {
+                Some(self.input.take())
+            };
+            self.update_draft(channel_id, draft);
+            let tray = std::mem::take(&mut self.attachment_tray);
+            if !tray.is_empty() {
+                self.attachment_trays.insert(channel_id, tray);
+            }
+        }
+        if let Some(channel_id) = self.channels.selected_item() {
+            self.attachment_tray = self.attachment_trays.remove(channel_id).unwrap_or_default();
+        }
+        // the reply target belongs to the previous channel
+        self.replying = None;
+        self.mentions.clear();
+        self.find.reset();
+        self.restore_draft();
+    }
+
+    /// Persists the input as draft of the selected channel
+    pub fn save_draft(&mut self) {
+        if self.editing.is_none()
+            && let Some(&channel_id) = self.channels.selected_item()
+        {
+            self.update_draft(channel_id, Some(self.input.data.clone()));
+        }
+    }
+
+    /// Replaces the input with the draft of the selected channel
+    pub(super) fn restore_draft(&mut self) {
+        let draft = self
+            .channels
+            .selected_item()
+            .and_then(|&channel_id| self.storage.channel(channel_id))
+            .and_then(|channel| channel.draft.clone())
+            .unwrap_or_default();
+        self.input = Input {
+            cursor: Cursor::end(&draft),
+            data: draft,
+        };
+    }
+
+    /// Stores the draft of the channel; an empty draft removes it
+    pub(super) fn update_draft(&mut self, channel_id: ChannelId, draft: Option<String>) {
+        let draft = draft.filter(|draft| !draft.is_empty());
+        if let Some(channel) = self.storage.channel(channel_id)
+            && channel.draft != draft
+        {
+            debug!(?channel_id, has_draft = draft.is_some(), "updating draft");
+            let mut channel = channel.into_owned();
+            channel.draft = draft;
+            self.storage.store_channel(channel);
+        }
+    }
+
+    /// Whether there is unsent input or are pending attachments in the channel
+    pub fn has_draft(&self, channel: &Channel) -> bool {
+        if self.channels.selected_item() == Some(&channel.id) {
+            (self.editing.is_none() && !self.input.is_empty()) || !self.attachment_tray.is_empty()
+        } else {
+            channel.draft.is_some() || self.attachment_trays.contains_key(&channel.id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::tests::test_app;
+    use crate::attachment_tray::TrayAttachment;
+    use crate::data::TypingSet;
+    use crate::storage::MessageId;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_draft_is_kept_per_channel() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let first_channel_id = app.channels.items[0];
+        let second_channel_id = ChannelId::User(uuid::Uuid::new_v4());
+        app.storage.store_channel(Channel {
+            id: second_channel_id,
+            name: "second".to_string(),
+            group_data: None,
+            unread_messages: 0,
+            muted: false,
+            typing: TypingSet::new(false),
+            expire_timer: None,
+            draft: None,
+        });
+        app.channels.items.push(second_channel_id);
+        app.messages.entry(second_channel_id).or_default();
+
+        for c in "Hello".chars() {
+            app.get_input().put_char(c);
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.jpeg");
+        std::fs::write(&path, [42]).unwrap();
+        app.attachment_tray.add(TrayAttachment::new(path).unwrap());
+        app.replying = Some(MessageId::new(first_channel_id, app.user_id, 1));
+        app.select_next_channel();
+        assert_eq!(app.channels.selected_item(), Some(&second_channel_id));
+        assert!(app.input.is_empty());
+        assert!(app.attachment_tray.is_empty());
+        assert_eq!(app.replying, None);
+        let first_channel = app.storage.channel(first_channel_id).unwrap();
+        assert_eq!(first_channel.draft.as_deref(), Some("Hello"));
+        assert!(app.has_draft(&first_channel));
+
+        app.get_input().put_char('!');
+        app.select_previous_channel();
+        assert_eq!(app.input.data, "Hello");
+        assert_eq!(app.input.cursor, Cursor::end("Hello"));
+        assert_eq!(app.attachment_tray.attachments().len(), 1);
+        let second_channel = app.storage.channel(second_channel_id).unwrap();
+        assert_eq!(second_channel.draft.as_deref(), Some("!"));
+
+        // sending the input removes the draft
+        app.save_draft();
+        app.send_input(0);
+        let first_channel = app.storage.channel(first_channel_id).unwrap();
+        assert_eq!(first_channel.draft, None);
+        assert!(!app.has_draft(&first_channel));
+    }
+}
@@ -0,0 +1,199 @@
+//! Disappearing messages
+
+use tracing::debug;
+
+use crate::attachment_gc::remove_unreferenced;
+use crate::data::ChannelId;
+use crate::storage::MessageId;
+use crate::util::utc_now_timestamp_msec;
+
+use super::App;
+
+impl App {
+    /// Deletes messages whose expiration timer elapsed
+    pub fn step_expiry(&mut self) {
+        self.purge_expired_messages(utc_now_timestamp_msec());
+    }
+
+    pub(super) fn purge_expired_messages(&mut self, now: u64) {
+        let expired: Vec<MessageId> = self.storage.expired_messages(now).collect();
+        let mut attachments = Vec::new();
+        for &message_id in &expired {
+            debug!(?message_id, "purging expired message");
+            if let Some(message) = self.storage.message(message_id) {
+                attachments.extend(message.attachments.iter().cloned());
+            }
+            self.storage.delete_message(message_id);
+            self.remove_message_from_list(message_id);
+        }
+        if !attachments.is_empty() {
+            remove_unreferenced(&*self.storage, &expired, attachments);
+        }
+    }
+
+    /// Removes the message from the list of the channel and keeps the selection
+    fn remove_message_from_list(&mut self, message_id: MessageId) {
+        let Some(messages) = self.messages.get_mut(&message_id.channel_id) else {
+            return;
+        };
+        let Some(pos) = messages.items.iter().position(|&id| id == message_id) else {
+            return;
+        };
+        messages.items.remove(pos);
+
+        // messages are selected from the end of the list
+        let removed_idx = messages.items.len() - pos;
+        if let Some(idx) = messages.state.selected() {
+            let idx = if idx > removed_idx {
+                Some(idx - 1)
+            } else if idx == messages.items.len() {
+                idx.checked_sub(1)
+            } else {
+                Some(idx)
+            };
+            messages.state.select(idx);
+        }
+        if self.replying == Some(message_id) {
+            self.replying = None;
+        }
+        self.revealed_spoilers.remove(&message_id);
+    }
+
+    /// Updates the timer of disappearing messages of the channel and returns it
+    ///
+    /// A timer of zero seconds disables disappearing messages.
+    pub(super) fn update_expire_timer(
+        &mut self,
+        channel_id: ChannelId,
+        expire_timer: Option<u32>,
+    ) -> Option<u32> {
+        let expire_timer = expire_timer.filter(|&timer| timer > 0);
+        if let Some(channel) = self.storage.channel(channel_id)
+            && channel.expire_timer != expire_timer
+        {
+            debug!(?channel_id, ?expire_timer, "updating expire timer");
+            let mut channel = channel.into_owned();
+            channel.expire_timer = expire_timer;
+            self.storage.store_channel(channel);
+        }
+        expire_timer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use uuid::Uuid;
+
+    use crate::app::tests::test_app;
+    use crate::data::Message;
+    use crate::signal::Attachment;
+
+    use super::*;
+
+    #[test]
+    fn test_purge_expired_messages() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let user_id = app.user_id;
+
+        let dir = tempfile::tempdir().unwrap();
+        let filename: PathBuf = dir.path().join("image.png");
+        std::fs::write(&filename, b"image").unwrap();
+        // shared with another message after a garbage collection
+        let shared = Attachment {
+            id: "shared".to_string(),
+            content_type: "image/png".to_string(),
+            filename: dir.path().join("shared.png"),
+            size: 6,
+        };
+        std::fs::write(&shared.filename, b"shared").unwrap();
+
+        app.add_message_to_channel(
+            0,
+            Message {
+                expire_timer: Some(10),
+                expires_at: Some(10_000),
+                attachments: vec![
+                    Attachment {
+                        id: "image".to_string(),
+                        content_type: "image/png".to_string(),
+                        filename: filename.clone(),
+                        size: 5,
+                    },
+                    shared.clone(),
+                ],
+                ..Message::text(user_id, 1, "Expiring".to_string())
+            },
+        );
+        app.add_message_to_channel(
+            0,
+            Message {
+                expire_timer: Some(10),
+                attachments: vec![shared.clone()],
+                ..Message::text(user_id, 2, "Not read".to_string())
+            },
+        );
+        // select the first message
+        app.messages
+            .get_mut(&channel_id)
+            .unwrap()
+            .state
+            .select(Some(2));
+
+        let message_id = move |arrived_at| MessageId::new(channel_id, user_id, arrived_at);
+
+        app.purge_expired_messages(9_999);
+        assert_eq!(app.messages[&channel_id].items, [0, 1, 2].map(message_id));
+
+        app.purge_expired_messages(10_000);
+        assert_eq!(app.messages[&channel_id].items, [0, 2].map(message_id));
+        assert_eq!(app.messages[&channel_id].state.selected(), Some(1));
+        assert!(app.storage.message(message_id(1)).is_none());
+        assert!(!filename.exists());
+        assert!(shared.filename.exists());
+    }
+
+    #[test]
+    fn test_mark_as_read_starts_expiry() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let from_id = Uuid::new_v4();
+        let message_id = MessageId::new(channel_id, from_id, 1);
+        app.add_message_to_channel(
+            0,
+            Message {
+                expire_timer: Some(10),
+                ..Message::text(from_id, 1, "Hello".to_string())
+            },
+        );
+        assert_eq!(app.storage.message(message_id).unwrap().expires_at, None);
+
+        app.mark_as_read([message_id]);
+        let expires_at = app.storage.message(message_id).unwrap().expires_at;
+        assert!(expires_at.is_some());
+
+        // timer is not restarted
+        app.mark_as_read([message_id]);
+        assert_eq!(
+            app.storage.message(message_id).unwrap().expires_at,
+            expires_at
+        );
+    }
+
+    #[test]
+    fn test_update_expire_timer() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+
+        assert_eq!(app.update_expire_timer(channel_id, Some(300)), Some(300));
+        assert_eq!(
+            app.storage.channel(channel_id).unwrap().expire_timer,
+            Some(300)
+        );
+
+        assert_eq!(app.update_expire_timer(channel_id, Some(0)), None);
+        assert_eq!(app.storage.channel(channel_id).unwrap().expire_timer, None);
+    }
+}
@@ -0,0 +1,185 @@
+//! Find of text in the messages of the selected channel
+
+use tracing::debug;
+
+use crate::command::MoveDirection;
+use crate::event::Event;
+use crate::storage::{MessageId, MessagePosition};
+use crate::ui::{NameResolver, find_in_message};
+
+use super::App;
+
+/// Number of messages scanned for matches in one step
+const FIND_STEP_SIZE: usize = 1000;
+
+impl App {
+    /// Opens the find prompt, or closes it and removes the highlighted matches
+    pub(super) fn toggle_find(&mut self) {
+        let is_shown = self.find.is_shown;
+        self.find.reset();
+        self.find.is_shown = !is_shown;
+    }
+
+    /// Closes the find prompt and keeps the matches highlighted
+    pub(super) fn on_find_enter(&mut self) {
+        self.find.is_shown = false;
+    }
+
+    /// Removes the highlighted matches; returns whether there were any
+    pub(super) fn reset_find(&mut self) -> bool {
+        let is_active = self.find.target().is_some();
+        self.find.reset();
+        is_active
+    }
+
+    /// Starts finding the input of the find prompt if it changed
+    pub(super) fn update_find(&mut self) {
+        if !self.find.is_shown || !self.find.is_outdated() {
+            return;
+        }
+        let Some(&channel_id) = self.channels.selected_item() else {
+            return;
+        };
+        self.find.restart(channel_id);
+        if !self.find.is_step_scheduled {
+            self.step_find();
+        }
+    }
+
+    /// Scans the next messages for matches and schedules the next step via [`Event::FindStep`]
+    pub(super) fn step_find(&mut self) {
+        self.find.is_step_scheduled = false;
+        if !self.find.is_pending() {
+            return;
+        }
+        let had_selected = self.find.selected_match().is_some();
+
+        let (matches, scanned_until, is_complete) = self.scan_messages();
+        self.find.add_scanned(matches, scanned_until, is_complete);
+        if !had_selected {
+            self.select_find_match();
+        }
+
+        if self.find.is_pending() {
+            self.find.is_step_scheduled = true;
+            self.event_tx
+                .send(Event::FindStep)
+                .expect("event sender gone");
+        } else {
+            debug!(matches = self.find.matches().len(), "finished find");
+        }
+    }
+
+    /// Scans the next messages (from the newest to the oldest) for the pattern as displayed
+    ///
+    /// Returns the matching messages, the oldest scanned message and whether all messages were
+    /// scanned.
+    fn scan_messages(&self) -> (Vec<MessageId>, Option<MessagePosition>, bool) {
+        let Some((channel_id, pattern)) = self.find.target() else {
+            return (Vec::new(), None, true);
+        };
+        let names = NameResolver::compute(self, std::iter::empty());
+        let before = self.find.scanned_until().unwrap_or(MessagePosition::LATEST);
+        // one more message is read to find out whether all messages are scanned
+        let mut messages = self
+            .storage
+            .messages_before(channel_id, before, FIND_STEP_SIZE + 1)
+            .rev();
+
+        let mut matches = Vec::new();
+        let mut last_scanned = None;
+        for message in messages.by_ref().take(FIND_STEP_SIZE) {
+            last_scanned = Some(MessagePosition::of(&message));
+            let message_id = MessageId::of(channel_id, &message);
+            let show_spoilers = self.is_spoiler_revealed(message_id);
+            if !find_in_message(&names, &message, pattern, show_spoilers).is_empty() {
+                matches.push(message_id);
+            }
+        }
+        let is_complete = messages.next().is_none();
+        (matches, last_scanned, is_complete)
+    }
+
+    pub(super) fn select_find_match_in_direction(&mut self, direction: MoveDirection) {
+        match direction {
+            MoveDirection::Previous => self.find.select_older(),
+            MoveDirection::Next => self.find.select_newer(),
+        }
+        self.select_find_match();
+    }
+
+    /// Selects the message of the selected match
+    fn select_find_match(&mut self) -> Option<()> {
+        let message_id = self.find.selected_match()?;
+        self.select_message(message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::tests::test_app;
+    use crate::data::{AssociatedValue, BodyRange, Message};
+
+    use super::*;
+
+    fn find(app: &mut App, pattern: &str) {
+        app.toggle_find();
+        for c in pattern.chars() {
+            app.get_input().put_char(c);
+        }
+        app.update_find();
+        while app.find.is_pending() {
+            app.step_find();
+        }
+    }
+
+    #[test]
+    fn test_find_selects_matches() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        for arrived_at in 1..=(2 * FIND_STEP_SIZE as u64) {
+            let text = if arrived_at % 500 == 0 {
+                "Needle in the haystack"
+            } else {
+                "hay"
+            };
+            app.add_message_to_channel(0, Message::text(app.user_id, arrived_at, text.to_string()));
+        }
+        let message_id = move |arrived_at| MessageId::new(channel_id, app.user_id, arrived_at);
+
+        find(&mut app, "NEEDLE");
+        assert_eq!(app.find.matches(), [2000, 1500, 1000, 500].map(message_id));
+        assert_eq!(app.selected_message_id(), Some(message_id(2000)));
+
+        app.select_find_match_in_direction(MoveDirection::Previous);
+        app.select_find_match_in_direction(MoveDirection::Previous);
+        assert_eq!(app.selected_message_id(), Some(message_id(1000)));
+        app.select_find_match_in_direction(MoveDirection::Next);
+        assert_eq!(app.selected_message_id(), Some(message_id(1500)));
+
+        // closing the prompt keeps the matches
+        app.on_find_enter();
+        assert_eq!(app.find.pattern(channel_id), Some("NEEDLE"));
+        assert!(app.reset_find());
+        assert_eq!(app.find.pattern(channel_id), None);
+    }
+
+    #[test]
+    fn test_find_matches_displayed_mentions() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let mut message = Message::text(app.user_id, 1, "hi ￼".to_string());
+        message.body_ranges = vec![BodyRange {
+            start: 3,
+            end: 4,
+            value: AssociatedValue::MentionUuid(app.user_id),
+        }];
+        app.add_message_to_channel(0, message);
+
+        find(&mut app, "@tyler");
+        let channel_id = app.channels.items[0];
+        assert_eq!(
+            app.find.matches(),
+            [MessageId::new(channel_id, app.user_id, 1)]
+        );
+    }
+}
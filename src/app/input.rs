@@ -1,6 +1,7 @@
 use std::borrow::Cow;
 use std::io::Cursor;
 use std::path::Path;
+use std::time::Instant;
 
 use anyhow::Context as _;
 use arboard::ImageData;
@@ -10,16 +11,20 @@ use crossterm::event::{KeyCode, KeyEvent};
 use image::codecs::png::PngEncoder;
 use image::{ImageBuffer, ImageEncoder, Rgba};
 use presage::libsignal_service::sender::AttachmentSpec;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
+use crate::attachment_tray::load_attachment;
 use crate::command::{
     Command, DirectionVertical, MoveAmountText, MoveAmountVisual, MoveDirection, Widget, WindowMode,
 };
-use crate::data::Message;
+use crate::data::{ChannelId, Message, SendState};
+use crate::mention::Mention;
 use crate::storage::MessageId;
+use crate::ui::NameResolver;
 use crate::util::{ATTACHMENT_REGEX, URL_REGEX};
 
-use super::{App, HandleReactionOptions, open_file, open_url, to_emoji};
+use super::outgoing::OutgoingMessage;
+use super::{App, HandleReactionOptions, open_url, to_emoji};
 
 impl App {
     async fn on_command(&mut self, command: Command) -> anyhow::Result<()> {
@@ -55,20 +60,61 @@ impl App {
             Command::SelectChannel(MoveDirection::Next) => self.select_next_channel(),
             Command::SelectChannelModal(MoveDirection::Previous) => self.select_channel_prev(),
             Command::SelectChannelModal(MoveDirection::Next) => self.select_channel_next(),
+            Command::SelectMention(MoveDirection::Previous) => self.mention_completion.prev(),
+            Command::SelectMention(MoveDirection::Next) => self.mention_completion.next(),
+            Command::CompleteMention => {
+                self.complete_mention();
+            }
             Command::KillWholeLine => self.get_input().on_delete_line(),
             Command::BeginningOfLine => self.get_input().on_home(),
             Command::EndOfLine => self.get_input().on_end(),
             Command::EditMessage => {
                 self.start_editing();
             }
-            // Command::ReplyMessage => unimplemented!("{command:?}"),
-            // Command::DeleteMessage => unimplemented!("{command:?}"),
+            Command::ReplyMessage => {
+                self.start_replying();
+            }
+            Command::ToggleSpoiler => {
+                self.toggle_spoiler();
+            }
+            Command::DeleteMessage => {
+                self.delete_message();
+            }
+            Command::RetryMessage => {
+                self.retry_message();
+            }
             Command::ToggleChannelModal => {
                 if !self.select_channel.is_shown {
                     self.select_channel.reset(&*self.storage);
                 }
                 self.select_channel.is_shown = !self.select_channel.is_shown;
             }
+            Command::ToggleSearch => self.toggle_search(),
+            Command::SelectSearchResult(MoveDirection::Previous) => self.search.prev(),
+            Command::SelectSearchResult(MoveDirection::Next) => self.search.next(),
+            Command::Find => self.toggle_find(),
+            Command::FindMatch(direction) => self.select_find_match_in_direction(direction),
+            Command::ToggleAttachments => {
+                self.toggle_attachments();
+            }
+            Command::SelectAttachment(MoveDirection::Previous) => self.attachment_picker.prev(),
+            Command::SelectAttachment(MoveDirection::Next) => self.attachment_picker.next(),
+            Command::AttachmentAction(action) => {
+                self.attachment_action(action);
+            }
+            Command::AddAttachment => self.toggle_add_attachment(),
+            Command::RemoveAttachment => {
+                self.remove_attachment();
+            }
+            Command::SelectPendingAttachment(MoveDirection::Previous) => {
+                self.attachment_tray.prev()
+            }
+            Command::SelectPendingAttachment(MoveDirection::Next) => self.attachment_tray.next(),
+            Command::CompleteAttachmentPath => {
+                self.add_attachment.complete();
+            }
+            Command::SelectPathCompletion(MoveDirection::Previous) => self.add_attachment.prev(),
+            Command::SelectPathCompletion(MoveDirection::Next) => self.add_attachment.next(),
             Command::ToggleMultiline => {
                 self.is_multiline_input = !self.is_multiline_input;
             }
@@ -83,6 +129,9 @@ impl App {
             Command::OpenFile => {
                 self.try_open_file();
             }
+            Command::DownloadAttachment => {
+                self.download_attachments();
+            }
             Command::DeleteCharacter(MoveDirection::Previous) => {
                 self.get_input().on_backspace();
             }
@@ -112,16 +161,34 @@ impl App {
     }
 
     pub async fn on_key(&mut self, key: KeyEvent) -> anyhow::Result<()> {
-        if let Some(cmd) = self.event_to_command(&key) {
-            self.on_command(cmd.clone()).await?;
+        let input_len = self.input.data.len();
+        let input_state = (
+            input_len,
+            self.input.cursor.idx,
+            self.channels.state.selected(),
+        );
+
+        let command = self.event_to_command(&key).cloned();
+        if command != Some(Command::DeleteMessage) {
+            // any other key cancels the deletion awaiting confirmation
+            self.deleting = None;
+        }
+        if let Some(cmd) = command {
+            self.on_command(cmd).await?;
         } else {
             match key.code {
                 KeyCode::Char('\r') => self.get_input().put_char('\n'),
+                KeyCode::Enter if self.search.is_shown => self.on_search_enter(),
+                KeyCode::Enter if self.find.is_shown => self.on_find_enter(),
+                KeyCode::Enter if self.attachment_picker.is_shown => {
+                    self.on_attachments_enter();
+                }
+                KeyCode::Enter if self.add_attachment.is_shown => self.on_add_attachment_enter(),
                 KeyCode::Enter => {
                     if !self.select_channel.is_shown {
                         if self.is_multiline_input {
                             self.get_input().new_line();
-                        } else if !self.input.data.is_empty() {
+                        } else if !self.input.data.is_empty() || !self.attachment_tray.is_empty() {
                             if let Some(idx) = self.channels.state.selected() {
                                 self.send_input(idx);
                             }
@@ -140,21 +207,98 @@ impl App {
                             .enumerate()
                             .find(|(_, id)| **id == channel_id)
                             .context("channel disappeared during channel select popup")?;
-                        self.channels.state.select(Some(idx));
+                        self.select_channel_idx(idx);
                     }
                 }
                 KeyCode::Esc => {
-                    if !self.reset_editing() {
+                    if !self.mention_completion.hide()
+                        && !self.reset_editing()
+                        && !self.reset_find()
+                    {
+                        if self.selected_message_id().is_none() {
+                            self.reset_replying();
+                        }
                         self.reset_message_selection();
                     }
                 }
+                // the input of the attachments popup is only used by the save prompt
+                KeyCode::Char(_)
+                    if self.attachment_picker.is_shown && !self.attachment_picker.is_saving => {}
                 KeyCode::Char(c) => self.get_input().put_char(c),
                 _ => {}
             }
         }
+
+        self.update_find();
+        if self.add_attachment.is_shown {
+            self.add_attachment.update_completions();
+        }
+
+        let now = Instant::now();
+        if input_len != self.input.data.len() {
+            self.on_input_edited(now);
+        } else {
+            self.update_typing(now);
+        }
+        if input_state
+            != (
+                self.input.data.len(),
+                self.input.cursor.idx,
+                self.channels.state.selected(),
+            )
+        {
+            self.update_mention_completion();
+        }
         Ok(())
     }
 
+    /// Shows the members of the selected group channel matching the `@` query at the cursor
+    fn update_mention_completion(&mut self) {
+        let Some((start, query)) =
+            crate::mention::query_at(&self.input.data, self.input.cursor.idx)
+        else {
+            self.mention_completion.hide();
+            return;
+        };
+        let query = query.to_lowercase();
+
+        let members = self
+            .channels
+            .selected_item()
+            .and_then(|&channel_id| self.storage.channel(channel_id))
+            .and_then(|channel| Some(channel.group_data.as_ref()?.members.clone()))
+            .unwrap_or_default();
+        let names = NameResolver::compute(self, std::iter::empty());
+        let mut candidates: Vec<Mention> = members
+            .into_iter()
+            .filter(|&user_id| user_id != self.user_id)
+            .map(|user_id| Mention {
+                name: names.resolve(user_id).0.into_owned(),
+                user_id,
+            })
+            .filter(|mention| mention.name.to_lowercase().contains(&query))
+            .collect();
+        candidates.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+        self.mention_completion.show(start, candidates);
+    }
+
+    /// Replaces the `@` query at the cursor by the selected mention
+    fn complete_mention(&mut self) -> Option<()> {
+        let (start, mention) = self.mention_completion.selected()?;
+        let mention = mention.clone();
+        self.mention_completion.hide();
+
+        while self.input.cursor.idx > start {
+            self.input.on_backspace();
+        }
+        for c in format!("@{} ", mention.name).chars() {
+            self.input.put_char(c);
+        }
+        self.mentions.push(mention);
+        Some(())
+    }
+
     fn try_open_url_or_file(&mut self) -> Option<()> {
         self.try_open_url().or_else(|| self.try_open_file())
     }
@@ -173,22 +317,28 @@ impl App {
     ///
     /// Does nothing if no message is selected and the message contains no attachments.
     fn try_open_file(&mut self) -> Option<()> {
-        let message = self.selected_message()?;
-        open_file(&message)?;
+        let attachment = self.selected_message()?.attachments.first()?.clone();
+        let result = self
+            .decrypted_path(&attachment)
+            .and_then(|path| opener::open(path).context("failed to open attachment"));
+        if let Err(error) = result {
+            let path = attachment.filename.display().to_string();
+            error!(path, %error, "failed to open");
+        }
         self.reset_message_selection();
         Some(())
     }
 
-    fn selected_message_id(&self) -> Option<MessageId> {
+    pub(super) fn selected_message_id(&self) -> Option<MessageId> {
         let channel_id = self.channels.selected_item()?;
         let messages = self.messages.get(channel_id)?;
         let message_idx = messages.state.selected()?;
-        let arrived_at = messages.items[messages
+        let message_id = messages.items[messages
             .items
             .len()
             .checked_sub(message_idx)?
             .checked_sub(1)?];
-        Some(MessageId::new(*channel_id, arrived_at))
+        Some(message_id)
     }
 
     pub(super) fn selected_message(&self) -> Option<Cow<'_, Message>> {
@@ -236,11 +386,9 @@ impl App {
         self.signal_manager
             .send_reaction(&channel, &message, emoji.clone(), remove);
 
-        let channel_id = channel.id;
-        let arrived_at = message.arrived_at;
+        let message_id = MessageId::of(channel.id, &message);
         self.handle_reaction(
-            channel_id,
-            arrived_at,
+            message_id,
             self.signal_manager.user_id(),
             emoji,
             HandleReactionOptions::new().remove(remove),
@@ -259,51 +407,110 @@ impl App {
     }
 
     pub(super) fn send_input(&mut self, channel_idx: usize) {
-        let input = self.take_input();
-        let (input, attachments) = Self::extract_attachments(&input, Local::now(), || {
-            self.clipboard.as_mut().map(|c| c.get_image())
+        let loaded = self.attachment_tray.load().and_then(|mut attachments| {
+            let (input, extracted) =
+                Self::extract_attachments(&self.input.data, Local::now(), || {
+                    self.clipboard.as_mut().map(|c| c.get_image())
+                })?;
+            attachments.extend(extracted);
+            Ok((input, attachments))
         });
+        let (text, attachments) = match loaded {
+            Ok(loaded) => loaded,
+            Err(error) => {
+                // keep the input, such that the message is not sent without its attachments
+                error!(%error, "failed to load attachments");
+                self.attachment_tray.set_error(format!("{error:#}"));
+                return;
+            }
+        };
+        let input = self.take_input();
+        let tray = std::mem::take(&mut self.attachment_tray);
         let channel_id = self.channels.items[channel_idx];
-        let channel = self
-            .storage
-            .channel(channel_id)
-            .expect("non-existent channel");
+        self.update_draft(channel_id, None);
         let editing = self.editing.take();
-        let quote = editing.is_none().then(|| self.selected_message()).flatten();
-        let (sent_message, response) = self.signal_manager.send_text(
-            &channel,
+        let message = OutgoingMessage {
+            text,
+            mentions: std::mem::take(&mut self.mentions),
+            quote: self
+                .replying
+                .take()
+                .filter(|id| editing.is_none() && id.channel_id == channel_id),
+            editing,
             input,
-            quote.as_deref(),
-            editing.map(|id| id.arrived_at),
-            attachments,
-        );
+            tray,
+        };
+        // the attachments are sanitized in the background
+        self.send_outgoing(channel_id, message, attachments);
 
-        let message_id = MessageId::new(channel_id, sent_message.arrived_at);
-        let tx = self.event_tx.clone();
-        tokio::spawn(async move {
-            if let Ok(result) = response.await {
-                tx.send(crate::event::Event::SentTextResult { message_id, result })
-                    .expect("event sender gone");
-            } else {
-                error!(?message_id, "response for sending message was lost");
-            }
-        });
+        self.stop_typing();
+        self.reset_message_selection();
+        self.reset_unread_messages();
+        self.bubble_up_channel(channel_idx);
+    }
+
+    /// Sends the message, or queues it until connected again
+    pub(super) fn send_message(
+        &mut self,
+        channel_id: ChannelId,
+        message: OutgoingMessage,
+        attachments: Vec<(AttachmentSpec, Vec<u8>)>,
+    ) {
+        let Some(channel) = self.storage.channel(channel_id) else {
+            warn!(?channel_id, "not sending message to non-existent channel");
+            return;
+        };
+        let members = channel
+            .group_data
+            .as_ref()
+            .map(|group_data| group_data.members.as_slice())
+            .unwrap_or_default();
+        let mentions: Vec<Mention> = message
+            .mentions
+            .into_iter()
+            .filter(|mention| members.contains(&mention.user_id))
+            .collect();
+        let editing = message.editing;
+        let quote = message.quote.and_then(|id| self.storage.message(id));
+        let sent_message = if self.online {
+            let (sent_message, response) = self.signal_manager.send_text(
+                &channel,
+                message.text,
+                &mentions,
+                quote.as_deref(),
+                editing.map(|id| id.arrived_at),
+                attachments,
+            );
+            self.forward_send_result(MessageId::of(channel_id, &sent_message), response);
+            sent_message
+        } else {
+            // sent when connected again
+            let mut sent_message = self.signal_manager.compose_text(
+                &channel,
+                message.text,
+                &mentions,
+                quote.as_deref(),
+                editing.map(|id| id.arrived_at),
+                attachments,
+            );
+            sent_message.send_state = Some(SendState::Pending);
+            sent_message
+        };
+
+        let message_id = MessageId::of(channel_id, &sent_message);
 
         if let Some(id) = editing {
             self.storage
                 .store_edited_message(channel_id, id.arrived_at, sent_message);
         } else {
-            let sent_message = self.storage.store_message(channel_id, sent_message);
-            self.messages
-                .get_mut(&channel_id)
-                .expect("non-existent channel")
-                .items
-                .push(sent_message.arrived_at);
+            self.storage.store_message(channel_id, sent_message);
+            let messages = self.messages.entry(channel_id).or_default();
+            messages.items.push(message_id);
+            if let Some(idx) = messages.state.selected() {
+                // keep selection on the old message
+                messages.state.select(Some(idx + 1));
+            }
         };
-
-        self.reset_message_selection();
-        self.reset_unread_messages();
-        self.bubble_up_channel(channel_idx);
     }
 
     pub fn copy_selection(&mut self) {
@@ -322,14 +529,47 @@ impl App {
     }
 
     /// Returns `true` if editing was reset, otherwise `false`
-    fn reset_editing(&mut self) -> bool {
+    pub(super) fn reset_editing(&mut self) -> bool {
         let is_reset = self.editing.take().is_some();
         if is_reset {
             self.take_input();
+            self.mentions.clear();
         }
         is_reset
     }
 
+    /// Shows or hides the spoilers of the selected message
+    fn toggle_spoiler(&mut self) -> Option<()> {
+        let message_id = self.selected_message_id()?;
+        if !self.revealed_spoilers.remove(&message_id) {
+            self.revealed_spoilers.insert(message_id);
+        }
+        Some(())
+    }
+
+    /// Returns `true` if replying was reset, otherwise `false`
+    fn reset_replying(&mut self) -> bool {
+        self.replying.take().is_some()
+    }
+
+    /// Pins the selected message as reply target of the next sent message
+    pub(super) fn start_replying(&mut self) -> Option<()> {
+        if self.editing.is_some() {
+            return None;
+        }
+
+        let message_id = self.selected_message_id()?;
+        let message = self.storage.message(message_id)?;
+        if message.deleted {
+            return None;
+        }
+
+        self.replying.replace(message_id);
+        self.reset_message_selection();
+
+        Some(())
+    }
+
     fn start_editing(&mut self) -> Option<()> {
         if !self.input.is_empty() {
             return None;
@@ -347,16 +587,41 @@ impl App {
             .last()
             .map(|last_edit| last_edit.arrived_at)
             .unwrap_or(message.arrived_at);
-        let message_id = MessageId::new(message_id.channel_id, target_sent_timestamp);
+        let message_id = MessageId::new(message_id.channel_id, self.user_id, target_sent_timestamp);
         let text = message.message.clone()?;
 
         self.editing.replace(message_id);
+        self.replying.take();
         self.input.data = text;
         self.input.on_end();
 
         Some(())
     }
 
+    /// Deletes the selected message for everyone
+    ///
+    /// Only own messages can be deleted. The first call only asks for confirmation; the message is
+    /// deleted if it is called again for the same message.
+    pub(super) fn delete_message(&mut self) -> Option<()> {
+        let message_id = self.selected_message_id()?;
+        let message = self.storage.message(message_id)?;
+        if message.from_id != self.user_id || message.deleted {
+            return None;
+        }
+        if self.deleting.take() != Some(message_id) {
+            self.deleting = Some(message_id);
+            return Some(());
+        }
+
+        let channel = self.storage.channel(message_id.channel_id)?;
+        self.signal_manager.send_delete(&channel, &message);
+
+        self.handle_delete(message_id.channel_id, message_id.arrived_at, self.user_id);
+        self.reset_message_selection();
+
+        Some(())
+    }
+
     pub fn event_to_command<'r>(&'r self, event: &KeyEvent) -> Option<&'r Command> {
         let mut combiner = Combiner::default();
         let keys_pressed = combiner.transform(*event)?;
@@ -364,13 +629,43 @@ impl App {
             vec![WindowMode::Anywhere, WindowMode::Help]
         } else if self.is_select_channel_shown() {
             vec![WindowMode::Anywhere, WindowMode::ChannelModal]
+        } else if self.search.is_shown {
+            vec![
+                WindowMode::Anywhere,
+                WindowMode::Search,
+                WindowMode::TextInput,
+            ]
+        } else if self.find.is_shown {
+            vec![
+                WindowMode::Anywhere,
+                WindowMode::Find,
+                WindowMode::TextInput,
+            ]
+        } else if self.attachment_picker.is_shown {
+            vec![
+                WindowMode::Anywhere,
+                WindowMode::Attachments,
+                WindowMode::TextInput,
+            ]
+        } else if self.add_attachment.is_shown {
+            vec![
+                WindowMode::Anywhere,
+                WindowMode::AddAttachment,
+                WindowMode::TextInput,
+            ]
+        } else if self.mention_completion.is_shown() {
+            vec![
+                WindowMode::Anywhere,
+                WindowMode::MentionCompletion,
+                WindowMode::Normal,
+            ]
         } else if self.is_multiline_input {
             vec![
                 WindowMode::Anywhere,
                 WindowMode::Multiline,
                 WindowMode::Normal,
             ]
-        } else if self.input.is_empty() {
+        } else if self.input.is_empty() && self.selected_message_id().is_some() {
             vec![
                 WindowMode::Anywhere,
                 WindowMode::MessageSelected,
@@ -394,11 +689,15 @@ impl App {
         }
     }
 
+    /// Replaces `file://` links in the input by the attachments they point to
+    ///
+    /// Fails if any of the attachments cannot be loaded, such that the message is not sent without
+    /// it.
     pub(super) fn extract_attachments<Tz: TimeZone>(
         input: &str,
         at: DateTime<Tz>,
         mut get_clipboard_img: impl FnMut() -> Option<Result<ImageData<'static>, arboard::Error>>,
-    ) -> (String, Vec<(AttachmentSpec, Vec<u8>)>)
+    ) -> anyhow::Result<(String, Vec<(AttachmentSpec, Vec<u8>)>)>
     where
         Tz::Offset: std::fmt::Display,
     {
@@ -412,69 +711,59 @@ impl App {
             offset = m.end();
 
             Some(if path_str.starts_with("clip") {
-                // clipboard
-                let img = get_clipboard_img()?
-                    .inspect_err(|error| error!(%error, "failed to get clipboard image"))
-                    .ok()?;
-
-                let width: u32 = img.width.try_into().ok()?;
-                let height: u32 = img.height.try_into().ok()?;
-
-                let mut bytes = Vec::new();
-                let mut cursor = Cursor::new(&mut bytes);
-                let encoder = PngEncoder::new(&mut cursor);
-
-                let png: ImageBuffer<Rgba<_>, _> = ImageBuffer::from_raw(width, height, img.bytes)?;
-                let data: Vec<_> = png.into_raw().iter().map(|b| b.swap_bytes()).collect();
-                encoder
-                    .write_image(
-                        &data,
-                        img.width as _,
-                        img.height as _,
-                        image::ExtendedColorType::Rgba8,
-                    )
-                    .inspect_err(|error| error!(%error, "failed to encode image"))
-                    .ok()?;
-
-                let file_name = format!("screenshot-{}.png", at.format("%Y-%m-%dT%H:%M:%S%z"));
-
-                let spec = AttachmentSpec {
-                    content_type: "image/png".to_owned(),
-                    length: bytes.len(),
-                    file_name: Some(file_name),
-                    width: Some(width),
-                    height: Some(height),
-                    ..Default::default()
-                };
-                (spec, bytes)
+                get_clipboard_img()
+                    .context("clipboard is disabled")
+                    .and_then(|img| img.context("failed to get clipboard image"))
+                    .and_then(|img| clipboard_attachment(img, &at))
             } else {
-                // path
-
-                // TODO: Show error to user if the file does not exist. This would prevent not
-                // sending the attachment in the end.
-
-                let path = Path::new(path_str);
-                let bytes = std::fs::read(path).ok()?;
-                let content_type = mime_guess::from_path(path)
-                    .first()
-                    .map(|mime| mime.essence_str().to_string())
-                    .unwrap_or_default();
-                let file_name = path.file_name().map(|f| f.to_string_lossy().into());
-                let spec = AttachmentSpec {
-                    content_type,
-                    length: bytes.len(),
-                    file_name,
-                    ..Default::default()
-                };
-
-                (spec, bytes)
+                load_attachment(Path::new(path_str))
             })
         });
 
-        let attachments = attachments.collect();
+        let attachments = attachments.collect::<anyhow::Result<_>>()?;
         clean_input.push_str(&input[offset..]);
         let clean_input = clean_input.trim().to_string();
 
-        (clean_input, attachments)
+        Ok((clean_input, attachments))
     }
 }
+
+/// Encodes the image from the clipboard as PNG attachment
+fn clipboard_attachment<Tz: TimeZone>(
+    img: ImageData<'static>,
+    at: &DateTime<Tz>,
+) -> anyhow::Result<(AttachmentSpec, Vec<u8>)>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let width: u32 = img.width.try_into()?;
+    let height: u32 = img.height.try_into()?;
+
+    let mut bytes = Vec::new();
+    let mut cursor = Cursor::new(&mut bytes);
+    let encoder = PngEncoder::new(&mut cursor);
+
+    let png: ImageBuffer<Rgba<_>, _> =
+        ImageBuffer::from_raw(width, height, img.bytes).context("invalid clipboard image")?;
+    let data: Vec<_> = png.into_raw().iter().map(|b| b.swap_bytes()).collect();
+    encoder
+        .write_image(
+            &data,
+            img.width as _,
+            img.height as _,
+            image::ExtendedColorType::Rgba8,
+        )
+        .context("failed to encode image")?;
+
+    let file_name = format!("screenshot-{}.png", at.format("%Y-%m-%dT%H:%M:%S%z"));
+
+    let spec = AttachmentSpec {
+        content_type: "image/png".to_owned(),
+        length: bytes.len(),
+        file_name: Some(file_name),
+        width: Some(width),
+        height: Some(height),
+        ..Default::default()
+    };
+    Ok((spec, bytes))
+}
@@ -1,23 +1,27 @@
 use std::borrow::Cow;
-use std::cmp::Reverse;
 use std::collections::BTreeMap;
 
 use anyhow::{Context as _, anyhow};
 use itertools::Itertools;
 use presage::libsignal_service::content::{Content, ContentBody, Metadata};
 use presage::libsignal_service::protocol::ServiceId;
+use presage::proto::GroupContextV2;
+use presage::proto::data_message::{Delete, Reaction};
 use presage::proto::sync_message::{Read, Sent};
 use presage::proto::{
     AttachmentPointer, DataMessage, EditMessage, ReceiptMessage, SyncMessage, TypingMessage,
 };
-use presage::proto::{GroupContextV2, data_message::Reaction};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::data::{BodyRange, ChannelId, Message, TypingAction, TypingSet};
+use crate::event::Event;
 use crate::receipt::{Receipt, ReceiptEvent};
-use crate::signal::{Attachment, GroupIdentifierBytes};
-use crate::storage::MessageId;
+use crate::signal::{
+    Attachment, GroupIdentifierBytes, LONG_TEXT_CONTENT_TYPE, PendingAttachment, pending_attachment,
+};
+use crate::storage::{MessageId, MessagePosition};
+use crate::util::utc_now_timestamp_msec;
 
 use super::{
     App, HandleReactionOptions, add_emoji_from_sticker, notification_text_for_attachments,
@@ -41,6 +45,66 @@ impl App {
         }
 
         let (channel_idx, message) = match (content.metadata, content.body) {
+            // Message deleted for everyone by us from a different device
+            (
+                _,
+                ContentBody::SynchronizeMessage(SyncMessage {
+                    sent:
+                        Some(Sent {
+                            destination_service_id: ref dest_str,
+                            destination_service_id_binary: ref dest_binary,
+                            message:
+                                Some(DataMessage {
+                                    group_v2,
+                                    delete:
+                                        Some(Delete {
+                                            target_sent_timestamp: Some(target_sent_timestamp),
+                                        }),
+                                    ..
+                                }),
+                            ..
+                        }),
+                    ..
+                }),
+            ) => {
+                let channel_id = if let Some(GroupContextV2 {
+                    master_key: Some(master_key),
+                    ..
+                }) = group_v2
+                {
+                    ChannelId::from_master_key_bytes(master_key)?
+                } else {
+                    let uuid = parse_uuid(dest_str.as_deref(), dest_binary.as_deref())
+                        .context("missing destination in sync delete")?;
+                    ChannelId::User(uuid)
+                };
+                self.handle_delete(channel_id, target_sent_timestamp, user_id);
+                return Ok(());
+            }
+            // Message deleted for everyone
+            (
+                Metadata { sender, .. },
+                ContentBody::DataMessage(DataMessage {
+                    group_v2,
+                    delete:
+                        Some(Delete {
+                            target_sent_timestamp: Some(target_sent_timestamp),
+                        }),
+                    ..
+                }),
+            ) => {
+                let channel_id = if let Some(GroupContextV2 {
+                    master_key: Some(master_key),
+                    ..
+                }) = group_v2
+                {
+                    ChannelId::from_master_key_bytes(master_key)?
+                } else {
+                    ChannelId::User(sender.raw_uuid())
+                };
+                self.handle_delete(channel_id, target_sent_timestamp, sender.raw_uuid());
+                return Ok(());
+            }
             // Private note message
             (
                 _,
@@ -57,6 +121,7 @@ impl App {
                                     sticker,
                                     body_ranges,
                                     reaction: None,
+                                    expire_timer,
                                     ..
                                 }),
                             ..
@@ -65,12 +130,21 @@ impl App {
                 }),
             ) if parse_uuid(dest_str.as_deref(), dest_binary.as_deref()) == Some(user_id) => {
                 let channel_idx = self.ensure_own_channel_exists();
-                let attachments = self.save_attachments(attachment_pointers).await;
+                let channel_id = self.channels.items[channel_idx];
+                let expire_timer = self.update_expire_timer(channel_id, expire_timer);
+                let (attachments, pending_attachments) = self
+                    .save_attachments(channel_id, &mut body, attachment_pointers)
+                    .await;
                 add_emoji_from_sticker(&mut body, sticker);
 
                 let body_ranges = body_ranges.into_iter().filter_map(BodyRange::from_proto);
 
-                let message = Message::new(user_id, body, body_ranges, timestamp, attachments);
+                let mut message = Message {
+                    expire_timer,
+                    pending_attachments,
+                    ..Message::new(user_id, body, body_ranges, timestamp, attachments)
+                };
+                message.start_expiry(timestamp);
                 (channel_idx, message)
             }
             // reactions
@@ -102,6 +176,11 @@ impl App {
                     ..
                 }),
             ) => {
+                let target_author_uuid = parse_uuid(
+                    target_author_aci_str.as_deref(),
+                    target_author_aci_binary.as_deref(),
+                )
+                .context("missing target author ACI in sync reaction")?;
                 let channel_id = if let Some(GroupContextV2 {
                     master_key: Some(master_key),
                     ..
@@ -111,12 +190,7 @@ impl App {
                 } else if let Some(uuid) = parse_uuid(dest_str.as_deref(), dest_binary.as_deref()) {
                     ChannelId::User(uuid)
                 } else {
-                    let uuid = parse_uuid(
-                        target_author_aci_str.as_deref(),
-                        target_author_aci_binary.as_deref(),
-                    )
-                    .context("missing target author ACI in sync reaction")?;
-                    ChannelId::User(uuid)
+                    ChannelId::User(target_author_uuid)
                 };
 
                 let channel_muted = self
@@ -125,8 +199,7 @@ impl App {
                     .map(|c| c.muted)
                     .unwrap_or(false);
                 self.handle_reaction(
-                    channel_id,
-                    target_sent_timestamp,
+                    MessageId::new(channel_id, target_author_uuid, target_sent_timestamp),
                     sender.raw_uuid(),
                     emoji,
                     HandleReactionOptions::new()
@@ -161,6 +234,11 @@ impl App {
                     ..
                 }),
             ) => {
+                let target_author_uuid = parse_uuid(
+                    target_author_aci_str.as_deref(),
+                    target_author_aci_binary.as_deref(),
+                )
+                .context("missing target author ACI in reaction")?;
                 let channel_id = if let Some(GroupContextV2 {
                     master_key: Some(master_key),
                     ..
@@ -169,12 +247,7 @@ impl App {
                     ChannelId::from_master_key_bytes(master_key)?
                 } else if sender.raw_uuid() == self.user_id {
                     // reaction from us => target author is the user channel
-                    let uuid = parse_uuid(
-                        target_author_aci_str.as_deref(),
-                        target_author_aci_binary.as_deref(),
-                    )
-                    .context("missing target author ACI in reaction")?;
-                    ChannelId::User(uuid)
+                    ChannelId::User(target_author_uuid)
                 } else {
                     // reaction is from somebody else => they are the user channel
                     ChannelId::User(sender.raw_uuid())
@@ -186,8 +259,7 @@ impl App {
                     .map(|c| c.muted)
                     .unwrap_or(false);
                 self.handle_reaction(
-                    channel_id,
-                    target_sent_timestamp,
+                    MessageId::new(channel_id, target_author_uuid, target_sent_timestamp),
                     sender.raw_uuid(),
                     emoji,
                     HandleReactionOptions::new()
@@ -217,6 +289,7 @@ impl App {
                                     sticker,
                                     body_ranges,
                                     reaction: None,
+                                    expire_timer,
                                     ..
                                 }),
                             ..
@@ -254,15 +327,24 @@ impl App {
                     return Ok(());
                 };
 
+                let channel_id = self.channels.items[channel_idx];
+                let expire_timer = self.update_expire_timer(channel_id, expire_timer);
+
                 add_emoji_from_sticker(&mut body, sticker);
                 let quote = quote.and_then(Message::from_quote).map(Box::new);
-                let attachments = self.save_attachments(attachment_pointers).await;
+                let (attachments, pending_attachments) = self
+                    .save_attachments(channel_id, &mut body, attachment_pointers)
+                    .await;
                 let body_ranges = body_ranges.into_iter().filter_map(BodyRange::from_proto);
 
-                let message = Message {
+                // our own messages start expiring when sent
+                let mut message = Message {
                     quote,
+                    expire_timer,
+                    pending_attachments,
                     ..Message::new(user_id, body, body_ranges, timestamp, attachments)
                 };
+                message.start_expiry(timestamp);
 
                 if message.is_empty() {
                     debug!("dropping empty message");
@@ -283,6 +365,7 @@ impl App {
                     attachments: attachment_pointers,
                     sticker,
                     body_ranges,
+                    expire_timer,
                     ..
                 }),
             ) => {
@@ -346,9 +429,14 @@ impl App {
                     (channel_idx, from, channel_muted)
                 };
 
+                let channel_id = self.channels.items[channel_idx];
+                let expire_timer = self.update_expire_timer(channel_id, expire_timer);
+
                 add_emoji_from_sticker(&mut body, sticker);
 
-                let attachments = self.save_attachments(attachment_pointers).await;
+                let (attachments, pending_attachments) = self
+                    .save_attachments(channel_id, &mut body, attachment_pointers)
+                    .await;
                 if !channel_muted {
                     self.notify_about_message(&from, body.as_deref(), &attachments);
                 }
@@ -362,8 +450,12 @@ impl App {
 
                 let quote = quote.and_then(Message::from_quote).map(Box::new);
                 let body_ranges = body_ranges.into_iter().filter_map(BodyRange::from_proto);
+                // the expiration timer is started when the message is read
                 let message = Message {
                     quote,
+                    receipt: Receipt::Delivered,
+                    expire_timer,
+                    pending_attachments,
                     ..Message::new(sender.raw_uuid(), body, body_ranges, timestamp, attachments)
                 };
 
@@ -440,6 +532,17 @@ impl App {
         self.bell();
     }
 
+    /// Remembers the messages visible on the screen, which are marked as read on the next tick
+    pub fn set_shown_messages(&mut self, message_ids: Vec<MessageId>) {
+        self.shown_messages = message_ids;
+    }
+
+    /// Marks the messages shown since the last tick as read
+    pub fn step_read(&mut self) {
+        let shown = std::mem::take(&mut self.shown_messages);
+        self.mark_as_read(shown);
+    }
+
     pub fn step_receipts(&mut self) {
         self.receipt_handler.step(self.signal_manager.as_ref());
     }
@@ -497,7 +600,41 @@ impl App {
         self.receipt_handler.add_receipt_event(event);
     }
 
-    fn handle_receipt(&mut self, sender_uuid: Uuid, receipt: Receipt, mut timestamps: Vec<u64>) {
+    /// Marks the given delivered messages of other users as read
+    ///
+    /// Also starts the expiration timer of disappearing messages.
+    ///
+    /// Must be called only for messages which were actually shown. Read receipts are queued only
+    /// if enabled in the config.
+    pub(super) fn mark_as_read(&mut self, message_ids: impl IntoIterator<Item = MessageId>) {
+        let now = utc_now_timestamp_msec();
+        for message_id in message_ids {
+            let Some(message) = self.storage.message(message_id) else {
+                continue;
+            };
+            let is_unread =
+                message.receipt == Receipt::Delivered && message.from_id != self.user_id;
+            let is_expiry_pending = message.expire_timer.is_some() && message.expires_at.is_none();
+            if !is_unread && !is_expiry_pending {
+                continue;
+            }
+            let mut message = message.into_owned();
+            message.start_expiry(now);
+            if is_unread {
+                message.receipt = Receipt::Read;
+            }
+            self.storage.store_message(message_id.channel_id, message);
+            if is_unread && self.config.send_read_receipts {
+                self.add_receipt_event(ReceiptEvent::new(
+                    message_id.from_id,
+                    message_id.arrived_at,
+                    Receipt::Read,
+                ));
+            }
+        }
+    }
+
+    fn handle_receipt(&mut self, sender_uuid: Uuid, receipt: Receipt, timestamps: Vec<u64>) {
         let sender_channels: Vec<ChannelId> = self
             .storage
             .channels()
@@ -512,7 +649,6 @@ impl App {
             .map(|channel| channel.id)
             .collect();
 
-        timestamps.sort_unstable_by_key(|&ts| Reverse(ts));
         if timestamps.is_empty() {
             return;
         }
@@ -520,19 +656,14 @@ impl App {
         let mut found_channel_id = None;
         let mut messages_to_store = Vec::new();
 
-        'outer: for channel_id in sender_channels {
-            let mut messages = self.storage.messages(channel_id).rev();
+        for channel_id in sender_channels {
+            // receipts are only sent for our own messages
             for &ts in &timestamps {
-                // Note: `&mut` is needed to advance the iterator `messages` with each `ts`.
-                // Since these are sorted in reverse order, we can continue advancing messages
-                // without consuming them.
-                if let Some(msg) = (&mut messages)
-                    .take_while(|msg| msg.arrived_at >= ts)
-                    .find(|msg| msg.arrived_at == ts)
-                {
-                    let mut msg = msg.into_owned();
+                let message_id = MessageId::new(channel_id, self.user_id, ts);
+                if let Some(msg) = self.storage.message(message_id) {
                     if msg.receipt < receipt {
-                        msg.receipt = msg.receipt.max(receipt);
+                        let mut msg = msg.into_owned();
+                        msg.receipt = receipt;
                         messages_to_store.push(msg);
                     }
                     found_channel_id = Some(channel_id);
@@ -541,7 +672,7 @@ impl App {
 
             if found_channel_id.is_some() {
                 // if one ts was found, then all other ts have to be in the same channel
-                break 'outer;
+                break;
             }
         }
 
@@ -554,8 +685,7 @@ impl App {
 
     pub(super) async fn handle_reaction(
         &mut self,
-        channel_id: ChannelId,
-        target_sent_timestamp: u64,
+        target_message_id: MessageId,
         sender_uuid: Uuid,
         emoji: String,
         HandleReactionOptions {
@@ -564,10 +694,8 @@ impl App {
             bell,
         }: HandleReactionOptions,
     ) -> Option<()> {
-        let mut message = self
-            .storage
-            .message(MessageId::new(channel_id, target_sent_timestamp))?
-            .into_owned();
+        let channel_id = target_message_id.channel_id;
+        let mut message = self.storage.message(target_message_id)?.into_owned();
         let from_current_user = self.user_id == message.from_id;
 
         let reaction_idx = message
@@ -627,22 +755,177 @@ impl App {
         Some(())
     }
 
+    /// Replaces the message and all its edits by a tombstone
+    ///
+    /// Only the author of a message is allowed to delete it, therefore the message is looked up
+    /// by the sender of the delete.
+    pub(super) fn handle_delete(
+        &mut self,
+        channel_id: ChannelId,
+        target_sent_timestamp: u64,
+        sender_uuid: Uuid,
+    ) -> Option<()> {
+        let message_id = MessageId::new(channel_id, sender_uuid, target_sent_timestamp);
+        let message = self.storage.message(message_id)?.into_owned();
+
+        let edits: Vec<Message> = self
+            .storage
+            .edits(message_id)
+            .map(Cow::into_owned)
+            .collect();
+        for mut message in edits.into_iter().chain([message]) {
+            message.delete();
+            self.storage.store_message(channel_id, message);
+        }
+
+        Some(())
+    }
+
+    /// Downloads the attachments allowed by the download policy of the config
+    ///
+    /// The other attachments are returned as pending, and are downloaded on request. A long-text
+    /// attachment is always downloaded and replaces the truncated `body`.
     async fn save_attachments(
         &mut self,
+        channel_id: ChannelId,
+        body: &mut Option<String>,
         attachment_pointers: Vec<AttachmentPointer>,
-    ) -> Vec<Attachment> {
+    ) -> (Vec<Attachment>, Vec<PendingAttachment>) {
+        let muted = self
+            .storage
+            .channel(channel_id)
+            .is_some_and(|channel| channel.muted);
         let mut attachments = vec![];
+        let mut pending_attachments = vec![];
         for attachment_pointer in attachment_pointers {
+            let is_long_text = attachment_pointer.content_type() == LONG_TEXT_CONTENT_TYPE;
+            if !is_long_text
+                && !self.config.downloads.should_download(
+                    attachment_pointer.content_type(),
+                    attachment_pointer.size(),
+                    muted,
+                )
+            {
+                match pending_attachment(attachment_pointer) {
+                    Ok(attachment) => pending_attachments.push(attachment),
+                    Err(e) => warn!("failed to keep attachment: {}", e),
+                }
+                continue;
+            }
             match self
                 .signal_manager
                 .save_attachment(attachment_pointer)
                 .await
             {
-                Ok(attachment) => attachments.push(attachment),
+                Ok(attachment) => attachments.extend(self.inline_long_text(body, attachment)),
                 Err(e) => warn!("failed to save attachment: {}", e),
             }
         }
-        attachments
+        (attachments, pending_attachments)
+    }
+
+    /// Replaces the body by the content of a long-text attachment, which is removed afterwards
+    ///
+    /// Other attachments are returned back. If the long text cannot be read, the attachment is
+    /// kept next to the truncated body.
+    fn inline_long_text(
+        &self,
+        body: &mut Option<String>,
+        attachment: Attachment,
+    ) -> Option<Attachment> {
+        if attachment.content_type != LONG_TEXT_CONTENT_TYPE {
+            return Some(attachment);
+        }
+        let data = match self.files.read(&attachment.filename) {
+            Ok(data) => data,
+            Err(error) => {
+                warn!(%error, "failed to read long text");
+                return Some(attachment);
+            }
+        };
+        *body = Some(String::from_utf8_lossy(&data).into_owned());
+        if let Err(error) = std::fs::remove_file(&attachment.filename) {
+            warn!(%error, path =% attachment.filename.display(), "failed to remove long text");
+        }
+        None
+    }
+
+    /// Starts downloading the pending attachments of the selected message
+    ///
+    /// The downloaded attachments are added to the message by
+    /// [`Event::DownloadedAttachments`]. Returns `None` if there is nothing to download, or the
+    /// attachments are already being downloaded.
+    pub(super) fn download_attachments(&mut self) -> Option<()> {
+        let message_id = self.selected_message_id()?;
+        if self.downloading.contains(&message_id) {
+            return None;
+        }
+        let message = self.storage.message(message_id)?;
+        let (pending, attachment_pointers): (Vec<PendingAttachment>, Vec<AttachmentPointer>) =
+            message
+                .pending_attachments
+                .iter()
+                .filter_map(|pending| {
+                    let pointer = pending
+                        .pointer()
+                        .inspect_err(|error| warn!(%error, "failed to download attachment"))
+                        .ok()?;
+                    Some((pending.clone(), pointer))
+                })
+                .unzip();
+        if pending.is_empty() {
+            return None;
+        }
+
+        let response = self
+            .signal_manager
+            .download_attachments(attachment_pointers);
+        self.downloading.insert(message_id);
+        let tx = self.event_tx.clone();
+        tokio::spawn(async move {
+            let results = response.await.unwrap_or_else(|_| {
+                error!(?message_id, "response for downloading attachments was lost");
+                Vec::new()
+            });
+            let results = pending.into_iter().zip(results).collect();
+            tx.send(Event::DownloadedAttachments {
+                message_id,
+                results,
+            })
+            .expect("event sender gone");
+        });
+        Some(())
+    }
+
+    /// Replaces the downloaded pending attachments of the message by the saved ones
+    ///
+    /// Attachments which failed to download stay pending.
+    pub(super) fn handle_downloaded_attachments(
+        &mut self,
+        message_id: MessageId,
+        results: Vec<(PendingAttachment, anyhow::Result<Attachment>)>,
+    ) {
+        self.downloading.remove(&message_id);
+        let Some(message) = self.storage.message(message_id) else {
+            // deleted while downloading
+            return;
+        };
+        let mut message = message.into_owned();
+        for (pending, result) in results {
+            match result {
+                Ok(attachment) => {
+                    message
+                        .pending_attachments
+                        .retain(|attachment| attachment.id != pending.id);
+                    let attachment = self.inline_long_text(&mut message.message, attachment);
+                    message.attachments.extend(attachment);
+                }
+                Err(error) => {
+                    warn!(%error, "failed to download attachment");
+                }
+            }
+        }
+        self.storage.store_message(message_id.channel_id, message);
     }
 
     fn notify(&self, summary: &str, text: &str) {
@@ -701,14 +984,14 @@ impl App {
             // Note: target_sent_timestamp points to the previous edit or the original message
             let edited = self
                 .storage
-                .message(MessageId::new(channel_id, target_sent_timestamp))
+                .message(MessageId::new(channel_id, from_id, target_sent_timestamp))
                 .context("no message to edit")?;
 
             // get original message
             let mut original = if let Some(arrived_at) = edited.edit {
                 // previous edit => get original message
                 self.storage
-                    .message(MessageId::new(channel_id, arrived_at))
+                    .message(MessageId::new(channel_id, from_id, arrived_at))
                     .context("no original edited message")?
                     .into_owned()
             } else {
@@ -757,10 +1040,17 @@ impl App {
             .iter()
             .filter_map(|read| {
                 let arrived_at = read.timestamp?;
-                let channel_id = self.storage.message_channel(arrived_at)?;
+                let from_id: Uuid = read.parse_sender_aci()?.into();
+                let channel_id = self.storage.message_channel(from_id, arrived_at)?;
+                // reading can only decrease the number of unread messages
+                let unread_messages = self.storage.channel(channel_id)?.unread_messages;
                 let num_unread = self
                     .storage
-                    .messages(channel_id)
+                    .messages_before(
+                        channel_id,
+                        MessagePosition::LATEST,
+                        unread_messages as usize,
+                    )
                     .rev()
                     .take_while(|msg| arrived_at < msg.arrived_at)
                     .count();
@@ -778,6 +1068,26 @@ impl App {
                 self.storage.store_channel(channel);
             }
         }
+        // Start expiration timers of messages read on another device
+        let now = utc_now_timestamp_msec();
+        for read in read {
+            let (Some(arrived_at), Some(from_id)) = (read.timestamp, read.parse_sender_aci())
+            else {
+                continue;
+            };
+            let from_id: Uuid = from_id.into();
+            let Some(channel_id) = self.storage.message_channel(from_id, arrived_at) else {
+                continue;
+            };
+            let message_id = MessageId::new(channel_id, from_id, arrived_at);
+            let Some(message) = self.storage.message(message_id) else {
+                continue;
+            };
+            let mut message = message.into_owned();
+            if message.start_expiry(now) {
+                self.storage.store_message(channel_id, message);
+            }
+        }
     }
 }
 
@@ -833,12 +1143,14 @@ impl SyncSentExt for Sent {
 
 #[cfg(test)]
 mod tests {
+    use presage::libsignal_service::prelude::AttachmentIdentifier;
+
     use crate::app::tests::test_app;
 
     use super::*;
 
     #[test]
-    #[ignore = "forgetful storage does not support lookup by arrived_at"]
+    #[ignore = "forgetful storage does not support lookup of the message channel"]
     fn test_handle_read() {
         let (mut app, _events, _sent_messages) = test_app();
 
@@ -861,10 +1173,85 @@ mod tests {
             .unread_messages = 1;
 
         app.handle_read(&[Read {
+            sender_aci: Some(message.from_id.to_string()),
             timestamp: Some(message.arrived_at),
             ..Default::default()
         }]);
 
         assert_eq!(app.storage.channel(channel_id).unwrap().unread_messages, 0);
     }
+
+    #[tokio::test]
+    async fn test_download_attachments() {
+        let (mut app, mut events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        app.config.downloads.max_size = Some(1_000_000);
+
+        let pointer = AttachmentPointer {
+            content_type: Some("video/mp4".to_string()),
+            size: Some(12_000_000),
+            digest: Some(vec![42; 32]),
+            attachment_identifier: Some(AttachmentIdentifier::CdnKey("video".to_string())),
+            ..Default::default()
+        };
+        let (attachments, pending_attachments) = app
+            .save_attachments(channel_id, &mut None, vec![pointer])
+            .await;
+        assert!(attachments.is_empty());
+        assert_eq!(pending_attachments.len(), 1);
+
+        let message = Message {
+            pending_attachments,
+            ..Message::text(app.user_id, 1, "video".to_string())
+        };
+        app.add_message_to_channel(0, message);
+        app.messages
+            .get_mut(&channel_id)
+            .unwrap()
+            .state
+            .select(Some(0));
+        assert_eq!(app.download_attachments(), Some(()));
+        // already being downloaded
+        assert_eq!(app.download_attachments(), None);
+
+        let event = events.recv().await.unwrap();
+        assert!(matches!(event, Event::DownloadedAttachments { .. }));
+        app.handle_event(event).unwrap();
+        let message_id = MessageId::new(channel_id, app.user_id, 1);
+        let message = app.storage.message(message_id).unwrap();
+        assert!(message.pending_attachments.is_empty());
+        assert_eq!(message.attachments.len(), 1);
+        assert_eq!(message.attachments[0].id, "video");
+        assert_eq!(message.attachments[0].size, 12_000_000);
+
+        // nothing left to download
+        assert_eq!(app.download_attachments(), None);
+    }
+
+    #[test]
+    fn test_inline_long_text() {
+        let (app, _events, _sent_messages) = test_app();
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("long.txt");
+        let text = "long ".repeat(500);
+        std::fs::write(&filename, &text).unwrap();
+        let attachment = |content_type: &str| Attachment {
+            id: "long".to_string(),
+            content_type: content_type.to_string(),
+            filename: filename.clone(),
+            size: text.len() as u32,
+        };
+
+        let mut body = Some("long long".to_string());
+        let image = attachment("image/png");
+        assert_eq!(app.inline_long_text(&mut body, image.clone()), Some(image));
+        assert_eq!(body.as_deref(), Some("long long"));
+
+        assert_eq!(
+            app.inline_long_text(&mut body, attachment(LONG_TEXT_CONTENT_TYPE)),
+            None
+        );
+        assert_eq!(body, Some(text));
+        assert!(!filename.exists());
+    }
 }
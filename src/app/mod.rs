@@ -1,8 +1,9 @@
 use std::borrow::Cow;
 use std::cell::Cell;
 use std::cmp::Reverse;
-use std::collections::BTreeMap;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 use anyhow::Context as _;
 use itertools::Itertools;
@@ -11,29 +12,52 @@ use tokio::sync::mpsc;
 use tracing::{debug, error, warn};
 use uuid::Uuid;
 
+use crate::attachment_files::AttachmentFiles;
+use crate::attachment_picker::AttachmentPicker;
+use crate::attachment_tray::{AddAttachment, AttachmentTray};
 use crate::channels::SelectChannel;
 use crate::command::{ModeKeybinding, get_keybindings};
 use crate::config::Config;
 use crate::data::{Channel, ChannelId, Message, TypingSet};
 use crate::event::Event;
+use crate::find::Find;
 use crate::input::Input;
+use crate::mention::{Mention, MentionCompletion};
+use crate::preview::{GraphicsProtocol, ImagePreviews};
 use crate::receipt::ReceiptHandler;
+use crate::search::Search;
 use crate::signal::{Attachment, SignalManager};
-use crate::storage::{MessageId, Storage};
+use crate::storage::{MessageId, MessagePosition, Storage};
 use crate::util::StatefulList;
 
 use presage::proto::data_message::Sticker;
 
+mod attachment_gc;
+mod attachment_picker;
+mod attachment_tray;
 mod channel;
+mod draft;
+mod expiry;
+mod find;
 mod input;
 mod message;
+mod outbox;
+mod outgoing;
+mod search;
+mod typing;
+
+/// Number of messages loaded at once into the list of messages of a channel
+pub const MESSAGES_PAGE_SIZE: usize = 200;
 
 pub struct App {
     pub config: Config,
     signal_manager: Box<dyn SignalManager>,
     pub storage: Box<dyn Storage>,
     pub channels: StatefulList<ChannelId>,
-    pub messages: BTreeMap<ChannelId, StatefulList<u64 /* arrived at*/>>,
+    /// Loaded latest messages of each channel
+    pub messages: BTreeMap<ChannelId, StatefulList<MessageId>>,
+    /// Channels with older messages in the storage which are not loaded into `messages` yet
+    older_messages: BTreeSet<ChannelId>,
     pub help_scroll: (u16, u16),
     pub user_id: Uuid,
     pub should_quit: bool,
@@ -44,37 +68,84 @@ pub struct App {
     pub input: Input,
     pub is_multiline_input: bool,
     editing: Option<MessageId>,
+    /// Message which is quoted by the next sent message
+    replying: Option<MessageId>,
+    /// Own message whose deletion for everyone awaits confirmation by deleting it again
+    deleting: Option<MessageId>,
+    /// Messages visible on the screen, marked as read on the next tick
+    shown_messages: Vec<MessageId>,
+    /// Messages with revealed spoilers
+    revealed_spoilers: BTreeSet<MessageId>,
     pub(crate) select_channel: SelectChannel,
+    pub(crate) search: Search,
+    /// Find in the messages of the selected channel
+    pub(crate) find: Find,
+    /// Popup with the attachments of the selected message
+    pub(crate) attachment_picker: AttachmentPicker,
+    /// Messages whose pending attachments are being downloaded
+    downloading: BTreeSet<MessageId>,
+    /// Files sent with the next message
+    pub(crate) attachment_tray: AttachmentTray,
+    /// Attachment trays of the channels which are not selected
+    attachment_trays: BTreeMap<ChannelId, AttachmentTray>,
+    /// Sent messages whose attachments are prepared in the background
+    outgoing: outgoing::Outgoing,
+    /// Popup for adding a file to the attachment tray
+    pub(crate) add_attachment: AddAttachment,
+    pub(crate) mention_completion: MentionCompletion,
+    /// Mentions chosen from the completion while composing the input
+    mentions: Vec<Mention>,
+    /// Typing indicator sent for the input
+    typing: Option<typing::Typing>,
+    /// Whether we are connected to Signal; new messages are queued while offline
+    online: bool,
+    retry: outbox::Retry,
     clipboard: Option<arboard::Clipboard>,
     event_tx: mpsc::UnboundedSender<Event>,
     // It is expensive to hit the signal manager contacts storage, so we cache it
     names_cache: Cell<Option<BTreeMap<Uuid, String>>>,
     pub mode_keybindings: ModeKeybinding,
+    /// Thumbnails of image attachments and graphics drawn on the screen
+    pub previews: ImagePreviews,
+    /// Saved attachments, which are encrypted at rest
+    files: AttachmentFiles,
+    /// Private directory to which attachments are decrypted for opening them; created on demand
+    decrypted_files: Option<tempfile::TempDir>,
+    /// When the attachment files were garbage collected last, or the app was started
+    attachments_gc_at: Option<Instant>,
+    /// Storage used to collect the garbage of attachment files in the background
+    attachments_gc_storage: Option<attachment_gc::GcStorage>,
 }
 
 impl App {
     pub fn try_new(
         config: Config,
         signal_manager: Box<dyn SignalManager>,
-        storage: Box<dyn Storage>,
+        mut storage: Box<dyn Storage>,
     ) -> anyhow::Result<(Self, mpsc::UnboundedReceiver<Event>)> {
         let user_id = signal_manager.user_id();
 
-        // build index of channels and messages for using them as lists content
+        // build index of channels and their latest messages for using them as lists content
         let mut channels: StatefulList<ChannelId> = Default::default();
+        channels.items = storage.channels().map(|channel| channel.id).collect();
         let mut messages: BTreeMap<_, StatefulList<_>> = BTreeMap::new();
-        for channel in storage.channels() {
-            channels.items.push(channel.id);
-            let channel_messages = &mut messages.entry(channel.id).or_default().items;
-            for message in storage.messages(channel.id) {
-                channel_messages.push(message.arrived_at);
+        let mut older_messages = BTreeSet::new();
+        for &channel_id in &channels.items {
+            let page = storage.load_messages_before(
+                channel_id,
+                MessagePosition::LATEST,
+                MESSAGES_PAGE_SIZE,
+            );
+            if page.len() == MESSAGES_PAGE_SIZE {
+                older_messages.insert(channel_id);
             }
+            messages.entry(channel_id).or_default().items = page;
         }
         channels.items.sort_unstable_by_key(|channel_id| {
-            let last_message_arrived_at = storage
-                .messages(*channel_id)
-                .next_back()
-                .map(|msg| msg.arrived_at);
+            let last_message_arrived_at = messages[channel_id]
+                .items
+                .last()
+                .map(|message_id| message_id.arrived_at);
             let channel_name = storage
                 .channel(*channel_id)
                 .map(|channel| channel.name.clone());
@@ -91,13 +162,25 @@ impl App {
         let mode_keybindings = get_keybindings(&config.keybindings, config.default_keybindings)
             .expect("keybinding configuration failed");
 
-        let app = Self {
+        let files = signal_manager.attachment_files();
+        let previews = ImagePreviews::new(
+            config
+                .image_previews
+                .protocol
+                .unwrap_or_else(GraphicsProtocol::detect),
+            config.image_previews.max_height,
+            files.clone(),
+            event_tx.clone(),
+        );
+
+        let mut app = Self {
             config,
             signal_manager,
             user_id,
             storage,
             channels,
             messages,
+            older_messages,
             help_scroll: (0, 0),
             should_quit: false,
             open_editor_requested: false,
@@ -107,12 +190,35 @@ impl App {
             input: Default::default(),
             is_multiline_input: false,
             editing: None,
+            replying: None,
+            deleting: None,
+            shown_messages: Vec::new(),
+            revealed_spoilers: Default::default(),
             select_channel: Default::default(),
+            search: Default::default(),
+            find: Default::default(),
+            attachment_picker: Default::default(),
+            downloading: Default::default(),
+            attachment_tray: Default::default(),
+            attachment_trays: Default::default(),
+            outgoing: Default::default(),
+            add_attachment: Default::default(),
+            mention_completion: Default::default(),
+            mentions: Default::default(),
+            typing: None,
+            online: false,
+            retry: Default::default(),
             clipboard,
             event_tx,
             names_cache: Default::default(),
             mode_keybindings,
+            previews,
+            files,
+            decrypted_files: None,
+            attachments_gc_at: None,
+            attachments_gc_storage: None,
         };
+        app.restore_draft();
         Ok((app, event_rx))
     }
 
@@ -133,9 +239,39 @@ impl App {
         self.names_cache.replace(Some(cache));
     }
 
+    /// Path to a plaintext version of the attachment for opening it with other applications
+    ///
+    /// The decrypted files are removed when the app is dropped.
+    pub(super) fn decrypted_path(&mut self, attachment: &Attachment) -> anyhow::Result<PathBuf> {
+        let temp_dir = match self.decrypted_files.take() {
+            Some(temp_dir) => temp_dir,
+            None => {
+                let mut builder = tempfile::Builder::new();
+                builder.prefix("gurk-");
+                // only accessible by the user
+                #[cfg(unix)]
+                builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o700));
+                builder
+                    .tempdir()
+                    .context("failed to create directory for decrypted attachments")?
+            }
+        };
+        let temp_dir = self.decrypted_files.insert(temp_dir);
+        self.files
+            .decrypted_path(&attachment.filename, temp_dir.path())
+    }
+
     pub fn get_input(&mut self) -> &mut Input {
         if self.select_channel.is_shown {
             &mut self.select_channel.input
+        } else if self.search.is_shown {
+            &mut self.search.input
+        } else if self.find.is_shown {
+            &mut self.find.input
+        } else if self.attachment_picker.is_shown {
+            &mut self.attachment_picker.input
+        } else if self.add_attachment.is_shown {
+            &mut self.add_attachment.input
         } else {
             &mut self.input
         }
@@ -268,23 +404,60 @@ impl App {
     pub fn handle_event(&mut self, event: Event) -> anyhow::Result<()> {
         match event {
             Event::SentTextResult { message_id, result } => {
-                if let Err(error) = result {
-                    let mut message = self
-                        .storage
-                        .message(message_id)
-                        .context("no message")?
-                        .into_owned();
-                    message.send_failed = Some(error.to_string());
-                    self.storage.store_message(message_id.channel_id, message);
-                }
+                self.handle_send_result(message_id, result, Instant::now())
+            }
+            Event::FindStep => {
+                self.step_find();
+                Ok(())
+            }
+            Event::DownloadedAttachments {
+                message_id,
+                results,
+            } => {
+                self.handle_downloaded_attachments(message_id, results);
+                Ok(())
+            }
+            Event::PreparedAttachments {
+                channel_id,
+                id,
+                result,
+            } => {
+                self.handle_prepared_attachments(channel_id, id, result);
+                Ok(())
+            }
+            Event::FoundAttachmentsGarbage { result } => {
+                self.handle_attachments_garbage(result);
+                Ok(())
+            }
+            Event::CollectedAttachmentsGarbage { report } => {
+                self.handle_collected_attachments_garbage(report);
+                Ok(())
+            }
+            Event::ThumbnailDecoded { id, image } => {
+                self.previews.insert_thumbnail(id, image);
+                Ok(())
             }
         }
-        Ok(())
     }
 
     pub(crate) fn is_editing(&self) -> bool {
         self.editing.is_some()
     }
+
+    pub(crate) fn is_deleting(&self) -> bool {
+        self.deleting.is_some()
+    }
+
+    pub(crate) fn is_spoiler_revealed(&self, message_id: MessageId) -> bool {
+        self.revealed_spoilers.contains(&message_id)
+    }
+
+    /// Returns the message which is replied to in the selected channel, if any
+    pub(crate) fn reply_target(&self) -> Option<Cow<'_, Message>> {
+        let channel_id = self.channels.selected_item()?;
+        let message_id = self.replying.filter(|id| &id.channel_id == channel_id)?;
+        self.storage.message(message_id)
+    }
 }
 
 #[derive(Debug, Default)]
@@ -338,16 +511,6 @@ pub(super) fn open_url(message: &Message, url_regex: &Regex) -> Option<()> {
     Some(())
 }
 
-pub(super) fn open_file(message: &Message) -> Option<()> {
-    let attachment = message.attachments.first()?;
-    let file: &Path = attachment.filename.as_ref();
-    if let Err(error) = opener::open(file) {
-        let path = file.display().to_string();
-        error!(path, %error, "failed to open");
-    }
-    Some(())
-}
-
 pub(super) fn notification_text_for_attachments(attachments: &[Attachment]) -> Option<String> {
     match attachments.len() {
         0 => None,
@@ -368,11 +531,14 @@ pub(crate) mod tests {
     use std::rc::Rc;
 
     use chrono::{DateTime, FixedOffset};
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
     use arboard::ImageData;
 
+    use crate::command::{Command, MoveAmountText, MoveDirection};
     use crate::config::User;
-    use crate::data::GroupData;
+    use crate::data::{AssociatedValue, BodyRange, GroupData, Style};
+    use crate::receipt::{Receipt, ReceiptEvent};
     use crate::signal::GroupMasterKeyBytes;
     use crate::signal::test::SignalManagerMock;
     use crate::storage::{ForgetfulStorage, MemCache};
@@ -401,6 +567,8 @@ pub(crate) mod tests {
             unread_messages: 1,
             muted: false,
             typing: TypingSet::GroupTyping(Default::default()),
+            expire_timer: None,
+            draft: None,
         };
         storage.store_channel(channel);
         storage.store_message(
@@ -411,12 +579,16 @@ pub(crate) mod tests {
                 arrived_at: 0,
                 quote: Default::default(),
                 attachments: Default::default(),
+                pending_attachments: Default::default(),
                 reactions: Default::default(),
                 receipt: Default::default(),
                 body_ranges: Default::default(),
-                send_failed: Default::default(),
+                send_state: Default::default(),
                 edit: Default::default(),
                 edited: Default::default(),
+                deleted: Default::default(),
+                expire_timer: Default::default(),
+                expires_at: Default::default(),
             },
         );
 
@@ -430,6 +602,7 @@ pub(crate) mod tests {
         )
         .unwrap();
         app.channels.state.select(Some(0));
+        app.online = true;
 
         (app, events, sent_messages)
     }
@@ -453,12 +626,11 @@ pub(crate) mod tests {
 
         assert_eq!(app.get_input().data, "");
 
-        match events.recv().await.unwrap() {
-            Event::SentTextResult { message_id, result } => {
-                assert_eq!(message_id.arrived_at, msg.arrived_at);
-                assert!(result.is_ok());
-            }
-        }
+        let Event::SentTextResult { message_id, result } = events.recv().await.unwrap() else {
+            panic!("unexpected event");
+        };
+        assert_eq!(message_id.arrived_at, msg.arrived_at);
+        assert!(result.is_ok());
     }
 
     #[tokio::test]
@@ -477,12 +649,11 @@ pub(crate) mod tests {
 
         assert_eq!(app.get_input().data, "");
 
-        match events.recv().await.unwrap() {
-            Event::SentTextResult { message_id, result } => {
-                assert_eq!(message_id.arrived_at, msg.arrived_at);
-                assert!(result.is_ok());
-            }
-        }
+        let Event::SentTextResult { message_id, result } = events.recv().await.unwrap() else {
+            panic!("unexpected event");
+        };
+        assert_eq!(message_id.arrived_at, msg.arrived_at);
+        assert!(result.is_ok());
     }
 
     #[tokio::test]
@@ -499,12 +670,126 @@ pub(crate) mod tests {
         let msg = sent_messages.borrow()[0].clone();
         assert_eq!(msg.message.as_ref().unwrap(), "\u{1F44D}");
 
-        match events.recv().await.unwrap() {
-            Event::SentTextResult { message_id, result } => {
-                assert_eq!(message_id.arrived_at, msg.arrived_at);
-                assert!(result.is_ok());
-            }
+        let Event::SentTextResult { message_id, result } = events.recv().await.unwrap() else {
+            panic!("unexpected event");
+        };
+        assert_eq!(message_id.arrived_at, msg.arrived_at);
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_send_input_with_markup() {
+        let (mut app, _events, sent_messages) = test_app();
+        for c in "*Hello*, World!".chars() {
+            app.get_input().put_char(c);
         }
+        app.send_input(0);
+
+        let msg = sent_messages.borrow()[0].clone();
+        assert_eq!(msg.message.as_deref(), Some("Hello, World!"));
+        assert_eq!(
+            msg.body_ranges,
+            [BodyRange {
+                start: 0,
+                end: 5,
+                value: AssociatedValue::Style(Style::Bold),
+            }]
+        );
+
+        let channel_id = app.channels.items[0];
+        let stored = app
+            .storage
+            .message(MessageId::of(channel_id, &msg))
+            .unwrap();
+        assert_eq!(stored.body_ranges, msg.body_ranges);
+    }
+
+    #[tokio::test]
+    async fn test_send_input_with_mention() {
+        let (mut app, _events, sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let member = Uuid::new_v4();
+        let mut channel = app.storage.channel(channel_id).unwrap().into_owned();
+        channel.group_data.as_mut().unwrap().members.push(member);
+        app.storage.store_channel(channel);
+        app.names_cache
+            .replace(Some([(member, "Bob".to_string())].into_iter().collect()));
+
+        for c in "hi @b".chars() {
+            app.on_key(KeyEvent::from(KeyCode::Char(c))).await.unwrap();
+        }
+        assert_eq!(
+            app.mention_completion.candidates(),
+            [Mention {
+                name: "Bob".to_string(),
+                user_id: member,
+            }]
+        );
+
+        app.on_key(KeyEvent::from(KeyCode::Enter)).await.unwrap();
+        assert!(!app.mention_completion.is_shown());
+        assert_eq!(app.input.data, "hi @Bob ");
+
+        app.on_key(KeyEvent::from(KeyCode::Enter)).await.unwrap();
+        let msg = sent_messages.borrow()[0].clone();
+        assert_eq!(msg.message.as_deref(), Some("hi \u{FFFC} "));
+        assert_eq!(
+            msg.body_ranges,
+            [BodyRange {
+                start: 3,
+                end: 4,
+                value: AssociatedValue::MentionUuid(member),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_input_without_reply_does_not_quote_selected_message() {
+        let (mut app, _events, sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        app.messages
+            .get_mut(&channel_id)
+            .unwrap()
+            .state
+            .select(Some(0));
+
+        for c in "Hello".chars() {
+            app.get_input().put_char(c);
+        }
+        app.send_input(0);
+
+        let msg = sent_messages.borrow()[0].clone();
+        assert_eq!(msg.quote, None);
+    }
+
+    #[tokio::test]
+    async fn test_send_input_with_reply() {
+        let (mut app, _events, sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let arrived_at = app.messages[&channel_id].items[0].arrived_at;
+        app.messages
+            .get_mut(&channel_id)
+            .unwrap()
+            .state
+            .select(Some(0));
+
+        app.start_replying();
+        assert_eq!(app.messages[&channel_id].state.selected(), None);
+        assert_eq!(
+            app.reply_target().map(|message| message.arrived_at),
+            Some(arrived_at)
+        );
+
+        for c in "Hello".chars() {
+            app.get_input().put_char(c);
+        }
+        app.send_input(0);
+
+        let msg = sent_messages.borrow()[0].clone();
+        let quote = msg.quote.expect("no quote");
+        assert_eq!(quote.arrived_at, arrived_at);
+        assert_eq!(quote.message.as_deref(), Some("First message"));
+        assert!(app.reply_target().is_none());
     }
 
     #[tokio::test]
@@ -521,12 +806,8 @@ pub(crate) mod tests {
         app.get_input().put_char('\u{1F44D}');
         app.add_reaction(0, None).await;
 
-        let arrived_at = app.messages[&channel_id].items[0];
-        let reactions = &app
-            .storage
-            .message(MessageId::new(channel_id, arrived_at))
-            .unwrap()
-            .reactions;
+        let message_id = app.messages[&channel_id].items[0];
+        let reactions = &app.storage.message(message_id).unwrap().reactions;
         assert_eq!(reactions.len(), 1);
         assert_eq!(reactions[0], (app.user_id, "\u{1F44D}".to_string()));
     }
@@ -547,12 +828,8 @@ pub(crate) mod tests {
         }
         app.add_reaction(0, None).await;
 
-        let arrived_at = app.messages[&channel_id].items[0];
-        let reactions = &app
-            .storage
-            .message(MessageId::new(channel_id, arrived_at))
-            .unwrap()
-            .reactions;
+        let message_id = app.messages[&channel_id].items[0];
+        let reactions = &app.storage.message(message_id).unwrap().reactions;
         assert_eq!(reactions.len(), 1);
         assert_eq!(reactions[0], (app.user_id, "\u{1F44D}".to_string()));
     }
@@ -568,23 +845,15 @@ pub(crate) mod tests {
             .state
             .select(Some(0));
 
-        let arrived_at = app.messages[&channel_id].items[0];
-        let mut message = app
-            .storage
-            .message(MessageId::new(channel_id, arrived_at))
-            .unwrap()
-            .into_owned();
+        let message_id = app.messages[&channel_id].items[0];
+        let mut message = app.storage.message(message_id).unwrap().into_owned();
         message
             .reactions
             .push((app.user_id, "\u{1F44D}".to_string()));
         app.storage.store_message(channel_id, message);
         app.add_reaction(0, None).await;
 
-        let reactions = &app
-            .storage
-            .message(MessageId::new(channel_id, arrived_at))
-            .unwrap()
-            .reactions;
+        let reactions = &app.storage.message(message_id).unwrap().reactions;
         assert!(reactions.is_empty());
     }
 
@@ -604,15 +873,189 @@ pub(crate) mod tests {
         app.add_reaction(0, None).await;
 
         assert_eq!(app.get_input().data, ":thumbsup");
-        let arrived_at = app.messages[&channel_id].items[0];
-        let reactions = &app
-            .storage
-            .message(MessageId::new(channel_id, arrived_at))
-            .unwrap()
-            .reactions;
+        let message_id = app.messages[&channel_id].items[0];
+        let reactions = &app.storage.message(message_id).unwrap().reactions;
         assert!(reactions.is_empty());
     }
 
+    #[test]
+    fn test_delete_message() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        app.messages
+            .get_mut(&channel_id)
+            .unwrap()
+            .state
+            .select(Some(0));
+
+        // the first delete only asks for confirmation
+        app.delete_message();
+        assert!(app.is_deleting());
+        let message_id = app.messages[&channel_id].items[0];
+        assert!(!app.storage.message(message_id).unwrap().deleted);
+
+        app.delete_message();
+        assert!(!app.is_deleting());
+        let message = app.storage.message(message_id).unwrap();
+        assert!(message.deleted);
+        assert_eq!(message.message, None);
+    }
+
+    #[tokio::test]
+    async fn test_delete_message_canceled_by_other_key() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        app.messages
+            .get_mut(&channel_id)
+            .unwrap()
+            .state
+            .select(Some(0));
+
+        let delete = KeyEvent::new(KeyCode::Char('d'), KeyModifiers::CONTROL);
+        app.on_key(delete).await.unwrap();
+        assert!(app.is_deleting());
+        app.on_key(KeyEvent::from(KeyCode::Char('x')))
+            .await
+            .unwrap();
+        assert!(!app.is_deleting());
+
+        let message_id = app.messages[&channel_id].items[0];
+        assert!(!app.storage.message(message_id).unwrap().deleted);
+    }
+
+    #[test]
+    fn test_mark_as_read() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let sender = Uuid::new_v4();
+        app.storage.store_message(
+            channel_id,
+            Message {
+                receipt: Receipt::Delivered,
+                ..Message::text(sender, 1, "Hello".to_string())
+            },
+        );
+
+        // own messages are skipped
+        let message_id = MessageId::new(channel_id, sender, 1);
+        app.mark_as_read([MessageId::new(channel_id, app.user_id, 0), message_id]);
+
+        let message = app.storage.message(message_id).unwrap();
+        assert_eq!(message.receipt, Receipt::Read);
+        let mut expected = ReceiptHandler::new();
+        expected.add_receipt_event(ReceiptEvent::new(sender, 1, Receipt::Read));
+        assert_eq!(app.receipt_handler, expected);
+
+        // read receipt is queued only once
+        app.mark_as_read([message_id]);
+        assert_eq!(app.receipt_handler, expected);
+    }
+
+    #[test]
+    fn test_step_read() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let sender = Uuid::new_v4();
+        app.storage.store_message(
+            channel_id,
+            Message {
+                receipt: Receipt::Delivered,
+                ..Message::text(sender, 1, "Hello".to_string())
+            },
+        );
+        let message_id = MessageId::new(channel_id, sender, 1);
+
+        // drawing only remembers the shown messages
+        app.set_shown_messages(vec![message_id]);
+        let message = app.storage.message(message_id).unwrap();
+        assert_eq!(message.receipt, Receipt::Delivered);
+
+        app.step_read();
+        let message = app.storage.message(message_id).unwrap();
+        assert_eq!(message.receipt, Receipt::Read);
+        assert!(app.shown_messages.is_empty());
+    }
+
+    #[test]
+    fn test_mark_as_read_without_read_receipts() {
+        let (mut app, _events, _sent_messages) = test_app();
+        app.config.send_read_receipts = false;
+        let channel_id = app.channels.items[0];
+        let sender = Uuid::new_v4();
+        app.storage.store_message(
+            channel_id,
+            Message {
+                receipt: Receipt::Delivered,
+                ..Message::text(sender, 1, "Hello".to_string())
+            },
+        );
+
+        let message_id = MessageId::new(channel_id, sender, 1);
+        app.mark_as_read([message_id]);
+
+        let message = app.storage.message(message_id).unwrap();
+        assert_eq!(message.receipt, Receipt::Read);
+        assert_eq!(app.receipt_handler, ReceiptHandler::new());
+    }
+
+    #[test]
+    fn test_handle_delete_from_other_author() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let message_id = app.messages[&channel_id].items[0];
+
+        let result = app.handle_delete(channel_id, message_id.arrived_at, Uuid::new_v4());
+        assert_eq!(result, None);
+
+        let message = app.storage.message(message_id).unwrap();
+        assert!(!message.deleted);
+        assert_eq!(message.message.as_deref(), Some("First message"));
+    }
+
+    #[test]
+    fn test_load_older_messages() {
+        let (app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let mut storage = app.storage;
+        let page_size = MESSAGES_PAGE_SIZE as u64;
+        for arrived_at in 1..=2 * page_size {
+            storage.store_message(
+                channel_id,
+                Message::text(app.user_id, arrived_at, "Hello".to_string()),
+            );
+        }
+
+        let (mut app, _events) =
+            App::try_new(app.config, Box::new(SignalManagerMock::new()), storage).unwrap();
+        app.channels.state.select(Some(0));
+        assert_eq!(app.messages[&channel_id].items.len(), MESSAGES_PAGE_SIZE);
+
+        // approaching the oldest loaded message loads the next page
+        for _ in 0..=MESSAGES_PAGE_SIZE / 2 {
+            app.on_pgup();
+        }
+        assert_eq!(
+            app.messages[&channel_id].items.len(),
+            2 * MESSAGES_PAGE_SIZE
+        );
+        assert_eq!(
+            app.selected_message_id(),
+            Some(MessageId::new(
+                channel_id,
+                app.user_id,
+                2 * page_size - page_size / 2
+            ))
+        );
+
+        // selecting a message loads all messages up to it
+        let first_message_id = MessageId::new(channel_id, app.user_id, 0);
+        assert_eq!(app.select_message(first_message_id), Some(()));
+        let messages = &app.messages[&channel_id];
+        assert_eq!(messages.items.len(), 2 * MESSAGES_PAGE_SIZE + 1);
+        assert_eq!(messages.items[0], first_message_id);
+        assert!(!app.older_messages.contains(&channel_id));
+    }
+
     #[test]
     fn test_to_emoji() {
         assert_eq!(to_emoji("\u{1F680}"), Some("\u{1F680}"));
@@ -647,7 +1090,7 @@ pub(crate) mod tests {
         let at: DateTime<FixedOffset> = at_str.parse().unwrap();
 
         let (cleaned_message, specs) =
-            App::extract_attachments(&message, at, || Some(Ok(clipboard_image.clone())));
+            App::extract_attachments(&message, at, || Some(Ok(clipboard_image.clone()))).unwrap();
         assert_eq!(cleaned_message, "Hello, World!");
         dbg!(&specs);
 
@@ -661,5 +1104,30 @@ pub(crate) mod tests {
             specs[2].0.file_name,
             Some(format!("screenshot-{at_str}.png"))
         );
+
+        // a missing file or clipboard image is an error instead of being dropped silently
+        let missing = format!("file://{}", tempdir.path().join("missing.png").display());
+        assert!(App::extract_attachments(&missing, at, || None).is_err());
+        assert!(App::extract_attachments("file://clip", at, || None).is_err());
+    }
+
+    #[test]
+    fn test_popup_falls_back_to_text_input_bindings() {
+        let (mut app, _events, _sent_messages) = test_app();
+        app.toggle_search();
+
+        let down = KeyEvent::from(KeyCode::Down);
+        assert_eq!(
+            app.event_to_command(&down),
+            Some(&Command::SelectSearchResult(MoveDirection::Next))
+        );
+        let left = KeyEvent::from(KeyCode::Left);
+        assert_eq!(
+            app.event_to_command(&left),
+            Some(&Command::MoveText(
+                MoveDirection::Previous,
+                MoveAmountText::Character
+            ))
+        );
     }
 }
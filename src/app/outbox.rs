@@ -0,0 +1,312 @@
+//! Queueing and retrying of own messages which could not be sent
+
+use std::time::Instant;
+
+use anyhow::Context as _;
+use tokio::sync::oneshot;
+use tracing::{debug, error};
+
+use crate::backoff::Backoff;
+use crate::data::SendState;
+use crate::event::Event;
+use crate::storage::MessageId;
+
+use super::App;
+
+/// Number of failed sending attempts after which a message is not retried automatically
+const MAX_SEND_ATTEMPTS: u32 = 5;
+
+/// Schedule of the automatic retry of failed messages
+#[derive(Debug, Default)]
+pub(super) struct Retry {
+    backoff: Backoff,
+    /// When the failed messages are retried next
+    at: Option<Instant>,
+}
+
+impl App {
+    /// Sends all queued and failed messages after (re)connecting
+    ///
+    /// Messages which failed permanently are only sent again via the `retry_message` command.
+    pub fn on_connected(&mut self) {
+        self.online = true;
+        self.retry = Default::default();
+        self.resend_outbox();
+    }
+
+    /// Queues new messages until connected again
+    pub fn on_disconnected(&mut self) {
+        self.online = false;
+    }
+
+    /// Retries failed messages when the backoff timeout elapsed
+    pub fn step_outbox(&mut self) {
+        self.retry_due(Instant::now());
+    }
+
+    pub(super) fn retry_due(&mut self, now: Instant) {
+        if self.online && self.retry.at.is_some_and(|at| at <= now) {
+            self.retry.at = None;
+            self.resend_outbox();
+        }
+    }
+
+    /// Sends the selected message again, which failed to be sent
+    pub(super) fn retry_message(&mut self) -> Option<()> {
+        let message_id = self.selected_message_id()?;
+        let mut message = self.storage.message(message_id)?.into_owned();
+        if !matches!(
+            message.send_state,
+            Some(SendState::Retrying { .. } | SendState::Failed(_))
+        ) {
+            return None;
+        }
+        message.send_state = Some(SendState::Pending);
+        self.storage.store_message(message_id.channel_id, message);
+        if self.online {
+            self.resend(message_id);
+        }
+        Some(())
+    }
+
+    pub(super) fn handle_send_result(
+        &mut self,
+        message_id: MessageId,
+        result: anyhow::Result<()>,
+        now: Instant,
+    ) -> anyhow::Result<()> {
+        let mut message = self
+            .storage
+            .message(message_id)
+            .context("no message")?
+            .into_owned();
+        let send_state = match result {
+            Ok(()) => {
+                self.retry.backoff.reset();
+                None
+            }
+            Err(error) => {
+                debug!(?message_id, %error, "failed to send message");
+                Some(self.failed_send_state(message.send_state.as_ref(), error, now))
+            }
+        };
+        if message.send_state != send_state {
+            message.send_state = send_state;
+            self.storage.store_message(message_id.channel_id, message);
+        }
+        Ok(())
+    }
+
+    /// Emits the result of sending a message as [`Event::SentTextResult`]
+    pub(super) fn forward_send_result(
+        &self,
+        message_id: MessageId,
+        response: oneshot::Receiver<anyhow::Result<()>>,
+    ) {
+        let tx = self.event_tx.clone();
+        tokio::spawn(async move {
+            if let Ok(result) = response.await {
+                tx.send(Event::SentTextResult { message_id, result })
+                    .expect("event sender gone");
+            } else {
+                error!(?message_id, "response for sending message was lost");
+            }
+        });
+    }
+
+    fn failed_send_state(
+        &mut self,
+        send_state: Option<&SendState>,
+        error: anyhow::Error,
+        now: Instant,
+    ) -> SendState {
+        if !self.online {
+            // sent again when connected
+            return SendState::Pending;
+        }
+        let attempts = match send_state {
+            Some(SendState::Retrying { attempts, .. }) => attempts + 1,
+            _ => 1,
+        };
+        if attempts >= MAX_SEND_ATTEMPTS {
+            return SendState::Failed(error.to_string());
+        }
+        if self.retry.at.is_none() {
+            self.retry.at = Some(now + self.retry.backoff.get());
+        }
+        SendState::Retrying {
+            attempts,
+            error: error.to_string(),
+        }
+    }
+
+    fn resend_outbox(&mut self) {
+        let outbox: Vec<MessageId> = self.storage.outbox().collect();
+        for message_id in outbox {
+            let is_failed = self
+                .storage
+                .message(message_id)
+                .is_some_and(|message| matches!(message.send_state, Some(SendState::Failed(_))));
+            if !is_failed {
+                self.resend(message_id);
+            }
+        }
+    }
+
+    fn resend(&mut self, message_id: MessageId) -> Option<()> {
+        debug!(?message_id, "sending message again");
+        let message = self.storage.message(message_id)?;
+        let channel = self.storage.channel(message_id.channel_id)?;
+        match self.signal_manager.resend_message(&channel, &message) {
+            Ok(response) => self.forward_send_result(message_id, response),
+            Err(error) => {
+                // retrying does not help, e.g. when an attachment was removed
+                error!(?message_id, %error, "failed to send message again");
+                let mut message = message.into_owned();
+                message.send_state = Some(SendState::Failed(error.to_string()));
+                self.storage.store_message(message_id.channel_id, message);
+            }
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+
+    use crate::app::tests::test_app;
+    use crate::data::Message;
+    use crate::signal::Attachment;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_failed_message_is_retried() {
+        let (mut app, mut events, sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let message_id = MessageId::new(channel_id, app.user_id, 1);
+        app.add_message_to_channel(0, Message::text(app.user_id, 1, "Hello".to_string()));
+        let now = Instant::now();
+
+        app.handle_send_result(message_id, Err(anyhow!("timeout")), now)
+            .unwrap();
+        let message = app.storage.message(message_id).unwrap();
+        assert_eq!(
+            message.send_state,
+            Some(SendState::Retrying {
+                attempts: 1,
+                error: "timeout".to_string()
+            })
+        );
+
+        // not due yet
+        app.retry_due(now);
+        assert!(sent_messages.borrow().is_empty());
+
+        app.retry_due(now + Duration::from_secs(1));
+        assert_eq!(sent_messages.borrow().len(), 1);
+        assert_eq!(sent_messages.borrow()[0].arrived_at, 1);
+
+        let Event::SentTextResult { message_id, result } = events.recv().await.unwrap() else {
+            panic!("unexpected event");
+        };
+        app.handle_send_result(message_id, result, now).unwrap();
+        let message = app.storage.message(message_id).unwrap();
+        assert_eq!(message.send_state, None);
+    }
+
+    #[tokio::test]
+    async fn test_message_with_missing_attachment_fails() {
+        let (mut app, _events, sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let message_id = MessageId::new(channel_id, app.user_id, 1);
+        let message = Message {
+            attachments: vec![Attachment {
+                id: "digest".to_string(),
+                content_type: "image/jpeg".to_string(),
+                filename: "/nonexistent/photo.jpeg".into(),
+                size: 1,
+            }],
+            send_state: Some(SendState::Pending),
+            ..Message::text(app.user_id, 1, "Hello".to_string())
+        };
+        app.add_message_to_channel(0, message);
+
+        app.on_connected();
+        assert!(sent_messages.borrow().is_empty());
+        let message = app.storage.message(message_id).unwrap();
+        assert!(matches!(message.send_state, Some(SendState::Failed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_failed_message_is_retried_on_request() {
+        let (mut app, _events, sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let message_id = MessageId::new(channel_id, app.user_id, 1);
+        app.add_message_to_channel(0, Message::text(app.user_id, 1, "Hello".to_string()));
+        let now = Instant::now();
+
+        for _ in 0..MAX_SEND_ATTEMPTS {
+            app.handle_send_result(message_id, Err(anyhow!("timeout")), now)
+                .unwrap();
+        }
+        let message = app.storage.message(message_id).unwrap();
+        assert_eq!(
+            message.send_state,
+            Some(SendState::Failed("timeout".to_string()))
+        );
+
+        // permanently failed messages are not retried automatically
+        app.on_connected();
+        assert!(sent_messages.borrow().is_empty());
+
+        app.messages
+            .get_mut(&channel_id)
+            .unwrap()
+            .state
+            .select(Some(0));
+        assert_eq!(app.retry_message(), Some(()));
+        assert_eq!(sent_messages.borrow().len(), 1);
+        let message = app.storage.message(message_id).unwrap();
+        assert_eq!(message.send_state, Some(SendState::Pending));
+    }
+
+    #[tokio::test]
+    async fn test_offline_message_is_queued() {
+        let (mut app, mut events, sent_messages) = test_app();
+        app.on_disconnected();
+
+        for c in "Hello".chars() {
+            app.get_input().put_char(c);
+        }
+        app.send_input(0);
+        // nothing is sent while offline
+        assert!(sent_messages.borrow().is_empty());
+        assert!(events.try_recv().is_err());
+        let channel_id = app.channels.items[0];
+        let message_id = *app.messages[&channel_id].items.last().unwrap();
+        let message = app.storage.message(message_id).unwrap();
+        assert_eq!(message.message.as_deref(), Some("Hello"));
+        assert_eq!(message.send_state, Some(SendState::Pending));
+
+        // failure while offline keeps the message queued
+        app.handle_send_result(message_id, Err(anyhow!("offline")), Instant::now())
+            .unwrap();
+        let message = app.storage.message(message_id).unwrap();
+        assert_eq!(message.send_state, Some(SendState::Pending));
+
+        app.on_connected();
+        assert_eq!(sent_messages.borrow().len(), 1);
+        assert_eq!(sent_messages.borrow()[0].arrived_at, message_id.arrived_at);
+        let Event::SentTextResult { message_id, result } = events.recv().await.unwrap() else {
+            panic!("unexpected event");
+        };
+        app.handle_send_result(message_id, result, Instant::now())
+            .unwrap();
+        let message = app.storage.message(message_id).unwrap();
+        assert_eq!(message.send_state, None);
+    }
+}
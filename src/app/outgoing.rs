@@ -0,0 +1,248 @@
+//! Own messages whose attachments are prepared in the background before they are sent
+//!
+//! Removing the metadata of images and downscaling them is too slow for the UI thread. The messages
+//! of a channel are still sent in the order in which they were written: a message waits for the
+//! messages before it, even if it has no attachments itself.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use anyhow::Context as _;
+use presage::libsignal_service::sender::AttachmentSpec;
+use tracing::error;
+
+use crate::attachment_tray::AttachmentTray;
+use crate::cursor::Cursor;
+use crate::data::ChannelId;
+use crate::event::Event;
+use crate::input::Input;
+use crate::mention::Mention;
+use crate::sanitize::sanitize_attachment;
+use crate::storage::MessageId;
+
+use super::App;
+
+type Attachments = Vec<(AttachmentSpec, Vec<u8>)>;
+
+/// Message written in the input box
+pub(super) struct OutgoingMessage {
+    /// Input without the `file://` links of the attachments
+    pub text: String,
+    pub mentions: Vec<Mention>,
+    pub quote: Option<MessageId>,
+    pub editing: Option<MessageId>,
+    /// Input as written, restored if the attachments cannot be prepared
+    pub input: String,
+    /// Restored together with the input
+    pub tray: AttachmentTray,
+}
+
+/// Messages waiting for their attachments or for the messages before them, per channel
+#[derive(Default)]
+pub(super) struct Outgoing {
+    next_id: u64,
+    queues: BTreeMap<ChannelId, VecDeque<Queued>>,
+}
+
+struct Queued {
+    id: u64,
+    message: OutgoingMessage,
+    /// `None` while the attachments are prepared
+    attachments: Option<Attachments>,
+}
+
+impl Outgoing {
+    fn push(
+        &mut self,
+        channel_id: ChannelId,
+        message: OutgoingMessage,
+        attachments: Option<Attachments>,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.queues
+            .entry(channel_id)
+            .or_default()
+            .push_back(Queued {
+                id,
+                message,
+                attachments,
+            });
+        id
+    }
+
+    fn get_mut(&mut self, channel_id: ChannelId, id: u64) -> Option<&mut Queued> {
+        self.queues
+            .get_mut(&channel_id)?
+            .iter_mut()
+            .find(|queued| queued.id == id)
+    }
+
+    fn remove(&mut self, channel_id: ChannelId, id: u64) -> Option<OutgoingMessage> {
+        let queue = self.queues.get_mut(&channel_id)?;
+        let idx = queue.iter().position(|queued| queued.id == id)?;
+        let queued = queue.remove(idx)?;
+        if queue.is_empty() {
+            self.queues.remove(&channel_id);
+        }
+        Some(queued.message)
+    }
+
+    /// Removes the first message of the channel if its attachments are prepared
+    fn pop_ready(&mut self, channel_id: ChannelId) -> Option<(OutgoingMessage, Attachments)> {
+        let queue = self.queues.get_mut(&channel_id)?;
+        queue.front()?.attachments.as_ref()?;
+        let queued = queue.pop_front()?;
+        if queue.is_empty() {
+            self.queues.remove(&channel_id);
+        }
+        Some((queued.message, queued.attachments?))
+    }
+}
+
+impl App {
+    /// Sends the message after its attachments are prepared and the messages before it are sent
+    pub(super) fn send_outgoing(
+        &mut self,
+        channel_id: ChannelId,
+        message: OutgoingMessage,
+        attachments: Attachments,
+    ) {
+        if attachments.is_empty() {
+            if self.outgoing.queues.contains_key(&channel_id) {
+                self.outgoing.push(channel_id, message, Some(attachments));
+            } else {
+                self.send_message(channel_id, message, attachments);
+            }
+            return;
+        }
+        let id = self.outgoing.push(channel_id, message, None);
+        let config = self.config.uploads.clone();
+        let tx = self.event_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = attachments
+                .into_iter()
+                .map(|(mut spec, bytes)| {
+                    let bytes =
+                        sanitize_attachment(&mut spec, bytes, &config).with_context(|| {
+                            format!(
+                                "failed to sanitize {}",
+                                spec.file_name.as_deref().unwrap_or("attachment")
+                            )
+                        })?;
+                    Ok((spec, bytes))
+                })
+                .collect();
+            // the app is gone if the receiver is dropped
+            let _ = tx.send(Event::PreparedAttachments {
+                channel_id,
+                id,
+                result,
+            });
+        });
+    }
+
+    pub(super) fn handle_prepared_attachments(
+        &mut self,
+        channel_id: ChannelId,
+        id: u64,
+        result: anyhow::Result<Attachments>,
+    ) {
+        match result {
+            Ok(attachments) => {
+                if let Some(queued) = self.outgoing.get_mut(channel_id, id) {
+                    queued.attachments = Some(attachments);
+                }
+            }
+            Err(error) => {
+                error!(%error, "failed to prepare attachments");
+                if let Some(message) = self.outgoing.remove(channel_id, id) {
+                    self.restore_outgoing(channel_id, message, format!("{error:#}"));
+                }
+            }
+        }
+        while let Some((message, attachments)) = self.outgoing.pop_ready(channel_id) {
+            self.send_message(channel_id, message, attachments);
+        }
+    }
+
+    /// Puts the input and the attachments of the message back into its channel, unless another
+    /// message is already written there
+    fn restore_outgoing(&mut self, channel_id: ChannelId, message: OutgoingMessage, error: String) {
+        let mut tray = message.tray;
+        tray.set_error(error);
+        if self.channels.selected_item() == Some(&channel_id) {
+            if self.editing.is_none() && self.input.is_empty() {
+                self.input = Input {
+                    cursor: Cursor::end(&message.input),
+                    data: message.input,
+                };
+            }
+            if self.attachment_tray.is_empty() {
+                self.attachment_tray = tray;
+            }
+        } else {
+            if self
+                .storage
+                .channel(channel_id)
+                .is_some_and(|channel| channel.draft.is_none())
+            {
+                self.update_draft(channel_id, Some(message.input));
+            }
+            self.attachment_trays.entry(channel_id).or_insert(tray);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::tests::test_app;
+    use crate::attachment_tray::TrayAttachment;
+    use crate::event::Event;
+
+    #[tokio::test]
+    async fn test_messages_are_sent_in_order() {
+        let (mut app, mut events, sent_messages) = test_app();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "notes").unwrap();
+
+        app.attachment_tray.add(TrayAttachment::new(path).unwrap());
+        app.input.data = "first".to_owned();
+        app.send_input(0);
+        app.input.data = "second".to_owned();
+        app.send_input(0);
+        assert!(sent_messages.borrow().is_empty());
+
+        let event = events.recv().await.unwrap();
+        assert!(matches!(event, Event::PreparedAttachments { .. }));
+        app.handle_event(event).unwrap();
+        let texts: Vec<_> = sent_messages
+            .borrow()
+            .iter()
+            .map(|message| message.message.clone().unwrap())
+            .collect();
+        assert_eq!(texts, ["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn test_input_is_restored_if_attachments_fail() {
+        let (mut app, mut events, sent_messages) = test_app();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.jpeg");
+        // start of a JPEG image without any data
+        std::fs::write(&path, [0xff, 0xd8, 0xff]).unwrap();
+
+        app.attachment_tray.add(TrayAttachment::new(path).unwrap());
+        app.input.data = "photo".to_owned();
+        app.send_input(0);
+        assert!(app.input.data.is_empty());
+
+        let event = events.recv().await.unwrap();
+        assert!(matches!(event, Event::PreparedAttachments { .. }));
+        app.handle_event(event).unwrap();
+        assert!(sent_messages.borrow().is_empty());
+        assert_eq!(app.input.data, "photo");
+        assert_eq!(app.attachment_tray.attachments().len(), 1);
+        assert!(app.attachment_tray.error().is_some());
+    }
+}
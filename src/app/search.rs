@@ -0,0 +1,140 @@
+//! Search of messages across all channels
+
+use tracing::debug;
+
+use crate::search::ParsedQuery;
+use crate::storage::{MessageId, SearchQuery};
+use crate::util::local_start_of_day_timestamp_msec;
+
+use super::App;
+
+/// Maximum number of shown search results
+const MAX_SEARCH_RESULTS: usize = 200;
+
+impl App {
+    pub(super) fn toggle_search(&mut self) {
+        if !self.search.is_shown {
+            self.search.reset();
+        }
+        self.search.is_shown = !self.search.is_shown;
+    }
+
+    /// Searches for the input of the search popup, or jumps to the selected result if the input
+    /// was already searched for
+    pub(super) fn on_search_enter(&mut self) {
+        if self.search.is_outdated() {
+            self.search_messages();
+        } else if let Some(message_id) = self.search.selected_result() {
+            self.search.is_shown = false;
+            self.jump_to_message(message_id);
+        }
+    }
+
+    fn search_messages(&mut self) {
+        let results = ParsedQuery::parse(&self.search.input.data)
+            .map(|parsed| {
+                let query = self.resolve_query(parsed);
+                debug!(?query, "searching messages");
+                self.storage.search(&query, MAX_SEARCH_RESULTS).collect()
+            })
+            .map_err(|error| error.to_string());
+        self.search.set_results(results);
+    }
+
+    /// Resolves the names of senders and channels in the query
+    pub(super) fn resolve_query(&self, parsed: ParsedQuery) -> SearchQuery {
+        let from = parsed.from.map(|name| {
+            let name = name.to_lowercase();
+            let mut from: Vec<_> = self
+                .storage
+                .names()
+                .filter(|(_, contact_name)| contact_name.to_lowercase().contains(&name))
+                .map(|(id, _)| id)
+                .collect();
+            if self.config.user.display_name.to_lowercase().contains(&name) {
+                from.push(self.user_id);
+            }
+            from
+        });
+        let channels = parsed.channel.map(|name| {
+            let name = name.to_lowercase();
+            self.storage
+                .channels()
+                .filter(|channel| self.channel_name(channel).to_lowercase().contains(&name))
+                .map(|channel| channel.id)
+                .collect()
+        });
+        SearchQuery {
+            words: parsed.words,
+            from,
+            channels,
+            has_attachment: parsed.has_attachment,
+            before: parsed.before.and_then(local_start_of_day_timestamp_msec),
+            after: parsed.after.and_then(local_start_of_day_timestamp_msec),
+        }
+    }
+
+    /// Selects the channel of the message and the message in it
+    pub(super) fn jump_to_message(&mut self, message_id: MessageId) -> Option<()> {
+        let channel_idx = self
+            .channels
+            .items
+            .iter()
+            .position(|&channel_id| channel_id == message_id.channel_id)?;
+        self.select_channel_idx(channel_idx);
+        self.reset_unread_messages();
+        self.select_message(message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::tests::test_app;
+    use crate::data::Message;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve_query() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let alice = uuid::Uuid::new_v4();
+        app.storage.store_name(alice, "Alice".to_string());
+
+        let parsed =
+            ParsedQuery::parse("from:ali in:TEST has:attachment after:2026-01-01 word").unwrap();
+        let query = app.resolve_query(parsed);
+        assert_eq!(query.words, ["word"]);
+        assert_eq!(query.from, Some(vec![alice]));
+        assert_eq!(query.channels, Some(vec![channel_id]));
+        assert!(query.has_attachment);
+        assert_eq!(query.before, None);
+        assert!(query.after.is_some());
+
+        let parsed = ParsedQuery::parse("from:tyler in:unknown").unwrap();
+        let query = app.resolve_query(parsed);
+        assert_eq!(query.from, Some(vec![app.user_id]));
+        assert_eq!(query.channels, Some(vec![]));
+    }
+
+    #[test]
+    fn test_jump_to_message() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        for arrived_at in 1..=3 {
+            app.add_message_to_channel(
+                0,
+                Message::text(app.user_id, arrived_at, "Hello".to_string()),
+            );
+        }
+
+        let message_id = MessageId::new(channel_id, app.user_id, 1);
+        assert_eq!(app.jump_to_message(message_id), Some(()));
+        let messages = &app.messages[&channel_id];
+        assert_eq!(messages.items[1], message_id);
+        assert_eq!(messages.state.selected(), Some(2));
+
+        let message_id = MessageId::new(channel_id, app.user_id, 4);
+        assert_eq!(app.jump_to_message(message_id), None);
+    }
+}
@@ -0,0 +1,171 @@
+//! Sending of our own typing indicators
+
+use std::time::{Duration, Instant};
+
+use crate::data::{ChannelId, TypingAction};
+
+use super::App;
+
+/// Interval in which a started typing indicator is repeated while typing
+const TYPING_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+/// Duration without editing the input after which we stop typing
+const TYPING_IDLE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Started typing indicator sent to a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Typing {
+    channel_id: ChannelId,
+    /// When the started typing indicator was sent
+    sent_at: Instant,
+    /// When the input was edited the last time
+    edited_at: Instant,
+}
+
+impl App {
+    /// Stops typing after being idle or when the selected channel changed
+    pub fn step_typing(&mut self) {
+        self.update_typing(Instant::now());
+    }
+
+    pub(super) fn update_typing(&mut self, now: Instant) {
+        if let Some(typing) = self.typing
+            && (self.channels.selected_item() != Some(&typing.channel_id)
+                || now.duration_since(typing.edited_at) >= TYPING_IDLE_TIMEOUT)
+        {
+            self.stop_typing();
+        }
+    }
+
+    /// Sends a started typing indicator to the selected channel, if not sent recently
+    ///
+    /// Stops typing if the input was cleared.
+    pub(super) fn on_input_edited(&mut self, now: Instant) {
+        self.update_typing(now);
+        if self.input.is_empty() {
+            self.stop_typing();
+            return;
+        }
+        if !self.config.send_typing_indicators {
+            return;
+        }
+        let Some(&channel_id) = self.channels.selected_item() else {
+            return;
+        };
+        if channel_id == self.user_id {
+            return; // note to self
+        }
+
+        match &mut self.typing {
+            Some(typing) if now.duration_since(typing.sent_at) < TYPING_REFRESH_INTERVAL => {
+                typing.edited_at = now;
+            }
+            _ => {
+                self.send_typing(channel_id, TypingAction::Started);
+                self.typing = Some(Typing {
+                    channel_id,
+                    sent_at: now,
+                    edited_at: now,
+                });
+            }
+        }
+    }
+
+    /// Sends a stopped typing indicator if we are typing
+    pub(super) fn stop_typing(&mut self) {
+        if let Some(typing) = self.typing.take() {
+            self.send_typing(typing.channel_id, TypingAction::Stopped);
+        }
+    }
+
+    fn send_typing(&self, channel_id: ChannelId, action: TypingAction) {
+        if let Some(channel) = self.storage.channel(channel_id) {
+            self.signal_manager.send_typing(&channel, action);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::tests::test_app;
+
+    use super::*;
+
+    #[test]
+    fn test_typing_started_and_refreshed() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let channel_id = app.channels.items[0];
+        let start = Instant::now();
+
+        app.input.put_char('a');
+        app.on_input_edited(start);
+        let started = Typing {
+            channel_id,
+            sent_at: start,
+            edited_at: start,
+        };
+        assert_eq!(app.typing, Some(started));
+
+        // throttled
+        let edited_at = start + Duration::from_secs(2);
+        app.input.put_char('b');
+        app.on_input_edited(edited_at);
+        assert_eq!(
+            app.typing,
+            Some(Typing {
+                edited_at,
+                ..started
+            })
+        );
+
+        // refreshed
+        let mut edited_at = edited_at;
+        while edited_at < start + TYPING_REFRESH_INTERVAL {
+            edited_at += Duration::from_secs(2);
+            app.input.put_char('c');
+            app.on_input_edited(edited_at);
+        }
+        assert_eq!(
+            app.typing,
+            Some(Typing {
+                channel_id,
+                sent_at: edited_at,
+                edited_at,
+            })
+        );
+    }
+
+    #[test]
+    fn test_typing_stopped() {
+        let (mut app, _events, _sent_messages) = test_app();
+        let start = Instant::now();
+
+        // idle
+        app.input.put_char('a');
+        app.on_input_edited(start);
+        app.update_typing(start + TYPING_IDLE_TIMEOUT);
+        assert_eq!(app.typing, None);
+
+        // cleared input
+        app.on_input_edited(start);
+        app.input.take();
+        app.on_input_edited(start);
+        assert_eq!(app.typing, None);
+
+        // switched channel
+        app.input.put_char('a');
+        app.on_input_edited(start);
+        app.channels.state.select(None);
+        app.update_typing(start);
+        assert_eq!(app.typing, None);
+    }
+
+    #[test]
+    fn test_typing_disabled() {
+        let (mut app, _events, _sent_messages) = test_app();
+        app.config.send_typing_indicators = false;
+
+        app.input.put_char('a');
+        app.on_input_edited(Instant::now());
+        assert_eq!(app.typing, None);
+    }
+}
@@ -0,0 +1,341 @@
+//! Attachment files in the data directory, encrypted at rest
+//!
+//! The files are encrypted with AES-256-GCM using a key derived from the passphrase via Argon2id.
+//! An encrypted file consists of [`MAGIC`], a random nonce and the ciphertext. Files without the
+//! magic bytes are read as plaintext; they were saved before encryption was introduced.
+
+use std::fmt;
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io::{Read, Write as _};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{Context as _, anyhow};
+use argon2::Argon2;
+use rand::RngCore;
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+use crate::passphrase::Passphrase;
+
+const MAGIC: &[u8; 8] = b"GURKENC\x01";
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+
+/// Salt of the key derivation; not secret, but the key cannot be derived again without it
+const SALT_FILE: &str = "attachments.salt";
+/// Created when the files saved before encryption was introduced are encrypted
+const MIGRATED_FILE: &str = "attachments.encrypted";
+/// Appended to the file name of a file while it is encrypted
+const ENCRYPTING_SUFFIX: &str = ".encrypting";
+
+/// Reads and writes attachment files, encrypting them if a key is set
+#[derive(Clone, Default)]
+pub struct AttachmentFiles {
+    key: Option<Arc<Zeroizing<[u8; KEY_LEN]>>>,
+}
+
+impl fmt::Debug for AttachmentFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // without the key
+        f.debug_struct("AttachmentFiles")
+            .field("encrypted", &self.key.is_some())
+            .finish()
+    }
+}
+
+impl AttachmentFiles {
+    /// Derives the key from the passphrase
+    ///
+    /// The salt of the key derivation is created in the data directory on first use.
+    pub fn open(data_dir: &Path, passphrase: &Passphrase) -> anyhow::Result<Self> {
+        let salt = load_or_create_salt(data_dir)?;
+        let mut key = Zeroizing::new([0; KEY_LEN]);
+        Argon2::default()
+            .hash_password_into(passphrase.as_ref().as_bytes(), &salt, &mut *key)
+            .map_err(|error| anyhow!("failed to derive attachments key: {error}"))?;
+        Ok(Self {
+            key: Some(Arc::new(key)),
+        })
+    }
+
+    /// Encrypts the plaintext files saved before encryption was introduced, once
+    ///
+    /// Files which cannot be encrypted are skipped with a warning; they stay readable as
+    /// plaintext, and the migration is tried again on the next start.
+    pub fn migrate(&self, data_dir: &Path) {
+        let migrated = data_dir.join(MIGRATED_FILE);
+        if migrated.exists() {
+            return;
+        }
+        let files_dir = data_dir.join("files");
+        remove_encrypting_files(&files_dir);
+        let (count, failed) = self.encrypt_existing(&files_dir);
+        info!(count, failed, "encrypted existing attachments");
+        if failed == 0
+            && let Err(error) = std::fs::write(&migrated, b"")
+        {
+            warn!(%error, path =% migrated.display(), "failed to mark attachments as encrypted");
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_key(key: [u8; KEY_LEN]) -> Self {
+        Self {
+            key: Some(Arc::new(Zeroizing::new(key))),
+        }
+    }
+
+    pub fn write(&self, path: &Path, data: &[u8]) -> anyhow::Result<()> {
+        let data = match &self.key {
+            Some(key) => encrypt(key, data)?,
+            None => data.to_vec(),
+        };
+        std::fs::write(path, data)
+            .with_context(|| format!("failed to write attachment at: {}", path.display()))
+    }
+
+    pub fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let data = std::fs::read(path)
+            .with_context(|| format!("failed to read attachment at: {}", path.display()))?;
+        let Some(encrypted) = data.strip_prefix(MAGIC) else {
+            return Ok(data);
+        };
+        let key = self
+            .key
+            .as_ref()
+            .context("attachment is encrypted, but no key is set")?;
+        decrypt(key, encrypted)
+            .with_context(|| format!("failed to decrypt attachment at: {}", path.display()))
+    }
+
+    /// Path to a plaintext version of the file, e.g. for opening it with another application
+    ///
+    /// Encrypted files are decrypted into `temp_dir`, which should only be accessible by the user.
+    /// The decrypted files and their directories are created accessible only by the user, too.
+    /// Plaintext files are not copied.
+    pub fn decrypted_path(&self, path: &Path, temp_dir: &Path) -> anyhow::Result<PathBuf> {
+        if self.key.is_none() || !is_encrypted_file(path)? {
+            return Ok(path.to_owned());
+        }
+        // files are unique within their date directory
+        let dir = temp_dir.join(path.parent().and_then(Path::file_name).unwrap_or_default());
+        let dest = dir.join(path.file_name().context("attachment without file name")?);
+        if !dest.exists() {
+            let data = self.read(path)?;
+            let mut dir_builder = DirBuilder::new();
+            dir_builder.recursive(true);
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                dir_builder.mode(0o700);
+                options.mode(0o600);
+            }
+            dir_builder
+                .create(&dir)
+                .with_context(|| format!("failed to create dir: {}", dir.display()))?;
+            options
+                .open(&dest)
+                .and_then(|mut file| file.write_all(&data))
+                .with_context(|| format!("failed to write {}", dest.display()))?;
+        }
+        Ok(dest)
+    }
+
+    /// Encrypts the plaintext files in the directory recursively
+    ///
+    /// Returns the number of encrypted files and of the files which failed to be encrypted.
+    fn encrypt_existing(&self, dir: &Path) -> (usize, usize) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return (0, 0);
+        };
+        let (mut count, mut failed) = (0, 0);
+        for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+            if path.is_dir() {
+                let (dir_count, dir_failed) = self.encrypt_existing(&path);
+                count += dir_count;
+                failed += dir_failed;
+                continue;
+            }
+            match self.encrypt_file(&path) {
+                Ok(true) => count += 1,
+                Ok(false) => {}
+                Err(error) => {
+                    warn!(%error, path =% path.display(), "failed to encrypt attachment");
+                    failed += 1;
+                }
+            }
+        }
+        (count, failed)
+    }
+
+    /// Encrypts the file in place; returns `false` if it is encrypted already
+    fn encrypt_file(&self, path: &Path) -> anyhow::Result<bool> {
+        if is_encrypted_file(path)? {
+            return Ok(false);
+        }
+        let data = std::fs::read(path)
+            .with_context(|| format!("failed to read attachment at: {}", path.display()))?;
+        // the plaintext file is only replaced once the encrypted one is complete
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(ENCRYPTING_SUFFIX);
+        let tmp_path = PathBuf::from(tmp_path);
+        let result = self.write(&tmp_path, &data).and_then(|()| {
+            std::fs::rename(&tmp_path, path)
+                .with_context(|| format!("failed to replace attachment at: {}", path.display()))
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp_path);
+        }
+        result.map(|()| true)
+    }
+}
+
+/// Removes the incomplete encrypted files of an interrupted migration
+///
+/// Only files whose plaintext file still exists are removed, since the encrypted file replaces
+/// the plaintext one once it is complete.
+fn remove_encrypting_files(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+        if path.is_dir() {
+            remove_encrypting_files(&path);
+        } else if let Some(plaintext) = path
+            .to_str()
+            .and_then(|path| path.strip_suffix(ENCRYPTING_SUFFIX))
+            && Path::new(plaintext).exists()
+        {
+            warn!(path =% path.display(), "removing incomplete encrypted attachment");
+            if let Err(error) = std::fs::remove_file(&path) {
+                warn!(%error, path =% path.display(), "failed to remove attachment");
+            }
+        }
+    }
+}
+
+fn encrypt(key: &[u8; KEY_LEN], plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut nonce = [0; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = Aes256Gcm::new(key.into())
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| anyhow!("failed to encrypt attachment"))?;
+    Ok([MAGIC.as_slice(), &nonce, &ciphertext].concat())
+}
+
+fn decrypt(key: &[u8; KEY_LEN], data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        return Err(anyhow!("attachment is truncated"));
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    Aes256Gcm::new(key.into())
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("wrong key or corrupted attachment"))
+}
+
+fn is_encrypted_file(path: &Path) -> anyhow::Result<bool> {
+    let mut file = File::open(path)
+        .with_context(|| format!("failed to read attachment at: {}", path.display()))?;
+    let mut magic = [0; MAGIC.len()];
+    Ok(file.read_exact(&mut magic).is_ok() && &magic == MAGIC)
+}
+
+fn load_or_create_salt(data_dir: &Path) -> anyhow::Result<[u8; SALT_LEN]> {
+    let path = data_dir.join(SALT_FILE);
+    match std::fs::read(&path) {
+        Ok(salt) => salt
+            .try_into()
+            .map_err(|_| anyhow!("invalid salt in {}", path.display())),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            let mut salt = [0; SALT_LEN];
+            rand::thread_rng().fill_bytes(&mut salt);
+            std::fs::create_dir_all(data_dir)
+                .with_context(|| format!("failed to create dir: {}", data_dir.display()))?;
+            std::fs::write(&path, salt)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            Ok(salt)
+        }
+        Err(error) => Err(error).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_at_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.jpeg");
+        let files = AttachmentFiles::with_key([42; KEY_LEN]);
+
+        files.write(&path, b"jpeg data").unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.starts_with(MAGIC));
+        assert!(!data.windows(9).any(|w| w == b"jpeg data"));
+        assert_eq!(files.read(&path).unwrap(), b"jpeg data");
+
+        let error = AttachmentFiles::with_key([0; KEY_LEN])
+            .read(&path)
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("wrong key"));
+        assert!(AttachmentFiles::default().read(&path).is_err());
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let decrypted = files.decrypted_path(&path, temp_dir.path()).unwrap();
+        assert!(decrypted.starts_with(temp_dir.path()));
+        assert_eq!(decrypted.file_name().unwrap(), "photo.jpeg");
+        assert_eq!(std::fs::read(&decrypted).unwrap(), b"jpeg data");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&decrypted).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_encrypt_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let date_dir = dir.path().join("2024-01-01");
+        std::fs::create_dir(&date_dir).unwrap();
+        let plaintext = date_dir.join("photo.jpeg");
+        std::fs::write(&plaintext, b"jpeg data").unwrap();
+        let encrypted = date_dir.join("other.jpeg");
+
+        let files = AttachmentFiles::with_key([42; KEY_LEN]);
+        files.write(&encrypted, b"other data").unwrap();
+        assert_eq!(files.read(&plaintext).unwrap(), b"jpeg data");
+
+        assert_eq!(files.encrypt_existing(dir.path()), (1, 0));
+        assert!(is_encrypted_file(&plaintext).unwrap());
+        assert_eq!(files.read(&plaintext).unwrap(), b"jpeg data");
+        assert_eq!(files.read(&encrypted).unwrap(), b"other data");
+    }
+
+    #[test]
+    fn test_migrate_removes_incomplete_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let date_dir = dir.path().join("files").join("2024-01-01");
+        std::fs::create_dir_all(&date_dir).unwrap();
+        let plaintext = date_dir.join("photo.jpeg");
+        std::fs::write(&plaintext, b"jpeg data").unwrap();
+        // left over from an interrupted migration
+        let incomplete = date_dir.join("photo.jpeg.encrypting");
+        std::fs::write(&incomplete, &MAGIC[..4]).unwrap();
+
+        let files = AttachmentFiles::with_key([42; KEY_LEN]);
+        files.migrate(dir.path());
+        assert!(!incomplete.exists());
+        assert!(is_encrypted_file(&plaintext).unwrap());
+        assert_eq!(files.read(&plaintext).unwrap(), b"jpeg data");
+        assert!(dir.path().join(MIGRATED_FILE).exists());
+    }
+}
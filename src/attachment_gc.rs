@@ -0,0 +1,407 @@
+//! Garbage collection of attachment files
+//!
+//! Attachments are saved in `files/<date>/` in the data directory, and the same attachment is
+//! saved again each time it arrives. The collection merges the copies with the same id (the digest
+//! of the attachment) into the first one, which is then referenced by all their messages, and
+//! removes the files which are not referenced by any message anymore.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context as _;
+use tracing::{debug, warn};
+
+use crate::data::{ChannelId, Message};
+use crate::signal::Attachment;
+use crate::storage::{AttachmentFile, MessageId, Storage};
+
+/// Unreferenced files younger than this are kept, because their message might not be stored yet
+const MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug)]
+pub struct GcOptions<'a> {
+    /// Directory with the saved attachments in its `files` directory
+    pub data_dir: &'a Path,
+    /// Only reports the files which would be removed
+    pub dry_run: bool,
+}
+
+/// Files removed by the collection, or which would be removed in a dry run
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Files not referenced by any message
+    pub unreferenced: Vec<PathBuf>,
+    /// Copies of attachments which are replaced by another copy
+    pub duplicates: Vec<PathBuf>,
+    /// Messages which referenced a replaced copy
+    pub updated_messages: usize,
+    /// Total size of the removed files
+    pub reclaimable_bytes: u64,
+}
+
+impl fmt::Display for GcReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "unreferenced files:  {}", self.unreferenced.len())?;
+        writeln!(f, "duplicate files:     {}", self.duplicates.len())?;
+        writeln!(f, "updated messages:    {}", self.updated_messages)?;
+        write!(f, "reclaimable bytes:   {}", self.reclaimable_bytes)
+    }
+}
+
+/// Garbage found by [`find_garbage`], which is removed by [`Garbage::update_messages`] and
+/// [`Garbage::remove_files`]
+#[derive(Debug)]
+pub struct Garbage {
+    pub report: GcReport,
+    /// Path of the kept copy, as stored in the messages, by canonical path of the replaced copy
+    replacements: HashMap<PathBuf, PathBuf>,
+    /// Messages which reference a replaced copy
+    outdated: BTreeSet<MessageId>,
+    files_dir: PathBuf,
+}
+
+/// Merges copies of the same attachment and removes unreferenced attachment files
+///
+/// Files which were modified within the last hour are never considered unreferenced.
+pub fn collect_garbage(storage: &mut dyn Storage, options: &GcOptions) -> anyhow::Result<GcReport> {
+    collect(storage, options, MIN_AGE)
+}
+
+fn collect(
+    storage: &mut dyn Storage,
+    options: &GcOptions,
+    min_age: Duration,
+) -> anyhow::Result<GcReport> {
+    let garbage = find(storage, options.data_dir, min_age)?;
+    if !options.dry_run {
+        garbage.update_messages(storage);
+        // the messages are updated before their files are removed
+        storage.save();
+        garbage.remove_files();
+    }
+    Ok(garbage.report)
+}
+
+/// Finds the copies of the same attachment and the unreferenced attachment files without
+/// changing anything
+///
+/// Since the storage is only read, this can run in the background with another handle of the
+/// storage. Files which were modified within the last hour are never considered unreferenced.
+pub fn find_garbage(storage: &dyn Storage, data_dir: &Path) -> anyhow::Result<Garbage> {
+    find(storage, data_dir, MIN_AGE)
+}
+
+fn find(storage: &dyn Storage, data_dir: &Path, min_age: Duration) -> anyhow::Result<Garbage> {
+    let files: Vec<(AttachmentFile, PathBuf)> = storage
+        .attachment_files()
+        .filter_map(|file| {
+            let path = canonical(&file.filename)?;
+            Some((file, path))
+        })
+        .collect();
+
+    // existing copies of each attachment by canonical path, with the path as stored in the message
+    let mut copies: BTreeMap<&str, BTreeMap<&Path, &Path>> = BTreeMap::new();
+    for (file, path) in &files {
+        copies
+            .entry(file.attachment_id.as_str())
+            .or_default()
+            .entry(path.as_path())
+            .or_insert(file.filename.as_path());
+    }
+
+    let mut referenced = HashSet::new();
+    let mut replacements: HashMap<PathBuf, PathBuf> = HashMap::new();
+    for (id, copies) in copies {
+        let mut copies = copies.into_iter();
+        let Some((kept, kept_filename)) = copies.next() else {
+            continue;
+        };
+        let kept_size = file_size(kept);
+        for (path, _) in copies {
+            if kept_size.is_some() && file_size(path) == kept_size {
+                replacements.insert(path.to_owned(), kept_filename.to_owned());
+            } else {
+                warn!(id, path =% path.display(), "keeping copy of attachment with different size");
+                referenced.insert(path.to_owned());
+            }
+        }
+        referenced.insert(kept.to_owned());
+    }
+
+    let outdated: BTreeSet<MessageId> = files
+        .iter()
+        .filter(|(_, path)| replacements.contains_key(path))
+        .map(|(file, _)| file.message_id)
+        .collect();
+    let updated_messages = outdated
+        .iter()
+        .map(|&message_id| replace_files(storage, message_id, &replacements).len())
+        .sum();
+
+    let mut report = GcReport {
+        updated_messages,
+        ..Default::default()
+    };
+    let files_dir = data_dir.join("files");
+    let now = SystemTime::now();
+    for path in list_files(&files_dir)? {
+        let Some(canonical_path) = canonical(&path) else {
+            continue;
+        };
+        if referenced.contains(&canonical_path) {
+            continue;
+        }
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        if replacements.contains_key(&canonical_path) {
+            report.duplicates.push(path);
+        } else if metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .is_some_and(|age| age >= min_age)
+        {
+            report.unreferenced.push(path);
+        } else {
+            continue;
+        }
+        report.reclaimable_bytes += metadata.len();
+    }
+
+    Ok(Garbage {
+        report,
+        replacements,
+        outdated,
+        files_dir,
+    })
+}
+
+impl Garbage {
+    /// Points the messages to the kept copies of their attachments
+    ///
+    /// The messages are read again, such that changes to them since the garbage was found are
+    /// kept.
+    pub fn update_messages(&self, storage: &mut dyn Storage) {
+        for &message_id in &self.outdated {
+            for (channel_id, message) in replace_files(storage, message_id, &self.replacements) {
+                storage.store_message(channel_id, message);
+            }
+        }
+    }
+
+    /// Removes the files of the report
+    ///
+    /// Must only be called after the updated messages are persisted.
+    pub fn remove_files(&self) {
+        let report = &self.report;
+        for path in report.duplicates.iter().chain(&report.unreferenced) {
+            debug!(path =% path.display(), "removing attachment");
+            if let Err(error) = std::fs::remove_file(path) {
+                warn!(%error, path =% path.display(), "failed to remove attachment");
+            }
+        }
+        remove_empty_dirs(&self.files_dir);
+    }
+}
+
+/// The message and its edits with the replaced copies of attachments, if any of them changed
+fn replace_files(
+    storage: &dyn Storage,
+    message_id: MessageId,
+    replacements: &HashMap<PathBuf, PathBuf>,
+) -> Vec<(ChannelId, Message)> {
+    let Some(message) = storage.message(message_id) else {
+        return Vec::new();
+    };
+    let edits: Vec<_> = if message.edited {
+        storage.edits(message_id).collect()
+    } else {
+        Vec::new()
+    };
+    std::iter::once(message)
+        .chain(edits)
+        .filter_map(|message| {
+            let mut message = message.into_owned();
+            let mut is_updated = false;
+            for attachment in &mut message.attachments {
+                if let Some(kept) =
+                    canonical(&attachment.filename).and_then(|path| replacements.get(&path))
+                {
+                    attachment.filename = kept.clone();
+                    is_updated = true;
+                }
+            }
+            is_updated.then_some((message_id.channel_id, message))
+        })
+        .collect()
+}
+
+/// Removes those of the attachment files of the deleted messages which are not referenced by any
+/// other message
+///
+/// After a garbage collection, several messages can share the same file. Only the messages with an
+/// attachment with the same id are looked at. The deleted messages are ignored, since they might
+/// not be removed from the storage yet.
+pub(crate) fn remove_unreferenced(
+    storage: &dyn Storage,
+    deleted: &[MessageId],
+    attachments: Vec<Attachment>,
+) {
+    for attachment in attachments {
+        let path = attachment.filename;
+        let is_referenced = canonical(&path).is_some_and(|canonical_path| {
+            storage
+                .attachment_copies(&attachment.id)
+                .filter(|file| !deleted.contains(&file.message_id))
+                .any(|file| canonical(&file.filename).as_ref() == Some(&canonical_path))
+        });
+        if is_referenced {
+            continue;
+        }
+        if let Err(error) = std::fs::remove_file(&path) {
+            warn!(%error, path =% path.display(), "failed to remove attachment");
+        }
+    }
+}
+
+/// Paths are compared canonically, such that a differently spelled data directory does not lead
+/// to referenced files being removed
+fn canonical(path: &Path) -> Option<PathBuf> {
+    std::fs::canonicalize(path).ok()
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|metadata| metadata.len())
+}
+
+fn list_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read dir: {}", dir.display()));
+        }
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("failed to read dir: {}", dir.display()))?
+            .path();
+        if path.is_dir() {
+            files.extend(list_files(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn remove_empty_dirs(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+        if path.is_dir() {
+            remove_empty_dirs(&path);
+            // fails if the directory is not empty
+            let _ = std::fs::remove_dir(&path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{Channel, TypingSet};
+    use crate::storage::{ForgetfulStorage, MemCache};
+
+    use super::*;
+
+    fn attachment(id: &str, filename: PathBuf) -> Attachment {
+        Attachment {
+            id: id.to_owned(),
+            content_type: "image/jpeg".to_owned(),
+            filename,
+            size: 1,
+        }
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let dir = tempfile::tempdir().unwrap();
+        let files_dir = dir.path().join("files");
+        let day1 = files_dir.join("2024-01-01");
+        let day2 = files_dir.join("2024-01-02");
+        std::fs::create_dir_all(&day1).unwrap();
+        std::fs::create_dir_all(&day2).unwrap();
+        let photo = day1.join("photo.jpeg");
+        let copy = day2.join("photo.jpeg");
+        let orphan = day2.join("orphan.jpeg");
+        std::fs::write(&photo, [42]).unwrap();
+        std::fs::write(&copy, [42]).unwrap();
+        std::fs::write(&orphan, [1, 2, 3]).unwrap();
+
+        let mut storage = MemCache::new(ForgetfulStorage);
+        let user_id = uuid::Uuid::new_v4();
+        let channel_id = ChannelId::User(user_id);
+        storage.store_channel(Channel {
+            id: channel_id,
+            name: "Tyler".to_owned(),
+            group_data: None,
+            unread_messages: 0,
+            muted: false,
+            typing: TypingSet::SingleTyping(false),
+            expire_timer: None,
+            draft: None,
+        });
+        let first = Message {
+            attachments: vec![attachment("digest", photo.clone())],
+            ..Message::text(user_id, 1, String::new())
+        };
+        let second = Message {
+            attachments: vec![attachment("digest", copy.clone())],
+            ..Message::text(user_id, 2, String::new())
+        };
+        storage.store_message(channel_id, first);
+        storage.store_message(channel_id, second);
+
+        let options = GcOptions {
+            data_dir: dir.path(),
+            dry_run: true,
+        };
+        // the orphan is too young
+        let report = collect_garbage(&mut storage, &options).unwrap();
+        assert_eq!(report.duplicates, [copy.clone()]);
+        assert!(report.unreferenced.is_empty());
+
+        let report = collect(&mut storage, &options, Duration::ZERO).unwrap();
+        assert_eq!(
+            report,
+            GcReport {
+                unreferenced: vec![orphan.clone()],
+                duplicates: vec![copy.clone()],
+                updated_messages: 1,
+                reclaimable_bytes: 4,
+            }
+        );
+        assert!(copy.exists() && orphan.exists());
+
+        let options = GcOptions {
+            dry_run: false,
+            ..options
+        };
+        collect(&mut storage, &options, Duration::ZERO).unwrap();
+        assert!(photo.exists());
+        assert!(!copy.exists() && !orphan.exists() && !day2.exists());
+        let second = storage
+            .message(MessageId::new(channel_id, user_id, 2))
+            .unwrap();
+        assert_eq!(second.attachments[0].filename, photo);
+
+        let report = collect(&mut storage, &options, Duration::ZERO).unwrap();
+        assert_eq!(report, GcReport::default());
+    }
+}
@@ -0,0 +1,120 @@
+//! Popup listing the attachments of a message
+
+use ratatui::widgets::ListState;
+
+use crate::input::Input;
+use crate::signal::Attachment;
+
+/// Popup for opening, saving and copying the attachments of the selected message
+#[derive(Default)]
+pub(crate) struct AttachmentPicker {
+    pub is_shown: bool,
+    pub state: ListState,
+    /// Whether the prompt for the path to save the selected attachment to is shown
+    pub is_saving: bool,
+    pub input: Input,
+    attachments: Vec<Attachment>,
+    /// Outcome of the last action; shown at the bottom of the popup
+    status: Option<Result<String, String>>,
+}
+
+impl AttachmentPicker {
+    pub fn show(&mut self, attachments: Vec<Attachment>) {
+        *self = Self {
+            is_shown: true,
+            ..Default::default()
+        };
+        self.state.select(attachments.first().map(|_| 0));
+        self.attachments = attachments;
+    }
+
+    pub fn hide(&mut self) {
+        *self = Default::default();
+    }
+
+    pub fn attachments(&self) -> &[Attachment] {
+        &self.attachments
+    }
+
+    pub fn selected(&self) -> Option<&Attachment> {
+        self.attachments.get(self.state.selected()?)
+    }
+
+    pub fn status(&self) -> Option<Result<&str, &str>> {
+        self.status
+            .as_ref()
+            .map(|status| status.as_deref().map_err(String::as_str))
+    }
+
+    pub fn set_status(&mut self, status: Result<String, String>) {
+        self.status = Some(status);
+    }
+
+    /// Shows the prompt for the path to save the selected attachment to, prefilled with `path`
+    pub fn start_saving(&mut self, path: String) {
+        self.is_saving = true;
+        self.input.data = path;
+        self.input.on_end();
+    }
+
+    pub fn stop_saving(&mut self) {
+        self.is_saving = false;
+        self.input = Default::default();
+    }
+
+    pub fn prev(&mut self) {
+        let selected = self
+            .state
+            .selected()
+            .map(|idx| idx.saturating_sub(1))
+            .unwrap_or(0);
+        self.state.select(Some(selected));
+    }
+
+    pub fn next(&mut self) {
+        let last = self.attachments.len().saturating_sub(1);
+        let selected = self
+            .state
+            .selected()
+            .map(|idx| (idx + 1).min(last))
+            .unwrap_or(0);
+        self.state.select(Some(selected));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(name: &str) -> Attachment {
+        Attachment {
+            id: name.to_owned(),
+            content_type: "image/jpeg".to_owned(),
+            filename: format!("/tmp/{name}.jpeg").into(),
+            size: 42,
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let mut picker = AttachmentPicker::default();
+        picker.show(vec![attachment("a"), attachment("b")]);
+        assert_eq!(picker.selected().unwrap().id, "a");
+
+        picker.next();
+        picker.next();
+        assert_eq!(picker.selected().unwrap().id, "b");
+        picker.prev();
+        picker.prev();
+        assert_eq!(picker.selected().unwrap().id, "a");
+
+        picker.start_saving("/tmp/a.jpeg".to_owned());
+        picker.set_status(Err("failed".to_owned()));
+        assert_eq!(picker.status(), Some(Err("failed")));
+
+        picker.hide();
+        assert!(!picker.is_shown);
+        assert!(!picker.is_saving);
+        assert!(picker.selected().is_none());
+    }
+}
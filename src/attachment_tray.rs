@@ -0,0 +1,331 @@
+//! Files attached to the next sent message
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, bail};
+use presage::libsignal_service::sender::AttachmentSpec;
+use ratatui::widgets::ListState;
+
+use crate::input::Input;
+use crate::util::expand_home;
+
+/// Maximum size of an attachment accepted by the Signal servers
+pub(crate) const MAX_ATTACHMENT_SIZE: u64 = 100 * 1024 * 1024;
+
+/// Validated file which is going to be sent with the next message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TrayAttachment {
+    pub path: PathBuf,
+    pub content_type: String,
+    pub size: u64,
+}
+
+impl TrayAttachment {
+    /// Fails if the file is missing, unreadable or too big to be sent
+    pub fn new(path: PathBuf) -> anyhow::Result<Self> {
+        let size = validate(&path)?;
+        Ok(Self {
+            content_type: content_type(&path),
+            path,
+            size,
+        })
+    }
+
+    pub fn file_name(&self) -> Cow<'_, str> {
+        self.path.file_name().unwrap_or_default().to_string_lossy()
+    }
+}
+
+/// Pending attachments shown above the input box
+#[derive(Default)]
+pub(crate) struct AttachmentTray {
+    attachments: Vec<TrayAttachment>,
+    selected: Option<usize>,
+    /// Why the last message could not be sent
+    error: Option<String>,
+}
+
+impl AttachmentTray {
+    pub fn is_empty(&self) -> bool {
+        self.attachments.is_empty()
+    }
+
+    pub fn attachments(&self) -> &[TrayAttachment] {
+        &self.attachments
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    /// Adds the attachment and selects it
+    pub fn add(&mut self, attachment: TrayAttachment) {
+        self.attachments.push(attachment);
+        self.selected = Some(self.attachments.len() - 1);
+        self.error = None;
+    }
+
+    pub fn remove_selected(&mut self) -> Option<TrayAttachment> {
+        let idx = self.selected?;
+        let attachment = self.attachments.remove(idx);
+        // keep the selection at the same position unless the last entry was removed
+        self.selected = if idx < self.attachments.len() {
+            Some(idx)
+        } else {
+            self.attachments.len().checked_sub(1)
+        };
+        self.error = None;
+        Some(attachment)
+    }
+
+    pub fn prev(&mut self) {
+        if let Some(idx) = self.selected {
+            self.selected = Some(idx.saturating_sub(1));
+        }
+    }
+
+    pub fn next(&mut self) {
+        if let Some(idx) = self.selected {
+            self.selected = Some((idx + 1).min(self.attachments.len() - 1));
+        }
+    }
+
+    /// Reads all attachments; fails if any of them cannot be sent anymore
+    pub fn load(&self) -> anyhow::Result<Vec<(AttachmentSpec, Vec<u8>)>> {
+        self.attachments
+            .iter()
+            .map(|attachment| load_attachment(&attachment.path))
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Default::default();
+    }
+}
+
+/// Popup for adding a file to the attachment tray, completing the typed path
+#[derive(Default)]
+pub(crate) struct AddAttachment {
+    pub is_shown: bool,
+    pub input: Input,
+    pub state: ListState,
+    completions: Vec<String>,
+    /// Input for which the completions were computed
+    completed_for: Option<String>,
+    error: Option<String>,
+}
+
+impl AddAttachment {
+    pub fn show(&mut self) {
+        *self = Self {
+            is_shown: true,
+            ..Default::default()
+        };
+        self.update_completions();
+    }
+
+    pub fn hide(&mut self) {
+        *self = Default::default();
+    }
+
+    pub fn completions(&self) -> &[String] {
+        &self.completions
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    /// Recomputes the completions if the input has changed since the last time
+    pub fn update_completions(&mut self) {
+        if self.completed_for.as_ref() == Some(&self.input.data) {
+            return;
+        }
+        self.completions = complete_path(&self.input.data);
+        self.state
+            .select((!self.completions.is_empty()).then_some(0));
+        self.completed_for = Some(self.input.data.clone());
+        self.error = None;
+    }
+
+    /// Replaces the input by the selected completion
+    pub fn complete(&mut self) -> bool {
+        let Some(completion) = self
+            .state
+            .selected()
+            .and_then(|idx| self.completions.get(idx))
+        else {
+            return false;
+        };
+        self.input.data = completion.clone();
+        self.input.on_end();
+        self.update_completions();
+        true
+    }
+
+    pub fn prev(&mut self) {
+        let selected = self
+            .state
+            .selected()
+            .map(|idx| idx.saturating_sub(1))
+            .unwrap_or(0);
+        self.state.select(Some(selected));
+    }
+
+    pub fn next(&mut self) {
+        let last = self.completions.len().saturating_sub(1);
+        let selected = self
+            .state
+            .selected()
+            .map(|idx| (idx + 1).min(last))
+            .unwrap_or(0);
+        self.state.select(Some(selected));
+    }
+}
+
+/// Reads the file at `path` to be sent as attachment
+pub(crate) fn load_attachment(path: &Path) -> anyhow::Result<(AttachmentSpec, Vec<u8>)> {
+    validate(path)?;
+    let bytes = std::fs::read(path).with_context(|| format!("cannot attach {}", path.display()))?;
+    let spec = AttachmentSpec {
+        content_type: content_type(path),
+        length: bytes.len(),
+        file_name: path.file_name().map(|f| f.to_string_lossy().into()),
+        ..Default::default()
+    };
+    Ok((spec, bytes))
+}
+
+/// Returns the size of the file if it can be sent as attachment
+fn validate(path: &Path) -> anyhow::Result<u64> {
+    let context = || format!("cannot attach {}", path.display());
+    let metadata = std::fs::metadata(path).with_context(context)?;
+    if !metadata.is_file() {
+        bail!("{}: not a file", context());
+    }
+    if metadata.len() > MAX_ATTACHMENT_SIZE {
+        bail!(
+            "{}: larger than {} MiB",
+            context(),
+            MAX_ATTACHMENT_SIZE / 1024 / 1024
+        );
+    }
+    File::open(path).with_context(context)?;
+    Ok(metadata.len())
+}
+
+fn content_type(path: &Path) -> String {
+    mime_guess::from_path(path)
+        .first()
+        .map(|mime| mime.essence_str().to_string())
+        .unwrap_or_default()
+}
+
+/// Paths of the entries in the directory of the partially typed `input` which start with its file
+/// name; directories end with a slash
+///
+/// Hidden entries are only completed if the file name starts with a dot.
+pub(crate) fn complete_path(input: &str) -> Vec<String> {
+    let (dir, prefix) = input.split_at(input.rfind('/').map_or(0, |idx| idx + 1));
+    let dir_path = if dir.is_empty() {
+        PathBuf::from(".")
+    } else {
+        expand_home(dir)
+    };
+    let Ok(entries) = std::fs::read_dir(dir_path) else {
+        return Vec::new();
+    };
+    let mut completions: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let suffix = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{dir}{name}{suffix}"))
+        })
+        .collect();
+    completions.sort_unstable();
+    completions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete_path() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("photos")).unwrap();
+        std::fs::write(dir.path().join("photo.jpeg"), [42]).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), [42]).unwrap();
+        std::fs::write(dir.path().join(".hidden"), [42]).unwrap();
+
+        let dir = format!("{}/", dir.path().display());
+        assert_eq!(
+            complete_path(&format!("{dir}pho")),
+            [format!("{dir}photo.jpeg"), format!("{dir}photos/")]
+        );
+        assert_eq!(complete_path(&dir).len(), 3);
+        assert_eq!(complete_path(&format!("{dir}.")), [format!("{dir}.hidden")]);
+        assert!(complete_path(&format!("{dir}missing/")).is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.jpeg");
+        std::fs::write(&path, [42]).unwrap();
+
+        let attachment = TrayAttachment::new(path.clone()).unwrap();
+        assert_eq!(attachment.content_type, "image/jpeg");
+        assert_eq!(attachment.size, 1);
+        assert_eq!(attachment.file_name(), "photo.jpeg");
+
+        let error = TrayAttachment::new(dir.path().join("missing.jpeg")).unwrap_err();
+        assert!(format!("{error:#}").contains("missing.jpeg"));
+        assert!(TrayAttachment::new(dir.path().to_owned()).is_err());
+
+        let big = File::create(dir.path().join("big.bin")).unwrap();
+        big.set_len(MAX_ATTACHMENT_SIZE + 1).unwrap();
+        assert!(TrayAttachment::new(dir.path().join("big.bin")).is_err());
+    }
+
+    #[test]
+    fn test_tray_selection() {
+        let attachment = |name: &str| TrayAttachment {
+            path: name.into(),
+            content_type: String::new(),
+            size: 0,
+        };
+        let mut tray = AttachmentTray::default();
+        tray.add(attachment("a"));
+        tray.add(attachment("b"));
+        tray.add(attachment("c"));
+        assert_eq!(tray.selected(), Some(2));
+
+        tray.prev();
+        assert_eq!(tray.remove_selected(), Some(attachment("b")));
+        assert_eq!(tray.selected(), Some(1));
+        assert_eq!(tray.remove_selected(), Some(attachment("c")));
+        assert_eq!(tray.selected(), Some(0));
+        assert_eq!(tray.remove_selected(), Some(attachment("a")));
+        assert_eq!(tray.selected(), None);
+        assert_eq!(tray.remove_selected(), None);
+    }
+}
@@ -4,7 +4,7 @@ use ratatui::widgets::ListState;
 
 use crate::data::ChannelId;
 use crate::input::Input;
-use crate::storage::Storage;
+use crate::storage::{MessagePosition, Storage};
 
 #[derive(Default)]
 pub(crate) struct SelectChannel {
@@ -34,8 +34,8 @@ impl SelectChannel {
 
         self.items.sort_unstable_by_key(|item| {
             let last_message_arrived_at = storage
-                .messages(item.channel_id)
-                .last()
+                .messages_before(item.channel_id, MessagePosition::LATEST, 1)
+                .next()
                 .map(|message| message.arrived_at);
             (Reverse(last_message_arrived_at), item.name.clone())
         });
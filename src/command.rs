@@ -116,6 +116,26 @@ pub enum MessageSelector {
     Marked,
 }
 
+#[derive(
+    Clone,
+    Default,
+    Debug,
+    Eq,
+    PartialEq,
+    strum_macros::Display,
+    strum_macros::VariantNames,
+    EnumString,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum AttachmentAction {
+    #[default]
+    Open,
+    Save,
+    CopyPath,
+    CopyImage,
+    Reveal,
+}
+
 #[derive(
     Clone,
     Debug,
@@ -134,6 +154,13 @@ pub enum WindowMode {
     Anywhere,
     Help,
     ChannelModal,
+    Search,
+    Find,
+    Attachments,
+    AddAttachment,
+    /// Editing of the input line of a popup, used if the mode of the popup has no binding
+    TextInput,
+    MentionCompletion,
     Multiline,
     MessageSelected,
     Normal,
@@ -189,6 +216,33 @@ pub enum Command {
         to_string = "select_channel_modal {0}"
     )]
     SelectChannelModal(MoveDirection),
+    #[strum(props(desc = "Open pop-up for searching messages in all channels"))]
+    ToggleSearch,
+    #[strum(props(
+        desc = "Select next/previous result in search pop-up",
+        usage = "select_search_result previous|next"
+    ))]
+    #[strum(
+        serialize = "select_search_result",
+        to_string = "select_search_result {0}"
+    )]
+    SelectSearchResult(MoveDirection),
+    #[strum(props(desc = "Open/close prompt for finding text in the messages of the channel"))]
+    Find,
+    #[strum(props(
+        desc = "Select previous (older)/next (newer) message matching the find prompt",
+        usage = "find_match previous|next"
+    ))]
+    #[strum(serialize = "find_match", to_string = "find_match {0}")]
+    FindMatch(MoveDirection),
+    #[strum(props(
+        desc = "Select next/previous member in mention completion",
+        usage = "select_mention previous|next"
+    ))]
+    #[strum(serialize = "select_mention", to_string = "select_mention {0}")]
+    SelectMention(MoveDirection),
+    #[strum(props(desc = "Insert the selected member as mention"))]
+    CompleteMention,
     #[strum(props(
         desc = "Select next/previous message",
         usage = "select_message previous|next entry"
@@ -225,14 +279,60 @@ pub enum Command {
     OpenUrl,
     #[strum(props(desc = "Try to open the first file attachment of the selected message"))]
     OpenFile,
+    #[strum(props(desc = "Download the pending attachments of the selected message"))]
+    DownloadAttachment,
+    #[strum(props(desc = "Open/close pop-up listing the attachments of the selected message"))]
+    ToggleAttachments,
+    #[strum(props(
+        desc = "Select next/previous attachment in attachments pop-up",
+        usage = "select_attachment previous|next"
+    ))]
+    #[strum(serialize = "select_attachment", to_string = "select_attachment {0}")]
+    SelectAttachment(MoveDirection),
+    #[strum(props(
+        desc = "Open, save or copy the selected attachment, or reveal its directory",
+        usage = "attachment_action open|save|copy_path|copy_image|reveal"
+    ))]
+    #[strum(serialize = "attachment_action", to_string = "attachment_action {0}")]
+    AttachmentAction(AttachmentAction),
+    #[strum(props(desc = "Open/close pop-up for attaching a file to the next message"))]
+    AddAttachment,
+    #[strum(props(desc = "Remove the selected pending attachment"))]
+    RemoveAttachment,
+    #[strum(props(
+        desc = "Select previous/next pending attachment",
+        usage = "select_pending_attachment previous|next"
+    ))]
+    #[strum(
+        serialize = "select_pending_attachment",
+        to_string = "select_pending_attachment {0}"
+    )]
+    SelectPendingAttachment(MoveDirection),
+    #[strum(props(desc = "Complete the path in the add attachment pop-up"))]
+    CompleteAttachmentPath,
+    #[strum(props(
+        desc = "Select previous/next path completion",
+        usage = "select_path_completion previous|next"
+    ))]
+    #[strum(
+        serialize = "select_path_completion",
+        to_string = "select_path_completion {0}"
+    )]
+    SelectPathCompletion(MoveDirection),
     #[strum(props(desc = "Toggle mute for the selected channel"))]
     ToggleMuteChannel,
     #[strum(props(desc = "Toggle channel list pane visibility"))]
     ToggleChannelList,
-    // ReplyMessage,
+    #[strum(props(desc = "Reply to selected message"))]
+    ReplyMessage,
+    #[strum(props(desc = "Show/hide spoilers of the selected message"))]
+    ToggleSpoiler,
     #[strum(props(desc = "Open external editor to compose a message"))]
     OpenEditor,
-    // DeleteMessage,
+    #[strum(props(desc = "Delete selected message for everyone"))]
+    DeleteMessage,
+    #[strum(props(desc = "Retry sending the selected message which failed to send"))]
+    RetryMessage,
 }
 
 #[derive(Clone, Debug)]
@@ -381,6 +481,90 @@ fn parse(input: &str) -> Result<Command, CommandParseError> {
             Ok(Command::SelectChannelModal(direction))
             // Ok(Command::SelectChannelModal(MoveDirection::from_str(args.first().unwrap_or(&""))?))
         }
+        Command::SelectSearchResult(_) => {
+            let direction = args.first().ok_or_else(|| E::InsufficientArgs {
+                cmd: cmd_str.to_string(),
+                hint: Some(MoveDirection::VARIANTS.join("|")),
+            })?;
+            let direction = MoveDirection::from_str(direction).map_err(|_e| E::BadEnumArg {
+                arg: direction.to_string(),
+                accept: MoveDirection::VARIANTS,
+                optional: false,
+            })?;
+            Ok(Command::SelectSearchResult(direction))
+        }
+        Command::FindMatch(_) => {
+            let direction = args.first().ok_or_else(|| E::InsufficientArgs {
+                cmd: cmd_str.to_string(),
+                hint: Some(MoveDirection::VARIANTS.join("|")),
+            })?;
+            let direction = MoveDirection::from_str(direction).map_err(|_e| E::BadEnumArg {
+                arg: direction.to_string(),
+                accept: MoveDirection::VARIANTS,
+                optional: false,
+            })?;
+            Ok(Command::FindMatch(direction))
+        }
+        Command::SelectAttachment(_) => {
+            let direction = args.first().ok_or_else(|| E::InsufficientArgs {
+                cmd: cmd_str.to_string(),
+                hint: Some(MoveDirection::VARIANTS.join("|")),
+            })?;
+            let direction = MoveDirection::from_str(direction).map_err(|_e| E::BadEnumArg {
+                arg: direction.to_string(),
+                accept: MoveDirection::VARIANTS,
+                optional: false,
+            })?;
+            Ok(Command::SelectAttachment(direction))
+        }
+        Command::AttachmentAction(_) => {
+            let action = args.first().ok_or_else(|| E::InsufficientArgs {
+                cmd: cmd_str.to_string(),
+                hint: Some(AttachmentAction::VARIANTS.join("|")),
+            })?;
+            let action = AttachmentAction::from_str(action).map_err(|_e| E::BadEnumArg {
+                arg: action.to_string(),
+                accept: AttachmentAction::VARIANTS,
+                optional: false,
+            })?;
+            Ok(Command::AttachmentAction(action))
+        }
+        Command::SelectPendingAttachment(_) => {
+            let direction = args.first().ok_or_else(|| E::InsufficientArgs {
+                cmd: cmd_str.to_string(),
+                hint: Some(MoveDirection::VARIANTS.join("|")),
+            })?;
+            let direction = MoveDirection::from_str(direction).map_err(|_e| E::BadEnumArg {
+                arg: direction.to_string(),
+                accept: MoveDirection::VARIANTS,
+                optional: false,
+            })?;
+            Ok(Command::SelectPendingAttachment(direction))
+        }
+        Command::SelectPathCompletion(_) => {
+            let direction = args.first().ok_or_else(|| E::InsufficientArgs {
+                cmd: cmd_str.to_string(),
+                hint: Some(MoveDirection::VARIANTS.join("|")),
+            })?;
+            let direction = MoveDirection::from_str(direction).map_err(|_e| E::BadEnumArg {
+                arg: direction.to_string(),
+                accept: MoveDirection::VARIANTS,
+                optional: false,
+            })?;
+            Ok(Command::SelectPathCompletion(direction))
+        }
+        Command::SelectMention(_) => {
+            let direction = args.first().ok_or_else(|| E::InsufficientArgs {
+                cmd: cmd_str.to_string(),
+                hint: Some(MoveDirection::VARIANTS.join("|")),
+            })?;
+            let direction = MoveDirection::from_str(direction).map_err(|_e| E::BadEnumArg {
+                arg: direction.to_string(),
+                accept: MoveDirection::VARIANTS,
+                optional: false,
+            })?;
+            Ok(Command::SelectMention(direction))
+        }
         Command::SelectMessage(_, _) => {
             let direction = args.first().ok_or_else(|| {
                 E::InsufficientArgs {
@@ -462,6 +646,7 @@ ctrl-c = "quit"
 
 [normal]
 ctrl-p = "toggle_channel_modal"
+ctrl-g = "toggle_search"
 alt-enter = "toggle_multiline"
 ctrl-left = "move_text previous character"
 ctrl-right = "move_text next character"
@@ -496,12 +681,25 @@ tab = "react"
 alt-m = "toggle_mute_channel"
 alt-l = "toggle_channel_list"
 ctrl-o = "open_editor"
+alt-p = "find_match previous"
+alt-n = "find_match next"
+alt-o = "add_attachment"
+alt-x = "remove_attachment"
+"alt-," = "select_pending_attachment previous"
+"alt-." = "select_pending_attachment next"
 
 [message_selected]
 alt-y = "copy_message selected"
 ctrl-e = "edit_message"
+ctrl-d = "delete_message"
+alt-r = "retry_message"
+alt-d = "download_attachment"
+alt-a = "toggle_attachments"
+ctrl-r = "reply_message"
+alt-s = "toggle_spoiler"
 ctrl-t = "react :thumbsup:"
 ctrl-h = "react ❤️"
+"/" = "find"
 
 [channel_modal]
 esc = "toggle_channel_modal"
@@ -530,6 +728,72 @@ ctrl-e = "end_of_line"
 backspace = "delete_character previous"
 delete = "delete_character next"
 
+[search]
+esc = "toggle_search"
+ctrl-g = "toggle_search"
+down = "select_search_result next"
+up = "select_search_result previous"
+ctrl-j = "select_search_result next"
+ctrl-k = "select_search_result previous"
+
+[find]
+esc = "find"
+up = "find_match previous"
+down = "find_match next"
+ctrl-k = "find_match previous"
+ctrl-j = "find_match next"
+
+[attachments]
+esc = "toggle_attachments"
+alt-a = "toggle_attachments"
+down = "select_attachment next"
+up = "select_attachment previous"
+ctrl-j = "select_attachment next"
+ctrl-k = "select_attachment previous"
+ctrl-s = "attachment_action save"
+alt-y = "attachment_action copy_path"
+ctrl-y = "attachment_action copy_image"
+ctrl-r = "attachment_action reveal"
+
+[add_attachment]
+esc = "add_attachment"
+alt-o = "add_attachment"
+tab = "complete_attachment_path"
+down = "select_path_completion next"
+up = "select_path_completion previous"
+ctrl-j = "select_path_completion next"
+ctrl-k = "select_path_completion previous"
+
+# fallback of the popups with an input line
+[text_input]
+ctrl-left = "move_text previous character"
+ctrl-right = "move_text next character"
+left = "move_text previous character"
+right = "move_text next character"
+alt-left = "move_text previous word"
+alt-right = "move_text next word"
+alt-f = "move_text next word"
+ctrl-f = "move_text next character"
+alt-b = "move_text previous word"
+ctrl-b = "move_text previous character"
+ctrl-u = "kill_backward_line"
+ctrl-w = "kill_word"
+alt-backspace = "kill_word"
+home = "beginning_of_line"
+ctrl-a = "beginning_of_line"
+end = "end_of_line"
+ctrl-e = "end_of_line"
+backspace = "delete_character previous"
+delete = "delete_character next"
+
+[mention_completion]
+down = "select_mention next"
+up = "select_mention previous"
+ctrl-j = "select_mention next"
+ctrl-k = "select_mention previous"
+tab = "complete_mention"
+enter = "complete_mention"
+
 [multiline]
 down = "move_text next line"
 up = "move_text previous line"
@@ -9,6 +9,7 @@ use url::Url;
 use std::path::{Path, PathBuf};
 use std::{fmt, fs};
 
+use crate::preview::GraphicsProtocol;
 use crate::{command::ModeKeybindingConfig, passphrase::Passphrase};
 
 const GURK_DB_NAME: &str = "gurk.sqlite";
@@ -35,6 +36,12 @@ pub struct Config {
     /// Whether to show receipts (sent, delivered, read) information next to your user name in UI
     #[serde(default = "default_true")]
     pub show_receipts: bool,
+    /// Whether to send read receipts for messages shown in the selected channel
+    #[serde(default = "default_true")]
+    pub send_read_receipts: bool,
+    /// Whether to notify others while typing a message
+    #[serde(default = "default_true")]
+    pub send_typing_indicators: bool,
     /// Notification settings
     #[serde(default, deserialize_with = "deserialize_notification_config")]
     pub notifications: NotificationConfig,
@@ -53,6 +60,21 @@ pub struct Config {
     /// If set, the full message text will be colored, not only the author name
     #[serde(default)]
     pub colored_messages: bool,
+    /// Inline previews of image attachments
+    #[serde(default)]
+    pub image_previews: ImagePreviewConfig,
+    /// Which incoming attachments are downloaded automatically
+    #[serde(default)]
+    pub downloads: DownloadConfig,
+    /// Processing of outgoing image attachments
+    #[serde(default)]
+    pub uploads: UploadConfig,
+    /// Interval in hours of the garbage collection of attachment files while running; disabled
+    /// if not set
+    ///
+    /// The collection is the same as done by `gurk gc-attachments`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gc_attachments_interval: Option<u32>,
     #[serde(default)]
     /// Keymaps
     pub keybindings: ModeKeybindingConfig,
@@ -99,6 +121,117 @@ impl Default for NotificationConfig {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImagePreviewConfig {
+    /// Whether to show image attachments inline below the message
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Maximum height of a preview in rows
+    #[serde(default = "ImagePreviewConfig::default_max_height")]
+    pub max_height: u16,
+    /// Protocol used to draw the images: `kitty`, `sixel` or `halfblocks`
+    ///
+    /// If not set, the protocol is detected from the terminal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<GraphicsProtocol>,
+}
+
+impl ImagePreviewConfig {
+    fn default_max_height() -> u16 {
+        10
+    }
+}
+
+impl Default for ImagePreviewConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_height: Self::default_max_height(),
+            protocol: None,
+        }
+    }
+}
+
+/// Policy for downloading incoming attachments
+///
+/// Attachments which are not downloaded automatically can be downloaded with the
+/// `download_attachment` command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadConfig {
+    /// Maximum size in bytes of automatically downloaded attachments; unlimited if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u32>,
+    /// MIME types of automatically downloaded attachments, e.g. `image/*`; all if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub content_types: Vec<String>,
+    /// Whether to download attachments in muted channels automatically
+    #[serde(default = "default_true")]
+    pub muted_channels: bool,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            max_size: None,
+            content_types: Vec::new(),
+            muted_channels: true,
+        }
+    }
+}
+
+impl DownloadConfig {
+    /// Whether an attachment is downloaded when it is received
+    pub fn should_download(&self, content_type: &str, size: u32, muted: bool) -> bool {
+        (!muted || self.muted_channels)
+            && self.max_size.is_none_or(|max_size| size <= max_size)
+            && (self.content_types.is_empty()
+                || self
+                    .content_types
+                    .iter()
+                    .any(|pattern| matches_content_type(pattern, content_type)))
+    }
+}
+
+/// Matches a MIME type against a pattern like `image/png` or `image/*`
+fn matches_content_type(pattern: &str, content_type: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(top_level) => content_type
+            .split_once('/')
+            .is_some_and(|(ty, _)| ty.eq_ignore_ascii_case(top_level)),
+        None => pattern.eq_ignore_ascii_case(content_type),
+    }
+}
+
+/// Processing of outgoing JPEG and PNG attachments before they are sent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadConfig {
+    /// Whether to remove metadata like EXIF and XMP, which may contain GPS coordinates
+    #[serde(default = "default_true")]
+    pub strip_metadata: bool,
+    /// Images with a larger width or height in pixels are downscaled to fit into it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_image_dimension: Option<u32>,
+    /// Quality (1-100) of re-encoded JPEG images
+    #[serde(default = "UploadConfig::default_jpeg_quality")]
+    pub jpeg_quality: u8,
+}
+
+impl UploadConfig {
+    fn default_jpeg_quality() -> u8 {
+        90
+    }
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            strip_metadata: true,
+            max_image_dimension: None,
+            jpeg_quality: Self::default_jpeg_quality(),
+        }
+    }
+}
+
 #[cfg(feature = "dev")]
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DeveloperConfig {
@@ -182,6 +315,8 @@ impl Config {
             deprecated_signal_db_path: default_signal_db_path(),
             first_name_only: false,
             show_receipts: true,
+            send_read_receipts: true,
+            send_typing_indicators: true,
             notifications: NotificationConfig::default(),
             bell: true,
             #[cfg(feature = "dev")]
@@ -189,6 +324,10 @@ impl Config {
             sqlite: Default::default(),
             passphrase: None,
             colored_messages: false,
+            image_previews: Default::default(),
+            downloads: Default::default(),
+            uploads: Default::default(),
+            gc_attachments_interval: None,
             default_keybindings: true,
             keybindings: ModeKeybindingConfig::default(),
         }
@@ -458,6 +597,67 @@ show_message_text = true
         assert!(config.notifications.show_message_text);
     }
 
+    #[test]
+    fn test_image_previews() {
+        let toml = r#"
+[user]
+display_name = "Test"
+[image_previews]
+max_height = 5
+protocol = "halfblocks"
+"#;
+        let config: Config = toml::de::from_str(toml).unwrap();
+        assert!(config.image_previews.enabled);
+        assert_eq!(config.image_previews.max_height, 5);
+        assert_eq!(
+            config.image_previews.protocol,
+            Some(GraphicsProtocol::HalfBlocks)
+        );
+    }
+
+    #[test]
+    fn test_downloads() {
+        let toml = r#"
+[user]
+display_name = "Test"
+[downloads]
+max_size = 1000000
+content_types = ["image/*", "application/pdf"]
+muted_channels = false
+"#;
+        let config: Config = toml::de::from_str(toml).unwrap();
+        let downloads = &config.downloads;
+        assert!(downloads.should_download("image/jpeg", 1000, false));
+        assert!(downloads.should_download("application/pdf", 1000000, false));
+        assert!(!downloads.should_download("image/jpeg", 1000001, false));
+        assert!(!downloads.should_download("video/mp4", 1000, false));
+        assert!(!downloads.should_download("image/jpeg", 1000, true));
+
+        // everything is downloaded by default
+        let downloads = DownloadConfig::default();
+        assert!(downloads.should_download("video/mp4", u32::MAX, true));
+    }
+
+    #[test]
+    fn test_uploads() {
+        let toml = r#"
+[user]
+display_name = "Test"
+[uploads]
+max_image_dimension = 2048
+jpeg_quality = 80
+"#;
+        let config: Config = toml::de::from_str(toml).unwrap();
+        assert_eq!(
+            config.uploads,
+            UploadConfig {
+                strip_metadata: true,
+                max_image_dimension: Some(2048),
+                jpeg_quality: 80,
+            }
+        );
+    }
+
     #[test]
     fn test_save_new_fails_or_existent() -> anyhow::Result<()> {
         let dir = tempdir()?;
@@ -11,7 +11,7 @@ use tracing::error;
 use uuid::Uuid;
 
 use crate::receipt::Receipt;
-use crate::signal::{Attachment, GroupIdentifierBytes, GroupMasterKeyBytes};
+use crate::signal::{Attachment, GroupIdentifierBytes, GroupMasterKeyBytes, PendingAttachment};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Channel {
@@ -21,6 +21,10 @@ pub struct Channel {
     pub unread_messages: u32,
     pub muted: bool,
     pub typing: TypingSet,
+    /// Timer in seconds after which messages disappear once read
+    pub expire_timer: Option<u32>,
+    /// Input which was not sent yet
+    pub draft: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -162,14 +166,18 @@ pub struct Message {
     pub quote: Option<Box<Message>>,
     #[serde(default)]
     pub attachments: Vec<Attachment>,
+    /// Attachments which are not downloaded yet; persisted in the pending attachments
+    #[serde(default)]
+    pub(crate) pending_attachments: Vec<PendingAttachment>,
     #[serde(default)]
     pub reactions: Vec<(Uuid, String)>,
     #[serde(default)]
     pub receipt: Receipt,
     #[serde(default)]
     pub(crate) body_ranges: Vec<BodyRange>,
-    #[serde(skip)]
-    pub(crate) send_failed: Option<String>,
+    /// Whether the message is not sent yet; persisted in the outbox
+    #[serde(default)]
+    pub(crate) send_state: Option<SendState>,
     /// Arrived at of the originally edited message
     ///
     /// When several edits are done, this is the arrived_at of the very first original message.
@@ -178,6 +186,30 @@ pub struct Message {
     /// Whether the message was edited
     #[serde(default)]
     pub(crate) edited: bool,
+    /// Whether the message was deleted for everyone
+    ///
+    /// The content of a deleted message is dropped, only the tombstone is kept.
+    #[serde(default)]
+    pub(crate) deleted: bool,
+    /// Timer in seconds of a disappearing message
+    #[serde(default)]
+    pub(crate) expire_timer: Option<u32>,
+    /// Time in milliseconds since epoch when the message expires
+    ///
+    /// Only set after the expiration timer was started, i.e. when the message was read.
+    #[serde(default)]
+    pub(crate) expires_at: Option<u64>,
+}
+
+/// State of an own message which was not sent successfully (yet)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum SendState {
+    /// Queued for sending, e.g. while offline
+    Pending,
+    /// Sending failed and is retried automatically
+    Retrying { attempts: u32, error: String },
+    /// Sending failed and is only retried on request
+    Failed(String),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -285,28 +317,36 @@ impl Message {
             arrived_at,
             quote: None,
             attachments,
+            pending_attachments: Default::default(),
             reactions: Default::default(),
             receipt: Receipt::Sent,
             body_ranges: body_ranges.into_iter().collect(),
-            send_failed: Default::default(),
+            send_state: Default::default(),
             edit: Default::default(),
             edited: Default::default(),
+            deleted: Default::default(),
+            expire_timer: Default::default(),
+            expires_at: Default::default(),
         }
     }
 
-    pub(crate) fn text(from_id: Uuid, arrived_at: u64, message: String) -> Self {
+    pub fn text(from_id: Uuid, arrived_at: u64, message: String) -> Self {
         Self {
             from_id,
             message: Some(message),
             arrived_at,
             quote: Default::default(),
             attachments: Default::default(),
+            pending_attachments: Default::default(),
             reactions: Default::default(),
             receipt: Default::default(),
             body_ranges: Default::default(),
-            send_failed: Default::default(),
+            send_state: Default::default(),
             edit: Default::default(),
             edited: Default::default(),
+            deleted: Default::default(),
+            expire_timer: Default::default(),
+            expires_at: Default::default(),
         }
     }
 
@@ -317,6 +357,7 @@ impl Message {
             arrived_at: quote.id?,
             quote: None,
             attachments: Default::default(),
+            pending_attachments: Default::default(),
             reactions: Default::default(),
             receipt: Receipt::Sent,
             body_ranges: quote
@@ -324,15 +365,45 @@ impl Message {
                 .into_iter()
                 .filter_map(BodyRange::from_proto)
                 .collect(),
-            send_failed: Default::default(),
+            send_state: Default::default(),
             edit: Default::default(),
             edited: Default::default(),
+            deleted: Default::default(),
+            expire_timer: Default::default(),
+            expires_at: Default::default(),
         })
     }
 
+    /// Drops the content of the message and turns it into a tombstone
+    pub(crate) fn delete(&mut self) {
+        self.message = None;
+        self.quote = None;
+        self.attachments.clear();
+        self.pending_attachments.clear();
+        self.reactions.clear();
+        self.body_ranges.clear();
+        self.edited = false;
+        self.deleted = true;
+    }
+
+    /// Starts the expiration timer of a disappearing message
+    ///
+    /// Returns `true` if the timer was started, and `false` if the message does not disappear or
+    /// the timer is already running.
+    pub(crate) fn start_expiry(&mut self, now: u64) -> bool {
+        match self.expire_timer {
+            Some(timer) if self.expires_at.is_none() => {
+                self.expires_at = Some(now + u64::from(timer) * 1000);
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.message.is_none()
             && self.attachments.is_empty()
+            && self.pending_attachments.is_empty()
             && self.reactions.is_empty()
             && self.quote.is_none()
     }
@@ -0,0 +1,76 @@
+//! Lock of the data directory against the use by several gurk processes at once
+
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{Context as _, bail};
+
+const LOCK_FILE: &str = "gurk.lock";
+
+/// Exclusive lock of the data directory, held until dropped
+///
+/// The lock is released by the operating system when the process exits, so a crashed gurk never
+/// leaves the data directory locked. Only unix systems support the lock; elsewhere it is a no-op.
+#[derive(Debug)]
+pub struct DataDirLock {
+    _file: File,
+}
+
+impl DataDirLock {
+    /// Locks the data directory, failing if another process holds the lock
+    pub fn acquire(data_dir: &Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(data_dir)
+            .with_context(|| format!("failed to create dir: {}", data_dir.display()))?;
+        let path = data_dir.join(LOCK_FILE);
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("failed to open lock file: {}", path.display()))?;
+        if !try_lock(&file).with_context(|| format!("failed to lock: {}", path.display()))? {
+            bail!(
+                "data directory is in use by another gurk process: {}",
+                data_dir.display()
+            );
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+/// Returns `false` if the file is already locked
+#[cfg(unix)]
+fn try_lock(file: &File) -> std::io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: the file descriptor is valid for the lifetime of `file`
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    if error.kind() == std::io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(error)
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> std::io::Result<bool> {
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_data_dir_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = DataDirLock::acquire(dir.path()).unwrap();
+        assert!(DataDirLock::acquire(dir.path()).is_err());
+        drop(lock);
+        DataDirLock::acquire(dir.path()).unwrap();
+    }
+}
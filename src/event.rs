@@ -1,3 +1,9 @@
+use image::RgbaImage;
+use presage::libsignal_service::sender::AttachmentSpec;
+
+use crate::attachment_gc::{Garbage, GcReport};
+use crate::data::ChannelId;
+use crate::signal::{Attachment, PendingAttachment};
 use crate::storage::MessageId;
 
 #[derive(Debug)]
@@ -6,4 +12,28 @@ pub enum Event {
         message_id: MessageId,
         result: anyhow::Result<()>,
     },
+    /// Scan the next messages for the pattern of the find prompt
+    FindStep,
+    /// Results of downloading the pending attachments of a message
+    DownloadedAttachments {
+        message_id: MessageId,
+        results: Vec<(PendingAttachment, anyhow::Result<Attachment>)>,
+    },
+    /// Attachments of an outgoing message prepared in the background
+    PreparedAttachments {
+        channel_id: ChannelId,
+        id: u64,
+        result: anyhow::Result<Vec<(AttachmentSpec, Vec<u8>)>>,
+    },
+    /// Garbage of attachment files found in the background
+    FoundAttachmentsGarbage {
+        result: anyhow::Result<Box<Garbage>>,
+    },
+    /// Garbage of attachment files removed in the background
+    CollectedAttachmentsGarbage { report: GcReport },
+    /// Thumbnail of an image attachment decoded in the background; `None` if it failed
+    ThumbnailDecoded {
+        id: String,
+        image: Option<RgbaImage>,
+    },
 }
@@ -0,0 +1,767 @@
+//! Export of the history of a channel to JSON, Markdown or HTML
+//!
+//! The JSON format is versioned by [`EXPORT_VERSION`]. Markdown and HTML are rendered from the
+//! same data and are meant for reading only.
+//!
+//! Attachment files are encrypted in the data directory. Therefore, they are exported as decrypted
+//! copies next to the export, to which the export links.
+
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, bail};
+use chrono::{DateTime, NaiveDate, Utc};
+use itertools::Itertools;
+use serde::Serialize;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::attachment_files::AttachmentFiles;
+use crate::data::{AssociatedValue, Channel, ChannelId, Message};
+use crate::signal::Attachment;
+use crate::storage::{MessageId, Storage};
+use crate::util::{local_start_of_day_timestamp_msec, utc_timestamp_msec_to_local};
+
+/// Version of the JSON export format
+///
+/// It is incremented on incompatible changes of the format. Adding new fields is a compatible
+/// change.
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Md,
+    Html,
+}
+
+/// Which messages to export
+#[derive(Debug)]
+pub struct ExportOptions<'a> {
+    /// Name or id of the channel
+    pub channel: &'a str,
+    /// First day (in local time) of the exported messages
+    pub since: Option<NaiveDate>,
+    /// Last day (in local time) of the exported messages
+    pub until: Option<NaiveDate>,
+    /// Directory to which the paths of attachments are made relative, if they are not copied
+    pub data_dir: &'a Path,
+    /// Own user id and name; our name is not stored with the names of the contacts
+    pub user: (Uuid, &'a str),
+    /// Where to write decrypted copies of the attachments; they are not copied if `None`
+    pub attachments: Option<AttachmentCopies<'a>>,
+}
+
+/// Decrypted copies of the exported attachments
+#[derive(Debug)]
+pub struct AttachmentCopies<'a> {
+    /// Directory to which the copies are written
+    pub dir: &'a Path,
+    /// Directory to which the paths of the copies are made relative, i.e. the one of the export
+    pub base_dir: &'a Path,
+    pub files: &'a AttachmentFiles,
+}
+
+/// Exported channel history
+#[derive(Debug, Serialize)]
+pub struct ChannelExport {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub channel: ExportedChannel,
+    pub messages: Vec<ExportedMessage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedChannel {
+    /// Uuid of the contact or hex-encoded id of the group
+    pub id: String,
+    pub name: String,
+    pub is_group: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportedAuthor {
+    pub id: Uuid,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedMessage {
+    /// Milliseconds since epoch; identifies the message in the channel
+    pub arrived_at: u64,
+    pub time: DateTime<Utc>,
+    pub author: ExportedAuthor,
+    /// Text with resolved mentions
+    pub text: Option<String>,
+    pub quote: Option<ExportedQuote>,
+    pub attachments: Vec<ExportedAttachment>,
+    pub reactions: Vec<ExportedReaction>,
+    /// All versions of the text from the original to the latest one; empty if not edited
+    pub edits: Vec<ExportedEdit>,
+    pub deleted: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedQuote {
+    pub arrived_at: u64,
+    pub author: ExportedAuthor,
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedAttachment {
+    pub content_type: String,
+    /// Path of the decrypted copy relative to the export, or of the file relative to the data
+    /// directory if it is not copied or stored in the data directory
+    pub path: String,
+    pub size: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedReaction {
+    pub author: ExportedAuthor,
+    pub emoji: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedEdit {
+    pub arrived_at: u64,
+    pub time: DateTime<Utc>,
+    pub text: Option<String>,
+}
+
+/// Exports the messages of the channel in the given format
+pub fn export(
+    storage: &dyn Storage,
+    options: &ExportOptions,
+    format: ExportFormat,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let export = ChannelExport::collect(storage, options)?;
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, &export)?;
+            writeln!(out)?;
+        }
+        ExportFormat::Md => out.write_all(export.to_markdown().as_bytes())?,
+        ExportFormat::Html => out.write_all(export.to_html().as_bytes())?,
+    }
+    Ok(())
+}
+
+impl ChannelExport {
+    pub fn collect(storage: &dyn Storage, options: &ExportOptions) -> anyhow::Result<Self> {
+        let channel = find_channel(storage, options.channel)?;
+        let since = options
+            .since
+            .and_then(local_start_of_day_timestamp_msec)
+            .unwrap_or(0);
+        let until = options
+            .until
+            .and_then(|date| date.succ_opt())
+            .and_then(local_start_of_day_timestamp_msec)
+            .unwrap_or(u64::MAX);
+
+        let exporter = Exporter {
+            storage,
+            options,
+            channel: &channel,
+        };
+        // older messages are not read from the storage, once the start of the range is reached
+        let mut messages: Vec<_> = storage
+            .messages_rev(channel.id)
+            .skip_while(|message| until <= message.arrived_at)
+            .take_while(|message| since <= message.arrived_at)
+            .map(|message| exporter.message(&message))
+            .collect();
+        messages.reverse();
+
+        Ok(Self {
+            version: EXPORT_VERSION,
+            exported_at: Utc::now(),
+            channel: ExportedChannel {
+                id: channel_id_string(channel.id),
+                name: channel.name.clone(),
+                is_group: !channel.id.is_user(),
+            },
+            messages,
+        })
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("# {}\n", self.channel.name);
+        let mut day = None;
+        for message in &self.messages {
+            let time = utc_timestamp_msec_to_local(message.arrived_at);
+            if day != Some(time.date_naive()) {
+                day = Some(time.date_naive());
+                let _ = write!(out, "\n## {}\n", time.format("%Y-%m-%d"));
+            }
+
+            let _ = write!(
+                out,
+                "\n**{}** {}\n",
+                message.author.name,
+                time.format("%H:%M")
+            );
+            if let Some(quote) = &message.quote {
+                let text = quote.text.as_deref().unwrap_or_default();
+                let _ = writeln!(
+                    out,
+                    "> **{}**: {}",
+                    quote.author.name,
+                    text.lines().join("\n> ")
+                );
+            }
+            if message.deleted {
+                out.push_str("\n*This message was deleted.*\n");
+            }
+            if let Some(text) = &message.text {
+                let _ = write!(out, "\n{text}\n");
+            }
+            if !message.attachments.is_empty() {
+                out.push('\n');
+            }
+            for attachment in &message.attachments {
+                let _ = writeln!(
+                    out,
+                    "- [{}](<{}>)",
+                    attachment_name(&attachment.path),
+                    attachment.path
+                );
+            }
+            if !message.reactions.is_empty() {
+                let _ = write!(out, "\nReactions: {}\n", display_reactions(message));
+            }
+            if let Some((_latest, previous)) = message.edits.split_last() {
+                out.push_str("\nEdited, previous versions:\n\n");
+                for edit in previous {
+                    let time = utc_timestamp_msec_to_local(edit.arrived_at);
+                    let text = edit.text.as_deref().unwrap_or_default();
+                    let _ = writeln!(out, "- {}: {}", time.format("%Y-%m-%d %H:%M"), text);
+                }
+            }
+        }
+        out
+    }
+
+    pub fn to_html(&self) -> String {
+        let name = escape_html(&self.channel.name);
+        let mut out = format!(
+            "<!DOCTYPE html>\n\
+            <html>\n\
+            <head>\n\
+            <meta charset=\"utf-8\">\n\
+            <title>{name}</title>\n\
+            <style>{HTML_STYLE}</style>\n\
+            </head>\n\
+            <body>\n\
+            <h1>{name}</h1>\n"
+        );
+        let mut day = None;
+        for message in &self.messages {
+            let time = utc_timestamp_msec_to_local(message.arrived_at);
+            if day != Some(time.date_naive()) {
+                day = Some(time.date_naive());
+                let _ = writeln!(out, "<h2>{}</h2>", time.format("%Y-%m-%d"));
+            }
+
+            let _ = writeln!(
+                out,
+                "<div class=\"message\" id=\"m{}\">\n\
+                <div class=\"header\"><span class=\"author\">{}</span> \
+                <time datetime=\"{}\">{}</time></div>",
+                message.arrived_at,
+                escape_html(&message.author.name),
+                message.time.to_rfc3339(),
+                time.format("%H:%M"),
+            );
+            if let Some(quote) = &message.quote {
+                let _ = writeln!(
+                    out,
+                    "<blockquote><a href=\"#m{}\">{}</a>: {}</blockquote>",
+                    quote.arrived_at,
+                    escape_html(&quote.author.name),
+                    escape_html_text(quote.text.as_deref().unwrap_or_default()),
+                );
+            }
+            if message.deleted {
+                out.push_str("<p class=\"deleted\">This message was deleted.</p>\n");
+            }
+            if let Some(text) = &message.text {
+                let _ = writeln!(out, "<p>{}</p>", escape_html_text(text));
+            }
+            if !message.attachments.is_empty() {
+                out.push_str("<ul class=\"attachments\">\n");
+                for attachment in &message.attachments {
+                    let _ = writeln!(
+                        out,
+                        "<li><a href=\"{}\">{}</a></li>",
+                        escape_html(&attachment.path),
+                        escape_html(attachment_name(&attachment.path)),
+                    );
+                }
+                out.push_str("</ul>\n");
+            }
+            if !message.reactions.is_empty() {
+                let _ = writeln!(
+                    out,
+                    "<p class=\"reactions\">{}</p>",
+                    escape_html(&display_reactions(message))
+                );
+            }
+            if let Some((_latest, previous)) = message.edits.split_last() {
+                out.push_str("<details class=\"edits\"><summary>Edited</summary>\n<ol>\n");
+                for edit in previous {
+                    let time = utc_timestamp_msec_to_local(edit.arrived_at);
+                    let _ = writeln!(
+                        out,
+                        "<li><time datetime=\"{}\">{}</time>: {}</li>",
+                        edit.time.to_rfc3339(),
+                        time.format("%Y-%m-%d %H:%M"),
+                        escape_html_text(edit.text.as_deref().unwrap_or_default()),
+                    );
+                }
+                out.push_str("</ol>\n</details>\n");
+            }
+            out.push_str("</div>\n");
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; max-width: 50em; margin: auto; } \
+    .message { margin: 0.5em 0; } \
+    .author { font-weight: bold; } \
+    time { color: gray; } \
+    blockquote { color: gray; border-left: 2px solid gray; margin: 0; padding-left: 0.5em; } \
+    .deleted { font-style: italic; }";
+
+/// Converts stored messages into exported ones
+struct Exporter<'a> {
+    storage: &'a dyn Storage,
+    options: &'a ExportOptions<'a>,
+    channel: &'a Channel,
+}
+
+impl Exporter<'_> {
+    fn message(&self, message: &Message) -> ExportedMessage {
+        let message_id = MessageId::of(self.channel.id, message);
+        let edits = if message.edited {
+            self.storage
+                .edits(message_id)
+                .map(|edit| ExportedEdit {
+                    arrived_at: edit.arrived_at,
+                    time: timestamp(edit.arrived_at),
+                    text: self.text(&edit),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        ExportedMessage {
+            arrived_at: message.arrived_at,
+            time: timestamp(message.arrived_at),
+            author: self.author(message.from_id),
+            text: self.text(message),
+            quote: message.quote.as_ref().map(|quote| ExportedQuote {
+                arrived_at: quote.arrived_at,
+                author: self.author(quote.from_id),
+                text: self.text(quote),
+            }),
+            attachments: message
+                .attachments
+                .iter()
+                .map(|attachment| ExportedAttachment {
+                    content_type: attachment.content_type.clone(),
+                    path: self.attachment_path(attachment),
+                    size: attachment.size,
+                })
+                .collect(),
+            reactions: message
+                .reactions
+                .iter()
+                .map(|(from_id, emoji)| ExportedReaction {
+                    author: self.author(*from_id),
+                    emoji: emoji.clone(),
+                })
+                .collect(),
+            edits,
+            deleted: message.deleted,
+        }
+    }
+
+    fn author(&self, id: Uuid) -> ExportedAuthor {
+        let (user_id, user_name) = self.options.user;
+        let name = if id == user_id {
+            user_name.to_owned()
+        } else if let Some(name) = self.storage.name(id) {
+            name.into_owned()
+        } else if self.channel.id == id {
+            self.channel.name.clone()
+        } else {
+            id.to_string()
+        };
+        ExportedAuthor { id, name }
+    }
+
+    /// Text of the message with mentions replaced by `@name`
+    fn text(&self, message: &Message) -> Option<String> {
+        let text = message.message.as_deref()?;
+        let mut out = String::with_capacity(text.len());
+        // body ranges are given in UTF-16 code units
+        let mut utf16_pos = 0;
+        for c in text.chars() {
+            let mention = message
+                .body_ranges
+                .iter()
+                .find_map(|range| match range.value {
+                    AssociatedValue::MentionUuid(id) if usize::from(range.start) == utf16_pos => {
+                        Some(id)
+                    }
+                    _ => None,
+                });
+            match mention {
+                Some(id) if c == '￼' => {
+                    out.push('@');
+                    out.push_str(&self.author(id).name);
+                }
+                _ => out.push(c),
+            }
+            utf16_pos += c.len_utf16();
+        }
+        Some(out)
+    }
+
+    /// Path of the decrypted copy of the attachment, or of the file itself if it is not copied
+    fn attachment_path(&self, attachment: &Attachment) -> String {
+        if let Some(copies) = &self.options.attachments {
+            match self.copy_attachment(copies, attachment) {
+                Ok(copy) => return relative_path(&copy, copies.base_dir),
+                Err(error) => {
+                    warn!(%error, "failed to export attachment");
+                }
+            }
+        }
+        relative_path(&attachment.filename, self.options.data_dir)
+    }
+
+    /// Writes the decrypted copy of the attachment, unless it exists from a previous export
+    ///
+    /// The copies keep the date directories of the files directory, in which file names are unique.
+    fn copy_attachment(
+        &self,
+        copies: &AttachmentCopies,
+        attachment: &Attachment,
+    ) -> anyhow::Result<PathBuf> {
+        let path = &attachment.filename;
+        let relative = match path.strip_prefix(self.options.data_dir.join("files")) {
+            Ok(relative) => relative,
+            Err(_) => Path::new(path.file_name().context("attachment without file name")?),
+        };
+        let copy = copies.dir.join(relative);
+        if !copy.exists() {
+            let data = copies.files.read(path)?;
+            if let Some(dir) = copy.parent() {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("failed to create dir: {}", dir.display()))?;
+            }
+            std::fs::write(&copy, data)
+                .with_context(|| format!("failed to write {}", copy.display()))?;
+        }
+        Ok(copy)
+    }
+}
+
+/// Path relative to `base` with `/` as separator
+fn relative_path(path: &Path, base: &Path) -> String {
+    match path.strip_prefix(base) {
+        Ok(relative) => relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .join("/"),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+fn find_channel(storage: &dyn Storage, name_or_id: &str) -> anyhow::Result<Channel> {
+    let name = name_or_id.to_lowercase();
+    let channels: Vec<Channel> = storage
+        .channels()
+        .filter(|channel| {
+            channel_id_string(channel.id) == name_or_id || channel.name.to_lowercase() == name
+        })
+        .map(|channel| channel.into_owned())
+        .collect();
+    match <[Channel; 1]>::try_from(channels) {
+        Ok([channel]) => Ok(channel),
+        Err(channels) if channels.is_empty() => bail!("no channel '{name_or_id}'"),
+        Err(channels) => {
+            let ids = channels
+                .iter()
+                .map(|channel| channel_id_string(channel.id))
+                .join(", ");
+            bail!("channel name '{name_or_id}' is ambiguous, use one of the ids: {ids}")
+        }
+    }
+}
+
+fn channel_id_string(channel_id: ChannelId) -> String {
+    match channel_id {
+        ChannelId::User(id) => id.to_string(),
+        ChannelId::Group(id) => hex::encode(id),
+    }
+}
+
+fn timestamp(arrived_at: u64) -> DateTime<Utc> {
+    i64::try_from(arrived_at)
+        .ok()
+        .and_then(DateTime::from_timestamp_millis)
+        .unwrap_or_default()
+}
+
+fn attachment_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn display_reactions(message: &ExportedMessage) -> String {
+    message
+        .reactions
+        .iter()
+        .map(|reaction| format!("{} {}", reaction.emoji, reaction.author.name))
+        .join(", ")
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Escapes the text and keeps its line breaks
+fn escape_html_text(s: &str) -> String {
+    escape_html(s).replace('\n', "<br>\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use url::Url;
+
+    use crate::data::{BodyRange, TypingSet};
+    use crate::passphrase::Passphrase;
+    use crate::signal::Attachment;
+    use crate::storage::SqliteStorage;
+
+    use super::*;
+
+    const USER_ID: Uuid = Uuid::nil();
+    const ALICE: Uuid = Uuid::from_u128(1);
+
+    async fn storage() -> SqliteStorage {
+        let url: Url = "sqlite::memory:".parse().unwrap();
+        let mut storage = SqliteStorage::open(&url, &Passphrase::new("secret").unwrap())
+            .await
+            .unwrap();
+        let channel_id = ChannelId::User(ALICE);
+        storage.store_channel(Channel {
+            id: channel_id,
+            name: "Alice".to_string(),
+            group_data: None,
+            unread_messages: 0,
+            muted: false,
+            typing: TypingSet::new(false),
+            expire_timer: None,
+            draft: None,
+        });
+        storage.store_name(ALICE, "Alice Liddell".to_string());
+
+        let mut first = Message::text(ALICE, 1000, "Hi ￼ & <friends>".to_string());
+        first.body_ranges = vec![BodyRange {
+            start: 3,
+            end: 4,
+            value: AssociatedValue::MentionUuid(USER_ID),
+        }];
+        first.reactions = vec![(USER_ID, "👍".to_string())];
+        first.attachments = vec![Attachment {
+            id: "d51e9a35".to_string(),
+            content_type: "image/jpeg".to_string(),
+            filename: PathBuf::from("/data/gurk/files/2023-12-21/image.jpeg"),
+            size: 1024,
+        }];
+        storage.store_message(channel_id, first);
+
+        let mut reply = Message::text(USER_ID, 2000, "Hello".to_string());
+        reply.quote = Some(Box::new(Message::text(ALICE, 1000, String::new())));
+        storage.store_message(channel_id, reply);
+        storage.store_edited_message(
+            channel_id,
+            2000,
+            Message::text(USER_ID, 3000, "Hello, Alice".to_string()),
+        );
+
+        storage.store_message(
+            channel_id,
+            Message::text(ALICE, 4 * 86_400_000, "Later".to_string()),
+        );
+        storage
+    }
+
+    fn options(channel: &str) -> ExportOptions<'_> {
+        ExportOptions {
+            channel,
+            since: None,
+            until: None,
+            data_dir: Path::new("/data/gurk"),
+            user: (USER_ID, "Tyler"),
+            attachments: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export_json() {
+        let storage = storage().await;
+        let mut out = Vec::new();
+        export(&storage, &options("alice"), ExportFormat::Json, &mut out).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["version"], EXPORT_VERSION);
+        assert_eq!(json["channel"]["id"], ALICE.to_string());
+        assert_eq!(json["channel"]["is_group"], false);
+
+        let messages = json["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+
+        let first = &messages[0];
+        assert_eq!(first["arrived_at"], 1000);
+        assert_eq!(first["author"]["name"], "Alice Liddell");
+        assert_eq!(first["text"], "Hi @Tyler & <friends>");
+        assert_eq!(
+            first["attachments"][0]["path"],
+            "files/2023-12-21/image.jpeg"
+        );
+        assert_eq!(first["reactions"][0]["author"]["name"], "Tyler");
+        assert_eq!(first["reactions"][0]["emoji"], "👍");
+        assert_eq!(first["edits"], serde_json::json!([]));
+
+        let reply = &messages[1];
+        assert_eq!(reply["text"], "Hello, Alice");
+        assert_eq!(reply["quote"]["arrived_at"], 1000);
+        assert_eq!(reply["quote"]["author"]["name"], "Alice Liddell");
+        let edits: Vec<_> = reply["edits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|edit| edit["text"].as_str().unwrap())
+            .collect();
+        assert_eq!(edits, ["Hello", "Hello, Alice"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export_date_range() {
+        let storage = storage().await;
+        let later = utc_timestamp_msec_to_local(4 * 86_400_000).date_naive();
+        let alice_id = ALICE.to_string();
+
+        let since = ExportOptions {
+            since: Some(later),
+            ..options(&alice_id)
+        };
+        let export = ChannelExport::collect(&storage, &since).unwrap();
+        let arrived_at: Vec<u64> = export.messages.iter().map(|m| m.arrived_at).collect();
+        assert_eq!(arrived_at, [4 * 86_400_000]);
+
+        let until = ExportOptions {
+            until: later.pred_opt(),
+            ..options(&alice_id)
+        };
+        let export = ChannelExport::collect(&storage, &until).unwrap();
+        let arrived_at: Vec<u64> = export.messages.iter().map(|m| m.arrived_at).collect();
+        assert_eq!(arrived_at, [1000, 2000]);
+
+        assert!(ChannelExport::collect(&storage, &options("Bob")).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export_markdown_and_html() {
+        let storage = storage().await;
+        let export = ChannelExport::collect(&storage, &options("Alice")).unwrap();
+
+        let markdown = export.to_markdown();
+        assert!(markdown.starts_with("# Alice\n"));
+        assert!(markdown.contains("\nHi @Tyler & <friends>\n"));
+        assert!(markdown.contains("- [image.jpeg](<files/2023-12-21/image.jpeg>)\n"));
+        assert!(markdown.contains("> **Alice Liddell**: Hi @Tyler & <friends>\n"));
+        assert!(markdown.contains("\nReactions: 👍 Tyler\n"));
+        assert!(markdown.contains("\nEdited, previous versions:\n\n"));
+
+        let html = export.to_html();
+        assert!(html.contains("<p>Hi @Tyler &amp; &lt;friends&gt;</p>"));
+        assert!(html.contains("<li><a href=\"files/2023-12-21/image.jpeg\">image.jpeg</a></li>"));
+        assert!(html.contains(
+            "<blockquote><a href=\"#m1000\">Alice Liddell</a>: Hi @Tyler &amp; &lt;friends&gt;</blockquote>"
+        ));
+        assert!(html.ends_with("</body>\n</html>\n"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export_decrypted_attachments() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let date_dir = data_dir.path().join("files").join("2023-12-22");
+        std::fs::create_dir_all(&date_dir).unwrap();
+        let filename = date_dir.join("photo.jpeg");
+        let files = AttachmentFiles::with_key([42; 32]);
+        files.write(&filename, b"jpeg data").unwrap();
+
+        let mut storage = storage().await;
+        let mut message = Message::text(ALICE, 5000, "Photo".to_string());
+        message.attachments = vec![Attachment {
+            id: "photo".to_string(),
+            content_type: "image/jpeg".to_string(),
+            filename,
+            size: 9,
+        }];
+        storage.store_message(ChannelId::User(ALICE), message);
+
+        let export_dir = tempfile::tempdir().unwrap();
+        let copies_dir = export_dir.path().join("alice_files");
+        let options = ExportOptions {
+            data_dir: data_dir.path(),
+            attachments: Some(AttachmentCopies {
+                dir: &copies_dir,
+                base_dir: export_dir.path(),
+                files: &files,
+            }),
+            ..options("Alice")
+        };
+        let export = ChannelExport::collect(&storage, &options).unwrap();
+        let paths: Vec<&str> = export
+            .messages
+            .iter()
+            .flat_map(|message| &message.attachments)
+            .map(|attachment| attachment.path.as_str())
+            .collect();
+        // the file of the first message does not exist
+        assert_eq!(
+            paths,
+            [
+                "/data/gurk/files/2023-12-21/image.jpeg",
+                "alice_files/2023-12-22/photo.jpeg"
+            ]
+        );
+        let copy = copies_dir.join("2023-12-22").join("photo.jpeg");
+        assert_eq!(std::fs::read(copy).unwrap(), b"jpeg data");
+    }
+}
@@ -0,0 +1,189 @@
+//! Find of text in the messages of the selected channel
+
+use std::ops::Range;
+
+use crate::data::ChannelId;
+use crate::input::Input;
+use crate::storage::{MessageId, MessagePosition};
+
+/// Byte ranges of the case-insensitive, non-overlapping occurrences of the pattern in the text
+pub(crate) fn match_ranges(text: &str, pattern: &str) -> Vec<Range<usize>> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    if pattern.is_empty() {
+        return Vec::new();
+    }
+    let mut ranges = Vec::new();
+    let mut pos = 0;
+    while let Some(first) = text[pos..].chars().next() {
+        let mut chars = text[pos..].chars();
+        let is_match = pattern
+            .iter()
+            .all(|&p| chars.next().is_some_and(|c| eq_ignore_case(c, p)));
+        if is_match {
+            let end = text.len() - chars.as_str().len();
+            ranges.push(pos..end);
+            pos = end;
+        } else {
+            pos += first.len_utf8();
+        }
+    }
+    ranges
+}
+
+fn eq_ignore_case(a: char, b: char) -> bool {
+    a == b || a.to_lowercase().eq(b.to_lowercase())
+}
+
+/// Find of a pattern in the messages of a channel
+///
+/// Messages are scanned in steps from the newest to the oldest one, such that the matches in big
+/// histories are collected without blocking the rendering.
+#[derive(Default)]
+pub(crate) struct Find {
+    /// Whether the prompt for the pattern is shown
+    pub is_shown: bool,
+    pub input: Input,
+    /// Channel and pattern of the matches
+    target: Option<(ChannelId, String)>,
+    /// Matching messages from the newest to the oldest
+    matches: Vec<MessageId>,
+    /// Index of the selected match
+    selected: Option<usize>,
+    /// Position of the oldest scanned message
+    scanned_until: Option<MessagePosition>,
+    is_complete: bool,
+    /// Whether the next scan step was already requested
+    pub is_step_scheduled: bool,
+}
+
+impl Find {
+    /// Resets the find except for the scheduled step, which is still going to be delivered
+    pub fn reset(&mut self) {
+        *self = Self {
+            is_step_scheduled: self.is_step_scheduled,
+            ..Default::default()
+        };
+    }
+
+    /// Pattern whose matches are highlighted in the channel
+    pub fn pattern(&self, channel_id: ChannelId) -> Option<&str> {
+        let (target_channel_id, pattern) = self.target()?;
+        (target_channel_id == channel_id).then_some(pattern)
+    }
+
+    pub fn target(&self) -> Option<(ChannelId, &str)> {
+        let (channel_id, pattern) = self.target.as_ref()?;
+        Some((*channel_id, pattern))
+    }
+
+    /// Whether the matches do not correspond to the input anymore
+    pub fn is_outdated(&self) -> bool {
+        let pattern = self
+            .target
+            .as_ref()
+            .map_or("", |(_, pattern)| pattern.as_str());
+        pattern != self.input.data
+    }
+
+    /// Drops the matches and starts scanning the channel for the input
+    pub fn restart(&mut self, channel_id: ChannelId) {
+        self.target = (!self.input.data.is_empty()).then(|| (channel_id, self.input.data.clone()));
+        self.matches.clear();
+        self.selected = None;
+        self.scanned_until = None;
+        self.is_complete = false;
+    }
+
+    /// Whether there are messages left to scan
+    pub fn is_pending(&self) -> bool {
+        self.target.is_some() && !self.is_complete
+    }
+
+    pub fn scanned_until(&self) -> Option<MessagePosition> {
+        self.scanned_until
+    }
+
+    /// Adds the matches found in the next scanned messages
+    ///
+    /// The first found match is selected.
+    pub fn add_scanned(
+        &mut self,
+        matches: Vec<MessageId>,
+        scanned_until: Option<MessagePosition>,
+        is_complete: bool,
+    ) {
+        self.matches.extend(matches);
+        if self.selected.is_none() && !self.matches.is_empty() {
+            self.selected = Some(0);
+        }
+        self.scanned_until = scanned_until.or(self.scanned_until);
+        self.is_complete = is_complete;
+    }
+
+    pub fn matches(&self) -> &[MessageId] {
+        &self.matches
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    pub fn selected_match(&self) -> Option<MessageId> {
+        self.matches.get(self.selected?).copied()
+    }
+
+    /// Selects the next older match
+    pub fn select_older(&mut self) {
+        let last = self.matches.len().saturating_sub(1);
+        self.selected = self.selected.map(|idx| (idx + 1).min(last));
+    }
+
+    /// Selects the next newer match
+    pub fn select_newer(&mut self) {
+        self.selected = self.selected.map(|idx| idx.saturating_sub(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_ranges() {
+        assert_eq!(match_ranges("Hello, hello!", "hello"), [0..5, 7..12]);
+        assert_eq!(match_ranges("aaaa", "aa"), [0..2, 2..4]);
+        assert_eq!(match_ranges("Grüße ÜBER", "ü"), [2..4, 8..10]);
+        assert!(match_ranges("hello", "").is_empty());
+        assert!(match_ranges("hell", "hello").is_empty());
+    }
+
+    #[test]
+    fn test_find_selection() {
+        let channel_id = ChannelId::User(uuid::Uuid::nil());
+        let message_id = |arrived_at| MessageId::new(channel_id, uuid::Uuid::nil(), arrived_at);
+        let mut find = Find::default();
+        find.input.data = "hello".to_string();
+        assert!(find.is_outdated());
+
+        find.restart(channel_id);
+        assert!(!find.is_outdated());
+        assert!(find.is_pending());
+        assert_eq!(find.pattern(channel_id), Some("hello"));
+
+        let position = |arrived_at| MessagePosition::from(message_id(arrived_at));
+        find.add_scanned(vec![], Some(position(10)), false);
+        assert_eq!(find.selected_match(), None);
+        find.add_scanned(vec![message_id(8), message_id(5)], Some(position(5)), false);
+        assert_eq!(find.selected_match(), Some(message_id(8)));
+        find.add_scanned(vec![message_id(2)], Some(position(1)), true);
+        assert!(!find.is_pending());
+        assert_eq!(find.scanned_until(), Some(position(1)));
+
+        find.select_older();
+        find.select_older();
+        find.select_older();
+        assert_eq!(find.selected_match(), Some(message_id(2)));
+        find.select_newer();
+        assert_eq!(find.selected_match(), Some(message_id(5)));
+    }
+}
@@ -0,0 +1,714 @@
+//! Import of the message history from a Signal backup
+//!
+//! Signal backups are a sequence of frames (recipients, chats, chat items, ...). The importer
+//! reads the plaintext export of a backup, in which each line holds one frame in the JSON mapping
+//! of the protobuf backup format. Attachment files are looked up in the `files` directory next to
+//! the export by the hex-encoded plaintext hash of the attachment.
+//!
+//! Already existing messages are not touched, so importing the same backup again is a no-op.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+use std::path::Path;
+
+use anyhow::Context;
+use base64::prelude::*;
+use presage::proto::AttachmentPointer;
+use serde::{Deserialize, Deserializer, de};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::attachment_files::AttachmentFiles;
+use crate::data::{
+    AssociatedValue, BodyRange, Channel, ChannelId, GroupData, Message, Style, TypingSet,
+};
+use crate::receipt::Receipt;
+use crate::signal::{Attachment, GroupMasterKeyBytes, save_attachment};
+use crate::storage::{MessageId, Storage};
+
+#[derive(Debug)]
+pub struct ImportOptions<'a> {
+    /// Directory with the attachment files of the backup
+    pub files_dir: &'a Path,
+    /// Directory to which the attachments are copied
+    pub data_dir: &'a Path,
+    /// Encryption of the copied attachments
+    pub attachment_files: &'a AttachmentFiles,
+    /// Own user id and name; used for outgoing messages and the "Note to Self" chat
+    pub user: (Uuid, &'a str),
+}
+
+/// Counts of the imported and skipped data
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// Newly created channels
+    pub channels: usize,
+    pub messages: usize,
+    /// Messages skipped because a message with the same arrived at already exists
+    pub duplicates: usize,
+    /// Chat items which are not supported, e.g. calls, stickers or group updates
+    pub unsupported: usize,
+    /// Attachments whose file is not part of the backup
+    pub missing_attachments: usize,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "created channels:     {}", self.channels)?;
+        writeln!(f, "imported messages:    {}", self.messages)?;
+        writeln!(f, "skipped duplicates:   {}", self.duplicates)?;
+        writeln!(f, "skipped unsupported:  {}", self.unsupported)?;
+        write!(f, "missing attachments:  {}", self.missing_attachments)
+    }
+}
+
+/// Imports the frames of a plaintext backup export read line by line from the input
+pub fn import(
+    storage: &mut dyn Storage,
+    options: &ImportOptions,
+    input: impl BufRead,
+) -> anyhow::Result<ImportSummary> {
+    let mut importer = Importer {
+        storage,
+        options,
+        recipients: HashMap::new(),
+        chats: HashMap::new(),
+        summary: ImportSummary::default(),
+    };
+    for (idx, line) in input.lines().enumerate() {
+        let line = line.context("failed to read backup")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let frame: Frame = serde_json::from_str(&line)
+            .with_context(|| format!("invalid frame at line {}", idx + 1))?;
+        importer.frame(frame);
+    }
+    importer.storage.save();
+    Ok(importer.summary)
+}
+
+struct Importer<'a> {
+    storage: &'a mut dyn Storage,
+    options: &'a ImportOptions<'a>,
+    /// Supported recipients by their id in the backup
+    recipients: HashMap<u64, ImportedRecipient>,
+    /// Channels of the supported chats by their id in the backup
+    chats: HashMap<u64, ChannelId>,
+    summary: ImportSummary,
+}
+
+enum ImportedRecipient {
+    User { id: Uuid, name: Option<String> },
+    Group { channel: Channel },
+}
+
+impl Importer<'_> {
+    fn frame(&mut self, frame: Frame) {
+        if let Some(recipient) = frame.recipient {
+            self.recipient(recipient);
+        } else if let Some(chat) = frame.chat {
+            self.chat(chat);
+        } else if let Some(item) = frame.chat_item {
+            self.chat_item(item);
+        }
+    }
+
+    fn recipient(&mut self, recipient: Recipient) {
+        let imported = if recipient.self_recipient.is_some() {
+            let (id, name) = self.options.user;
+            ImportedRecipient::User {
+                id,
+                name: Some(name.to_owned()),
+            }
+        } else if let Some(contact) = recipient.contact {
+            let Some(id) = contact.aci.as_deref().and_then(uuid_from_base64) else {
+                debug!(id = recipient.id.0, "skipping contact without aci");
+                return;
+            };
+            let name = contact.name();
+            if let Some(name) = &name
+                && self.storage.name(id).is_none()
+            {
+                self.storage.store_name(id, name.clone());
+            }
+            ImportedRecipient::User { id, name }
+        } else if let Some(group) = recipient.group {
+            match group.channel() {
+                Ok(channel) => ImportedRecipient::Group { channel },
+                Err(error) => {
+                    warn!(%error, id = recipient.id.0, "skipping invalid group");
+                    return;
+                }
+            }
+        } else {
+            return;
+        };
+        self.recipients.insert(recipient.id.0, imported);
+    }
+
+    fn chat(&mut self, chat: Chat) {
+        let mut channel = match self.recipients.get(&chat.recipient_id.0) {
+            Some(ImportedRecipient::User { id, name }) => Channel {
+                id: (*id).into(),
+                name: name.clone().unwrap_or_else(|| id.to_string()),
+                group_data: None,
+                unread_messages: 0,
+                muted: false,
+                typing: TypingSet::new(false),
+                expire_timer: None,
+                draft: None,
+            },
+            Some(ImportedRecipient::Group { channel }) => channel.clone(),
+            None => return,
+        };
+        if self.storage.channel(channel.id).is_none() {
+            channel.expire_timer = chat
+                .expiration_timer_ms
+                .and_then(|timer| u32::try_from(timer.0 / 1000).ok())
+                .filter(|&timer| timer > 0);
+            self.storage.store_channel(channel.clone());
+            self.summary.channels += 1;
+        }
+        self.chats.insert(chat.id.0, channel.id);
+    }
+
+    fn chat_item(&mut self, item: ChatItem) {
+        let Some(&channel_id) = self.chats.get(&item.chat_id.0) else {
+            self.summary.unsupported += 1;
+            return;
+        };
+        let (Some(from_id), Some(standard)) = (self.author(item.author_id), item.standard_message)
+        else {
+            self.summary.unsupported += 1;
+            return;
+        };
+        let arrived_at = item.date_sent.0;
+        if self
+            .storage
+            .message(MessageId::new(channel_id, from_id, arrived_at))
+            .is_some()
+        {
+            self.summary.duplicates += 1;
+            return;
+        }
+
+        let (text, body_ranges) = standard.text.map(Text::into_parts).unwrap_or_default();
+        let quote = standard.quote.and_then(|quote| {
+            let arrived_at = quote.target_sent_timestamp?.0;
+            let from_id = self.author(quote.author_id)?;
+            let (text, body_ranges) = quote.text.map(Text::into_parts).unwrap_or_default();
+            Some(Box::new(Message {
+                message: text,
+                body_ranges,
+                ..Message::text(from_id, arrived_at, String::new())
+            }))
+        });
+        let attachments = standard
+            .attachments
+            .into_iter()
+            .filter_map(|attachment| self.attachment(attachment.pointer, arrived_at))
+            .collect();
+        let reactions = standard
+            .reactions
+            .into_iter()
+            .filter_map(|reaction| Some((self.author(reaction.author_id)?, reaction.emoji)))
+            .collect();
+        let expire_timer = item
+            .expires_in_ms
+            .and_then(|expires_in| u32::try_from(expires_in.0 / 1000).ok())
+            .filter(|&timer| timer > 0);
+        let expires_at = item
+            .expire_start_date
+            .zip(item.expires_in_ms)
+            .filter(|_| expire_timer.is_some())
+            .map(|(start, expires_in)| start.0.saturating_add(expires_in.0));
+
+        let message = Message {
+            message: text,
+            quote,
+            attachments,
+            reactions,
+            receipt: if item.outgoing.is_some() {
+                Receipt::Sent
+            } else {
+                Receipt::Nothing
+            },
+            body_ranges,
+            expire_timer,
+            expires_at,
+            ..Message::text(from_id, arrived_at, String::new())
+        };
+        self.storage.store_message(channel_id, message);
+        self.summary.messages += 1;
+    }
+
+    fn author(&self, recipient_id: U64) -> Option<Uuid> {
+        match self.recipients.get(&recipient_id.0)? {
+            ImportedRecipient::User { id, .. } => Some(*id),
+            ImportedRecipient::Group { .. } => None,
+        }
+    }
+
+    /// Copies the file of the attachment into the data directory
+    fn attachment(&mut self, pointer: FilePointer, arrived_at: u64) -> Option<Attachment> {
+        let digest = pointer
+            .locator_info
+            .and_then(|info| info.plaintext_hash)
+            .and_then(|hash| BASE64_STANDARD.decode(hash).ok());
+        let Some(digest) = digest else {
+            self.summary.missing_attachments += 1;
+            return None;
+        };
+        let path = self.options.files_dir.join(hex::encode(&digest));
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(error) => {
+                warn!(%error, path =% path.display(), "missing attachment file");
+                self.summary.missing_attachments += 1;
+                return None;
+            }
+        };
+        let pointer = AttachmentPointer {
+            content_type: pointer.content_type,
+            file_name: pointer.file_name,
+            digest: Some(digest),
+            size: u32::try_from(data.len()).ok(),
+            upload_timestamp: Some(arrived_at),
+            ..Default::default()
+        };
+        match save_attachment(
+            self.options.data_dir,
+            self.options.attachment_files,
+            pointer,
+            &data,
+        ) {
+            Ok(attachment) => Some(attachment),
+            Err(error) => {
+                warn!(%error, "failed to import attachment");
+                self.summary.missing_attachments += 1;
+                None
+            }
+        }
+    }
+}
+
+// Frames of the backup; only the imported fields are deserialized. Fields of type `bytes` are
+// base64-encoded, 64-bit integers are encoded as strings or numbers.
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Frame {
+    recipient: Option<Recipient>,
+    chat: Option<Chat>,
+    chat_item: Option<ChatItem>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Recipient {
+    id: U64,
+    contact: Option<Contact>,
+    group: Option<Group>,
+    #[serde(rename = "self")]
+    self_recipient: Option<de::IgnoredAny>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Contact {
+    aci: Option<String>,
+    e164: Option<U64>,
+    profile_given_name: Option<String>,
+    profile_family_name: Option<String>,
+    system_given_name: Option<String>,
+    system_family_name: Option<String>,
+}
+
+impl Contact {
+    /// Name from the system contacts, otherwise from the profile or the phone number
+    fn name(&self) -> Option<String> {
+        let join = |given: &Option<String>, family: &Option<String>| {
+            let name = [given.as_deref(), family.as_deref()]
+                .into_iter()
+                .flatten()
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+            (!name.is_empty()).then_some(name)
+        };
+        join(&self.system_given_name, &self.system_family_name)
+            .or_else(|| join(&self.profile_given_name, &self.profile_family_name))
+            .or_else(|| self.e164.map(|e164| format!("+{}", e164.0)))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Group {
+    master_key: String,
+    snapshot: Option<GroupSnapshot>,
+}
+
+impl Group {
+    fn channel(self) -> anyhow::Result<Channel> {
+        let master_key_bytes: GroupMasterKeyBytes = BASE64_STANDARD
+            .decode(&self.master_key)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("invalid group master key"))?;
+        let id = ChannelId::from_master_key_bytes(master_key_bytes)?;
+        let snapshot = self.snapshot.unwrap_or_default();
+        let members = snapshot
+            .members
+            .iter()
+            .filter_map(|member| uuid_from_base64(&member.user_id))
+            .collect();
+        Ok(Channel {
+            id,
+            name: snapshot
+                .title
+                .and_then(|title| title.title)
+                .unwrap_or_else(|| "Unnamed group".to_owned()),
+            group_data: Some(GroupData {
+                master_key_bytes,
+                members,
+                revision: snapshot.version,
+            }),
+            unread_messages: 0,
+            muted: false,
+            typing: TypingSet::new(true),
+            expire_timer: None,
+            draft: None,
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GroupSnapshot {
+    title: Option<GroupTitle>,
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    members: Vec<GroupMember>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GroupTitle {
+    title: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GroupMember {
+    user_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Chat {
+    id: U64,
+    recipient_id: U64,
+    expiration_timer_ms: Option<U64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChatItem {
+    chat_id: U64,
+    author_id: U64,
+    date_sent: U64,
+    expire_start_date: Option<U64>,
+    expires_in_ms: Option<U64>,
+    outgoing: Option<de::IgnoredAny>,
+    standard_message: Option<StandardMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StandardMessage {
+    quote: Option<Quote>,
+    text: Option<Text>,
+    #[serde(default)]
+    attachments: Vec<MessageAttachment>,
+    #[serde(default)]
+    reactions: Vec<Reaction>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Quote {
+    target_sent_timestamp: Option<U64>,
+    author_id: U64,
+    text: Option<Text>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Text {
+    body: Option<String>,
+    #[serde(default)]
+    body_ranges: Vec<TextBodyRange>,
+}
+
+impl Text {
+    fn into_parts(self) -> (Option<String>, Vec<BodyRange>) {
+        let body_ranges = self
+            .body_ranges
+            .into_iter()
+            .filter_map(TextBodyRange::into_body_range)
+            .collect();
+        (self.body, body_ranges)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextBodyRange {
+    #[serde(default)]
+    start: u16,
+    #[serde(default)]
+    length: u16,
+    mention_aci: Option<String>,
+    style: Option<String>,
+}
+
+impl TextBodyRange {
+    fn into_body_range(self) -> Option<BodyRange> {
+        let value = if let Some(aci) = &self.mention_aci {
+            AssociatedValue::MentionUuid(uuid_from_base64(aci)?)
+        } else {
+            AssociatedValue::Style(match self.style.as_deref()? {
+                "BOLD" => Style::Bold,
+                "ITALIC" => Style::Italic,
+                "SPOILER" => Style::Spoiler,
+                "STRIKETHROUGH" => Style::Strikethrough,
+                "MONOSPACE" => Style::Monospace,
+                _ => return None,
+            })
+        };
+        Some(BodyRange {
+            start: self.start,
+            end: self.start.saturating_add(self.length),
+            value,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageAttachment {
+    pointer: FilePointer,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FilePointer {
+    content_type: Option<String>,
+    file_name: Option<String>,
+    locator_info: Option<LocatorInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LocatorInfo {
+    plaintext_hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Reaction {
+    emoji: String,
+    author_id: U64,
+}
+
+/// 64-bit integer, which is encoded as string in the JSON mapping of protobuf
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct U64(u64);
+
+impl<'de> Deserialize<'de> for U64 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Number(u64),
+            String(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Number(value) => Ok(Self(value)),
+            Repr::String(value) => value.parse().map(Self).map_err(de::Error::custom),
+        }
+    }
+}
+
+fn uuid_from_base64(value: &str) -> Option<Uuid> {
+    let bytes = BASE64_STANDARD.decode(value).ok()?;
+    Uuid::from_slice(&bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::storage::{ForgetfulStorage, MemCache};
+
+    use super::*;
+
+    const USER_ID: Uuid = Uuid::nil();
+    const ALICE: Uuid = Uuid::from_u128(1);
+
+    const BACKUP: &str = r#"{"version":"1","backupTimeMs":"1700000000000"}
+{"recipient":{"id":"1","self":{}}}
+{"recipient":{"id":"2","contact":{"aci":"AAAAAAAAAAAAAAAAAAAAAQ==","profileGivenName":"Alice","profileFamilyName":"Liddell"}}}
+{"recipient":{"id":"3","group":{"masterKey":"AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=","snapshot":{"title":{"title":"Wonderland"},"version":5,"members":[{"userId":"AAAAAAAAAAAAAAAAAAAAAA=="},{"userId":"AAAAAAAAAAAAAAAAAAAAAQ=="}]}}}}
+{"recipient":{"id":"4","releaseNotes":{}}}
+{"chat":{"id":"1","recipientId":"2","expirationTimerMs":"0"}}
+{"chat":{"id":"2","recipientId":"3","expirationTimerMs":"86400000"}}
+{"chat":{"id":"3","recipientId":"4"}}
+{"chatItem":{"chatId":"1","authorId":"2","dateSent":"1000","incoming":{},"standardMessage":{"text":{"body":"Hi ￼, look","bodyRanges":[{"start":3,"length":1,"mentionAci":"AAAAAAAAAAAAAAAAAAAAAA=="}]},"attachments":[{"pointer":{"contentType":"image/jpeg","fileName":"cat.jpeg","locatorInfo":{"plaintextHash":"jv05v/epSchuuo6xGjKwUoTh2xL1Wn5fOAimpD/Pq50="}}},{"pointer":{"contentType":"video/mp4","locatorInfo":{"plaintextHash":"//////////////////////////////////////////8="}}}],"reactions":[{"emoji":"👍","authorId":"1","sentTimestamp":"1500"}]}}}
+{"chatItem":{"chatId":"1","authorId":"1","dateSent":2000,"outgoing":{},"standardMessage":{"text":{"body":"Nice","bodyRanges":[{"length":4,"style":"BOLD"}]},"quote":{"targetSentTimestamp":"1000","authorId":"2","text":{"body":"Hi ￼, look"}}}}}
+{"chatItem":{"chatId":"2","authorId":"2","dateSent":"3000","expireStartDate":"3500","expiresInMs":"86400000","incoming":{},"standardMessage":{"text":{"body":"Hello group"}}}}
+{"chatItem":{"chatId":"2","authorId":"1","dateSent":"4000","outgoing":{},"updateMessage":{"groupChange":{}}}}
+{"chatItem":{"chatId":"3","authorId":"4","dateSent":"5000","incoming":{},"standardMessage":{"text":{"body":"Release notes"}}}}
+"#;
+
+    fn import_backup(
+        storage: &mut dyn Storage,
+        files_dir: &Path,
+        data_dir: &Path,
+    ) -> ImportSummary {
+        let options = ImportOptions {
+            files_dir,
+            data_dir,
+            attachment_files: &AttachmentFiles::default(),
+            user: (USER_ID, "Tyler"),
+        };
+        import(storage, &options, Cursor::new(BACKUP)).unwrap()
+    }
+
+    #[test]
+    fn test_import() {
+        let files_dir = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            files_dir
+                .path()
+                .join("8efd39bff7a949c86eba8eb11a32b05284e1db12f55a7e5f3808a6a43fcfab9d"),
+            "jpeg data",
+        )
+        .unwrap();
+
+        let mut storage = MemCache::new(ForgetfulStorage);
+        let summary = import_backup(&mut storage, files_dir.path(), data_dir.path());
+        assert_eq!(
+            summary,
+            ImportSummary {
+                channels: 2,
+                messages: 3,
+                duplicates: 0,
+                unsupported: 2,
+                missing_attachments: 1,
+            }
+        );
+        assert_eq!(storage.name(ALICE).unwrap(), "Alice Liddell");
+
+        let alice = storage.channel(ALICE.into()).unwrap();
+        assert_eq!(alice.name, "Alice Liddell");
+        assert_eq!(alice.expire_timer, None);
+
+        let first = storage
+            .message(MessageId::new(ALICE.into(), ALICE, 1000))
+            .unwrap();
+        assert_eq!(first.from_id, ALICE);
+        assert_eq!(first.message.as_deref(), Some("Hi ￼, look"));
+        assert_eq!(
+            first.body_ranges,
+            [BodyRange {
+                start: 3,
+                end: 4,
+                value: AssociatedValue::MentionUuid(USER_ID),
+            }]
+        );
+        assert_eq!(first.reactions, [(USER_ID, "👍".to_string())]);
+        assert_eq!(first.attachments.len(), 1);
+        let attachment = &first.attachments[0];
+        assert_eq!(attachment.content_type, "image/jpeg");
+        assert!(attachment.filename.starts_with(data_dir.path()));
+        assert_eq!(attachment.filename.file_name().unwrap(), "cat.jpeg");
+        assert_eq!(std::fs::read(&attachment.filename).unwrap(), b"jpeg data");
+
+        let reply = storage
+            .message(MessageId::new(ALICE.into(), USER_ID, 2000))
+            .unwrap();
+        assert_eq!(reply.from_id, USER_ID);
+        assert_eq!(reply.receipt, Receipt::Sent);
+        assert_eq!(
+            reply.body_ranges[0].value,
+            AssociatedValue::Style(Style::Bold)
+        );
+        let quote = reply.quote.as_ref().unwrap();
+        assert_eq!((quote.from_id, quote.arrived_at), (ALICE, 1000));
+
+        let group = storage
+            .channels()
+            .find(|channel| channel.name == "Wonderland")
+            .unwrap();
+        assert_eq!(group.expire_timer, Some(86400));
+        let group_data = group.group_data.as_ref().unwrap();
+        assert_eq!(group_data.members, [USER_ID, ALICE]);
+        assert_eq!(group_data.revision, 5);
+        let message = storage
+            .message(MessageId::new(group.id, ALICE, 3000))
+            .unwrap();
+        assert_eq!(message.expire_timer, Some(86400));
+        assert_eq!(message.expires_at, Some(3500 + 86_400_000));
+    }
+
+    #[test]
+    fn test_import_skips_existing_messages() {
+        let files_dir = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        let mut storage = MemCache::new(ForgetfulStorage);
+        storage.store_channel(Channel {
+            id: ALICE.into(),
+            name: "Alice".to_string(),
+            group_data: None,
+            unread_messages: 0,
+            muted: false,
+            typing: TypingSet::new(false),
+            expire_timer: None,
+            draft: None,
+        });
+        storage.store_message(
+            ALICE.into(),
+            Message::text(ALICE, 1000, "Already here".to_string()),
+        );
+
+        let summary = import_backup(&mut storage, files_dir.path(), data_dir.path());
+        assert_eq!((summary.channels, summary.messages), (1, 2));
+        assert_eq!(summary.duplicates, 1);
+        let existing = storage
+            .message(MessageId::new(ALICE.into(), ALICE, 1000))
+            .unwrap();
+        assert_eq!(existing.message.as_deref(), Some("Already here"));
+        assert_eq!(storage.channel(ALICE.into()).unwrap().name, "Alice");
+
+        let summary = import_backup(&mut storage, files_dir.path(), data_dir.path());
+        assert_eq!((summary.channels, summary.messages), (0, 0));
+        assert_eq!(summary.duplicates, 3);
+    }
+
+    #[test]
+    fn test_import_invalid_frame() {
+        let mut storage = MemCache::new(ForgetfulStorage);
+        let options = ImportOptions {
+            files_dir: Path::new("files"),
+            data_dir: Path::new("data"),
+            attachment_files: &AttachmentFiles::default(),
+            user: (USER_ID, "Tyler"),
+        };
+        let error = import(&mut storage, &options, Cursor::new("{}\nnot json\n")).unwrap_err();
+        assert_eq!(error.to_string(), "invalid frame at line 2");
+    }
+}
@@ -1,20 +1,33 @@
 //! Signal Messenger client for terminal
 
 pub mod app;
+pub mod attachment_files;
+pub mod attachment_gc;
+mod attachment_picker;
+mod attachment_tray;
 pub mod backoff;
 mod channels;
 pub mod command;
 pub mod config;
 pub mod cursor;
 pub mod data;
+pub mod data_dir_lock;
 #[cfg(feature = "dev")]
 pub mod dev;
 pub(crate) mod emoji;
 pub mod event;
+pub mod export;
+mod find;
+pub mod import;
 pub mod input;
+pub(crate) mod markup;
+pub mod mention;
 pub mod onboarding;
 pub mod passphrase;
+pub mod preview;
 pub mod receipt;
+mod sanitize;
+mod search;
 pub mod shortcuts;
 pub mod signal;
 pub mod storage;
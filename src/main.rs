@@ -1,12 +1,15 @@
 //! Signal Messenger client for terminal
 
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 
 use anyhow::{Context, anyhow};
-use chrono::{DateTime, Utc};
-use clap::Parser;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
 use crossterm::{
     event::{
         DisableMouseCapture, EnableMouseCapture, Event as CEvent, EventStream, KeyEvent,
@@ -15,6 +18,11 @@ use crossterm::{
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
+use gurk::attachment_files::AttachmentFiles;
+use gurk::attachment_gc::GcOptions;
+use gurk::data_dir_lock::DataDirLock;
+use gurk::export::{AttachmentCopies, ExportFormat, ExportOptions};
+use gurk::import::ImportOptions;
 use gurk::signal::LocalPool;
 use gurk::{app::App, config::Config};
 use gurk::{backoff::Backoff, passphrase::Passphrase};
@@ -33,7 +41,6 @@ use url::Url;
 const TARGET_FPS: u64 = 144;
 const RECEIPT_TICK_PERIOD: u64 = 144;
 const FRAME_BUDGET: Duration = Duration::from_millis(1000 / TARGET_FPS);
-const SAVE_BUDGET: Duration = Duration::from_millis(1000);
 const RECEIPT_BUDGET: Duration = Duration::from_millis(RECEIPT_TICK_PERIOD * 1000 / TARGET_FPS);
 
 #[derive(Debug, Parser)]
@@ -55,6 +62,60 @@ struct Args {
     /// When omitted, passphrase_command is read from the env "GURK_PASSPHRASE_COMMAND".
     #[arg(long, conflicts_with = "passphrase")]
     passphrase_command: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Exports the history of a channel
+    Export(ExportArgs),
+    /// Imports the message history from a plaintext Signal backup export
+    Import(ImportArgs),
+    /// Removes attachment files which are not referenced by any message and merges copies of the
+    /// same attachment
+    GcAttachments(GcAttachmentsArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct ExportArgs {
+    /// Name or id of the channel
+    #[arg(long)]
+    channel: String,
+    /// Format of the export; the JSON format is versioned and stable
+    #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+    format: ExportFormat,
+    /// Exports messages from this day on (YYYY-MM-DD)
+    #[arg(long)]
+    since: Option<NaiveDate>,
+    /// Exports messages up to and including this day (YYYY-MM-DD)
+    #[arg(long)]
+    until: Option<NaiveDate>,
+    /// File to write the export to; defaults to stdout
+    ///
+    /// Decrypted copies of the attachments are written to the `<name>_files` directory next to the
+    /// file. Without a file, links to attachments point to the encrypted files in the data
+    /// directory of gurk.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Args)]
+struct ImportArgs {
+    /// Backup export with one JSON frame per line
+    path: PathBuf,
+    /// Directory with the attachment files of the backup
+    ///
+    /// Defaults to the `files` directory next to the backup export.
+    #[arg(long)]
+    files: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Args)]
+struct GcAttachmentsArgs {
+    /// Only lists the files which would be removed and the reclaimable bytes
+    #[arg(long)]
+    dry_run: bool,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -93,7 +154,120 @@ fn main() -> anyhow::Result<()> {
         .worker_threads(2)
         .enable_all()
         .build()?;
-    runtime.block_on(run(config, passphrase, args.relink))
+    match args.command {
+        Some(Command::Export(export_args)) => {
+            runtime.block_on(export(config, passphrase, export_args))
+        }
+        Some(Command::Import(import_args)) => {
+            runtime.block_on(import(config, passphrase, import_args))
+        }
+        Some(Command::GcAttachments(gc_args)) => {
+            runtime.block_on(gc_attachments(config, passphrase, gc_args))
+        }
+        None => runtime.block_on(run(config, passphrase, args.relink)),
+    }
+}
+
+async fn open_storage(config: &Config, passphrase: &Passphrase) -> anyhow::Result<SqliteStorage> {
+    let url = match config
+        .sqlite
+        .as_ref()
+        .map(|sqlite_config| sqlite_config.url.clone())
+    {
+        Some(url) => url,
+        None => Url::from_file_path(config.gurk_db_path())
+            .map_err(|_| anyhow!("failed to convert gurk db path to url"))?,
+    };
+
+    debug!(%url, "opening sqlite data storage");
+    SqliteStorage::maybe_encrypt_and_open(&url, passphrase, false)
+        .await
+        .with_context(|| format!("failed to open sqlite data storage at: {url}"))
+}
+
+async fn export(config: Config, passphrase: Passphrase, args: ExportArgs) -> anyhow::Result<()> {
+    let user_id = signal::registered_user_id(&config, &passphrase).await?;
+    let storage = open_storage(&config, &passphrase).await?;
+    let attachment_files = AttachmentFiles::open(&config.data_dir, &passphrase)
+        .context("failed to open attachment files")?;
+
+    // attachments are copied next to the export file
+    let copies_dir = args.output.as_ref().map(|path| {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{name}_files"))
+    });
+    let base_dir = args.output.as_ref().and_then(|path| path.parent());
+    let options = ExportOptions {
+        channel: &args.channel,
+        since: args.since,
+        until: args.until,
+        data_dir: &config.data_dir,
+        user: (user_id, &config.user.display_name),
+        attachments: copies_dir.as_ref().map(|dir| AttachmentCopies {
+            dir,
+            base_dir: base_dir.unwrap_or(Path::new("")),
+            files: &attachment_files,
+        }),
+    };
+    let mut out: BufWriter<Box<dyn Write>> = match &args.output {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("failed to create export file: {}", path.display()))?;
+            BufWriter::new(Box::new(file))
+        }
+        None => BufWriter::new(Box::new(std::io::stdout().lock())),
+    };
+    gurk::export::export(&storage, &options, args.format, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+async fn import(config: Config, passphrase: Passphrase, args: ImportArgs) -> anyhow::Result<()> {
+    // the running app caches the channels and would overwrite the imported ones
+    let _lock = DataDirLock::acquire(&config.data_dir).context("quit gurk before importing")?;
+    let user_id = signal::registered_user_id(&config, &passphrase).await?;
+    let attachment_files = AttachmentFiles::open(&config.data_dir, &passphrase)
+        .context("failed to open attachment files")?;
+    let mut storage = open_storage(&config, &passphrase).await?;
+
+    let files_dir = match args.files {
+        Some(files_dir) => files_dir,
+        None => args.path.with_file_name("files"),
+    };
+    let options = ImportOptions {
+        files_dir: &files_dir,
+        data_dir: &config.data_dir,
+        attachment_files: &attachment_files,
+        user: (user_id, &config.user.display_name),
+    };
+    let file = File::open(&args.path)
+        .with_context(|| format!("failed to open backup: {}", args.path.display()))?;
+    let summary = gurk::import::import(&mut storage, &options, BufReader::new(file))?;
+    println!("{summary}");
+    Ok(())
+}
+
+async fn gc_attachments(
+    config: Config,
+    passphrase: Passphrase,
+    args: GcAttachmentsArgs,
+) -> anyhow::Result<()> {
+    // the app would keep referencing the removed copies of attachments in its cache
+    let _lock = DataDirLock::acquire(&config.data_dir)
+        .context("quit gurk before collecting the garbage of attachments")?;
+    let mut storage = open_storage(&config, &passphrase).await?;
+    let options = GcOptions {
+        data_dir: &config.data_dir,
+        dry_run: args.dry_run,
+    };
+    let report = gurk::attachment_gc::collect_garbage(&mut storage, &options)?;
+    if args.dry_run {
+        for path in report.duplicates.iter().chain(&report.unreferenced) {
+            println!("would remove {}", path.display());
+        }
+    }
+    println!("{report}");
+    Ok(())
 }
 
 async fn is_online() -> bool {
@@ -114,36 +288,25 @@ pub enum Event {
     Quit(Option<anyhow::Error>),
     ContactSynced(DateTime<Utc>),
     Tick,
+    Connected,
+    Disconnected,
     AppEvent(gurk::event::Event),
 }
 
 async fn run(config: Config, passphrase: Passphrase, relink: bool) -> anyhow::Result<()> {
+    let _lock = DataDirLock::acquire(&config.data_dir)?;
     let local_pool = LocalPool::new();
 
     let mut signal_manager =
         signal::ensure_linked_device(relink, local_pool.clone(), &config, &passphrase).await?;
 
-    let mut storage: Box<dyn Storage> = {
-        let url = match config
-            .sqlite
-            .as_ref()
-            .map(|sqlite_config| sqlite_config.url.clone())
-        {
-            Some(url) => url,
-            None => Url::from_file_path(config.gurk_db_path())
-                .map_err(|_| anyhow!("failed to convert gurk db path to url"))?,
-        };
-
-        debug!(%url, "opening sqlite data storage");
-        let sqlite_storage = SqliteStorage::maybe_encrypt_and_open(&url, &passphrase, false)
-            .await
-            .with_context(|| format!("failed to open sqlite data storage at: {url}"))?;
-        Box::new(MemCache::new(sqlite_storage))
-    };
+    let sqlite_storage = open_storage(&config, &passphrase).await?;
+    let mut storage: Box<dyn Storage> = Box::new(MemCache::new(sqlite_storage.clone()));
 
     sync_from_signal(&*signal_manager, &mut *storage).await;
 
     let (mut app, mut app_events) = App::try_new(config, signal_manager.clone_boxed(), storage)?;
+    app.set_attachments_gc_storage(sqlite_storage);
     app.populate_names_cache().await;
 
     let (tx, mut rx) = tokio::sync::mpsc::channel::<Event>(100);
@@ -177,12 +340,20 @@ async fn run(config: Config, passphrase: Passphrase, relink: bool) -> anyhow::Re
         let mut backoff = Backoff::new();
         loop {
             let mut messages = if !is_online().await {
+                inner_tx
+                    .send(Event::Disconnected)
+                    .await
+                    .expect("logic error: events channel closed");
                 tokio::time::sleep(std::time::Duration::from_secs(10)).await;
                 continue;
             } else {
                 match signal_manager.receive_messages().await {
                     Ok(messages) => {
                         info!("connected and listening for incoming messages");
+                        inner_tx
+                            .send(Event::Connected)
+                            .await
+                            .expect("logic error: events channel closed");
                         messages
                     }
                     Err(e) => {
@@ -208,6 +379,10 @@ async fn run(config: Config, passphrase: Passphrase, relink: bool) -> anyhow::Re
                     .expect("logic error: events channel closed")
             }
 
+            inner_tx
+                .send(Event::Disconnected)
+                .await
+                .expect("logic error: events channel closed");
             let after = backoff.get();
             error!(?after, "messages channel disconnected. trying to reconnect");
             tokio::time::sleep(after).await;
@@ -228,11 +403,11 @@ async fn run(config: Config, passphrase: Passphrase, relink: bool) -> anyhow::Re
 
     let mut res = Ok(()); // result on quit
     let mut last_render_at = Instant::now();
-    let mut last_save_at = Instant::now();
     let is_render_spawned = Arc::new(AtomicBool::new(false));
 
     let tick_tx = tx.clone();
-    // Tick to trigger receipt sending
+    // Tick to trigger marking of shown messages as read, receipt sending, stopping of idle typing,
+    // purging of expired messages, retrying of failed messages and garbage collection of attachments
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(RECEIPT_BUDGET);
         loop {
@@ -263,7 +438,15 @@ async fn run(config: Config, passphrase: Passphrase, relink: bool) -> anyhow::Re
                 });
             }
         } else {
-            terminal.draw(|f| ui::draw(f, &mut app))?;
+            let mut shown_messages = Vec::new();
+            terminal.draw(|f| shown_messages = ui::draw(f, &mut app))?;
+            if app.previews.take_needs_redraw() {
+                // moved or hidden image previews stay on the screen until cleared
+                app.previews.clear_graphics(terminal.backend_mut())?;
+                terminal.clear()?;
+                terminal.draw(|f| shown_messages = ui::draw(f, &mut app))?;
+            }
+            app.set_shown_messages(shown_messages);
             last_render_at = Instant::now();
         }
 
@@ -274,7 +457,18 @@ async fn run(config: Config, passphrase: Passphrase, relink: bool) -> anyhow::Re
 
         match event {
             Some(Event::Tick) => {
+                app.step_read();
                 app.step_receipts();
+                app.step_typing();
+                app.step_expiry();
+                app.step_outbox();
+                app.step_attachments_gc();
+            }
+            Some(Event::Connected) => {
+                app.on_connected();
+            }
+            Some(Event::Disconnected) => {
+                app.on_disconnected();
             }
             Some(Event::Click(event)) => match event.kind {
                 MouseEventKind::Down(MouseButton::Left) => {
@@ -285,7 +479,7 @@ async fn run(config: Config, passphrase: Passphrase, relink: bool) -> anyhow::Re
                             .map(|(_, row)| row as usize)
                             .filter(|&idx| idx < app.channels.items.len())
                     {
-                        app.channels.state.select(Some(channel_idx));
+                        app.select_channel_idx(channel_idx);
                         app.reset_unread_messages();
                     }
                 }
@@ -389,16 +583,15 @@ async fn run(config: Config, passphrase: Passphrase, relink: bool) -> anyhow::Re
             }
         }
 
-        if last_save_at.elapsed() > SAVE_BUDGET || app.should_quit {
-            app.storage.save();
-            last_save_at = Instant::now();
-        }
-
         if app.should_quit {
+            app.save_draft();
             break;
         }
     }
 
+    // writes are committed in the background; wait for the remaining ones
+    app.storage.save();
+
     execute!(
         terminal.backend_mut(),
         LeaveAlternateScreen,
@@ -0,0 +1,443 @@
+//! Markdown-like markup of outgoing messages
+//!
+//! Supported styles:
+//!
+//! * `*bold*`
+//! * `_italic_`
+//! * `~strikethrough~`
+//! * `` `monospace` ``
+//! * `||spoiler||`
+//!
+//! A marker opens a style only if it is followed by a non-whitespace character and is not
+//! preceded by an alphanumeric character. Symmetrically, a marker closes a style only if it is
+//! preceded by a non-whitespace character and is not followed by an alphanumeric character. This
+//! way, `snake_case_words` or `2 * 3 * 4` are left as is. Markers which are not matched are
+//! kept in the text. A marker character can be escaped by a backslash, e.g. `\*`; a literal
+//! backslash is written as `\\`. The content of a monospace span is taken verbatim.
+//!
+//! Mentions chosen in the input are written as `@name`. They are not subject to markup and are
+//! sent as the object replacement character with a mention range.
+
+use uuid::Uuid;
+
+use crate::data::{AssociatedValue, BodyRange, Style};
+use crate::mention::Mention;
+
+/// Placeholder of a mention in the message text
+const MENTION_PLACEHOLDER: char = '\u{FFFC}';
+
+/// Parses the markup of the text
+///
+/// Returns the text with stripped markers and the style ranges in UTF-16 code units of the
+/// returned text.
+pub(crate) fn parse(text: &str) -> (String, Vec<BodyRange>) {
+    let mut tokens = tokenize(text);
+    pair_markers(&mut tokens);
+
+    let mut out = String::with_capacity(text.len());
+    let mut pos: usize = 0; // in UTF-16 code units
+    let mut open_at: Vec<usize> = Vec::new();
+    let mut ranges = Vec::new();
+    for token in tokens {
+        match token {
+            Token::Text(s) => {
+                pos += s.encode_utf16().count();
+                out.push_str(&s);
+            }
+            Token::Code(s) => {
+                let start = pos;
+                pos += s.encode_utf16().count();
+                out.push_str(&s);
+                ranges.extend(body_range(
+                    start,
+                    pos,
+                    AssociatedValue::Style(Style::Monospace),
+                ));
+            }
+            Token::Marker {
+                matched: Some(Matched::Open),
+                ..
+            } => open_at.push(pos),
+            Token::Marker {
+                marker,
+                matched: Some(Matched::Close),
+                ..
+            } => {
+                let start = open_at.pop().expect("logic error: unbalanced markers");
+                ranges.extend(body_range(
+                    start,
+                    pos,
+                    AssociatedValue::Style(marker.style()),
+                ));
+            }
+            Token::Marker {
+                marker,
+                matched: None,
+                ..
+            } => {
+                pos += marker.as_str().len(); // markers are ASCII
+                out.push_str(marker.as_str());
+            }
+        }
+    }
+
+    ranges.sort_by_key(|range| (range.start, std::cmp::Reverse(range.end)));
+    (out, ranges)
+}
+
+/// Parses the markup of the text and resolves the given mentions
+///
+/// Each `@name` of a mention is replaced by a placeholder and a mention range.
+pub(crate) fn parse_with_mentions(text: &str, mentions: &[Mention]) -> (String, Vec<BodyRange>) {
+    let (text, mentioned) = replace_mentions(text, mentions);
+    let (text, mut ranges) = parse(&text);
+
+    // markup keeps all placeholders in order
+    let mut mentioned = mentioned.into_iter();
+    let mut pos: usize = 0; // in UTF-16 code units
+    for c in text.chars() {
+        if c == MENTION_PLACEHOLDER
+            && let Some(user_id) = mentioned.next()
+        {
+            ranges.extend(body_range(
+                pos,
+                pos + 1,
+                AssociatedValue::MentionUuid(user_id),
+            ));
+        }
+        pos += c.len_utf16();
+    }
+
+    ranges.sort_by_key(|range| (range.start, std::cmp::Reverse(range.end)));
+    (text, ranges)
+}
+
+/// Replaces `@name` of mentions by placeholders
+///
+/// Returns the mentioned users in order of their placeholders. Placeholders which are already
+/// in the text are dropped, since they can't be resolved to a user.
+fn replace_mentions(text: &str, mentions: &[Mention]) -> (String, Vec<Uuid>) {
+    // prefer the longest name, e.g. `@Alice Smith` over `@Alice`
+    let mut mentions: Vec<&Mention> = mentions.iter().collect();
+    mentions.sort_unstable_by_key(|mention| std::cmp::Reverse(mention.name.len()));
+
+    let mut out = String::with_capacity(text.len());
+    let mut mentioned = Vec::new();
+    let mut prev: Option<char> = None;
+    let mut chars = text.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        if c == '@' && !prev.is_some_and(char::is_alphanumeric) {
+            let rest = &text[idx + 1..];
+            let mention = mentions.iter().find(|mention| {
+                !mention.name.is_empty()
+                    && rest
+                        .strip_prefix(mention.name.as_str())
+                        .is_some_and(|after| !after.starts_with(char::is_alphanumeric))
+            });
+            if let Some(mention) = mention {
+                out.push(MENTION_PLACEHOLDER);
+                mentioned.push(mention.user_id);
+                prev = mention.name.chars().next_back();
+                chars.nth(mention.name.chars().count().saturating_sub(1));
+                continue;
+            }
+        }
+        if c != MENTION_PLACEHOLDER {
+            out.push(c);
+        }
+        prev = Some(c);
+    }
+    (out, mentioned)
+}
+
+fn body_range(start: usize, end: usize, value: AssociatedValue) -> Option<BodyRange> {
+    Some(BodyRange {
+        start: start.try_into().ok()?,
+        end: end.try_into().ok()?,
+        value,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Marker {
+    Bold,
+    Italic,
+    Strikethrough,
+    Spoiler,
+}
+
+impl Marker {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Bold => "*",
+            Self::Italic => "_",
+            Self::Strikethrough => "~",
+            Self::Spoiler => "||",
+        }
+    }
+
+    fn style(&self) -> Style {
+        match self {
+            Self::Bold => Style::Bold,
+            Self::Italic => Style::Italic,
+            Self::Strikethrough => Style::Strikethrough,
+            Self::Spoiler => Style::Spoiler,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Matched {
+    Open,
+    Close,
+}
+
+#[derive(Debug)]
+enum Token {
+    Text(String),
+    /// Verbatim content of a monospace span
+    Code(String),
+    Marker {
+        marker: Marker,
+        can_open: bool,
+        can_close: bool,
+        matched: Option<Matched>,
+    },
+}
+
+const ESCAPABLE: &[char] = &['*', '_', '~', '`', '|', '\\'];
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let marker = match c {
+            '\\' if chars.get(i + 1).is_some_and(|c| ESCAPABLE.contains(c)) => {
+                literal.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '`' if can_open(&chars, i, 1) => {
+                let closing =
+                    (i + 2..chars.len()).find(|&j| chars[j] == '`' && can_close(&chars, j, 1));
+                if let Some(j) = closing {
+                    flush(&mut literal, &mut tokens);
+                    tokens.push(Token::Code(chars[i + 1..j].iter().collect()));
+                    i = j + 1;
+                    continue;
+                }
+                None
+            }
+            '*' => Some(Marker::Bold),
+            '_' => Some(Marker::Italic),
+            '~' => Some(Marker::Strikethrough),
+            '|' if chars.get(i + 1) == Some(&'|') => Some(Marker::Spoiler),
+            _ => None,
+        };
+
+        match marker {
+            Some(marker) => {
+                let len = marker.as_str().len();
+                let can_open = can_open(&chars, i, len);
+                let can_close = can_close(&chars, i, len);
+                if can_open || can_close {
+                    flush(&mut literal, &mut tokens);
+                    tokens.push(Token::Marker {
+                        marker,
+                        can_open,
+                        can_close,
+                        matched: None,
+                    });
+                } else {
+                    literal.push_str(marker.as_str());
+                }
+                i += len;
+            }
+            None => {
+                literal.push(c);
+                i += 1;
+            }
+        }
+    }
+    flush(&mut literal, &mut tokens);
+    tokens
+}
+
+fn flush(literal: &mut String, tokens: &mut Vec<Token>) {
+    if !literal.is_empty() {
+        tokens.push(Token::Text(std::mem::take(literal)));
+    }
+}
+
+/// Marker of length `len` at `i` is followed by a non-whitespace and not preceded by alphanumeric
+fn can_open(chars: &[char], i: usize, len: usize) -> bool {
+    let prev = i.checked_sub(1).map(|j| chars[j]);
+    let next = chars.get(i + len);
+    next.is_some_and(|c| !c.is_whitespace()) && !prev.is_some_and(char::is_alphanumeric)
+}
+
+/// Marker of length `len` at `i` is preceded by a non-whitespace and not followed by alphanumeric
+fn can_close(chars: &[char], i: usize, len: usize) -> bool {
+    let prev = i.checked_sub(1).map(|j| chars[j]);
+    let next = chars.get(i + len);
+    prev.is_some_and(|c| !c.is_whitespace()) && !next.is_some_and(|c| c.is_alphanumeric())
+}
+
+/// Matches closing markers with the nearest opening marker of the same kind
+///
+/// Opening markers between a matched pair stay unmatched.
+fn pair_markers(tokens: &mut [Token]) {
+    let mut openers: Vec<(usize, Marker)> = Vec::new();
+    for idx in 0..tokens.len() {
+        let Token::Marker {
+            marker,
+            can_open,
+            can_close,
+            ..
+        } = tokens[idx]
+        else {
+            continue;
+        };
+
+        if can_close
+            && let Some(opener_pos) = openers.iter().rposition(|&(_, m)| m == marker)
+            // empty styles are not allowed
+            && openers[opener_pos].0 + 1 < idx
+        {
+            let (opener_idx, _) = openers[opener_pos];
+            openers.truncate(opener_pos);
+            set_matched(&mut tokens[opener_idx], Matched::Open);
+            set_matched(&mut tokens[idx], Matched::Close);
+            continue;
+        }
+        if can_open {
+            openers.push((idx, marker));
+        }
+    }
+}
+
+fn set_matched(token: &mut Token, value: Matched) {
+    if let Token::Marker { matched, .. } = token {
+        *matched = Some(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(start: u16, end: u16, style: Style) -> BodyRange {
+        BodyRange {
+            start,
+            end,
+            value: AssociatedValue::Style(style),
+        }
+    }
+
+    #[test]
+    fn test_parse_plain_text() {
+        let (text, ranges) = parse("Hello, World!");
+        assert_eq!(text, "Hello, World!");
+        assert_eq!(ranges, []);
+    }
+
+    #[test]
+    fn test_parse_styles() {
+        let (text, ranges) = parse("*bold* _italic_ ~strike~ `mono` ||spoiler||");
+        assert_eq!(text, "bold italic strike mono spoiler");
+        assert_eq!(
+            ranges,
+            [
+                style(0, 4, Style::Bold),
+                style(5, 11, Style::Italic),
+                style(12, 18, Style::Strikethrough),
+                style(19, 23, Style::Monospace),
+                style(24, 31, Style::Spoiler),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_nested_styles() {
+        let (text, ranges) = parse("*bold _and italic_*");
+        assert_eq!(text, "bold and italic");
+        assert_eq!(
+            ranges,
+            [style(0, 15, Style::Bold), style(5, 15, Style::Italic)]
+        );
+    }
+
+    #[test]
+    fn test_parse_utf16_offsets() {
+        let (text, ranges) = parse("😀 *ü*");
+        assert_eq!(text, "😀 ü");
+        // the emoji is encoded as a surrogate pair
+        assert_eq!(ranges, [style(3, 4, Style::Bold)]);
+    }
+
+    #[test]
+    fn test_parse_unmatched_markers() {
+        let (text, ranges) = parse("2 * 3 * 4 and snake_case_word and *open");
+        assert_eq!(text, "2 * 3 * 4 and snake_case_word and *open");
+        assert_eq!(ranges, []);
+
+        let (text, ranges) = parse("** and ||||");
+        assert_eq!(text, "** and ||||");
+        assert_eq!(ranges, []);
+    }
+
+    #[test]
+    fn test_parse_escaped_markers() {
+        let (text, ranges) = parse(r"\*not bold\* and \\ and \a");
+        assert_eq!(text, r"*not bold* and \ and \a");
+        assert_eq!(ranges, []);
+    }
+
+    #[test]
+    fn test_parse_with_mentions() {
+        let alice = Uuid::from_u128(1);
+        let alice_smith = Uuid::from_u128(2);
+        let mentions = [
+            Mention {
+                name: "Alice".to_string(),
+                user_id: alice,
+            },
+            Mention {
+                name: "Alice Smith".to_string(),
+                user_id: alice_smith,
+            },
+        ];
+        let (text, ranges) = parse_with_mentions(
+            "*hi @Alice Smith* and @Alice, not @Alicia or me@Alice \u{FFFC}",
+            &mentions,
+        );
+        assert_eq!(text, "hi \u{FFFC} and \u{FFFC}, not @Alicia or me@Alice ");
+        let mention = |start, user_id| BodyRange {
+            start,
+            end: start + 1,
+            value: AssociatedValue::MentionUuid(user_id),
+        };
+        assert_eq!(
+            ranges,
+            [
+                style(0, 4, Style::Bold),
+                mention(3, alice_smith),
+                mention(9, alice),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_monospace_is_verbatim() {
+        let (text, ranges) = parse("`*not bold*` *bold*");
+        assert_eq!(text, "*not bold* bold");
+        assert_eq!(
+            ranges,
+            [style(0, 10, Style::Monospace), style(11, 15, Style::Bold)]
+        );
+    }
+}
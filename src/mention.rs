@@ -0,0 +1,98 @@
+//! Completion of `@mentions` in the input box of group channels
+
+use ratatui::widgets::ListState;
+use uuid::Uuid;
+
+/// User mentioned as `@name` in the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mention {
+    pub name: String,
+    pub user_id: Uuid,
+}
+
+/// List of group members matching the `@` query in front of the cursor
+#[derive(Default)]
+pub(crate) struct MentionCompletion {
+    pub state: ListState,
+    /// Byte position of `@` in the input
+    start: usize,
+    candidates: Vec<Mention>,
+}
+
+impl MentionCompletion {
+    pub fn is_shown(&self) -> bool {
+        !self.candidates.is_empty()
+    }
+
+    pub fn show(&mut self, start: usize, candidates: Vec<Mention>) {
+        self.start = start;
+        self.candidates = candidates;
+        self.state.select(self.candidates.first().map(|_| 0));
+    }
+
+    /// Returns `true` if the completion was shown before
+    pub fn hide(&mut self) -> bool {
+        let is_shown = self.is_shown();
+        self.candidates.clear();
+        self.state = Default::default();
+        is_shown
+    }
+
+    pub fn prev(&mut self) {
+        let selected = self
+            .state
+            .selected()
+            .map(|idx| idx.saturating_sub(1))
+            .unwrap_or(0);
+        self.state.select(Some(selected));
+    }
+
+    pub fn next(&mut self) {
+        let last = self.candidates.len().saturating_sub(1);
+        let selected = self
+            .state
+            .selected()
+            .map(|idx| (idx + 1).min(last))
+            .unwrap_or(0);
+        self.state.select(Some(selected));
+    }
+
+    pub fn candidates(&self) -> &[Mention] {
+        &self.candidates
+    }
+
+    /// Returns the position of `@` in the input and the selected candidate
+    pub fn selected(&self) -> Option<(usize, &Mention)> {
+        let idx = self.state.selected()?;
+        Some((self.start, self.candidates.get(idx)?))
+    }
+}
+
+/// Returns the position of `@` and the query following it if the cursor is in a mention
+///
+/// The `@` must start a word and the query must not contain whitespace.
+pub(crate) fn query_at(text: &str, cursor_idx: usize) -> Option<(usize, &str)> {
+    let before_cursor = text.get(..cursor_idx)?;
+    let start = before_cursor.rfind('@')?;
+    let query = &before_cursor[start + 1..];
+    let is_word_start = before_cursor[..start]
+        .chars()
+        .next_back()
+        .is_none_or(char::is_whitespace);
+    (is_word_start && !query.contains(char::is_whitespace)).then_some((start, query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_at() {
+        assert_eq!(query_at("@", 1), Some((0, "")));
+        assert_eq!(query_at("hi @al", 6), Some((3, "al")));
+        assert_eq!(query_at("hi @al", 5), Some((3, "a")));
+        assert_eq!(query_at("hi @al x", 8), None);
+        assert_eq!(query_at("mail@example", 12), None);
+        assert_eq!(query_at("no mention", 10), None);
+    }
+}
@@ -0,0 +1,627 @@
+//! Inline previews of image attachments
+//!
+//! Images are decoded once into thumbnails in the background, which are cached by attachment id.
+//! Until a thumbnail is decoded, a placeholder line is drawn instead. A preview is drawn with the
+//! graphics protocol of the terminal (kitty or sixel), or approximated by colored unicode half
+//! blocks, which works in every terminal with true colors.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write as _;
+use std::io::{self, Cursor, Write};
+use std::rc::Rc;
+use std::task::Poll;
+
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use image::imageops::FilterType;
+use image::{ImageFormat, Rgb, RgbaImage};
+use ratatui::layout::Rect;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::attachment_files::AttachmentFiles;
+use crate::event::Event;
+use crate::signal::Attachment;
+
+/// Maximum width and height of a decoded thumbnail in pixels
+const MAX_THUMBNAIL_SIZE: u32 = 512;
+/// Maximum number of cached thumbnails
+const MAX_CACHED_THUMBNAILS: usize = 64;
+/// Size of a terminal cell in pixels if the terminal does not report it
+const DEFAULT_CELL_SIZE: (u16, u16) = (8, 16);
+/// Maximum size of the payload of a single kitty graphics command
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// Protocol used to draw images in the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphicsProtocol {
+    /// Kitty graphics protocol
+    Kitty,
+    /// Sixel graphics
+    Sixel,
+    /// Unicode half blocks with foreground and background colors
+    HalfBlocks,
+}
+
+impl GraphicsProtocol {
+    /// Guesses the protocol supported by the terminal from the environment
+    pub fn detect() -> Self {
+        Self::detect_from(|name| std::env::var(name).ok())
+    }
+
+    fn detect_from(var: impl Fn(&str) -> Option<String>) -> Self {
+        // graphics are not passed through by terminal multiplexers by default
+        if var("TMUX").is_some() || var("STY").is_some() {
+            return Self::HalfBlocks;
+        }
+        let term = var("TERM").unwrap_or_default();
+        let term_program = var("TERM_PROGRAM").unwrap_or_default();
+        if var("KITTY_WINDOW_ID").is_some()
+            || term.contains("kitty")
+            || term.contains("ghostty")
+            || matches!(term_program.as_str(), "WezTerm" | "ghostty")
+        {
+            Self::Kitty
+        } else if ["foot", "mlterm", "sixel", "contour"]
+            .iter()
+            .any(|name| term.contains(name))
+            || matches!(term_program.as_str(), "iTerm.app" | "contour")
+        {
+            Self::Sixel
+        } else {
+            Self::HalfBlocks
+        }
+    }
+}
+
+/// Preview of an image laid out in terminal cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreviewSize {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+/// Content of a preview drawn by the UI
+#[derive(Debug, PartialEq, Eq)]
+pub enum PreviewContent {
+    /// Colors of the upper and lower half of each cell, row by row
+    HalfBlocks(Vec<Vec<(Rgb<u8>, Rgb<u8>)>>),
+    /// Escape sequence drawing the image at the cursor position
+    Graphics(String),
+    /// Single line shown while the image is decoded
+    Placeholder,
+}
+
+struct Thumbnail {
+    image: RgbaImage,
+    /// Content of the last drawn preview and its size
+    content: Option<(PreviewSize, Rc<PreviewContent>)>,
+}
+
+#[derive(Default)]
+struct Thumbnails {
+    /// Thumbnails by attachment id; `None` if the attachment could not be decoded
+    by_id: HashMap<String, Option<Thumbnail>>,
+    /// Attachment ids in the order the thumbnails were decoded
+    order: VecDeque<String>,
+    /// Attachment ids of the thumbnails which are decoded in the background
+    decoding: HashSet<String>,
+}
+
+/// Cache of decoded thumbnails and state of the previews drawn on the screen
+pub struct ImagePreviews {
+    protocol: GraphicsProtocol,
+    /// Maximum height of a preview in rows
+    max_height: u16,
+    /// Size of a terminal cell in pixels
+    cell_size: (u16, u16),
+    thumbnails: RefCell<Thumbnails>,
+    /// Areas of the graphics drawn in the last frame
+    placements: Vec<Rect>,
+    needs_redraw: bool,
+    files: AttachmentFiles,
+    /// Receives the decoded thumbnails as [`Event::ThumbnailDecoded`]
+    event_tx: mpsc::UnboundedSender<Event>,
+}
+
+impl ImagePreviews {
+    pub fn new(
+        protocol: GraphicsProtocol,
+        max_height: u16,
+        files: AttachmentFiles,
+        event_tx: mpsc::UnboundedSender<Event>,
+    ) -> Self {
+        debug!(?protocol, "drawing image previews");
+        Self {
+            protocol,
+            max_height: max_height.max(1),
+            cell_size: DEFAULT_CELL_SIZE,
+            thumbnails: Default::default(),
+            placements: Vec::new(),
+            needs_redraw: false,
+            files,
+            event_tx,
+        }
+    }
+
+    pub fn protocol(&self) -> GraphicsProtocol {
+        self.protocol
+    }
+
+    /// Updates the size of a terminal cell in pixels as reported by the terminal
+    pub fn update_cell_size(&mut self) {
+        self.cell_size = crossterm::terminal::window_size()
+            .ok()
+            .filter(|size| size.columns > 0 && size.rows > 0)
+            .map(|size| (size.width / size.columns, size.height / size.rows))
+            .filter(|&(width, height)| width > 0 && height > 0)
+            .unwrap_or(DEFAULT_CELL_SIZE);
+    }
+
+    /// Preview of the image attachment fitting into `max_cols` columns
+    ///
+    /// Returns `None` if the attachment is not an image or could not be decoded. If the image is
+    /// not decoded yet, its decoding is started in the background, and a placeholder is returned.
+    pub fn preview(
+        &self,
+        attachment: &Attachment,
+        max_cols: u16,
+    ) -> Option<(PreviewSize, Rc<PreviewContent>)> {
+        if !attachment.content_type.starts_with("image/") || max_cols == 0 {
+            return None;
+        }
+        let mut thumbnails = self.thumbnails.borrow_mut();
+        let Poll::Ready(thumbnail) =
+            thumbnails.get_or_decode(attachment, || self.spawn_decode(attachment))
+        else {
+            let size = PreviewSize {
+                cols: max_cols,
+                rows: 1,
+            };
+            return Some((size, Rc::new(PreviewContent::Placeholder)));
+        };
+        let thumbnail = thumbnail?;
+        let size = fit(
+            thumbnail.image.dimensions(),
+            self.cell_size,
+            max_cols,
+            self.max_height,
+        );
+        if let Some((cached_size, content)) = &thumbnail.content
+            && *cached_size == size
+        {
+            return Some((size, content.clone()));
+        }
+        let content = Rc::new(match self.protocol {
+            GraphicsProtocol::HalfBlocks => half_blocks(&thumbnail.image, size),
+            GraphicsProtocol::Kitty => PreviewContent::Graphics(kitty(&thumbnail.image, size)),
+            GraphicsProtocol::Sixel => {
+                let (cell_width, cell_height) = self.cell_size;
+                let width = u32::from(size.cols) * u32::from(cell_width);
+                let height = u32::from(size.rows) * u32::from(cell_height);
+                let image =
+                    image::imageops::resize(&thumbnail.image, width, height, FilterType::Triangle);
+                PreviewContent::Graphics(sixel(&image))
+            }
+        });
+        thumbnail.content = Some((size, content.clone()));
+        Some((size, content))
+    }
+
+    /// Caches the thumbnail decoded in the background
+    pub fn insert_thumbnail(&mut self, id: String, image: Option<RgbaImage>) {
+        let thumbnail = image.map(|image| Thumbnail {
+            image,
+            content: None,
+        });
+        self.thumbnails.get_mut().insert(id, thumbnail);
+    }
+
+    fn spawn_decode(&self, attachment: &Attachment) {
+        let attachment = attachment.clone();
+        let files = self.files.clone();
+        let tx = self.event_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            let image = decode(&attachment, &files);
+            // the app is gone if the receiver is dropped
+            let _ = tx.send(Event::ThumbnailDecoded {
+                id: attachment.id,
+                image,
+            });
+        });
+    }
+
+    /// Remembers the areas of the graphics drawn in the current frame
+    ///
+    /// Text drawn over graphics does not erase them, therefore the screen needs to be redrawn
+    /// from scratch when the graphics moved.
+    pub fn set_placements(&mut self, placements: Vec<Rect>) {
+        if self.placements != placements {
+            self.needs_redraw = !self.placements.is_empty();
+            self.placements = placements;
+        }
+    }
+
+    /// Whether the screen must be cleared and redrawn because graphics moved
+    pub fn take_needs_redraw(&mut self) -> bool {
+        std::mem::take(&mut self.needs_redraw)
+    }
+
+    /// Removes the drawn graphics from the screen
+    pub fn clear_graphics(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.protocol == GraphicsProtocol::Kitty {
+            // delete all placements and free the image data
+            out.write_all(b"\x1b_Ga=d,d=A,q=2\x1b\\")?;
+            out.flush()?;
+        }
+        self.placements.clear();
+        Ok(())
+    }
+}
+
+impl Thumbnails {
+    /// Gets the cached thumbnail, or calls `spawn_decode` unless it is already being decoded
+    fn get_or_decode(
+        &mut self,
+        attachment: &Attachment,
+        spawn_decode: impl FnOnce(),
+    ) -> Poll<Option<&mut Thumbnail>> {
+        if !self.by_id.contains_key(&attachment.id) {
+            if self.decoding.insert(attachment.id.clone()) {
+                spawn_decode();
+            }
+            return Poll::Pending;
+        }
+        Poll::Ready(self.by_id.get_mut(&attachment.id).and_then(Option::as_mut))
+    }
+
+    fn insert(&mut self, id: String, thumbnail: Option<Thumbnail>) {
+        self.decoding.remove(&id);
+        if self.by_id.contains_key(&id) {
+            return;
+        }
+        if self.order.len() >= MAX_CACHED_THUMBNAILS
+            && let Some(evicted) = self.order.pop_front()
+        {
+            self.by_id.remove(&evicted);
+        }
+        self.order.push_back(id.clone());
+        self.by_id.insert(id, thumbnail);
+    }
+}
+
+fn decode(attachment: &Attachment, files: &AttachmentFiles) -> Option<RgbaImage> {
+    let image = files
+        .read(&attachment.filename)
+        .and_then(|data| {
+            let image = image::ImageReader::new(Cursor::new(data))
+                .with_guessed_format()?
+                .decode()?;
+            Ok(image)
+        })
+        .inspect_err(|error| {
+            warn!(%error, path =% attachment.filename.display(), "failed to decode image");
+        })
+        .ok()?;
+    let image = if image.width() > MAX_THUMBNAIL_SIZE || image.height() > MAX_THUMBNAIL_SIZE {
+        image.thumbnail(MAX_THUMBNAIL_SIZE, MAX_THUMBNAIL_SIZE)
+    } else {
+        image
+    };
+    Some(image.into_rgba8())
+}
+
+/// Size in cells of an image with the given pixel dimensions
+///
+/// The image is scaled down to fit, but never scaled up. It is at least one cell large, even if
+/// there is no space for it, e.g. in a very narrow terminal.
+fn fit(
+    (width, height): (u32, u32),
+    (cell_width, cell_height): (u16, u16),
+    max_cols: u16,
+    max_rows: u16,
+) -> PreviewSize {
+    let max_cols = max_cols.max(1);
+    let max_rows = max_rows.max(1);
+    let cols = f64::from(width) / f64::from(cell_width);
+    let rows = f64::from(height) / f64::from(cell_height);
+    let scale = (f64::from(max_cols) / cols)
+        .min(f64::from(max_rows) / rows)
+        .min(1.0);
+    PreviewSize {
+        cols: ((cols * scale).round() as u16).clamp(1, max_cols),
+        rows: ((rows * scale).round() as u16).clamp(1, max_rows),
+    }
+}
+
+/// Composes the pixel over a black background
+fn opaque(pixel: image::Rgba<u8>) -> Rgb<u8> {
+    let [r, g, b, a] = pixel.0;
+    let blend = |c: u8| (u16::from(c) * u16::from(a) / 255) as u8;
+    Rgb([blend(r), blend(g), blend(b)])
+}
+
+fn half_blocks(image: &RgbaImage, size: PreviewSize) -> PreviewContent {
+    let image = image::imageops::resize(
+        image,
+        u32::from(size.cols),
+        2 * u32::from(size.rows),
+        FilterType::Triangle,
+    );
+    let rows = (0..u32::from(size.rows))
+        .map(|row| {
+            (0..u32::from(size.cols))
+                .map(|col| {
+                    let upper = opaque(*image.get_pixel(col, 2 * row));
+                    let lower = opaque(*image.get_pixel(col, 2 * row + 1));
+                    (upper, lower)
+                })
+                .collect()
+        })
+        .collect();
+    PreviewContent::HalfBlocks(rows)
+}
+
+/// Kitty graphics command transmitting the image as PNG and displaying it scaled to the size
+fn kitty(image: &RgbaImage, size: PreviewSize) -> String {
+    let mut png = Vec::new();
+    if let Err(error) = image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png) {
+        warn!(%error, "failed to encode image as png");
+        return String::new();
+    }
+    let payload = BASE64.encode(png);
+    let mut chunks = payload.as_bytes().chunks(KITTY_CHUNK_SIZE).peekable();
+    let mut out = String::new();
+    let mut is_first = true;
+    while let Some(chunk) = chunks.next() {
+        let more = u8::from(chunks.peek().is_some());
+        // chunks of a base64 string are valid utf-8
+        let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+        let written = if is_first {
+            // q=2 suppresses responses, C=1 keeps the cursor in place
+            write!(
+                out,
+                "\x1b_Ga=T,f=100,t=d,q=2,C=1,c={},r={},m={more};{chunk}\x1b\\",
+                size.cols, size.rows
+            )
+        } else {
+            write!(out, "\x1b_Gm={more};{chunk}\x1b\\")
+        };
+        written.expect("formatting kitty command failed");
+        is_first = false;
+    }
+    out
+}
+
+/// Index of the color in the 6x6x6 color cube of the sixel palette
+fn sixel_color(pixel: image::Rgba<u8>) -> u8 {
+    let Rgb([r, g, b]) = opaque(pixel);
+    let level = |c: u8| ((u16::from(c) * 5 + 127) / 255) as u8;
+    level(r) * 36 + level(g) * 6 + level(b)
+}
+
+/// Sixel image with the colors quantized to a 6x6x6 color cube
+fn sixel(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let colors: Vec<u8> = image.pixels().map(|&pixel| sixel_color(pixel)).collect();
+
+    let mut out = format!("\x1bP0;1q\"1;1;{width};{height}");
+    for idx in 0..216u16 {
+        let percent = |level: u16| level * 100 / 5;
+        write!(
+            out,
+            "#{idx};2;{};{};{}",
+            percent(idx / 36),
+            percent(idx / 6 % 6),
+            percent(idx % 6)
+        )
+        .expect("formatting sixel palette failed");
+    }
+
+    let width = width as usize;
+    for band_start in (0..height as usize).step_by(6) {
+        let band_end = (band_start + 6).min(height as usize);
+        let mut band_colors: Vec<u8> = colors[band_start * width..band_end * width].to_vec();
+        band_colors.sort_unstable();
+        band_colors.dedup();
+        for (color_idx, &color) in band_colors.iter().enumerate() {
+            if color_idx > 0 {
+                // back to the start of the band
+                out.push('$');
+            }
+            write!(out, "#{color}").expect("formatting sixel color failed");
+            let sixels = (0..width).map(|x| {
+                let bits = (band_start..band_end)
+                    .enumerate()
+                    .filter(|&(_, y)| colors[y * width + x] == color)
+                    .fold(0, |bits, (dy, _)| bits | 1 << dy);
+                char::from(63 + bits)
+            });
+            push_run_length_encoded(&mut out, sixels);
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+fn push_run_length_encoded(out: &mut String, sixels: impl Iterator<Item = char>) {
+    fn push_run(out: &mut String, sixel: char, count: usize) {
+        if count > 3 {
+            write!(out, "!{count}{sixel}").expect("formatting sixel run failed");
+        } else {
+            out.extend(std::iter::repeat_n(sixel, count));
+        }
+    }
+    let mut run: Option<(char, usize)> = None;
+    for sixel in sixels {
+        match run.as_mut() {
+            Some((last, count)) if *last == sixel => *count += 1,
+            _ => {
+                if let Some((last, count)) = run {
+                    push_run(out, last, count);
+                }
+                run = Some((sixel, 1));
+            }
+        }
+    }
+    if let Some((last, count)) = run {
+        push_run(out, last, count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use image::Rgba;
+
+    use super::*;
+
+    fn detect(vars: &[(&str, &str)]) -> GraphicsProtocol {
+        let vars: BTreeMap<_, _> = vars.iter().copied().collect();
+        GraphicsProtocol::detect_from(|name| vars.get(name).map(|value| value.to_string()))
+    }
+
+    #[test]
+    fn test_detect_protocol() {
+        assert_eq!(detect(&[("TERM", "xterm-kitty")]), GraphicsProtocol::Kitty);
+        assert_eq!(
+            detect(&[("TERM", "xterm-256color"), ("TERM_PROGRAM", "WezTerm")]),
+            GraphicsProtocol::Kitty
+        );
+        assert_eq!(detect(&[("TERM", "foot")]), GraphicsProtocol::Sixel);
+        assert_eq!(
+            detect(&[("TERM", "xterm-256color")]),
+            GraphicsProtocol::HalfBlocks
+        );
+        assert_eq!(
+            detect(&[("TERM", "xterm-kitty"), ("TMUX", "/tmp/tmux-1000/default")]),
+            GraphicsProtocol::HalfBlocks
+        );
+    }
+
+    #[test]
+    fn test_fit() {
+        // never scaled up
+        assert_eq!(
+            fit((80, 64), (8, 16), 100, 10),
+            PreviewSize { cols: 10, rows: 4 }
+        );
+        // limited by the height
+        assert_eq!(
+            fit((400, 400), (8, 16), 100, 10),
+            PreviewSize { cols: 20, rows: 10 }
+        );
+        // limited by the width
+        assert_eq!(
+            fit((800, 160), (8, 16), 50, 10),
+            PreviewSize { cols: 50, rows: 5 }
+        );
+        assert_eq!(
+            fit((1, 1000), (8, 16), 50, 10),
+            PreviewSize { cols: 1, rows: 10 }
+        );
+        // no space left next to the prefix of the message
+        assert_eq!(
+            fit((80, 64), (8, 16), 0, 10),
+            PreviewSize { cols: 1, rows: 1 }
+        );
+    }
+
+    #[test]
+    fn test_half_blocks() {
+        let mut image = RgbaImage::new(1, 2);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        image.put_pixel(0, 1, Rgba([0, 0, 255, 128]));
+        let content = half_blocks(&image, PreviewSize { cols: 1, rows: 1 });
+        assert_eq!(
+            content,
+            PreviewContent::HalfBlocks(vec![vec![(Rgb([255, 0, 0]), Rgb([0, 0, 128]))]])
+        );
+    }
+
+    #[test]
+    fn test_sixel() {
+        let mut image = RgbaImage::from_pixel(5, 7, Rgba([0, 0, 0, 255]));
+        image.put_pixel(0, 0, Rgba([255, 255, 255, 255]));
+        image.put_pixel(0, 6, Rgba([255, 255, 255, 255]));
+        let sixel = sixel(&image);
+        assert!(sixel.starts_with("\x1bP0;1q\"1;1;5;7#0;2;0;0;0#1;2;0;0;20"));
+        let (_, bands) = sixel.split_once("#215;2;100;100;100").unwrap();
+        // first band: black except for the top left pixel, second band: single row
+        assert_eq!(bands, "#0}!4~$#215@!4?-#0?!4@$#215@!4?-\x1b\\");
+    }
+
+    #[test]
+    fn test_run_length_encoding() {
+        let mut out = String::new();
+        push_run_length_encoded(&mut out, "aaabbbbc".chars());
+        assert_eq!(out, "aaa!4bc");
+    }
+
+    #[test]
+    fn test_kitty_chunks() {
+        // noise does not compress, such that the image is sent in multiple chunks
+        let mut seed = 1u32;
+        let image = RgbaImage::from_fn(64, 64, |_, _| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            Rgba(seed.to_be_bytes())
+        });
+        let command = kitty(&image, PreviewSize { cols: 8, rows: 4 });
+        assert!(command.starts_with("\x1b_Ga=T,f=100,t=d,q=2,C=1,c=8,r=4,m=1;"));
+        assert!(command.ends_with("\x1b\\"));
+        let chunks: Vec<_> = command.split("\x1b_G").skip(1).collect();
+        assert!(chunks.len() > 1);
+        assert!(
+            chunks[..chunks.len() - 1]
+                .iter()
+                .all(|c| c.contains("m=1;"))
+        );
+        assert!(chunks.last().unwrap().contains("m=0;"));
+    }
+
+    #[tokio::test]
+    async fn test_preview_is_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename: PathBuf = dir.path().join("image.png");
+        RgbaImage::from_pixel(16, 32, Rgba([0, 255, 0, 255]))
+            .save(&filename)
+            .unwrap();
+        let attachment = Attachment {
+            id: "image".to_string(),
+            content_type: "image/png".to_string(),
+            filename,
+            size: 0,
+        };
+
+        let (tx, mut events) = mpsc::unbounded_channel();
+        let mut previews =
+            ImagePreviews::new(GraphicsProtocol::HalfBlocks, 10, Default::default(), tx);
+        // decoded in the background
+        let (_, placeholder) = previews.preview(&attachment, 80).unwrap();
+        assert_eq!(*placeholder, PreviewContent::Placeholder);
+        previews.preview(&attachment, 80).unwrap();
+        let Event::ThumbnailDecoded { id, image } = events.recv().await.unwrap() else {
+            panic!("unexpected event");
+        };
+        assert!(events.try_recv().is_err(), "decoded twice");
+        previews.insert_thumbnail(id, image);
+
+        let (size, content) = previews.preview(&attachment, 80).unwrap();
+        assert_eq!(size, PreviewSize { cols: 2, rows: 2 });
+        let (_, cached) = previews.preview(&attachment, 80).unwrap();
+        assert!(Rc::ptr_eq(&content, &cached));
+
+        let text = Attachment {
+            id: "text".to_string(),
+            content_type: "text/plain".to_string(),
+            ..attachment
+        };
+        assert!(previews.preview(&text, 80).is_none());
+    }
+}
@@ -136,7 +136,11 @@ impl ReceiptHandler {
         true
     }
 
-    pub fn step(&mut self, _signal_manager: &dyn SignalManager) -> bool {
+    /// Sends all queued receipts
+    ///
+    /// Receipts are batched per sender: at most one delivered and one read receipt message is
+    /// sent to each sender. Returns `true` if any receipts were sent.
+    pub fn step(&mut self, signal_manager: &dyn SignalManager) -> bool {
         if !self.do_tick() {
             return false;
         }
@@ -144,34 +148,20 @@ impl ReceiptHandler {
             return false;
         }
 
-        // For now, receipts are disabled
-        self.receipt_set.clear();
-        false
-
-        // // Get any key
-        // let uuid = *self.receipt_set.keys().next().unwrap();
-        //
-        // let j = self.receipt_set.entry(uuid);
-        // match j {
-        //     Entry::Occupied(mut e) => {
-        //         let u = e.get_mut();
-        //         if let Some((timestamps, receipt)) = u.get_data() {
-        //             signal_manager.send_receipt(uuid, timestamps, receipt);
-        //             if u.is_empty() {
-        //                 e.remove_entry();
-        //             }
-        //             true
-        //         } else {
-        //             false
-        //         }
-        //     }
-        //     Entry::Vacant(_) => false,
-        // }
+        for (uuid, mut queues) in self.receipt_set.drain() {
+            while let Some((mut timestamps, receipt)) = queues.get_data() {
+                timestamps.sort_unstable();
+                signal_manager.send_receipt(uuid, timestamps, receipt);
+            }
+        }
+        true
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::signal::test::SignalManagerMock;
+
     use super::*;
 
     #[test]
@@ -180,4 +170,34 @@ mod tests {
         assert!(Receipt::Sent < Receipt::Delivered);
         assert!(Receipt::Delivered < Receipt::Read);
     }
+
+    #[test]
+    fn test_step_batches_receipts_per_sender() {
+        let signal_manager = SignalManagerMock::new();
+        let sent_receipts = signal_manager.sent_receipts.clone();
+        let alice = Uuid::from_u128(1);
+        let bob = Uuid::from_u128(2);
+
+        let mut handler = ReceiptHandler::new();
+        handler.add_receipt_event(ReceiptEvent::new(alice, 2, Receipt::Delivered));
+        handler.add_receipt_event(ReceiptEvent::new(alice, 1, Receipt::Delivered));
+        handler.add_receipt_event(ReceiptEvent::new(alice, 1, Receipt::Read));
+        handler.add_receipt_event(ReceiptEvent::new(bob, 3, Receipt::Read));
+
+        assert!(handler.step(&signal_manager));
+        let mut sent = sent_receipts.take();
+        sent.sort_unstable_by_key(|&(uuid, _, receipt)| (uuid, receipt));
+        assert_eq!(
+            sent,
+            [
+                (alice, vec![2], Receipt::Delivered),
+                (alice, vec![1], Receipt::Read),
+                (bob, vec![3], Receipt::Read),
+            ]
+        );
+
+        // queues are drained
+        assert!(!handler.step(&signal_manager));
+        assert!(sent_receipts.borrow().is_empty());
+    }
 }
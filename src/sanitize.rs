@@ -0,0 +1,298 @@
+//! Removal of metadata from outgoing images
+
+use std::io::Cursor;
+
+use anyhow::Context as _;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{ColorType, DynamicImage, ImageDecoder, ImageEncoder, ImageFormat, ImageReader};
+use presage::libsignal_service::sender::AttachmentSpec;
+use tracing::warn;
+
+use crate::config::UploadConfig;
+
+/// Removes the metadata from JPEG and PNG images and downscales them if they are too big
+///
+/// JPEG images which are not downscaled keep their encoded pixels; only the EXIF, XMP and IPTC
+/// segments are removed, and the orientation is written back. All other images are re-encoded
+/// with their color profile, with the orientation applied to the pixels. Width, height and length
+/// of the `spec` are updated to match the returned data. Other attachments are returned untouched.
+///
+/// Downscaling is slow for large images, so this should not be called on the UI thread.
+pub(crate) fn sanitize_attachment(
+    spec: &mut AttachmentSpec,
+    bytes: Vec<u8>,
+    config: &UploadConfig,
+) -> anyhow::Result<Vec<u8>> {
+    let reader = ImageReader::new(Cursor::new(&bytes)).with_guessed_format()?;
+    let Some(format @ (ImageFormat::Jpeg | ImageFormat::Png)) = reader.format() else {
+        return Ok(bytes);
+    };
+    let mut decoder = reader.into_decoder().context("failed to decode image")?;
+    let orientation = decoder
+        .orientation()
+        .context("failed to read image orientation")?;
+    let (width, height) = decoder.dimensions();
+    let is_too_big = config
+        .max_image_dimension
+        .is_some_and(|max| width.max(height) > max);
+    if !config.strip_metadata && !is_too_big {
+        drop(decoder);
+        spec.width = Some(width);
+        spec.height = Some(height);
+        return Ok(bytes);
+    }
+    if format == ImageFormat::Jpeg
+        && !is_too_big
+        && let Some(stripped) = strip_jpeg_metadata(&bytes, orientation)
+    {
+        spec.width = Some(width);
+        spec.height = Some(height);
+        spec.length = stripped.len();
+        return Ok(stripped);
+    }
+
+    let icc_profile = decoder
+        .icc_profile()
+        .context("failed to read color profile")?;
+    let mut image = DynamicImage::from_decoder(decoder).context("failed to decode image")?;
+    image.apply_orientation(orientation);
+    if let Some(max) = config.max_image_dimension
+        && image.width().max(image.height()) > max
+    {
+        // keeps the aspect ratio
+        image = image.resize(max, max, FilterType::Lanczos3);
+    }
+
+    let mut sanitized = Vec::new();
+    let encoded = match format {
+        ImageFormat::Jpeg => {
+            // JPEG has no alpha channel
+            if !matches!(image.color(), ColorType::L8 | ColorType::Rgb8) {
+                image = image.to_rgb8().into();
+            }
+            let quality = config.jpeg_quality.clamp(1, 100);
+            let encoder = JpegEncoder::new_with_quality(&mut sanitized, quality);
+            image.write_with_encoder(with_icc_profile(encoder, icc_profile))
+        }
+        _ => {
+            let encoder = PngEncoder::new(&mut sanitized);
+            image.write_with_encoder(with_icc_profile(encoder, icc_profile))
+        }
+    };
+    encoded.context("failed to encode image")?;
+
+    spec.width = Some(image.width());
+    spec.height = Some(image.height());
+    spec.length = sanitized.len();
+    Ok(sanitized)
+}
+
+/// Keeps the color profile of the original image, such that the colors look the same
+fn with_icc_profile<E: ImageEncoder>(mut encoder: E, icc_profile: Option<Vec<u8>>) -> E {
+    if let Some(icc_profile) = icc_profile
+        && let Err(error) = encoder.set_icc_profile(icc_profile)
+    {
+        warn!(%error, "failed to keep color profile");
+    }
+    encoder
+}
+
+const APP1: u8 = 0xe1;
+const APP13: u8 = 0xed;
+const START_OF_SCAN: u8 = 0xda;
+const END_OF_IMAGE: u8 = 0xd9;
+
+/// Removes the APP1 (EXIF, XMP) and APP13 (IPTC) segments of the JPEG image, as well as any data
+/// after its end, e.g. the video of a motion photo
+///
+/// The orientation is kept in a new EXIF segment which only contains it. Returns `None` if the
+/// image is malformed.
+fn strip_jpeg_metadata(jpeg: &[u8], orientation: Orientation) -> Option<Vec<u8>> {
+    let mut rest = jpeg.strip_prefix(&[0xff, 0xd8])?;
+    let mut stripped = vec![0xff, 0xd8];
+    if orientation != Orientation::NoTransforms {
+        stripped.extend(exif_orientation_segment(orientation));
+    }
+    loop {
+        // markers can be preceded by fill bytes
+        let fill = rest.iter().take_while(|&&byte| byte == 0xff).count();
+        rest = rest.get(fill.checked_sub(1)?..)?;
+        let marker = *rest.get(1)?;
+        match marker {
+            // markers without a segment
+            0x01 | 0xd0..=0xd7 => {
+                stripped.extend(&rest[..2]);
+                rest = &rest[2..];
+            }
+            END_OF_IMAGE => {
+                stripped.extend(&rest[..2]);
+                return Some(stripped);
+            }
+            0x00 | 0xd8 => return None,
+            _ => {
+                let length = u16::from_be_bytes([*rest.get(2)?, *rest.get(3)?]);
+                let mut end = 2 + usize::from(length);
+                if marker == START_OF_SCAN {
+                    end += entropy_coded_len(rest.get(end..)?);
+                }
+                let segment = rest.get(..end)?;
+                if !matches!(marker, APP1 | APP13) {
+                    stripped.extend(segment);
+                }
+                rest = &rest[end..];
+            }
+        }
+    }
+}
+
+/// Length of the entropy-coded data at the start of `data`, which ends with the next marker
+fn entropy_coded_len(data: &[u8]) -> usize {
+    let mut idx = 0;
+    while let Some(offset) = data[idx..].iter().position(|&byte| byte == 0xff) {
+        idx += offset;
+        match data.get(idx + 1) {
+            // stuffed zero byte or restart marker
+            Some(0x00 | 0xd0..=0xd7) => idx += 2,
+            _ => return idx,
+        }
+    }
+    data.len()
+}
+
+/// APP1 segment with EXIF data consisting only of the orientation
+fn exif_orientation_segment(orientation: Orientation) -> Vec<u8> {
+    // big-endian TIFF header, followed by an IFD with a single short value and no next IFD
+    let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0".to_vec();
+    exif.extend([orientation.to_exif(), 0, 0, 0, 0, 0, 0]);
+    let mut segment = vec![0xff, APP1];
+    segment.extend((exif.len() as u16 + 2).to_be_bytes());
+    segment.extend(exif);
+    segment
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        RgbImage::new(width, height)
+            .write_with_encoder(JpegEncoder::new(&mut jpeg))
+            .unwrap();
+        jpeg
+    }
+
+    fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xff, marker];
+        segment.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+        segment.extend_from_slice(data);
+        segment
+    }
+
+    /// EXIF segment with the `orientation` followed by `secret`
+    fn exif(orientation: u8, secret: &[u8]) -> Vec<u8> {
+        // big-endian TIFF header followed by an IFD with the orientation
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0".to_vec();
+        exif.extend_from_slice(&[orientation, 0, 0, 0, 0, 0, 0]);
+        exif.extend_from_slice(secret);
+        segment(APP1, &exif)
+    }
+
+    /// Inserts the segments right after the start of image marker
+    fn with_segments(jpeg: &[u8], segments: &[Vec<u8>]) -> Vec<u8> {
+        [&jpeg[..2], &segments.concat(), &jpeg[2..]].concat()
+    }
+
+    fn decoder(image: &[u8]) -> impl ImageDecoder + '_ {
+        ImageReader::new(Cursor::new(image))
+            .with_guessed_format()
+            .unwrap()
+            .into_decoder()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sanitize_attachment() {
+        let secret = b"52.520008,13.404954";
+        let icc = segment(0xe2, b"ICC_PROFILE\0\x01\x01profile");
+        let plain = with_segments(&jpeg(40, 20), &[icc]);
+        let mut jpeg = with_segments(&plain, &[exif(1, secret), segment(APP13, secret)]);
+        jpeg.extend_from_slice(secret);
+        assert!(jpeg.windows(secret.len()).any(|w| w == secret));
+
+        let mut spec = AttachmentSpec {
+            content_type: "image/jpeg".to_owned(),
+            length: jpeg.len(),
+            ..Default::default()
+        };
+        // the encoded pixels and the color profile are kept
+        let config = UploadConfig::default();
+        let sanitized = sanitize_attachment(&mut spec, jpeg.clone(), &config).unwrap();
+        assert_eq!(sanitized, plain);
+        assert_eq!((spec.width, spec.height), (Some(40), Some(20)));
+        assert_eq!(spec.length, sanitized.len());
+
+        let config = UploadConfig {
+            max_image_dimension: Some(10),
+            ..Default::default()
+        };
+        let sanitized = sanitize_attachment(&mut spec, jpeg.clone(), &config).unwrap();
+        assert!(!sanitized.windows(secret.len()).any(|w| w == secret));
+        assert_eq!((spec.width, spec.height), (Some(10), Some(5)));
+        let image = image::load_from_memory(&sanitized).unwrap();
+        assert_eq!((image.width(), image.height()), (10, 5));
+        assert_eq!(
+            decoder(&sanitized).icc_profile().unwrap().as_deref(),
+            Some(&b"profile"[..])
+        );
+
+        // untouched if neither stripping nor downscaling is needed
+        let config = UploadConfig {
+            strip_metadata: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            sanitize_attachment(&mut spec, jpeg.clone(), &config).unwrap(),
+            jpeg
+        );
+
+        let text = b"not an image".to_vec();
+        assert_eq!(
+            sanitize_attachment(&mut spec, text.clone(), &config).unwrap(),
+            text
+        );
+    }
+
+    #[test]
+    fn test_sanitize_attachment_keeps_orientation() {
+        let secret = b"52.520008,13.404954";
+        // rotated by 90 degrees
+        let jpeg = with_segments(&jpeg(40, 20), &[exif(6, secret)]);
+        let mut spec = AttachmentSpec::default();
+
+        let config = UploadConfig::default();
+        let sanitized = sanitize_attachment(&mut spec, jpeg.clone(), &config).unwrap();
+        assert!(!sanitized.windows(secret.len()).any(|w| w == secret));
+        assert_eq!(
+            decoder(&sanitized).orientation().unwrap(),
+            Orientation::Rotate90
+        );
+
+        // the orientation is applied when re-encoding
+        let config = UploadConfig {
+            max_image_dimension: Some(10),
+            ..Default::default()
+        };
+        let sanitized = sanitize_attachment(&mut spec, jpeg, &config).unwrap();
+        assert_eq!((spec.width, spec.height), (Some(5), Some(10)));
+        assert_eq!(
+            decoder(&sanitized).orientation().unwrap(),
+            Orientation::NoTransforms
+        );
+    }
+}
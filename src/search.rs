@@ -0,0 +1,202 @@
+//! Search of messages across all channels
+
+use chrono::NaiveDate;
+use ratatui::widgets::ListState;
+
+use crate::input::Input;
+use crate::storage::MessageId;
+
+/// Query entered in the search popup
+///
+/// Words are separated by whitespace; double quotes group words with whitespace. The following
+/// filters are supported:
+///
+/// * `from:name`: sender of the message
+/// * `in:name`: channel of the message
+/// * `has:attachment`: message with at least one attachment
+/// * `before:YYYY-MM-DD`: message arrived before the date
+/// * `after:YYYY-MM-DD`: message arrived on or after the date
+///
+/// Example: `from:alice in:"Team chat" has:attachment before:2026-01-01 lunch`
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct ParsedQuery {
+    pub(crate) words: Vec<String>,
+    pub(crate) from: Option<String>,
+    pub(crate) channel: Option<String>,
+    pub(crate) has_attachment: bool,
+    pub(crate) before: Option<NaiveDate>,
+    pub(crate) after: Option<NaiveDate>,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub(crate) enum QueryError {
+    #[error("invalid date '{0}', expected YYYY-MM-DD")]
+    InvalidDate(String),
+    #[error("unknown filter 'has:{0}', expected 'has:attachment'")]
+    UnknownHas(String),
+}
+
+impl ParsedQuery {
+    pub(crate) fn parse(input: &str) -> Result<Self, QueryError> {
+        let mut query = Self::default();
+        for (token, is_quoted) in tokenize(input) {
+            let filter = if is_quoted {
+                None
+            } else {
+                token.split_once(':')
+            };
+            match filter {
+                Some(("from", name)) if !name.is_empty() => query.from = Some(name.to_owned()),
+                Some(("in", name)) if !name.is_empty() => query.channel = Some(name.to_owned()),
+                Some(("has", "attachment")) => query.has_attachment = true,
+                Some(("has", value)) => return Err(QueryError::UnknownHas(value.to_owned())),
+                Some(("before", date)) => query.before = Some(parse_date(date)?),
+                Some(("after", date)) => query.after = Some(parse_date(date)?),
+                _ => query.words.push(token),
+            }
+        }
+        Ok(query)
+    }
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, QueryError> {
+    date.parse()
+        .map_err(|_| QueryError::InvalidDate(date.to_owned()))
+}
+
+/// Splits the input at whitespace outside of double quotes and removes the quotes
+///
+/// Returns the tokens together with whether a token started with a quote.
+fn tokenize(input: &str) -> Vec<(String, bool)> {
+    let mut tokens = Vec::new();
+    let mut token: Option<(String, bool)> = None;
+    let mut in_quotes = false;
+    for c in input.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                token.get_or_insert_with(|| (String::new(), true));
+            }
+            c if c.is_whitespace() && !in_quotes => tokens.extend(token.take()),
+            c => token.get_or_insert_with(Default::default).0.push(c),
+        }
+    }
+    tokens.extend(token);
+    tokens.retain(|(token, _)| !token.is_empty());
+    tokens
+}
+
+/// Popup for searching messages
+#[derive(Default)]
+pub(crate) struct Search {
+    pub is_shown: bool,
+    pub input: Input,
+    pub state: ListState,
+    /// Input from which the results were found
+    searched: Option<String>,
+    results: Vec<MessageId>,
+    error: Option<String>,
+}
+
+impl Search {
+    pub fn reset(&mut self) {
+        *self = Default::default();
+    }
+
+    /// Whether the results do not correspond to the input anymore
+    pub fn is_outdated(&self) -> bool {
+        self.searched.as_deref() != Some(self.input.data.as_str())
+    }
+
+    pub fn set_results(&mut self, results: Result<Vec<MessageId>, String>) {
+        self.searched = Some(self.input.data.clone());
+        match results {
+            Ok(results) => {
+                self.state.select(results.first().map(|_| 0));
+                self.results = results;
+                self.error = None;
+            }
+            Err(error) => {
+                self.state.select(None);
+                self.results.clear();
+                self.error = Some(error);
+            }
+        }
+    }
+
+    pub fn results(&self) -> &[MessageId] {
+        &self.results
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn selected_result(&self) -> Option<MessageId> {
+        self.results.get(self.state.selected()?).copied()
+    }
+
+    pub fn prev(&mut self) {
+        let selected = self
+            .state
+            .selected()
+            .map(|idx| idx.saturating_sub(1))
+            .unwrap_or(0);
+        self.state.select(Some(selected));
+    }
+
+    pub fn next(&mut self) {
+        let last = self.results.len().saturating_sub(1);
+        let selected = self
+            .state
+            .selected()
+            .map(|idx| (idx + 1).min(last))
+            .unwrap_or(0);
+        self.state.select(Some(selected));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        let query = ParsedQuery::parse(
+            r#"from:alice in:"Team chat" has:attachment before:2026-01-01 lunch"#,
+        )
+        .unwrap();
+        assert_eq!(
+            query,
+            ParsedQuery {
+                words: vec!["lunch".to_owned()],
+                from: Some("alice".to_owned()),
+                channel: Some("Team chat".to_owned()),
+                has_attachment: true,
+                before: NaiveDate::from_ymd_opt(2026, 1, 1),
+                after: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_query_words() {
+        let query = ParsedQuery::parse(r#"  hello  "in:the box" https://example.org "#).unwrap();
+        assert_eq!(query.words, ["hello", "in:the box", "https://example.org"]);
+        assert_eq!(query.channel, None);
+
+        assert_eq!(ParsedQuery::parse("").unwrap(), ParsedQuery::default());
+    }
+
+    #[test]
+    fn test_parse_query_errors() {
+        assert_eq!(
+            ParsedQuery::parse("after:yesterday"),
+            Err(QueryError::InvalidDate("yesterday".to_owned()))
+        );
+        assert_eq!(
+            ParsedQuery::parse("has:link"),
+            Err(QueryError::UnknownHas("link".to_owned()))
+        );
+    }
+}
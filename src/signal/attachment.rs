@@ -7,17 +7,27 @@ use anyhow::Context;
 use chrono::Local;
 use mime_guess::mime::{APPLICATION_OCTET_STREAM, IMAGE_JPEG};
 use mime_guess::{Mime, get_mime_extensions};
+use presage::libsignal_service::sender::AttachmentSpec;
 use presage::proto::AttachmentPointer;
+use prost::Message as _;
 use regex::Regex;
 use tracing::info;
 
-use crate::signal::Attachment;
+use crate::attachment_files::AttachmentFiles;
+use crate::data::BodyRange;
+use crate::signal::{Attachment, PendingAttachment};
 use crate::util::utc_timestamp_msec_to_local;
 
 const DIGEST_BYTES_LEN: usize = 4;
 
-pub(super) fn save(
+/// Content type of the attachment with the full body of a long message
+pub(crate) const LONG_TEXT_CONTENT_TYPE: &str = "text/x-signal-plain";
+/// Maximum length in bytes of a sent body; longer bodies are sent as long-text attachment
+const MAX_BODY_LEN: usize = 2000;
+
+pub(crate) fn save(
     data_dir: impl AsRef<Path>,
+    files: &AttachmentFiles,
     pointer: AttachmentPointer,
     data: &[u8],
 ) -> anyhow::Result<Attachment> {
@@ -46,8 +56,7 @@ pub(super) fn save(
 
     std::fs::create_dir_all(&filedir)
         .with_context(|| format!("failed to create dir: {}", filedir.display()))?;
-    std::fs::write(&filepath, data)
-        .with_context(|| format!("failed to save attachment at: {}", filepath.display()))?;
+    files.write(&filepath, data)?;
 
     info!(dest =% filepath.display(), "saved attachment");
 
@@ -59,6 +68,82 @@ pub(super) fn save(
     })
 }
 
+/// Keeps the pointer of an attachment which is downloaded later
+pub(crate) fn pending(pointer: AttachmentPointer) -> anyhow::Result<PendingAttachment> {
+    let digest = pointer
+        .digest
+        .as_deref()
+        .context("dropping attachment without digest")?;
+    Ok(PendingAttachment {
+        id: hex::encode(digest),
+        content_type: pointer.content_type().to_owned(),
+        size: pointer.size.unwrap_or_default(),
+        pointer: pointer.encode_to_vec(),
+    })
+}
+
+impl PendingAttachment {
+    pub(crate) fn pointer(&self) -> anyhow::Result<AttachmentPointer> {
+        AttachmentPointer::decode(self.pointer.as_slice()).context("invalid attachment pointer")
+    }
+}
+
+/// Reads a saved attachment for uploading it again
+pub(super) fn load(
+    files: &AttachmentFiles,
+    attachment: &Attachment,
+) -> anyhow::Result<(AttachmentSpec, Vec<u8>)> {
+    let data = files.read(&attachment.filename)?;
+    let spec = AttachmentSpec {
+        content_type: attachment.content_type.clone(),
+        length: data.len(),
+        file_name: attachment
+            .filename
+            .file_name()
+            .map(|name| name.to_string_lossy().into()),
+        ..Default::default()
+    };
+    Ok((spec, data))
+}
+
+/// Moves a body longer than [`MAX_BODY_LEN`] into a long-text attachment
+///
+/// Returns the body to send, which is truncated at a char boundary if the attachment is returned.
+pub(super) fn split_long_text(body: &str) -> (String, Option<(AttachmentSpec, Vec<u8>)>) {
+    if body.len() <= MAX_BODY_LEN {
+        return (body.to_owned(), None);
+    }
+    let end = (0..=MAX_BODY_LEN)
+        .rev()
+        .find(|&idx| body.is_char_boundary(idx))
+        .unwrap_or_default();
+    let spec = AttachmentSpec {
+        content_type: LONG_TEXT_CONTENT_TYPE.to_owned(),
+        length: body.len(),
+        ..Default::default()
+    };
+    (
+        body[..end].to_owned(),
+        Some((spec, body.as_bytes().to_vec())),
+    )
+}
+
+/// Body ranges of a body truncated by [`split_long_text`]
+///
+/// The ranges are clipped to the UTF-16 length of the truncated body, and ranges starting beyond
+/// it are dropped. The full ranges only apply to the text of the long-text attachment.
+pub(super) fn truncated_body_ranges(body: &str, body_ranges: &[BodyRange]) -> Vec<BodyRange> {
+    let len = u16::try_from(body.encode_utf16().count()).unwrap_or(u16::MAX);
+    body_ranges
+        .iter()
+        .filter(|range| range.start < len)
+        .map(|range| BodyRange {
+            end: range.end.min(len),
+            ..range.clone()
+        })
+        .collect()
+}
+
 fn conflict_free_filename(filedir: &Path, name: String) -> PathBuf {
     let mut filepath = filedir.join(&name);
 
@@ -149,12 +234,14 @@ mod tests {
     #[test]
     fn test_save() {
         let tempdir = tempfile::tempdir().unwrap();
+        let files = AttachmentFiles::default();
 
         let digest = hex!("d51e9a355d4351ae5fbf2846d18bb384471555aa0ea6ee9075eb63f99ecddf77");
         let upload_timestamp = 1703160458 * 1000;
 
         let attachment = save(
             tempdir.path(),
+            &files,
             attachment_pointer("image/jpeg", &digest, Some("image.jpeg"), upload_timestamp),
             &[42],
         )
@@ -173,6 +260,7 @@ mod tests {
         // duplicate
         let attachment = save(
             tempdir.path(),
+            &files,
             attachment_pointer("image/jpeg", &digest, Some("image.jpeg"), upload_timestamp),
             &[42],
         )
@@ -185,6 +273,7 @@ mod tests {
         // without name
         let attachment = save(
             tempdir.path(),
+            &files,
             attachment_pointer("image/jpeg", &digest, None, upload_timestamp),
             &[42],
         )
@@ -197,6 +286,7 @@ mod tests {
         // without name and mime octet-stream
         let attachment = save(
             tempdir.path(),
+            &files,
             attachment_pointer("application/octet-stream", &digest, None, upload_timestamp),
             &[42],
         )
@@ -209,6 +299,7 @@ mod tests {
         // without name and mime pdf
         let attachment = save(
             tempdir.path(),
+            &files,
             attachment_pointer("application/pdf", &digest, None, upload_timestamp),
             &[42],
         )
@@ -219,6 +310,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_pending() {
+        let digest = hex!("d51e9a355d4351ae5fbf2846d18bb384471555aa0ea6ee9075eb63f99ecddf77");
+        let pointer = attachment_pointer("video/mp4", &digest, Some("video.mp4"), 1703160458000);
+
+        let pending = pending(pointer.clone()).unwrap();
+        assert_eq!(pending.id, hex::encode(digest));
+        assert_eq!(pending.content_type, "video/mp4");
+        assert_eq!(pending.size, 42);
+        assert_eq!(pending.pointer().unwrap(), pointer);
+    }
+
     #[test]
     fn test_derive_name() {
         assert_eq!(
@@ -251,4 +354,45 @@ mod tests {
             "d51e9a35.jpeg"
         );
     }
+
+    #[test]
+    fn test_split_long_text() {
+        let (body, attachment) = split_long_text("short");
+        assert_eq!(body, "short");
+        assert!(attachment.is_none());
+
+        // the limit falls into the middle of the two bytes of 'ä'
+        let text = format!("{}ä{}", "a".repeat(MAX_BODY_LEN - 1), "b".repeat(100));
+        let (body, attachment) = split_long_text(&text);
+        assert_eq!(body, "a".repeat(MAX_BODY_LEN - 1));
+        let (spec, data) = attachment.unwrap();
+        assert_eq!(spec.content_type, LONG_TEXT_CONTENT_TYPE);
+        assert_eq!(spec.length, text.len());
+        assert_eq!(data, text.as_bytes());
+    }
+
+    #[test]
+    fn test_truncated_body_ranges() {
+        let text = format!(
+            "*{}* {} *{}* _{}_",
+            "a".repeat(10),
+            "a".repeat(1979),
+            "b".repeat(20),
+            "c".repeat(5)
+        );
+        let (text, body_ranges) = crate::markup::parse_with_mentions(&text, &[]);
+        assert_eq!(body_ranges.len(), 3);
+        let (body, attachment) = split_long_text(&text);
+        assert!(attachment.is_some());
+
+        // the range crossing the end of the body is clipped, the one after it is dropped
+        let ranges: Vec<_> = truncated_body_ranges(&body, &body_ranges)
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect();
+        assert_eq!(ranges, [(0, 10), (1991, 2000)]);
+
+        let ranges = truncated_body_ranges("short", &body_ranges);
+        assert_eq!(ranges.len(), 1);
+    }
 }
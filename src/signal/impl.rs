@@ -10,8 +10,11 @@ use presage::libsignal_service::sender::AttachmentSpec;
 use presage::manager::Registered;
 use presage::model::contacts::Contact;
 use presage::model::groups::Group;
-use presage::proto::data_message::{Quote, Reaction};
-use presage::proto::{AttachmentPointer, DataMessage, EditMessage, GroupContextV2, ReceiptMessage};
+use presage::proto::data_message::{Delete, Quote, Reaction};
+use presage::proto::typing_message;
+use presage::proto::{
+    AttachmentPointer, DataMessage, EditMessage, GroupContextV2, ReceiptMessage, TypingMessage,
+};
 use presage::store::ContentsStore;
 use presage::{
     libsignal_service::content::{Content, ContentBody},
@@ -24,7 +27,9 @@ use tokio_stream::{Stream, StreamExt};
 use tracing::{error, warn};
 use uuid::Uuid;
 
-use crate::data::{Channel, ChannelId, GroupData, Message};
+use crate::attachment_files::AttachmentFiles;
+use crate::data::{Channel, ChannelId, GroupData, Message, TypingAction};
+use crate::mention::Mention;
 use crate::receipt::Receipt;
 use crate::util::utc_now_timestamp_msec;
 
@@ -36,6 +41,7 @@ use super::{
 pub(super) struct PresageManager {
     manager: presage::Manager<SqliteStore, Registered>,
     data_dir: PathBuf,
+    files: AttachmentFiles,
     local_pool: LocalPool,
 }
 
@@ -43,114 +49,44 @@ impl PresageManager {
     pub(super) fn new(
         manager: presage::Manager<SqliteStore, Registered>,
         data_dir: PathBuf,
+        files: AttachmentFiles,
         local_pool: LocalPool,
     ) -> Self {
         Self {
             manager,
             data_dir,
+            files,
             local_pool,
         }
     }
-}
-
-#[async_trait(?Send)]
-impl SignalManager for PresageManager {
-    fn clone_boxed(&self) -> Box<dyn SignalManager> {
-        Box::new(Self::new(
-            self.manager.clone(),
-            self.data_dir.clone(),
-            self.local_pool.clone(),
-        ))
-    }
-
-    fn user_id(&self) -> Uuid {
-        self.manager.registration_data().service_ids.aci
-    }
-
-    async fn resolve_group(
-        &mut self,
-        master_key_bytes: GroupMasterKeyBytes,
-    ) -> anyhow::Result<ResolvedGroup> {
-        let decrypted_group = self
-            .manager
-            .store()
-            .group(master_key_bytes)
-            .await?
-            .context("no group found")?;
-
-        let mut members = Vec::with_capacity(decrypted_group.members.len());
-        let mut profile_keys = Vec::with_capacity(decrypted_group.members.len());
-        for member in decrypted_group.members {
-            members.push(member.aci.into());
-            profile_keys.push(member.profile_key.bytes);
-        }
-
-        let name = decrypted_group.title;
-        let group_data = GroupData {
-            master_key_bytes,
-            members,
-            revision: decrypted_group.revision,
-        };
-
-        Ok(ResolvedGroup {
-            name,
-            group_data,
-            profile_keys,
-        })
-    }
-
-    async fn save_attachment(
-        &mut self,
-        attachment_pointer: AttachmentPointer,
-    ) -> anyhow::Result<Attachment> {
-        let attachment_data = self.manager.get_attachment(&attachment_pointer).await?;
-        attachment::save(&self.data_dir, attachment_pointer, &attachment_data)
-    }
-
-    fn send_receipt(&self, sender_uuid: Uuid, timestamps: Vec<u64>, receipt: Receipt) {
-        let now_timestamp = utc_now_timestamp_msec();
-        let data_message = ReceiptMessage {
-            r#type: Some(receipt.to_i32()),
-            timestamp: timestamps,
-        };
-
-        let mut manager = self.manager.clone();
-        self.local_pool.spawn(move || async move {
-            let body = ContentBody::ReceiptMessage(data_message);
-            if let Err(error) = manager
-                .send_message(ServiceId::Aci(sender_uuid.into()), body, now_timestamp)
-                .await
-            {
-                error!(%error, %sender_uuid, "failed to send receipt");
-            }
-        });
-    }
 
-    fn send_text(
+    /// Builds the message and the data message to send, and saves the attachments
+    fn compose(
         &self,
         channel: &Channel,
         text: String,
+        mentions: &[Mention],
         quote_message: Option<&Message>,
         edit_message_timestamp: Option<u64>,
-        attachments: Vec<(AttachmentSpec, Vec<u8>)>,
-    ) -> (Message, oneshot::Receiver<anyhow::Result<()>>) {
-        let message: String = crate::emoji::replace_shortcodes(&text).into_owned();
+        mut attachments: Vec<(AttachmentSpec, Vec<u8>)>,
+    ) -> (Message, DataMessage, Vec<(AttachmentSpec, Vec<u8>)>) {
+        let message = crate::emoji::replace_shortcodes(&text);
+        let (message, body_ranges) = crate::markup::parse_with_mentions(&message, mentions);
         let has_attachments = !attachments.is_empty();
 
         let timestamp = utc_now_timestamp_msec();
 
-        let quote = quote_message.map(|message| Quote {
-            id: Some(message.arrived_at),
-            author_aci: Some(message.from_id.to_string()),
-            text: message.message.clone(),
-            body_ranges: message.body_ranges.iter().map(From::from).collect(),
-            ..Default::default()
-        });
+        let quote = quote_message.map(quote_of);
         let quote_message = quote.clone().and_then(Message::from_quote).map(Box::new);
 
-        let mut data_message = DataMessage {
-            body: Some(message.clone()),
+        // the full body is kept in the message, only the sent body is truncated
+        let (body, long_text) = attachment::split_long_text(&message);
+        let sent_body_ranges = attachment::truncated_body_ranges(&body, &body_ranges);
+        let data_message = DataMessage {
+            body: Some(body),
+            body_ranges: sent_body_ranges.iter().map(From::from).collect(),
             quote,
+            expire_timer: channel.expire_timer,
             ..Default::default()
         };
 
@@ -167,7 +103,7 @@ impl SignalManager for PresageManager {
                     upload_timestamp: Some(utc_now_timestamp_msec()),
                     ..Default::default()
                 };
-                match attachment::save(&self.data_dir, attachment_pointer, data) {
+                match attachment::save(&self.data_dir, &self.files, attachment_pointer, data) {
                     Ok(attachment) => {
                         saved_attachments.push(attachment);
                     }
@@ -178,6 +114,40 @@ impl SignalManager for PresageManager {
             }
         }
 
+        if let Some(long_text) = long_text {
+            attachments.insert(0, long_text);
+        }
+
+        let mut message = Message {
+            from_id: self.user_id(),
+            message: Some(message),
+            arrived_at: timestamp,
+            quote: quote_message,
+            attachments: saved_attachments,
+            pending_attachments: Default::default(),
+            reactions: Default::default(),
+            receipt: Receipt::Sent,
+            body_ranges,
+            send_state: Default::default(),
+            edit: edit_message_timestamp,
+            edited: edit_message_timestamp.is_some(),
+            deleted: Default::default(),
+            expire_timer: channel.expire_timer,
+            expires_at: Default::default(),
+        };
+        message.start_expiry(timestamp);
+        (message, data_message, attachments)
+    }
+
+    /// Uploads the attachments and sends the data message to the channel in the background
+    fn spawn_send(
+        &self,
+        channel: &Channel,
+        mut data_message: DataMessage,
+        attachments: Vec<(AttachmentSpec, Vec<u8>)>,
+        edit_message_timestamp: Option<u64>,
+        timestamp: u64,
+    ) -> oneshot::Receiver<anyhow::Result<()>> {
         let (response_tx, response) = oneshot::channel();
         match channel.id {
             ChannelId::User(uuid) => {
@@ -255,23 +225,203 @@ impl SignalManager for PresageManager {
                 }
             }
         }
+        response
+    }
+}
 
-        let message = Message {
-            from_id: self.user_id(),
-            message: Some(message),
-            arrived_at: timestamp,
-            quote: quote_message,
-            attachments: saved_attachments,
-            reactions: Default::default(),
-            receipt: Receipt::Sent,
-            body_ranges: Default::default(),
-            send_failed: Default::default(),
-            edit: edit_message_timestamp,
-            edited: edit_message_timestamp.is_some(),
+#[async_trait(?Send)]
+impl SignalManager for PresageManager {
+    fn clone_boxed(&self) -> Box<dyn SignalManager> {
+        Box::new(Self::new(
+            self.manager.clone(),
+            self.data_dir.clone(),
+            self.files.clone(),
+            self.local_pool.clone(),
+        ))
+    }
+
+    fn user_id(&self) -> Uuid {
+        self.manager.registration_data().service_ids.aci
+    }
+
+    fn attachment_files(&self) -> AttachmentFiles {
+        self.files.clone()
+    }
+
+    async fn resolve_group(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+    ) -> anyhow::Result<ResolvedGroup> {
+        let decrypted_group = self
+            .manager
+            .store()
+            .group(master_key_bytes)
+            .await?
+            .context("no group found")?;
+
+        let mut members = Vec::with_capacity(decrypted_group.members.len());
+        let mut profile_keys = Vec::with_capacity(decrypted_group.members.len());
+        for member in decrypted_group.members {
+            members.push(member.aci.into());
+            profile_keys.push(member.profile_key.bytes);
+        }
+
+        let name = decrypted_group.title;
+        let group_data = GroupData {
+            master_key_bytes,
+            members,
+            revision: decrypted_group.revision,
         };
+
+        Ok(ResolvedGroup {
+            name,
+            group_data,
+            profile_keys,
+        })
+    }
+
+    async fn save_attachment(
+        &mut self,
+        attachment_pointer: AttachmentPointer,
+    ) -> anyhow::Result<Attachment> {
+        let attachment_data = self.manager.get_attachment(&attachment_pointer).await?;
+        attachment::save(
+            &self.data_dir,
+            &self.files,
+            attachment_pointer,
+            &attachment_data,
+        )
+    }
+
+    fn download_attachments(
+        &self,
+        attachment_pointers: Vec<AttachmentPointer>,
+    ) -> oneshot::Receiver<Vec<anyhow::Result<Attachment>>> {
+        let (response_tx, response) = oneshot::channel();
+        let manager = self.manager.clone();
+        let data_dir = self.data_dir.clone();
+        let files = self.files.clone();
+        self.local_pool.spawn(move || async move {
+            let mut results = Vec::with_capacity(attachment_pointers.len());
+            for attachment_pointer in attachment_pointers {
+                let result = match manager.get_attachment(&attachment_pointer).await {
+                    Ok(data) => attachment::save(&data_dir, &files, attachment_pointer, &data),
+                    Err(error) => Err(error.into()),
+                };
+                results.push(result);
+            }
+            let _ = response_tx.send(results);
+        });
+        response
+    }
+
+    fn send_receipt(&self, sender_uuid: Uuid, timestamps: Vec<u64>, receipt: Receipt) {
+        let now_timestamp = utc_now_timestamp_msec();
+        let data_message = ReceiptMessage {
+            r#type: Some(receipt.to_i32()),
+            timestamp: timestamps,
+        };
+
+        let mut manager = self.manager.clone();
+        self.local_pool.spawn(move || async move {
+            let body = ContentBody::ReceiptMessage(data_message);
+            if let Err(error) = manager
+                .send_message(ServiceId::Aci(sender_uuid.into()), body, now_timestamp)
+                .await
+            {
+                error!(%error, %sender_uuid, "failed to send receipt");
+            }
+        });
+    }
+
+    fn compose_text(
+        &self,
+        channel: &Channel,
+        text: String,
+        mentions: &[Mention],
+        quote_message: Option<&Message>,
+        edit_message_timestamp: Option<u64>,
+        attachments: Vec<(AttachmentSpec, Vec<u8>)>,
+    ) -> Message {
+        let (message, _, _) = self.compose(
+            channel,
+            text,
+            mentions,
+            quote_message,
+            edit_message_timestamp,
+            attachments,
+        );
+        message
+    }
+
+    fn send_text(
+        &self,
+        channel: &Channel,
+        text: String,
+        mentions: &[Mention],
+        quote_message: Option<&Message>,
+        edit_message_timestamp: Option<u64>,
+        attachments: Vec<(AttachmentSpec, Vec<u8>)>,
+    ) -> (Message, oneshot::Receiver<anyhow::Result<()>>) {
+        let (message, data_message, attachments) = self.compose(
+            channel,
+            text,
+            mentions,
+            quote_message,
+            edit_message_timestamp,
+            attachments,
+        );
+        let response = self.spawn_send(
+            channel,
+            data_message,
+            attachments,
+            edit_message_timestamp,
+            message.arrived_at,
+        );
         (message, response)
     }
 
+    fn resend_message(
+        &self,
+        channel: &Channel,
+        message: &Message,
+    ) -> anyhow::Result<oneshot::Receiver<anyhow::Result<()>>> {
+        let (body, body_ranges, long_text) = match message.message.as_deref() {
+            Some(text) => {
+                let (body, long_text) = attachment::split_long_text(text);
+                let body_ranges = attachment::truncated_body_ranges(&body, &message.body_ranges);
+                (Some(body), body_ranges, long_text)
+            }
+            None => (None, message.body_ranges.clone(), None),
+        };
+        let data_message = DataMessage {
+            body,
+            body_ranges: body_ranges.iter().map(From::from).collect(),
+            quote: message.quote.as_deref().map(quote_of),
+            expire_timer: message.expire_timer,
+            ..Default::default()
+        };
+        // without all of its attachments, the message is not sent at all
+        let attachments = long_text
+            .map(Ok)
+            .into_iter()
+            .chain(
+                message
+                    .attachments
+                    .iter()
+                    .map(|attachment| attachment::load(&self.files, attachment)),
+            )
+            .collect::<anyhow::Result<_>>()?;
+        // the original timestamp is reused, so that the recipients can deduplicate the message
+        Ok(self.spawn_send(
+            channel,
+            data_message,
+            attachments,
+            message.edit,
+            message.arrived_at,
+        ))
+    }
+
     fn send_reaction(&self, channel: &Channel, message: &Message, emoji: String, remove: bool) {
         let timestamp = utc_now_timestamp_msec();
         let target_author_uuid = message.from_id;
@@ -328,6 +478,107 @@ impl SignalManager for PresageManager {
         }
     }
 
+    fn send_delete(&self, channel: &Channel, message: &Message) {
+        let timestamp = utc_now_timestamp_msec();
+        let target_sent_timestamp = message.arrived_at;
+
+        let mut data_message = DataMessage {
+            delete: Some(Delete {
+                target_sent_timestamp: Some(target_sent_timestamp),
+            }),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        };
+
+        match (channel.id, channel.group_data.as_ref()) {
+            (ChannelId::User(uuid), _) => {
+                let mut manager = self.manager.clone();
+                let body = ContentBody::DataMessage(data_message);
+                self.local_pool.spawn(move || async move {
+                    if let Err(e) = manager
+                        .send_message(ServiceId::Aci(uuid.into()), body, timestamp)
+                        .await
+                    {
+                        error!(
+                            "failed to delete message {} for {}: {}",
+                            target_sent_timestamp, uuid, e
+                        );
+                    }
+                });
+            }
+            (ChannelId::Group(_), Some(group_data)) => {
+                let mut manager = self.manager.clone();
+
+                let master_key_bytes = group_data.master_key_bytes.to_vec();
+                data_message.group_v2 = Some(GroupContextV2 {
+                    master_key: Some(master_key_bytes.clone()),
+                    revision: Some(group_data.revision),
+                    ..Default::default()
+                });
+
+                self.local_pool.spawn(move || async move {
+                    if let Err(e) = manager
+                        .send_message_to_group(&master_key_bytes, data_message, timestamp)
+                        .await
+                    {
+                        error!(
+                            "failed to delete group message {}: {}",
+                            target_sent_timestamp, e
+                        );
+                    }
+                });
+            }
+            _ => {
+                error!("cannot send to broken channel without group data");
+            }
+        }
+    }
+
+    fn send_typing(&self, channel: &Channel, action: TypingAction) {
+        let timestamp = utc_now_timestamp_msec();
+        let action = match action {
+            TypingAction::Started => typing_message::Action::Started,
+            TypingAction::Stopped => typing_message::Action::Stopped,
+        };
+        let mut typing_message = TypingMessage {
+            timestamp: Some(timestamp),
+            action: Some(action.into()),
+            group_id: None,
+        };
+
+        match (channel.id, channel.group_data.as_ref()) {
+            (ChannelId::User(uuid), _) => {
+                let mut manager = self.manager.clone();
+                let body = ContentBody::TypingMessage(typing_message);
+                self.local_pool.spawn(move || async move {
+                    if let Err(error) = manager
+                        .send_message(ServiceId::Aci(uuid.into()), body, timestamp)
+                        .await
+                    {
+                        error!(dest =% uuid, %error, "failed to send typing indicator");
+                    }
+                });
+            }
+            (ChannelId::Group(group_id), Some(group_data)) => {
+                let mut manager = self.manager.clone();
+                let master_key_bytes = group_data.master_key_bytes.to_vec();
+                typing_message.group_id = Some(group_id.to_vec());
+                let body = ContentBody::TypingMessage(typing_message);
+                self.local_pool.spawn(move || async move {
+                    if let Err(error) = manager
+                        .send_message_to_group(&master_key_bytes, body, timestamp)
+                        .await
+                    {
+                        error!(%error, "failed to send group typing indicator");
+                    }
+                });
+            }
+            _ => {
+                error!("cannot send to broken channel without group data");
+            }
+        }
+    }
+
     async fn resolve_profile_name(
         &mut self,
         id: Uuid,
@@ -404,6 +655,16 @@ impl SignalManager for PresageManager {
     }
 }
 
+fn quote_of(message: &Message) -> Quote {
+    Quote {
+        id: Some(message.arrived_at),
+        author_aci: Some(message.from_id.to_string()),
+        text: message.message.clone(),
+        body_ranges: message.body_ranges.iter().map(From::from).collect(),
+        ..Default::default()
+    }
+}
+
 async fn upload_attachments(
     manager: &presage::Manager<SqliteStore, Registered>,
     attachments: Vec<(AttachmentSpec, Vec<u8>)>,
@@ -14,7 +14,9 @@ use tokio::sync::oneshot;
 use tokio_stream::Stream;
 use uuid::Uuid;
 
-use crate::data::{Channel, GroupData, Message};
+use crate::attachment_files::AttachmentFiles;
+use crate::data::{Channel, GroupData, Message, TypingAction};
+use crate::mention::Mention;
 use crate::receipt::Receipt;
 
 use super::{GroupMasterKeyBytes, ProfileKeyBytes};
@@ -26,6 +28,9 @@ pub trait SignalManager {
 
     fn user_id(&self) -> Uuid;
 
+    /// Access to the saved attachment files
+    fn attachment_files(&self) -> AttachmentFiles;
+
     async fn resolve_group(
         &mut self,
         master_key_bytes: GroupMasterKeyBytes,
@@ -36,19 +41,56 @@ pub trait SignalManager {
         attachment_pointer: AttachmentPointer,
     ) -> anyhow::Result<Attachment>;
 
+    /// Downloads and saves the attachments in the background
+    ///
+    /// The results are in the order of the attachment pointers.
+    fn download_attachments(
+        &self,
+        attachment_pointers: Vec<AttachmentPointer>,
+    ) -> oneshot::Receiver<Vec<anyhow::Result<Attachment>>>;
+
     fn send_receipt(&self, sender_uuid: Uuid, timestamps: Vec<u64>, receipt: Receipt);
 
+    /// Builds the message like [`Self::send_text`] without sending it
+    ///
+    /// The message is sent later via [`Self::resend_message`].
+    fn compose_text(
+        &self,
+        channel: &Channel,
+        text: String,
+        mentions: &[Mention],
+        quote_message: Option<&Message>,
+        edit_message_timestamp: Option<u64>,
+        attachments: Vec<(AttachmentSpec, Vec<u8>)>,
+    ) -> Message;
+
     fn send_text(
         &self,
         channel: &Channel,
         text: String,
+        mentions: &[Mention],
         quote_message: Option<&Message>,
         edit_message_timestamp: Option<u64>,
         attachments: Vec<(AttachmentSpec, Vec<u8>)>,
     ) -> (Message, oneshot::Receiver<anyhow::Result<()>>);
 
+    /// Sends the stored message again with its original timestamp
+    ///
+    /// Fails if the saved attachments of the message cannot be loaded.
+    fn resend_message(
+        &self,
+        channel: &Channel,
+        message: &Message,
+    ) -> anyhow::Result<oneshot::Receiver<anyhow::Result<()>>>;
+
     fn send_reaction(&self, channel: &Channel, message: &Message, emoji: String, remove: bool);
 
+    /// Deletes the message for everyone in the channel
+    fn send_delete(&self, channel: &Channel, message: &Message);
+
+    /// Notifies the channel that we started or stopped typing
+    fn send_typing(&self, channel: &Channel, action: TypingAction);
+
     async fn profile_name(&self, id: Uuid) -> Option<String>;
 
     /// Resolves contact name from user's profile via Signal server
@@ -82,3 +124,16 @@ pub struct Attachment {
     pub filename: PathBuf,
     pub size: u32,
 }
+
+/// Attachment which was not downloaded yet
+///
+/// The attachment is downloaded on request by the kept attachment pointer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingAttachment {
+    pub id: String,
+    pub content_type: String,
+    pub size: u32,
+    /// Protobuf encoded attachment pointer
+    pub pointer: Vec<u8>,
+}
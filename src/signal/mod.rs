@@ -9,16 +9,22 @@ use std::path::Path;
 use anyhow::{Context as _, anyhow};
 use futures_channel::oneshot;
 use image::Luma;
+use presage::store::StateStore as _;
 use presage::{libsignal_service::configuration::SignalServers, model::identity::OnNewIdentity};
 use presage_store_sqlite::SqliteStore;
 use tracing::{error, info};
 use url::Url;
+use uuid::Uuid;
 
+use crate::attachment_files::AttachmentFiles;
 use crate::{config::Config, passphrase::Passphrase};
 
+pub(crate) use self::attachment::{
+    LONG_TEXT_CONTENT_TYPE, pending as pending_attachment, save as save_attachment,
+};
 use self::r#impl::PresageManager;
 pub use self::local_pool::LocalPool;
-pub use self::manager::{Attachment, ResolvedGroup, SignalManager};
+pub use self::manager::{Attachment, PendingAttachment, ResolvedGroup, SignalManager};
 
 // TODO: these should be either re-exported from presage/libsignal-service
 const PROFILE_KEY_LEN: usize = 32;
@@ -36,17 +42,11 @@ pub async fn ensure_linked_device(
     config: &Config,
     passphrase: &Passphrase,
 ) -> anyhow::Result<Box<dyn SignalManager + Send>> {
-    let path = config.signal_db_path();
-    info!(path =% path.display(), "opening signal storage");
-    let url = Url::from_file_path(&path)
-        .map_err(|_| anyhow!("failed to convert path '{}' to file url", path.display()))?;
-    let store = SqliteStore::open_with_passphrase(
-        url.as_str(),
-        Some(passphrase.as_ref()),
-        OnNewIdentity::Trust,
-    )
-    .await
-    .with_context(|| format!("failed to open signal storage at: {}", path.display()))?;
+    let store = open_store(config, passphrase).await?;
+
+    let files = AttachmentFiles::open(&config.data_dir, passphrase)
+        .context("failed to open attachment files")?;
+    files.migrate(&config.data_dir);
 
     if !relink {
         match presage::Manager::load_registered(store.clone()).await {
@@ -55,25 +55,52 @@ pub async fn ensure_linked_device(
                 Ok(Box::new(PresageManager::new(
                     manager,
                     config.data_dir.clone(),
+                    files,
                     local_pool,
                 )))
             }
             Err(presage::Error::NotYetRegisteredError) => {
-                relink_device(local_pool, config, store).await
+                relink_device(local_pool, config, store, files).await
             }
             Err(error) => Err(error).context(
                 "error loading manager. Try again later or run with --relink to force relink",
             ),
         }
     } else {
-        relink_device(local_pool, config, store).await
+        relink_device(local_pool, config, store, files).await
     }
 }
 
+/// Id of the user of the linked device, without linking a new device if there is none
+pub async fn registered_user_id(config: &Config, passphrase: &Passphrase) -> anyhow::Result<Uuid> {
+    let store = open_store(config, passphrase).await?;
+    let registration_data = store
+        .load_registration_data()
+        .await
+        .context("failed to load registration data")?
+        .context("no linked device; run gurk first to link it")?;
+    Ok(registration_data.service_ids.aci)
+}
+
+async fn open_store(config: &Config, passphrase: &Passphrase) -> anyhow::Result<SqliteStore> {
+    let path = config.signal_db_path();
+    info!(path =% path.display(), "opening signal storage");
+    let url = Url::from_file_path(&path)
+        .map_err(|_| anyhow!("failed to convert path '{}' to file url", path.display()))?;
+    SqliteStore::open_with_passphrase(
+        url.as_str(),
+        Some(passphrase.as_ref()),
+        OnNewIdentity::Trust,
+    )
+    .await
+    .with_context(|| format!("failed to open signal storage at: {}", path.display()))
+}
+
 async fn relink_device(
     local_pool: LocalPool,
     config: &Config,
     store: SqliteStore,
+    files: AttachmentFiles,
 ) -> anyhow::Result<Box<dyn SignalManager + Send>> {
     // explicit relink => link device
     let at_hostname = hostname::get()
@@ -113,6 +140,7 @@ async fn relink_device(
     Ok(Box::new(PresageManager::new(
         manager,
         config.data_dir.clone(),
+        files,
         local_pool,
     )))
 }
@@ -13,16 +13,32 @@ use tokio::sync::oneshot;
 use tokio_stream::Stream;
 use uuid::Uuid;
 
-use crate::data::{Channel, GroupData, Message};
+use crate::attachment_files::AttachmentFiles;
+use crate::data::{Channel, GroupData, Message, TypingAction};
+use crate::mention::Mention;
 use crate::receipt::Receipt;
 use crate::util::utc_now_timestamp_msec;
 
 use super::{Attachment, GroupMasterKeyBytes, ProfileKeyBytes, ResolvedGroup, SignalManager};
 
+fn saved_attachment(attachment_pointer: AttachmentPointer) -> Attachment {
+    let id = match attachment_pointer.attachment_identifier.unwrap() {
+        AttachmentIdentifier::CdnId(id) => id.to_string(),
+        AttachmentIdentifier::CdnKey(id) => id,
+    };
+    Attachment {
+        id,
+        content_type: attachment_pointer.content_type.unwrap(),
+        filename: "somefile".to_string().into(),
+        size: attachment_pointer.size.unwrap(),
+    }
+}
+
 /// Signal manager mock which does not send any messages.
 pub struct SignalManagerMock {
     user_id: Uuid,
     pub sent_messages: Rc<RefCell<Vec<Message>>>,
+    pub sent_receipts: Rc<RefCell<Vec<(Uuid, Vec<u64>, Receipt)>>>,
 }
 
 impl SignalManagerMock {
@@ -30,6 +46,7 @@ impl SignalManagerMock {
         Self {
             user_id: Uuid::nil(),
             sent_messages: Default::default(),
+            sent_receipts: Default::default(),
         }
     }
 }
@@ -46,6 +63,10 @@ impl SignalManager for SignalManagerMock {
         self.user_id
     }
 
+    fn attachment_files(&self) -> AttachmentFiles {
+        AttachmentFiles::default()
+    }
+
     async fn resolve_group(
         &mut self,
         master_key_bytes: super::GroupMasterKeyBytes,
@@ -65,29 +86,39 @@ impl SignalManager for SignalManagerMock {
         &mut self,
         attachment_pointer: AttachmentPointer,
     ) -> anyhow::Result<Attachment> {
-        let id = match attachment_pointer.attachment_identifier.unwrap() {
-            AttachmentIdentifier::CdnId(id) => id.to_string(),
-            AttachmentIdentifier::CdnKey(id) => id,
-        };
-        Ok(Attachment {
-            id,
-            content_type: attachment_pointer.content_type.unwrap(),
-            filename: "somefile".to_string().into(),
-            size: attachment_pointer.size.unwrap(),
-        })
+        Ok(saved_attachment(attachment_pointer))
+    }
+
+    fn download_attachments(
+        &self,
+        attachment_pointers: Vec<AttachmentPointer>,
+    ) -> oneshot::Receiver<Vec<anyhow::Result<Attachment>>> {
+        let (tx, rx) = oneshot::channel();
+        let results = attachment_pointers
+            .into_iter()
+            .map(|attachment_pointer| Ok(saved_attachment(attachment_pointer)))
+            .collect();
+        let _ = tx.send(results);
+        rx
     }
 
-    fn send_receipt(&self, _: Uuid, _: Vec<u64>, _: Receipt) {}
+    fn send_receipt(&self, sender_uuid: Uuid, timestamps: Vec<u64>, receipt: Receipt) {
+        self.sent_receipts
+            .borrow_mut()
+            .push((sender_uuid, timestamps, receipt));
+    }
 
-    fn send_text(
+    fn compose_text(
         &self,
-        _channel: &Channel,
+        channel: &Channel,
         text: String,
+        mentions: &[Mention],
         quote_message: Option<&Message>,
         _edit_message_timestamp: Option<u64>,
         _attachments: Vec<(AttachmentSpec, Vec<u8>)>,
-    ) -> (Message, oneshot::Receiver<anyhow::Result<()>>) {
-        let message: String = crate::emoji::replace_shortcodes(&text).into_owned();
+    ) -> Message {
+        let message = crate::emoji::replace_shortcodes(&text);
+        let (message, body_ranges) = crate::markup::parse_with_mentions(&message, mentions);
         let timestamp = utc_now_timestamp_msec();
         let quote = quote_message.map(|message| Quote {
             id: Some(message.arrived_at),
@@ -96,28 +127,71 @@ impl SignalManager for SignalManagerMock {
             ..Default::default()
         });
         let quote_message = quote.and_then(Message::from_quote).map(Box::new);
-        let message = Message {
+        let mut message = Message {
             from_id: self.user_id(),
             message: Some(message),
             arrived_at: timestamp,
             quote: quote_message,
             attachments: Default::default(),
+            pending_attachments: Default::default(),
             reactions: Default::default(),
             receipt: Receipt::Sent,
-            body_ranges: Default::default(),
-            send_failed: Default::default(),
+            body_ranges,
+            send_state: Default::default(),
             edit: Default::default(),
             edited: Default::default(),
+            deleted: Default::default(),
+            expire_timer: channel.expire_timer,
+            expires_at: Default::default(),
         };
+        message.start_expiry(timestamp);
+        message
+    }
+
+    fn send_text(
+        &self,
+        channel: &Channel,
+        text: String,
+        mentions: &[Mention],
+        quote_message: Option<&Message>,
+        edit_message_timestamp: Option<u64>,
+        attachments: Vec<(AttachmentSpec, Vec<u8>)>,
+    ) -> (Message, oneshot::Receiver<anyhow::Result<()>>) {
+        let message = self.compose_text(
+            channel,
+            text,
+            mentions,
+            quote_message,
+            edit_message_timestamp,
+            attachments,
+        );
         self.sent_messages.borrow_mut().push(message.clone());
         let (tx, rx) = oneshot::channel();
         let _ = tx.send(Ok(()));
         (message, rx)
     }
 
+    fn resend_message(
+        &self,
+        _channel: &Channel,
+        message: &Message,
+    ) -> anyhow::Result<oneshot::Receiver<anyhow::Result<()>>> {
+        for attachment in &message.attachments {
+            self.attachment_files().read(&attachment.filename)?;
+        }
+        self.sent_messages.borrow_mut().push(message.clone());
+        let (tx, rx) = oneshot::channel();
+        let _ = tx.send(Ok(()));
+        Ok(rx)
+    }
+
     fn send_reaction(&self, _channel: &Channel, _message: &Message, _emoji: String, _remove: bool) {
     }
 
+    fn send_delete(&self, _channel: &Channel, _message: &Message) {}
+
+    fn send_typing(&self, _channel: &Channel, _action: TypingAction) {}
+
     async fn resolve_profile_name(
         &mut self,
         _id: Uuid,
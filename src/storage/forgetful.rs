@@ -4,7 +4,7 @@ use uuid::Uuid;
 
 use crate::data::{Channel, ChannelId, Message};
 
-use super::{MessageId, Metadata, Storage};
+use super::{AttachmentFile, MessageId, Metadata, SearchQuery, Storage};
 
 /// A storage which actually does not store anything, therefore forgetful.
 pub struct ForgetfulStorage;
@@ -44,6 +44,35 @@ impl Storage for ForgetfulStorage {
         Cow::Owned(message)
     }
 
+    fn delete_message(&mut self, _message_id: MessageId) {}
+
+    fn expiring_messages(&self) -> Box<dyn Iterator<Item = (MessageId, u64)> + '_> {
+        Box::new(std::iter::empty())
+    }
+
+    fn outbox(&self) -> Box<dyn Iterator<Item = MessageId> + '_> {
+        Box::new(std::iter::empty())
+    }
+
+    fn search(
+        &self,
+        _query: &SearchQuery,
+        _limit: usize,
+    ) -> Box<dyn Iterator<Item = MessageId> + '_> {
+        Box::new(std::iter::empty())
+    }
+
+    fn attachment_files(&self) -> Box<dyn Iterator<Item = AttachmentFile> + '_> {
+        Box::new(std::iter::empty())
+    }
+
+    fn attachment_copies(
+        &self,
+        _attachment_id: &str,
+    ) -> Box<dyn Iterator<Item = AttachmentFile> + '_> {
+        Box::new(std::iter::empty())
+    }
+
     fn names(&self) -> Box<dyn Iterator<Item = (Uuid, Cow<'_, str>)> + '_> {
         Box::new(std::iter::empty())
     }
@@ -66,7 +95,7 @@ impl Storage for ForgetfulStorage {
 
     fn save(&mut self) {}
 
-    fn message_channel(&self, _arrived_at: u64) -> Option<ChannelId> {
+    fn message_channel(&self, _from_id: Uuid, _arrived_at: u64) -> Option<ChannelId> {
         None
     }
 }
@@ -1,43 +1,139 @@
 use std::borrow::Cow;
-use std::collections::BTreeMap;
 use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
 
 use uuid::Uuid;
 
 use crate::data::{Channel, ChannelId, Message};
+use crate::signal::Attachment;
 
-use super::{MessageId, Metadata, Storage};
+use super::{AttachmentFile, MessageId, MessagePosition, Metadata, SearchQuery, Storage};
+
+/// Default maximum number of cached messages per channel
+pub const DEFAULT_MESSAGES_CAPACITY: usize = 5_000;
 
 /// Caches the data of the underlying Storage in memory
 ///
+/// Channels, names, metadata and the expiration times of messages are cached completely. Of the
+/// messages, only the latest ones of
+/// each channel are cached: the ones stored in this session and the ones loaded by
+/// [`Storage::load_messages_before`], up to a capacity per channel. Other messages are read from
+/// the underlying storage.
+///
 /// The following data is NOT cached:
 ///
-/// * edits
+/// * edits, except for the ones stored in this session
 pub struct MemCache<S: Storage> {
     channels: Vec<Channel>,
     channels_index: BTreeMap<ChannelId, usize>,
-    messages: BTreeMap<ChannelId, Vec<Message>>,
-    messages_index: BTreeMap<MessageId, usize>,
+    messages: BTreeMap<ChannelId, CachedMessages>,
+    /// Maximum number of cached messages per channel
+    messages_capacity: usize,
+    expiring: ExpiryIndex,
     names: BTreeMap<Uuid, String>,
     metadata: Metadata,
     storage: S,
 }
 
+/// Messages with a started expiration timer, except for edits
+///
+/// Allows to find the expired messages without reading the underlying storage.
+#[derive(Debug, Default)]
+struct ExpiryIndex {
+    by_expires_at: BTreeSet<(u64, MessageId)>,
+    expires_at: BTreeMap<MessageId, u64>,
+}
+
+impl ExpiryIndex {
+    fn update(&mut self, message_id: MessageId, expires_at: Option<u64>) {
+        if let Some(prev) = self.expires_at.remove(&message_id) {
+            self.by_expires_at.remove(&(prev, message_id));
+        }
+        if let Some(expires_at) = expires_at {
+            self.expires_at.insert(message_id, expires_at);
+            self.by_expires_at.insert((expires_at, message_id));
+        }
+    }
+
+    fn expired(&self, now: u64) -> impl Iterator<Item = MessageId> + '_ {
+        self.by_expires_at
+            .iter()
+            .take_while(move |&&(expires_at, _)| expires_at <= now)
+            .map(|&(_, message_id)| message_id)
+    }
+}
+
+/// Latest messages of a channel
+///
+/// Contains all messages of the channel (except for edits) which are positioned at or after the
+/// first cached message.
+#[derive(Debug, Default)]
+struct CachedMessages {
+    /// Messages by arrived_at and sender
+    messages: BTreeMap<MessagePosition, Message>,
+    /// Whether all messages of the channel are cached
+    is_complete: bool,
+}
+
+impl CachedMessages {
+    fn complete() -> Self {
+        Self {
+            messages: BTreeMap::new(),
+            is_complete: true,
+        }
+    }
+
+    fn first(&self) -> Option<MessagePosition> {
+        self.messages.keys().next().copied()
+    }
+
+    fn get(&self, message_id: MessageId) -> Option<&Message> {
+        self.messages.get(&MessagePosition::from(message_id))
+    }
+
+    fn insert(&mut self, message: Message) {
+        self.messages.insert(MessagePosition::of(&message), message);
+    }
+
+    /// Whether a message at this position belongs to the cached range of messages
+    fn covers(&self, position: MessagePosition) -> bool {
+        self.is_complete || self.first().is_some_and(|first| first <= position)
+    }
+
+    /// Cached messages positioned before `before` if they contain the page of `limit` messages
+    fn page(
+        &self,
+        before: MessagePosition,
+        limit: usize,
+    ) -> Option<impl DoubleEndedIterator<Item = &Message>> {
+        let page: Vec<&Message> = self
+            .messages
+            .range(..before)
+            .rev()
+            .map(|(_, message)| message)
+            .filter(|message| message.edit.is_none())
+            .take(limit)
+            .collect();
+        (self.is_complete || page.len() == limit).then(|| page.into_iter().rev())
+    }
+}
+
 impl<S: Storage> MemCache<S> {
     pub fn new(storage: S) -> Self {
+        Self::with_capacity(storage, DEFAULT_MESSAGES_CAPACITY)
+    }
+
+    /// Creates a cache which keeps at most `messages_capacity` messages per channel
+    ///
+    /// Messages are not loaded upfront, but on demand by [`Storage::load_messages_before`].
+    pub fn with_capacity(storage: S, messages_capacity: usize) -> Self {
         let mut channels: Vec<Channel> = Vec::new();
         let mut channels_index = BTreeMap::new();
-        let mut messages: BTreeMap<ChannelId, Vec<Message>> = BTreeMap::new();
-        let mut messages_index: BTreeMap<MessageId, usize> = BTreeMap::new();
+        let mut messages: BTreeMap<ChannelId, CachedMessages> = BTreeMap::new();
 
         // build in-memory cache
         for channel in storage.channels() {
-            let channel_messages = messages.entry(channel.id).or_default();
-            for message in storage.messages(channel.id) {
-                let message_id = MessageId::new(channel.id, message.arrived_at);
-                messages_index.insert(message_id, channel_messages.len());
-                channel_messages.push(message.clone().into_owned());
-            }
+            messages.insert(channel.id, CachedMessages::default());
             channels_index.insert(channel.id, channels.len());
             channels.push(channel.clone().into_owned());
         }
@@ -49,16 +145,58 @@ impl<S: Storage> MemCache<S> {
 
         let metadata = storage.metadata().into_owned();
 
+        let mut expiring = ExpiryIndex::default();
+        for (message_id, expires_at) in storage.expiring_messages() {
+            expiring.update(message_id, Some(expires_at));
+        }
+
         Self {
             channels,
             channels_index,
             messages,
-            messages_index,
+            messages_capacity,
+            expiring,
             names,
             metadata,
             storage,
         }
     }
+
+    /// Adds the files of the attachments of the cached messages matching `filter` to the `stored`
+    /// ones
+    ///
+    /// The cached messages are also stored in the underlying storage, so the files are
+    /// deduplicated.
+    fn with_cached_files(
+        &self,
+        stored: impl Iterator<Item = AttachmentFile>,
+        filter: impl Fn(&Attachment) -> bool,
+    ) -> Box<dyn Iterator<Item = AttachmentFile> + '_> {
+        let mut files: BTreeSet<AttachmentFile> = stored.collect();
+        for (&channel_id, cached) in &self.messages {
+            for message in cached.messages.values() {
+                // edits are returned with the id of the edited message
+                let arrived_at = message.edit.unwrap_or(message.arrived_at);
+                let message_id = MessageId::new(channel_id, message.from_id, arrived_at);
+                let attachments = message
+                    .attachments
+                    .iter()
+                    .filter(|attachment| filter(attachment));
+                files.extend(attachments.map(|attachment| AttachmentFile {
+                    message_id,
+                    attachment_id: attachment.id.clone(),
+                    filename: attachment.filename.clone(),
+                }));
+            }
+        }
+        Box::new(files.into_iter())
+    }
+
+    fn is_cached(&self, message_id: MessageId) -> bool {
+        self.messages
+            .get(&message_id.channel_id)
+            .is_some_and(|cached| cached.get(message_id).is_some())
+    }
 }
 
 impl<S: Storage> Storage for MemCache<S> {
@@ -76,6 +214,10 @@ impl<S: Storage> Storage for MemCache<S> {
             Entry::Vacant(entry) => {
                 entry.insert(self.channels.len());
                 self.channels.push(channel.clone());
+                // a new channel has no messages yet
+                self.messages
+                    .entry(channel.id)
+                    .or_insert_with(CachedMessages::complete);
             }
             Entry::Occupied(entry) => {
                 let idx = *entry.get();
@@ -90,11 +232,77 @@ impl<S: Storage> Storage for MemCache<S> {
         &self,
         channel_id: ChannelId,
     ) -> Box<dyn DoubleEndedIterator<Item = Cow<'_, Message>> + '_> {
-        if let Some(messages) = self.messages.get(&channel_id) {
-            Box::new(messages.iter().map(Cow::Borrowed))
+        let Some(cached) = self.messages.get(&channel_id) else {
+            return Box::new(std::iter::empty());
+        };
+        let cached_messages = cached
+            .messages
+            .values()
+            .filter(|message| message.edit.is_none())
+            .map(Cow::Borrowed);
+        if cached.is_complete {
+            Box::new(cached_messages)
         } else {
-            Box::new(std::iter::empty())
+            // older messages are only read from the storage when they are reached
+            let before = cached.first().unwrap_or(MessagePosition::LATEST);
+            let older = std::iter::once_with(move || {
+                self.storage.messages_before(channel_id, before, usize::MAX)
+            })
+            .flatten();
+            Box::new(older.chain(cached_messages))
+        }
+    }
+
+    fn messages_before(
+        &self,
+        channel_id: ChannelId,
+        before: MessagePosition,
+        limit: usize,
+    ) -> Box<dyn DoubleEndedIterator<Item = Cow<'_, Message>> + '_> {
+        let Some(cached) = self.messages.get(&channel_id) else {
+            return Box::new(std::iter::empty());
+        };
+        match cached.page(before, limit) {
+            Some(page) => Box::new(page.map(Cow::Borrowed)),
+            None => self.storage.messages_before(channel_id, before, limit),
+        }
+    }
+
+    fn load_messages_before(
+        &mut self,
+        channel_id: ChannelId,
+        before: MessagePosition,
+        limit: usize,
+    ) -> Vec<MessageId> {
+        let cached = self.messages.entry(channel_id).or_default();
+        if let Some(page) = cached.page(before, limit) {
+            return page
+                .map(|message| MessageId::of(channel_id, message))
+                .collect();
+        }
+
+        let page: Vec<Message> = self
+            .storage
+            .messages_before(channel_id, before, limit)
+            .map(Cow::into_owned)
+            .collect();
+        let message_ids = page
+            .iter()
+            .map(|message| MessageId::of(channel_id, message))
+            .collect();
+
+        // only extend the cached messages if the page is adjacent to them
+        let is_adjacent = match cached.first() {
+            Some(first) => first <= before,
+            None => before == MessagePosition::LATEST,
+        };
+        if is_adjacent && cached.messages.len() + page.len() <= self.messages_capacity {
+            cached.is_complete = page.len() < limit;
+            for message in page {
+                cached.insert(message);
+            }
         }
+        message_ids
     }
 
     fn edits(
@@ -105,37 +313,100 @@ impl<S: Storage> Storage for MemCache<S> {
     }
 
     fn message(&self, message_id: MessageId) -> Option<Cow<'_, Message>> {
-        let messages = self.messages.get(&message_id.channel_id)?;
         let cached = self
-            .messages_index
-            .get(&message_id)
-            .and_then(|&idx| messages.get(idx).map(Cow::Borrowed));
+            .messages
+            .get(&message_id.channel_id)
+            .and_then(|cached| cached.get(message_id));
         if let Some(message) = cached {
-            Some(message)
+            Some(Cow::Borrowed(message))
         } else {
-            let message = self.storage.message(message_id)?;
-            Some(message)
+            self.storage.message(message_id)
         }
     }
 
     fn store_message(&mut self, channel_id: ChannelId, message: Message) -> Cow<'_, Message> {
-        let message_id = MessageId::new(channel_id, message.arrived_at);
-        match self.messages_index.entry(message_id) {
-            Entry::Vacant(entry) => {
-                let messages = self.messages.entry(channel_id).or_default();
-                entry.insert(messages.len());
-                messages.push(message.clone());
-            }
-            Entry::Occupied(entry) => {
-                let idx = *entry.get();
-                let messages = self.messages.entry(channel_id).or_default();
-                let stored_message = &mut messages[idx];
-                *stored_message = message.clone();
+        if message.edit.is_none() {
+            self.expiring
+                .update(MessageId::of(channel_id, &message), message.expires_at);
+        }
+        let cached = self
+            .messages
+            .entry(channel_id)
+            .or_insert_with(CachedMessages::complete);
+        // messages older than the cached ones are only stored in the underlying storage
+        if cached.covers(MessagePosition::of(&message)) {
+            cached.insert(message.clone());
+            if cached.messages.len() > self.messages_capacity {
+                cached.messages.pop_first();
+                cached.is_complete = false;
             }
         }
         self.storage.store_message(channel_id, message)
     }
 
+    fn delete_message(&mut self, message_id: MessageId) {
+        self.expiring.update(message_id, None);
+        if let Some(cached) = self.messages.get_mut(&message_id.channel_id) {
+            cached.messages.remove(&MessagePosition::from(message_id));
+            cached.messages.retain(|_, message| {
+                message.from_id != message_id.from_id || message.edit != Some(message_id.arrived_at)
+            });
+        }
+        self.storage.delete_message(message_id);
+    }
+
+    fn expiring_messages(&self) -> Box<dyn Iterator<Item = (MessageId, u64)> + '_> {
+        Box::new(
+            self.expiring
+                .expires_at
+                .iter()
+                .map(|(&message_id, &expires_at)| (message_id, expires_at)),
+        )
+    }
+
+    fn expired_messages(&self, now: u64) -> Box<dyn Iterator<Item = MessageId> + '_> {
+        Box::new(self.expiring.expired(now))
+    }
+
+    fn outbox(&self) -> Box<dyn Iterator<Item = MessageId> + '_> {
+        let cached = self.messages.iter().flat_map(|(&channel_id, cached)| {
+            cached
+                .messages
+                .values()
+                .filter(|message| message.send_state.is_some())
+                .map(move |message| MessageId::of(channel_id, message))
+        });
+        let uncached = self
+            .storage
+            .outbox()
+            .filter(|&message_id| !self.is_cached(message_id));
+        let mut outbox: Vec<MessageId> = cached.chain(uncached).collect();
+        outbox.sort_unstable_by_key(|message_id| message_id.arrived_at);
+        Box::new(outbox.into_iter())
+    }
+
+    fn search(
+        &self,
+        query: &SearchQuery,
+        limit: usize,
+    ) -> Box<dyn Iterator<Item = MessageId> + '_> {
+        self.storage.search(query, limit) // Search index is not cached
+    }
+
+    fn attachment_files(&self) -> Box<dyn Iterator<Item = AttachmentFile> + '_> {
+        self.with_cached_files(self.storage.attachment_files(), |_| true)
+    }
+
+    fn attachment_copies(
+        &self,
+        attachment_id: &str,
+    ) -> Box<dyn Iterator<Item = AttachmentFile> + '_> {
+        self.with_cached_files(
+            self.storage.attachment_copies(attachment_id),
+            |attachment| attachment.id == attachment_id,
+        )
+    }
+
     fn names(&self) -> Box<dyn Iterator<Item = (Uuid, Cow<'_, str>)> + '_> {
         Box::new(
             self.names
@@ -173,8 +444,180 @@ impl<S: Storage> Storage for MemCache<S> {
         self.storage.save();
     }
 
-    fn message_channel(&self, arrived_at: u64) -> Option<ChannelId> {
+    fn message_channel(&self, from_id: Uuid, arrived_at: u64) -> Option<ChannelId> {
         // message arrived_at to channel_id conversion is not cached
-        self.storage.message_channel(arrived_at)
+        self.storage.message_channel(from_id, arrived_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use uuid::uuid;
+
+    use crate::data::TypingSet;
+    use crate::passphrase::Passphrase;
+    use crate::storage::SqliteStorage;
+
+    use super::*;
+
+    fn arrived_at<'a>(messages: impl Iterator<Item = Cow<'a, Message>>) -> Vec<u64> {
+        messages.map(|message| message.arrived_at).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_memcache_bounded_messages() {
+        let url: Url = "sqlite::memory:".parse().unwrap();
+        let mut storage = SqliteStorage::open(&url, &Passphrase::new("secret").unwrap())
+            .await
+            .unwrap();
+        let user_id = Uuid::nil();
+        let channel_id = ChannelId::User(user_id);
+        storage.store_channel(Channel {
+            id: channel_id,
+            name: "direct-channel".to_owned(),
+            group_data: None,
+            unread_messages: 0,
+            muted: false,
+            typing: TypingSet::new(false),
+            expire_timer: None,
+            draft: None,
+        });
+        let text = |arrived_at| Message::text(user_id, arrived_at, "hello".to_owned());
+        for arrived_at in 1..=10 {
+            storage.store_message(channel_id, text(arrived_at));
+        }
+
+        let mut cache = MemCache::with_capacity(storage, 6);
+        let message_id = |arrived_at| MessageId::new(channel_id, user_id, arrived_at);
+        let before = |arrived_at| MessagePosition {
+            arrived_at,
+            from_id: user_id,
+        };
+        assert_eq!(
+            cache.load_messages_before(channel_id, MessagePosition::LATEST, 4),
+            [7, 8, 9, 10].map(message_id)
+        );
+        assert!(cache.is_cached(message_id(7)));
+        assert!(!cache.is_cached(message_id(6)));
+        assert_eq!(
+            arrived_at(cache.messages(channel_id)),
+            (1..=10).collect::<Vec<_>>()
+        );
+
+        // exceeding the capacity, the page is not cached
+        assert_eq!(
+            cache.load_messages_before(channel_id, before(7), 4),
+            [3, 4, 5, 6].map(message_id)
+        );
+        assert!(!cache.is_cached(message_id(6)));
+
+        // new messages evict the oldest cached ones
+        for arrived_at in 11..=13 {
+            cache.store_message(channel_id, text(arrived_at));
+        }
+        assert!(!cache.is_cached(message_id(7)));
+        assert!(cache.message(message_id(7)).is_some());
+        assert_eq!(
+            arrived_at(cache.messages_before(channel_id, before(9), 3)),
+            [6, 7, 8]
+        );
+        assert_eq!(
+            arrived_at(cache.messages_before(channel_id, MessagePosition::LATEST, 3)),
+            [11, 12, 13]
+        );
+        assert_eq!(
+            arrived_at(cache.messages_rev(channel_id)),
+            (1..=13).rev().collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_memcache_messages_at_the_same_time() {
+        let url: Url = "sqlite::memory:".parse().unwrap();
+        let mut storage = SqliteStorage::open(&url, &Passphrase::new("secret").unwrap())
+            .await
+            .unwrap();
+        let channel_id = ChannelId::Group(*b"messages-at-the-same-time-------");
+        let senders = [
+            uuid!("00000000-0000-0000-0000-00000000000a"),
+            uuid!("00000000-0000-0000-0000-00000000000b"),
+            uuid!("00000000-0000-0000-0000-00000000000c"),
+        ];
+        storage.store_message(channel_id, Message::text(senders[0], 1, "hi".to_owned()));
+        for from_id in senders {
+            storage.store_message(channel_id, Message::text(from_id, 2, "hi".to_owned()));
+        }
+        storage.store_message(channel_id, Message::text(senders[0], 3, "hi".to_owned()));
+
+        let mut cache = MemCache::with_capacity(storage, 6);
+        let message_id = |from_id, arrived_at| MessageId::new(channel_id, from_id, arrived_at);
+        assert_eq!(
+            cache.load_messages_before(channel_id, MessagePosition::LATEST, 2),
+            [message_id(senders[2], 2), message_id(senders[0], 3)]
+        );
+        // the older messages sent at the same time as the first cached one are not skipped
+        assert_eq!(arrived_at(cache.messages(channel_id)), [1, 2, 2, 2, 3]);
+
+        let before = MessagePosition::from(message_id(senders[2], 2));
+        assert_eq!(
+            cache.load_messages_before(channel_id, before, 2),
+            [message_id(senders[0], 2), message_id(senders[1], 2)]
+        );
+        assert!(cache.is_cached(message_id(senders[0], 2)));
+        let before = MessagePosition::from(message_id(senders[0], 2));
+        assert_eq!(
+            arrived_at(cache.messages_before(channel_id, before, 2)),
+            [1]
+        );
+        assert_eq!(arrived_at(cache.messages_rev(channel_id)), [3, 2, 2, 2, 1]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_memcache_expired_messages() {
+        let url: Url = "sqlite::memory:".parse().unwrap();
+        let mut storage = SqliteStorage::open(&url, &Passphrase::new("secret").unwrap())
+            .await
+            .unwrap();
+        let user_id = Uuid::nil();
+        let channel_id = ChannelId::User(user_id);
+        let expiring = |arrived_at, expires_at| Message {
+            expires_at: Some(expires_at),
+            ..Message::text(user_id, arrived_at, "hello".to_owned())
+        };
+        storage.store_message(channel_id, expiring(1, 100));
+        storage.store_message(channel_id, expiring(2, 300));
+        storage.store_message(channel_id, Message::text(user_id, 3, "hi".to_owned()));
+
+        // the expiration times of the not cached messages are known
+        let mut cache = MemCache::with_capacity(storage, 6);
+        let message_id = |arrived_at| MessageId::new(channel_id, user_id, arrived_at);
+        assert!(!cache.is_cached(message_id(1)));
+        assert_eq!(cache.expiring_messages().count(), 2);
+        assert_eq!(
+            cache.expired_messages(100).collect::<Vec<_>>(),
+            [message_id(1)]
+        );
+
+        cache.store_message(channel_id, expiring(4, 200));
+        // edits are not included
+        cache.store_message(
+            channel_id,
+            Message {
+                edit: Some(4),
+                ..expiring(5, 0)
+            },
+        );
+        assert_eq!(
+            cache.expired_messages(299).collect::<Vec<_>>(),
+            [message_id(1), message_id(4)]
+        );
+
+        cache.delete_message(message_id(1));
+        cache.store_message(channel_id, Message::text(user_id, 4, "hi".to_owned()));
+        assert_eq!(
+            cache.expired_messages(u64::MAX).collect::<Vec<_>>(),
+            [message_id(2)]
+        );
     }
 }
@@ -4,6 +4,7 @@ mod memcache;
 mod sql;
 
 use std::borrow::Cow;
+use std::path::PathBuf;
 
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
@@ -39,10 +40,45 @@ pub trait Storage {
         &self,
         channel_id: ChannelId,
     ) -> Box<dyn DoubleEndedIterator<Item = Cow<'_, Message>> + '_>;
+    /// Up to `limit` latest messages positioned before `before`, sorted by arrived_at in ascending
+    /// order
+    ///
+    /// No edited messages must be included.
+    fn messages_before(
+        &self,
+        channel_id: ChannelId,
+        before: MessagePosition,
+        limit: usize,
+    ) -> Box<dyn DoubleEndedIterator<Item = Cow<'_, Message>> + '_> {
+        let mut messages: Vec<_> = self
+            .messages(channel_id)
+            .rev()
+            .skip_while(|message| MessagePosition::of(message) >= before)
+            .take(limit)
+            .collect();
+        messages.reverse();
+        Box::new(messages.into_iter())
+    }
+    /// Loads up to `limit` latest messages positioned before `before` and returns their ids in
+    /// ascending order of arrived_at
+    ///
+    /// Same as [`Storage::messages_before`], except that a caching storage keeps the loaded
+    /// messages in memory.
+    fn load_messages_before(
+        &mut self,
+        channel_id: ChannelId,
+        before: MessagePosition,
+        limit: usize,
+    ) -> Vec<MessageId> {
+        self.messages_before(channel_id, before, limit)
+            .map(|message| MessageId::of(channel_id, &message))
+            .collect()
+    }
     /// Gets the message by id
     fn message(&self, message_id: MessageId) -> Option<Cow<'_, Message>>;
 
-    fn message_channel(&self, arrived_at: u64) -> Option<ChannelId>;
+    /// Gets the channel of the message sent by `from_id` at `arrived_at`
+    fn message_channel(&self, from_id: Uuid, arrived_at: u64) -> Option<ChannelId>;
 
     fn edits(
         &self,
@@ -61,13 +97,16 @@ pub trait Storage {
         target_sent_timestampt: u64,
         message: Message,
     ) -> Option<Cow<'_, Message>> {
-        // Note: target_sent_timestamp points to the previous edit or the original message
-        let prev_edited = self.message(MessageId::new(channel_id, target_sent_timestampt))?;
+        // Note: target_sent_timestamp points to the previous edit or the original message, which
+        // are sent by the same user as the edit
+        let from_id = message.from_id;
+        let prev_edited =
+            self.message(MessageId::new(channel_id, from_id, target_sent_timestampt))?;
 
         // get original message
         let mut original = if let Some(arrived_at) = prev_edited.edit {
             // previous edit => get original message
-            self.message(MessageId::new(channel_id, arrived_at))?
+            self.message(MessageId::new(channel_id, from_id, arrived_at))?
                 .into_owned()
         } else {
             // original message => first edit
@@ -84,6 +123,7 @@ pub trait Storage {
 
         // store the incoming edit
         let body = message.message.clone();
+        let body_ranges = message.body_ranges.clone();
         self.store_message(
             channel_id,
             Message {
@@ -94,10 +134,54 @@ pub trait Storage {
 
         // override the body of the original message
         original.message = body;
+        original.body_ranges = body_ranges;
         original.edited = true;
         Some(self.store_message(channel_id, original))
     }
 
+    /// Deletes the message and all its edits
+    fn delete_message(&mut self, message_id: MessageId);
+
+    /// Messages whose expiration timer is started, with the time at which they expire (in
+    /// milliseconds since epoch)
+    ///
+    /// No edited messages are included.
+    fn expiring_messages(&self) -> Box<dyn Iterator<Item = (MessageId, u64)> + '_>;
+
+    /// Messages whose expiration timer elapsed at `now` (in milliseconds since epoch)
+    ///
+    /// No edited messages are included.
+    fn expired_messages(&self, now: u64) -> Box<dyn Iterator<Item = MessageId> + '_> {
+        Box::new(
+            self.expiring_messages()
+                .filter(move |&(_, expires_at)| expires_at <= now)
+                .map(|(message_id, _)| message_id),
+        )
+    }
+
+    /// Own messages which are not sent yet, sorted by arrived_at in ascending order
+    ///
+    /// Includes edits.
+    fn outbox(&self) -> Box<dyn Iterator<Item = MessageId> + '_>;
+
+    /// At most `limit` messages matching the `query` in all channels, sorted by arrived_at in
+    /// descending order
+    ///
+    /// No edited messages are included.
+    fn search(&self, query: &SearchQuery, limit: usize)
+    -> Box<dyn Iterator<Item = MessageId> + '_>;
+
+    /// Files of the attachments of all messages and edits
+    fn attachment_files(&self) -> Box<dyn Iterator<Item = AttachmentFile> + '_>;
+
+    /// Files of the attachments with the given id, see [`Storage::attachment_files`]
+    ///
+    /// These are the copies of the same attachment, or a single file shared by several messages.
+    fn attachment_copies(
+        &self,
+        attachment_id: &str,
+    ) -> Box<dyn Iterator<Item = AttachmentFile> + '_>;
+
     /// Names of contacts
     fn names(&self) -> Box<dyn Iterator<Item = (Uuid, Cow<'_, str>)> + '_>;
     /// Gets the name for the given contact `id`
@@ -115,32 +199,168 @@ pub trait Storage {
 
     /// Persists the data in the storage
     ///
+    /// Blocks until all data stored before the call is persisted. This is called before the app
+    /// quits.
+    ///
     /// ## Implementation note
     ///
-    /// The implementers of this trait, can persist for each store call, if it is efficient enough.
-    /// This methods must guarantee that the data is persisted in any case.
+    /// The implementers of this trait can persist the data in the background after each store
+    /// call. This methods must guarantee that the data is persisted in any case.
     fn save(&mut self);
 
     /// Returns `true` if this storage does not contains any channels and no names
     fn is_empty(&self) -> bool {
         self.channels().next().is_none() && self.names().next().is_none()
     }
+
+    /// Messages sorted by arrived_at in descending order, read page-wise via
+    /// [`Storage::messages_before`]
+    ///
+    /// Unlike [`Storage::messages`], older messages are only read when they are reached. No edited
+    /// messages are included.
+    fn messages_rev(
+        &self,
+        channel_id: ChannelId,
+    ) -> Box<dyn Iterator<Item = Cow<'_, Message>> + '_> {
+        let mut before = MessagePosition::LATEST;
+        let pages = std::iter::from_fn(move || {
+            let page: Vec<_> = self
+                .messages_before(channel_id, before, MESSAGES_PAGE_SIZE)
+                .collect();
+            before = MessagePosition::of(page.first()?);
+            Some(page.into_iter().rev())
+        });
+        Box::new(pages.flatten())
+    }
 }
 
-/// A message is identified by its channel and time of arrived in milliseconds
+/// Number of messages read at once by [`Storage::messages_rev`]
+const MESSAGES_PAGE_SIZE: usize = 1_000;
+
+/// A message is identified by its channel, its sender and its time of arrival in milliseconds
+///
+/// The time of arrival is the timestamp at which the message was sent, which is unique only per
+/// sender.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MessageId {
     pub channel_id: ChannelId,
     pub arrived_at: u64,
+    pub from_id: Uuid,
 }
 
 impl MessageId {
-    pub fn new(channel_id: ChannelId, arrived_at: u64) -> Self {
+    pub fn new(channel_id: ChannelId, from_id: Uuid, arrived_at: u64) -> Self {
         Self {
             channel_id,
             arrived_at,
+            from_id,
         }
     }
+
+    /// Id of the `message` in the channel
+    pub fn of(channel_id: ChannelId, message: &Message) -> Self {
+        Self::new(channel_id, message.from_id, message.arrived_at)
+    }
+}
+
+/// Position of a message in its channel, ordered by the time of arrival and then by the sender
+///
+/// Several senders may send messages at the same time, so pages of messages are delimited by the
+/// position of a message instead of only its time of arrival.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MessagePosition {
+    pub arrived_at: u64,
+    pub from_id: Uuid,
+}
+
+impl MessagePosition {
+    /// Position after all messages
+    pub const LATEST: Self = Self {
+        arrived_at: u64::MAX,
+        from_id: Uuid::max(),
+    };
+
+    /// Position of the `message`
+    pub fn of(message: &Message) -> Self {
+        Self {
+            arrived_at: message.arrived_at,
+            from_id: message.from_id,
+        }
+    }
+}
+
+impl From<MessageId> for MessagePosition {
+    fn from(message_id: MessageId) -> Self {
+        Self {
+            arrived_at: message_id.arrived_at,
+            from_id: message_id.from_id,
+        }
+    }
+}
+
+/// File of an attachment of a message
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AttachmentFile {
+    /// Message with the attachment
+    ///
+    /// The attachments of an edit are returned with the id of the edited message.
+    pub message_id: MessageId,
+    /// Id of the attachment, see [`crate::signal::Attachment::id`]
+    pub attachment_id: String,
+    pub filename: PathBuf,
+}
+
+/// Criteria of a message search
+///
+/// A message matches if it satisfies all criteria.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// Words which must all occur in the text of the message (as prefix of a word)
+    pub words: Vec<String>,
+    /// Possible senders of the message; any sender if `None`
+    pub from: Option<Vec<Uuid>>,
+    /// Possible channels of the message; any channel if `None`
+    pub channels: Option<Vec<ChannelId>>,
+    /// Whether the message must have at least one attachment
+    pub has_attachment: bool,
+    /// Exclusive upper bound of the arrival time in milliseconds
+    pub before: Option<u64>,
+    /// Inclusive lower bound of the arrival time in milliseconds
+    pub after: Option<u64>,
+}
+
+impl SearchQuery {
+    /// Whether the message of the channel satisfies all criteria
+    ///
+    /// The words are matched like by the full-text index of the sqlite storage: case-insensitively
+    /// as prefixes of the alphanumeric words of the text.
+    pub fn matches(&self, channel_id: ChannelId, message: &Message) -> bool {
+        let text = message
+            .message
+            .as_deref()
+            .unwrap_or_default()
+            .to_lowercase();
+        let text_words: Vec<&str> = alphanumeric_words(&text).collect();
+        self.words.iter().all(|word| {
+            alphanumeric_words(&word.to_lowercase())
+                .all(|word| text_words.iter().any(|text| text.starts_with(word)))
+        }) && self
+            .from
+            .as_ref()
+            .is_none_or(|from| from.contains(&message.from_id))
+            && self
+                .channels
+                .as_ref()
+                .is_none_or(|channels| channels.contains(&channel_id))
+            && (!self.has_attachment || !message.attachments.is_empty())
+            && self.before.is_none_or(|before| message.arrived_at < before)
+            && self.after.is_none_or(|after| message.arrived_at >= after)
+    }
+}
+
+fn alphanumeric_words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
 }
 
 /// Persisted metadata
@@ -1,6 +1,8 @@
 mod encoding;
 mod encrypt;
+mod pending;
 mod storage;
 mod util;
+mod writer;
 
 pub use storage::SqliteStorage;
@@ -0,0 +1,228 @@
+//! Writes which are queued but not committed yet
+//!
+//! Reads do not wait for the background task to commit the queued writes. Instead, they take the
+//! pending data relevant to them before querying the database, and overlay it on the result. Since
+//! a write is only removed from here after it is committed, the data is either in the database or
+//! here.
+
+use std::collections::BTreeMap;
+
+use uuid::Uuid;
+
+use crate::data::{Channel, ChannelId, Message};
+use crate::storage::{MessageId, MessagePosition, Metadata};
+
+use super::writer::Write;
+
+/// Latest queued data by key, together with the sequence number of its write
+#[derive(Default)]
+pub(super) struct Pending {
+    /// Sequence number of the last queued write
+    seq: u64,
+    channels: BTreeMap<ChannelId, (u64, Channel)>,
+    /// Stored messages and edits, or `None` if deleted
+    messages: BTreeMap<MessageId, (u64, Option<Message>)>,
+    names: BTreeMap<Uuid, (u64, String)>,
+    metadata: Option<(u64, Metadata)>,
+}
+
+impl Pending {
+    /// Records the queued write and returns its sequence number
+    pub(super) fn record(&mut self, write: &Write) -> u64 {
+        self.seq += 1;
+        let seq = self.seq;
+        match write {
+            Write::Channel(channel) => {
+                self.channels.insert(channel.id, (seq, channel.clone()));
+            }
+            Write::Message(channel_id, message) => {
+                let message_id = MessageId::of(*channel_id, message);
+                self.messages
+                    .insert(message_id, (seq, Some(message.clone())));
+            }
+            Write::DeleteMessage(message_id) => {
+                // edits are deleted together with the message
+                for (id, entry) in self.channel_messages_mut(message_id.channel_id) {
+                    let is_edit = entry.1.as_ref().is_some_and(|message| {
+                        id.from_id == message_id.from_id
+                            && message.edit == Some(message_id.arrived_at)
+                    });
+                    if is_edit {
+                        *entry = (seq, None);
+                    }
+                }
+                self.messages.insert(*message_id, (seq, None));
+            }
+            Write::Name(id, name) => {
+                self.names.insert(*id, (seq, name.clone()));
+            }
+            Write::Metadata(metadata) => {
+                self.metadata = Some((seq, metadata.clone()));
+            }
+        }
+        seq
+    }
+
+    /// Removes the data written up to and including the write with sequence number `seq`
+    pub(super) fn remove_committed(&mut self, seq: u64) {
+        self.channels.retain(|_, entry| entry.0 > seq);
+        self.messages.retain(|_, entry| entry.0 > seq);
+        self.names.retain(|_, entry| entry.0 > seq);
+        if self.metadata.as_ref().is_some_and(|entry| entry.0 <= seq) {
+            self.metadata = None;
+        }
+    }
+
+    pub(super) fn channels(&self) -> Vec<Channel> {
+        self.channels
+            .values()
+            .map(|(_, channel)| channel.clone())
+            .collect()
+    }
+
+    pub(super) fn channel(&self, channel_id: ChannelId) -> Option<Channel> {
+        self.channels
+            .get(&channel_id)
+            .map(|(_, channel)| channel.clone())
+    }
+
+    /// `Some(None)` if the message is deleted, and `None` if there is no pending write of it
+    pub(super) fn message(&self, message_id: MessageId) -> Option<Option<Message>> {
+        self.messages
+            .get(&message_id)
+            .map(|(_, message)| message.clone())
+    }
+
+    /// Ids of the pending messages, with the message if it is stored and matches `filter`
+    pub(super) fn messages(
+        &self,
+        filter: impl Fn(MessageId, &Message) -> bool,
+    ) -> Vec<(MessageId, Option<Message>)> {
+        filter_messages(self.messages.iter(), filter)
+    }
+
+    /// Same as [`Pending::messages`] restricted to the messages of the channel positioned before
+    /// `before`
+    pub(super) fn channel_messages(
+        &self,
+        channel_id: ChannelId,
+        before: MessagePosition,
+        filter: impl Fn(MessageId, &Message) -> bool,
+    ) -> Vec<(MessageId, Option<Message>)> {
+        let start = MessageId::new(channel_id, Uuid::nil(), 0);
+        let end = MessageId::new(channel_id, before.from_id, before.arrived_at);
+        filter_messages(self.messages.range(start..end), filter)
+    }
+
+    pub(super) fn names(&self) -> Vec<(Uuid, String)> {
+        self.names
+            .iter()
+            .map(|(&id, (_, name))| (id, name.clone()))
+            .collect()
+    }
+
+    pub(super) fn name(&self, id: Uuid) -> Option<String> {
+        self.names.get(&id).map(|(_, name)| name.clone())
+    }
+
+    pub(super) fn metadata(&self) -> Option<Metadata> {
+        self.metadata.as_ref().map(|(_, metadata)| metadata.clone())
+    }
+
+    fn channel_messages_mut(
+        &mut self,
+        channel_id: ChannelId,
+    ) -> impl Iterator<Item = (&MessageId, &mut (u64, Option<Message>))> {
+        let start = MessageId::new(channel_id, Uuid::nil(), 0);
+        let end = MessageId::new(channel_id, Uuid::max(), u64::MAX);
+        self.messages.range_mut(start..=end)
+    }
+}
+
+fn filter_messages<'a>(
+    messages: impl Iterator<Item = (&'a MessageId, &'a (u64, Option<Message>))>,
+    filter: impl Fn(MessageId, &Message) -> bool,
+) -> Vec<(MessageId, Option<Message>)> {
+    messages
+        .map(|(&message_id, (_, message))| {
+            let message = message
+                .as_ref()
+                .filter(|message| filter(message_id, message))
+                .cloned();
+            (message_id, message)
+        })
+        .collect()
+}
+
+/// Replaces the `stored` items with a pending write by the pending ones
+///
+/// The pending items without data, i.e. deleted or not matching the read, are removed.
+pub(super) fn overlay<K: Ord, T>(
+    stored: impl IntoIterator<Item = (K, T)>,
+    pending: impl IntoIterator<Item = (K, Option<T>)>,
+) -> Vec<(K, T)> {
+    let mut items: BTreeMap<K, Option<T>> = stored
+        .into_iter()
+        .map(|(key, item)| (key, Some(item)))
+        .collect();
+    items.extend(pending);
+    items
+        .into_iter()
+        .filter_map(|(key, item)| Some((key, item?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_messages() {
+        let user_id = Uuid::new_v4();
+        let channel_id = ChannelId::User(user_id);
+        let message_id = MessageId::new(channel_id, user_id, 1);
+        let mut pending = Pending::default();
+
+        let message = Message::text(user_id, 1, "hello".to_owned());
+        let first = pending.record(&Write::Message(channel_id, message.clone()));
+        let edit = Message {
+            edit: Some(1),
+            ..Message::text(user_id, 2, "hello, world".to_owned())
+        };
+        pending.record(&Write::Message(channel_id, edit));
+        assert_eq!(pending.message(message_id), Some(Some(message)));
+        let before = MessagePosition {
+            arrived_at: 2,
+            from_id: Uuid::nil(),
+        };
+        assert_eq!(
+            pending
+                .channel_messages(channel_id, before, |_, message| message.edit.is_none())
+                .len(),
+            1
+        );
+
+        // deleting the message deletes its edits
+        let delete = pending.record(&Write::DeleteMessage(message_id));
+        assert_eq!(pending.message(message_id), Some(None));
+        assert!(
+            pending
+                .messages(|_, _| true)
+                .iter()
+                .all(|(_, message)| message.is_none())
+        );
+
+        pending.remove_committed(first);
+        assert_eq!(pending.message(message_id), Some(None));
+        pending.remove_committed(delete);
+        assert_eq!(pending.message(message_id), None);
+        assert!(pending.messages(|_, _| true).is_empty());
+    }
+
+    #[test]
+    fn test_overlay() {
+        let stored = [(1, "a"), (2, "b"), (3, "c")];
+        let pending = [(2, None), (3, Some("C")), (4, Some("D"))];
+        assert_eq!(overlay(stored, pending), [(1, "a"), (3, "C"), (4, "D")]);
+    }
+}
@@ -1,4 +1,6 @@
 use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
 
 use sqlx::{
     SqlitePool,
@@ -11,21 +13,28 @@ use url::Url;
 use uuid::Uuid;
 
 use crate::receipt::Receipt;
-use crate::signal::Attachment;
-use crate::storage::{MessageId, Metadata, Storage};
+use crate::signal::{Attachment, PendingAttachment};
+use crate::storage::{AttachmentFile, MessageId, MessagePosition, Metadata, SearchQuery, Storage};
 use crate::{
-    data::{BodyRange, Channel, ChannelId, GroupData, Message, TypingSet},
+    data::{BodyRange, Channel, ChannelId, GroupData, Message, SendState, TypingSet},
     passphrase::Passphrase,
 };
 
 use super::encoding::BlobData;
 use super::encrypt::{encrypt_db, is_sqlite_encrypted_heuristics};
+use super::pending::overlay;
 use super::util::ResultExt as _;
+use super::writer::{Write, Writer, index_existing_attachments};
 
-const METADATA_ID: i64 = 0;
-
+/// Storage of the app data in an sqlite database
+///
+/// Writes are queued to a background task, see [`Writer`]. Reads do not wait until the queued
+/// writes are committed, but overlay the ones which are not committed yet on the result of the
+/// query. Only [`Storage::save`] waits until all writes are committed.
+#[derive(Clone)]
 pub struct SqliteStorage {
     pool: SqlitePool,
+    writer: Writer,
 }
 
 impl SqliteStorage {
@@ -59,8 +68,9 @@ impl SqliteStorage {
 
         let pool = SqlitePool::connect_with(opts.clone()).await?;
         sqlx::migrate!().run(&pool).await?;
+        index_existing_attachments(&pool).await?;
 
-        Ok(Self { pool })
+        Ok(Self::new(pool))
     }
 
     #[cfg(test)]
@@ -73,8 +83,22 @@ impl SqliteStorage {
 
         let pool = SqlitePool::connect_with(opts.clone()).await?;
         sqlx::migrate!().run(&pool).await?;
+        index_existing_attachments(&pool).await?;
+
+        Ok(Self::new(pool))
+    }
+
+    fn new(pool: SqlitePool) -> Self {
+        let writer = Writer::spawn(pool.clone());
+        Self { pool, writer }
+    }
 
-        Ok(Self { pool })
+    /// Runs and waits for the read query
+    ///
+    /// The pending writes to overlay on the result must be taken before the query is run, such
+    /// that a write committed in between is not missed.
+    fn read<F: Future>(&self, query: F) -> F::Output {
+        block_async_in_place(query)
     }
 }
 
@@ -85,6 +109,8 @@ struct SqlChannel {
     group_revision: Option<i64>,
     group_members: Option<BlobData<Vec<Uuid>>>,
     muted: bool,
+    expire_timer: Option<i64>,
+    draft: Option<String>,
 }
 
 impl SqlChannel {
@@ -96,6 +122,8 @@ impl SqlChannel {
             group_revision,
             group_members,
             muted,
+            expire_timer,
+            draft,
         } = self;
         use ChannelConvertError::*;
         let group_data = match (group_master_key, group_revision, group_members) {
@@ -114,6 +142,8 @@ impl SqlChannel {
             unread_messages: Default::default(),
             muted,
             typing: TypingSet::new(is_group),
+            expire_timer: expire_timer.and_then(|timer| timer.try_into().ok_logged()),
+            draft,
         })
     }
 }
@@ -134,6 +164,11 @@ struct SqlMessage {
     quote_receipt: Option<BlobData<Receipt>>,
     edit: Option<i64>,
     edited: bool,
+    deleted: bool,
+    expire_timer: Option<i64>,
+    expires_at: Option<i64>,
+    send_state: Option<BlobData<SendState>>,
+    pending_attachments: Option<BlobData<Vec<PendingAttachment>>>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -160,6 +195,11 @@ impl SqlMessage {
             quote_receipt,
             edit,
             edited,
+            deleted,
+            expire_timer,
+            expires_at,
+            send_state,
+            pending_attachments,
         } = self;
 
         let quote = quote_arrived_at
@@ -191,20 +231,117 @@ impl SqlMessage {
                 .map_err(|_| MessageConvertError::InvalidTimestamp)?,
             quote: quote.map(Box::new),
             attachments: attachments.map(BlobData::into_inner).unwrap_or_default(),
+            pending_attachments: pending_attachments
+                .map(BlobData::into_inner)
+                .unwrap_or_default(),
             reactions: reactions.map(BlobData::into_inner).unwrap_or_default(),
             receipt: receipt.map(BlobData::into_inner).unwrap_or_default(),
             body_ranges: body_ranges.map(BlobData::into_inner).unwrap_or_default(),
-            send_failed: Default::default(),
+            send_state: send_state.map(BlobData::into_inner),
             edit: edit.and_then(|edit| {
                 edit.try_into()
                     .map_err(|_| MessageConvertError::InvalidTimestamp)
                     .ok_logged()
             }),
             edited,
+            deleted,
+            expire_timer: expire_timer.and_then(|timer| timer.try_into().ok_logged()),
+            expires_at: expires_at.and_then(|expires_at| {
+                expires_at
+                    .try_into()
+                    .map_err(|_| MessageConvertError::InvalidTimestamp)
+                    .ok_logged()
+            }),
         })
     }
 }
 
+struct SqlSearchResult {
+    channel_id: ChannelId,
+    from_id: Uuid,
+    arrived_at: i64,
+}
+
+/// Encodes ids as JSON array of upper case hex strings, which are compared with `hex()` of a column
+fn hex_ids<'a>(ids: impl Iterator<Item = &'a [u8]>) -> String {
+    let ids: Vec<String> = ids.map(hex::encode_upper).collect();
+    serde_json::Value::from(ids).to_string()
+}
+
+/// Builds a full-text query matching all words as prefixes of words in a message
+fn fts_query(words: &[String]) -> String {
+    let words: Vec<String> = words
+        .iter()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    words.join(" ")
+}
+
+struct SqlAttachmentFile {
+    channel_id: ChannelId,
+    from_id: Uuid,
+    arrived_at: i64,
+    edit: Option<i64>,
+    attachment_id: String,
+    filename: String,
+}
+
+/// Overlays the attachment files of the pending messages matching `filter` on the stored ones
+fn overlay_attachment_files(
+    stored: sqlx::Result<Vec<SqlAttachmentFile>>,
+    pending: Vec<(MessageId, Option<Message>)>,
+    filter: impl Fn(&Attachment) -> bool,
+) -> Vec<AttachmentFile> {
+    // the files are overlaid per message, since all attachments of a message are written at once
+    let mut stored_files: BTreeMap<MessageId, Vec<AttachmentFile>> = BTreeMap::new();
+    for row in stored.ok_logged().into_iter().flatten() {
+        let timestamp = |timestamp: i64| {
+            u64::try_from(timestamp)
+                .map_err(|_| MessageConvertError::InvalidTimestamp)
+                .ok_logged()
+        };
+        let (Some(arrived_at), Some(edited_at)) = (
+            timestamp(row.arrived_at),
+            timestamp(row.edit.unwrap_or(row.arrived_at)),
+        ) else {
+            continue;
+        };
+        let message_id = MessageId::new(row.channel_id, row.from_id, arrived_at);
+        stored_files
+            .entry(message_id)
+            .or_default()
+            .push(AttachmentFile {
+                message_id: MessageId::new(row.channel_id, row.from_id, edited_at),
+                attachment_id: row.attachment_id,
+                filename: row.filename.into(),
+            });
+    }
+    let pending = pending.into_iter().map(|(message_id, message)| {
+        let files = message.map(|message| {
+            // edits are returned with the id of the edited message
+            let edited_id = MessageId {
+                arrived_at: message.edit.unwrap_or(message.arrived_at),
+                ..message_id
+            };
+            message
+                .attachments
+                .into_iter()
+                .filter(|attachment| filter(attachment))
+                .map(|attachment| AttachmentFile {
+                    message_id: edited_id,
+                    attachment_id: attachment.id,
+                    filename: attachment.filename,
+                })
+                .collect()
+        });
+        (message_id, files)
+    });
+    overlay(stored_files, pending)
+        .into_iter()
+        .flat_map(|(_, files)| files)
+        .collect()
+}
+
 #[derive(Debug, thiserror::Error)]
 enum ChannelConvertError {
     #[error("invalid master key bytes")]
@@ -220,7 +357,8 @@ struct SqlName {
 
 impl Storage for SqliteStorage {
     fn channels(&self) -> Box<dyn Iterator<Item = Cow<'_, Channel>> + '_> {
-        let channels = block_async_in_place(
+        let pending = self.writer.pending().channels();
+        let channels = self.read(
             query_as!(
                 SqlChannel,
                 r#"
@@ -230,119 +368,197 @@ impl Storage for SqliteStorage {
                          group_master_key,
                          group_revision,
                          group_members AS "group_members: _",
-                         muted AS "muted: _"
+                         muted AS "muted: _",
+                         expire_timer,
+                         draft
                     FROM channels
                 "#
             )
             .fetch_all(&self.pool),
         );
+        let stored = channels
+            .ok_logged()
+            .into_iter()
+            .flatten()
+            .filter_map(|channel| channel.convert().ok_logged())
+            .map(|channel| (channel.id, channel));
+        let pending = pending
+            .into_iter()
+            .map(|channel| (channel.id, Some(channel)));
         Box::new(
-            channels
-                .ok_logged()
+            overlay(stored, pending)
                 .into_iter()
-                .flatten()
-                .filter_map(|channel| channel.convert().ok_logged().map(Cow::Owned)),
+                .map(|(_, channel)| Cow::Owned(channel)),
         )
     }
 
     fn channel(&self, channel_id: ChannelId) -> Option<Cow<'_, Channel>> {
+        let pending = self.writer.pending().channel(channel_id);
+        if let Some(channel) = pending {
+            return Some(Cow::Owned(channel));
+        }
         let channel_id = &channel_id;
-        let channel = block_async_in_place(
-            query_as!(
-                SqlChannel,
-                r#"
+        let channel = self
+            .read(
+                query_as!(
+                    SqlChannel,
+                    r#"
                         SELECT
                             id AS "id: _",
                             name,
                             group_master_key,
                             group_revision,
                             group_members AS "group_members: _",
-                            muted AS "muted: _"
+                            muted AS "muted: _",
+                            expire_timer,
+                            draft
                         FROM channels
                         WHERE id = ?
                     "#,
-                channel_id
+                    channel_id
+                )
+                .fetch_optional(&self.pool),
             )
-            .fetch_optional(&self.pool),
-        )
-        .ok_logged()?;
+            .ok_logged()?;
         channel?.convert().ok_logged().map(Cow::Owned)
     }
 
     fn store_channel(&mut self, channel: Channel) -> Cow<'_, Channel> {
-        let id = &channel.id;
-        let name = &channel.name;
-        let (group_master_key, group_revision, group_members) = channel
-            .group_data
-            .as_ref()
-            .map(|group_data| {
-                (
-                    Some(&group_data.master_key_bytes[..]),
-                    Some(group_data.revision),
-                    Some(BlobData(group_data.members.as_slice())),
-                )
-            })
-            .unwrap_or_default();
-        let muted = channel.muted;
-        block_async_in_place(
-            query!(
+        self.writer.send(Write::Channel(channel.clone()));
+        Cow::Owned(channel)
+    }
+
+    fn messages(
+        &self,
+        channel_id: ChannelId,
+    ) -> Box<dyn DoubleEndedIterator<Item = Cow<'_, Message>> + '_> {
+        let pending = self.writer.pending().channel_messages(
+            channel_id,
+            MessagePosition::LATEST,
+            |_, message| message.edit.is_none(),
+        );
+        let messages = self.read(
+            query_as!(
+                SqlMessage,
                 r#"
-                    REPLACE INTO channels(id, name, group_master_key, group_revision, group_members, muted)
-                    VALUES (?, ?, ?, ?, ?, ?)
+                    SELECT
+                        m.sent_at AS "arrived_at!",
+                        m.from_id AS "from_id: _",
+                        m.message,
+                        m.receipt AS "receipt: _",
+                        m.body_ranges AS "body_ranges: _",
+                        m.attachments AS "attachments: _",
+                        m.reactions AS "reactions: _",
+                        q.sent_at AS "quote_arrived_at: _",
+                        q.from_id AS "quote_from_id: _",
+                        q.message AS quote_message,
+                        q.attachments AS "quote_attachments: _",
+                        q.body_ranges AS "quote_body_ranges: _",
+                        q.receipt AS "quote_receipt: _",
+                        NULL AS "edit: _",
+                        m.edited AS "edited: _",
+                        m.deleted AS "deleted: _",
+                        m.expire_timer,
+                        m.expires_at,
+                        o.state AS "send_state: _",
+                        p.attachments AS "pending_attachments: _"
+                    FROM messages AS m
+                    LEFT JOIN messages AS q
+                        ON q.channel_id = m.channel_id
+                        AND q.from_id = m.quote_from_id
+                        AND q.sent_at = m.quote_sent_at
+                    LEFT JOIN outbox AS o ON o.message_id = m.id
+                    LEFT JOIN pending_attachments AS p ON p.message_id = m.id
+                    WHERE m.channel_id = ?1 AND m.edit IS NULL
+                    ORDER BY m.sent_at ASC, m.from_id ASC
                 "#,
-                id,
-                name,
-                group_master_key,
-                group_revision,
-                group_members,
-                muted
+                &channel_id
             )
-            .execute(&self.pool),
+            .fetch_all(&self.pool),
+        );
+        let stored = messages
+            .ok_logged()
+            .into_iter()
+            .flatten()
+            .filter_map(|message| message.convert().ok_logged())
+            .map(|message| (MessageId::of(channel_id, &message), message));
+        Box::new(
+            overlay(stored, pending)
+                .into_iter()
+                .map(|(_, message)| Cow::Owned(message)),
         )
-        .ok_logged();
-        Cow::Owned(channel)
     }
 
-    fn messages(
+    fn messages_before(
         &self,
         channel_id: ChannelId,
+        before: MessagePosition,
+        limit: usize,
     ) -> Box<dyn DoubleEndedIterator<Item = Cow<'_, Message>> + '_> {
-        let channel_id = &channel_id;
-        let messages = block_async_in_place(
+        let pending = self
+            .writer
+            .pending()
+            .channel_messages(channel_id, before, |_, message| message.edit.is_none());
+        // each pending message replaces at most one of the queried ones
+        let sql_before = i64::try_from(before.arrived_at).unwrap_or(i64::MAX);
+        let sql_limit = i64::try_from(limit.saturating_add(pending.len())).unwrap_or(i64::MAX);
+        let messages = self.read(
             query_as!(
                 SqlMessage,
                 r#"
                     SELECT
-                        m.arrived_at AS "arrived_at!",
+                        m.sent_at AS "arrived_at!",
                         m.from_id AS "from_id: _",
                         m.message,
                         m.receipt AS "receipt: _",
                         m.body_ranges AS "body_ranges: _",
                         m.attachments AS "attachments: _",
                         m.reactions AS "reactions: _",
-                        q.arrived_at AS "quote_arrived_at: _",
+                        q.sent_at AS "quote_arrived_at: _",
                         q.from_id AS "quote_from_id: _",
                         q.message AS quote_message,
                         q.attachments AS "quote_attachments: _",
                         q.body_ranges AS "quote_body_ranges: _",
                         q.receipt AS "quote_receipt: _",
                         NULL AS "edit: _",
-                        m.edited AS "edited: _"
+                        m.edited AS "edited: _",
+                        m.deleted AS "deleted: _",
+                        m.expire_timer,
+                        m.expires_at,
+                        o.state AS "send_state: _",
+                        p.attachments AS "pending_attachments: _"
                     FROM messages AS m
-                    LEFT JOIN messages AS q ON q.arrived_at = m.quote AND q.channel_id = ?1
+                    LEFT JOIN messages AS q
+                        ON q.channel_id = m.channel_id
+                        AND q.from_id = m.quote_from_id
+                        AND q.sent_at = m.quote_sent_at
+                    LEFT JOIN outbox AS o ON o.message_id = m.id
+                    LEFT JOIN pending_attachments AS p ON p.message_id = m.id
                     WHERE m.channel_id = ?1 AND m.edit IS NULL
-                    ORDER BY m.arrived_at ASC
+                        AND (m.sent_at < ?2 OR (m.sent_at = ?2 AND m.from_id < ?3))
+                    ORDER BY m.sent_at DESC, m.from_id DESC
+                    LIMIT ?4
                 "#,
-                channel_id
+                &channel_id,
+                sql_before,
+                &before.from_id,
+                sql_limit,
             )
             .fetch_all(&self.pool),
         );
+        let stored = messages
+            .ok_logged()
+            .into_iter()
+            .flatten()
+            .filter_map(|message| message.convert().ok_logged())
+            .map(|message| (MessageId::of(channel_id, &message), message));
+        let messages = overlay(stored, pending);
+        let skip = messages.len().saturating_sub(limit);
         Box::new(
             messages
-                .ok_logged()
                 .into_iter()
-                .flatten()
-                .filter_map(|message| message.convert().ok_logged().map(Cow::Owned)),
+                .skip(skip)
+                .map(|(_, message)| Cow::Owned(message)),
         )
     }
 
@@ -351,6 +567,7 @@ impl Storage for SqliteStorage {
         message_id: MessageId,
     ) -> Box<dyn DoubleEndedIterator<Item = Cow<'_, Message>> + '_> {
         let channel_id = &message_id.channel_id;
+        let from_id = &message_id.from_id;
         let arrived_at: Option<i64> = message_id
             .arrived_at
             .try_into()
@@ -359,79 +576,125 @@ impl Storage for SqliteStorage {
         let Some(arrived_at) = arrived_at else {
             return Box::new(std::iter::empty());
         };
-        let messages = block_async_in_place(
+        let (original, pending) = {
+            let pending = self.writer.pending();
+            let edits = pending.channel_messages(
+                message_id.channel_id,
+                MessagePosition::LATEST,
+                |edit_id, edit| {
+                    edit_id.from_id == message_id.from_id
+                        && edit.edit == Some(message_id.arrived_at)
+                },
+            );
+            (pending.message(message_id), edits)
+        };
+        if let Some(None) = original {
+            // the edits are deleted together with the message
+            return Box::new(std::iter::empty());
+        }
+        let messages = self.read(
             query_as!(
                 SqlMessage,
                 r#"
                     SELECT
-                        m.arrived_at AS "arrived_at!",
+                        m.sent_at AS "arrived_at!",
                         m.from_id AS "from_id: _",
                         m.message,
                         m.receipt AS "receipt: _",
                         m.body_ranges AS "body_ranges: _",
                         m.attachments AS "attachments: _",
                         m.reactions AS "reactions: _",
-                        q.arrived_at AS "quote_arrived_at: _",
+                        q.sent_at AS "quote_arrived_at: _",
                         q.from_id AS "quote_from_id: _",
                         q.message AS quote_message,
                         q.attachments AS "quote_attachments: _",
                         q.body_ranges AS "quote_body_ranges: _",
                         q.receipt AS "quote_receipt: _",
                         NULL AS "edit: _",
-                        m.edited AS "edited: _"
+                        m.edited AS "edited: _",
+                        m.deleted AS "deleted: _",
+                        m.expire_timer,
+                        m.expires_at,
+                        o.state AS "send_state: _",
+                        p.attachments AS "pending_attachments: _"
                     FROM messages AS m
-                    LEFT JOIN messages AS q ON q.arrived_at = m.quote AND q.channel_id = ?1
-                    WHERE m.channel_id = ?1 AND m.edit == ?2
-                    ORDER BY m.arrived_at ASC
+                    LEFT JOIN messages AS q
+                        ON q.channel_id = m.channel_id
+                        AND q.from_id = m.quote_from_id
+                        AND q.sent_at = m.quote_sent_at
+                    LEFT JOIN outbox AS o ON o.message_id = m.id
+                    LEFT JOIN pending_attachments AS p ON p.message_id = m.id
+                    WHERE m.channel_id = ?1 AND m.from_id = ?2 AND m.edit = ?3
+                    ORDER BY m.sent_at ASC
                 "#,
                 channel_id,
+                from_id,
                 arrived_at,
             )
             .fetch_all(&self.pool),
         );
+        let stored = messages
+            .ok_logged()
+            .into_iter()
+            .flatten()
+            .filter_map(|message| message.convert().ok_logged())
+            .map(|message| (MessageId::of(message_id.channel_id, &message), message));
         Box::new(
-            messages
-                .ok_logged()
+            overlay(stored, pending)
                 .into_iter()
-                .flatten()
-                .filter_map(|message| message.convert().ok_logged().map(Cow::Owned)),
+                .map(|(_, message)| Cow::Owned(message)),
         )
     }
 
     fn message(&self, message_id: MessageId) -> Option<Cow<'_, Message>> {
+        let pending = self.writer.pending().message(message_id);
+        if let Some(message) = pending {
+            return message.map(Cow::Owned);
+        }
         let channel_id = &message_id.channel_id;
+        let from_id = &message_id.from_id;
         let arrived_at: i64 = message_id
             .arrived_at
             .try_into()
             .map_err(|_| MessageConvertError::InvalidTimestamp)
             .ok_logged()?;
-        let message = block_async_in_place(
+        let message = self.read(
             query_as!(
                 SqlMessage,
                 r#"
                     SELECT
-                        m.arrived_at,
+                        m.sent_at AS "arrived_at!",
                         m.from_id AS "from_id: _",
                         m.message,
                         m.receipt AS "receipt: _",
                         m.body_ranges AS "body_ranges: _",
                         m.attachments AS "attachments: _",
                         m.reactions AS "reactions: _",
-                        q.arrived_at AS "quote_arrived_at: _",
+                        q.sent_at AS "quote_arrived_at: _",
                         q.from_id AS "quote_from_id: _",
                         q.message AS quote_message,
                         q.attachments AS "quote_attachments: _",
                         q.body_ranges AS "quote_body_ranges: _",
                         q.receipt AS "quote_receipt: _",
                         m.edit,
-                        m.edited as "edited: _"
+                        m.edited as "edited: _",
+                        m.deleted as "deleted: _",
+                        m.expire_timer,
+                        m.expires_at,
+                        o.state AS "send_state: _",
+                        p.attachments AS "pending_attachments: _"
                     FROM messages AS m
-                    LEFT JOIN messages AS q ON q.arrived_at = m.quote AND q.channel_id = ?1
-                    WHERE m.channel_id = ?1 AND m.arrived_at = ?2
-                    GROUP BY m.arrived_at
+                    LEFT JOIN messages AS q
+                        ON q.channel_id = m.channel_id
+                        AND q.from_id = m.quote_from_id
+                        AND q.sent_at = m.quote_sent_at
+                    LEFT JOIN outbox AS o ON o.message_id = m.id
+                    LEFT JOIN pending_attachments AS p ON p.message_id = m.id
+                    WHERE m.channel_id = ?1 AND m.from_id = ?2 AND m.sent_at = ?3
                     LIMIT 1
                 "#,
                 channel_id,
+                from_id,
                 arrived_at
             )
             .fetch_optional(&self.pool),
@@ -441,86 +704,334 @@ impl Storage for SqliteStorage {
     }
 
     fn store_message(&mut self, channel_id: ChannelId, message: Message) -> Cow<'_, Message> {
-        let channel_id = &channel_id;
-        let arrived_at: i64 = message
-            .arrived_at
-            .try_into()
-            .map_err(|_| MessageConvertError::InvalidTimestamp)
-            .ok_logged()
-            .unwrap();
-        let from_id = &message.from_id;
-        let message_msg = message.message.as_deref();
-        let quote: Option<i64> = message.quote.as_ref().and_then(|quote| {
-            quote
+        self.writer
+            .send(Write::Message(channel_id, message.clone()));
+        Cow::Owned(message)
+    }
+
+    fn delete_message(&mut self, message_id: MessageId) {
+        self.writer.send(Write::DeleteMessage(message_id));
+    }
+
+    fn outbox(&self) -> Box<dyn Iterator<Item = MessageId> + '_> {
+        let pending = self
+            .writer
+            .pending()
+            .messages(|_, message| message.send_state.is_some());
+        let outbox = self.read(
+            query!(
+                r#"
+                    SELECT
+                        m.channel_id AS "channel_id: ChannelId",
+                        m.from_id AS "from_id: Uuid",
+                        m.sent_at AS arrived_at
+                    FROM outbox AS o
+                    JOIN messages AS m ON m.id = o.message_id
+                    ORDER BY m.sent_at ASC
+                "#
+            )
+            .fetch_all(&self.pool),
+        );
+        let stored = outbox.ok_logged().into_iter().flatten().filter_map(|row| {
+            let arrived_at = row
                 .arrived_at
                 .try_into()
                 .map_err(|_| MessageConvertError::InvalidTimestamp)
-                .ok_logged()
+                .ok_logged()?;
+            Some((MessageId::new(row.channel_id, row.from_id, arrived_at), ()))
         });
-        let receipt = BlobData(&message.receipt);
-        let body_ranges = BlobData(&message.body_ranges);
-        let attachments = BlobData(&message.attachments);
-        let reactions = BlobData(&message.reactions);
-        let edit: Option<i64> = message.edit.and_then(|edit| {
-            edit.try_into()
-                .map_err(|_| MessageConvertError::InvalidTimestamp)
-                .ok_logged()
+        let pending = pending
+            .into_iter()
+            .map(|(message_id, message)| (message_id, message.map(|_| ())));
+        let mut outbox: Vec<MessageId> = overlay(stored, pending)
+            .into_iter()
+            .map(|(message_id, ())| message_id)
+            .collect();
+        outbox.sort_by_key(|message_id| message_id.arrived_at);
+        Box::new(outbox.into_iter())
+    }
+
+    fn expiring_messages(&self) -> Box<dyn Iterator<Item = (MessageId, u64)> + '_> {
+        let pending = self
+            .writer
+            .pending()
+            .messages(|_, message| message.edit.is_none() && message.expires_at.is_some());
+        let expiring = self.read(
+            query!(
+                r#"
+                    SELECT
+                        channel_id AS "channel_id: ChannelId",
+                        from_id AS "from_id: Uuid",
+                        sent_at AS arrived_at,
+                        expires_at AS "expires_at!"
+                    FROM messages
+                    WHERE expires_at IS NOT NULL AND edit IS NULL
+                "#
+            )
+            .fetch_all(&self.pool),
+        );
+        let stored = expiring
+            .ok_logged()
+            .into_iter()
+            .flatten()
+            .filter_map(|row| {
+                let arrived_at = row
+                    .arrived_at
+                    .try_into()
+                    .map_err(|_| MessageConvertError::InvalidTimestamp)
+                    .ok_logged()?;
+                let expires_at = row
+                    .expires_at
+                    .try_into()
+                    .map_err(|_| MessageConvertError::InvalidTimestamp)
+                    .ok_logged()?;
+                let message_id = MessageId::new(row.channel_id, row.from_id, arrived_at);
+                Some((message_id, expires_at))
+            });
+        let pending = pending.into_iter().map(|(message_id, message)| {
+            (message_id, message.and_then(|message| message.expires_at))
+        });
+        Box::new(overlay(stored, pending).into_iter())
+    }
+
+    fn expired_messages(&self, now: u64) -> Box<dyn Iterator<Item = MessageId> + '_> {
+        let pending = self.writer.pending().messages(|_, message| {
+            message.edit.is_none() && message.expires_at.is_some_and(|at| at <= now)
         });
-        let edited: bool = message.edited;
-        let inserted = block_async_in_place(
+        let now: i64 = now.try_into().unwrap_or(i64::MAX);
+        let expired = self.read(
             query!(
-                "
-                    REPLACE INTO messages(
-                        arrived_at,
-                        channel_id,
-                        from_id,
-                        message,
-                        quote,
-                        receipt,
-                        body_ranges,
-                        attachments,
-                        reactions,
-                        edit,
-                        edited
-                    )
-                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
-                ",
-                arrived_at,
-                channel_id,
-                from_id,
-                message_msg,
-                quote,
-                receipt,
-                body_ranges,
-                attachments,
-                reactions,
-                edit,
-                edited
+                r#"
+                    SELECT
+                        channel_id AS "channel_id: ChannelId",
+                        from_id AS "from_id: Uuid",
+                        sent_at AS arrived_at
+                    FROM messages
+                    WHERE expires_at <= ? AND edit IS NULL
+                "#,
+                now
             )
-            .execute(&self.pool),
+            .fetch_all(&self.pool),
         );
-        inserted.ok_logged();
-        Cow::Owned(message)
+        let stored = expired.ok_logged().into_iter().flatten().filter_map(|row| {
+            let arrived_at = row
+                .arrived_at
+                .try_into()
+                .map_err(|_| MessageConvertError::InvalidTimestamp)
+                .ok_logged()?;
+            Some((MessageId::new(row.channel_id, row.from_id, arrived_at), ()))
+        });
+        let pending = pending
+            .into_iter()
+            .map(|(message_id, message)| (message_id, message.map(|_| ())));
+        Box::new(
+            overlay(stored, pending)
+                .into_iter()
+                .map(|(message_id, ())| message_id),
+        )
+    }
+
+    fn search(
+        &self,
+        query: &SearchQuery,
+        limit: usize,
+    ) -> Box<dyn Iterator<Item = MessageId> + '_> {
+        let pending = self.writer.pending().messages(|message_id, message| {
+            message.edit.is_none() && query.matches(message_id.channel_id, message)
+        });
+        let before: i64 = query
+            .before
+            .map(|before| before.try_into().unwrap_or(i64::MAX))
+            .unwrap_or(i64::MAX);
+        let after: i64 = query
+            .after
+            .map(|after| after.try_into().unwrap_or(i64::MAX))
+            .unwrap_or(0);
+        let from = query
+            .from
+            .as_deref()
+            .map(|from| hex_ids(from.iter().map(|id| id.as_bytes().as_slice())));
+        let channels = query.channels.as_deref().map(|channels| {
+            hex_ids(channels.iter().map(|channel_id| match channel_id {
+                ChannelId::User(uuid) => uuid.as_bytes().as_slice(),
+                ChannelId::Group(bytes) => bytes.as_slice(),
+            }))
+        });
+        let has_attachment = query.has_attachment;
+        // each pending message replaces at most one of the found ones
+        let sql_limit: i64 = limit
+            .saturating_add(pending.len())
+            .try_into()
+            .unwrap_or(i64::MAX);
+        // an empty list of attachments is encoded as a single zero byte
+        let results = if query.words.is_empty() {
+            self.read(
+                query_as!(
+                    SqlSearchResult,
+                    r#"
+                        SELECT
+                            channel_id AS "channel_id: _",
+                            from_id AS "from_id: _",
+                            sent_at AS arrived_at
+                        FROM messages
+                        WHERE sent_at < ?1 AND sent_at >= ?2 AND edit IS NULL
+                            AND (?3 IS NULL OR hex(from_id) IN (SELECT value FROM json_each(?3)))
+                            AND (?4 IS NULL OR hex(channel_id) IN (SELECT value FROM json_each(?4)))
+                            AND (NOT ?5 OR (attachments IS NOT NULL AND attachments != X'00'))
+                        ORDER BY sent_at DESC
+                        LIMIT ?6
+                    "#,
+                    before,
+                    after,
+                    from,
+                    channels,
+                    has_attachment,
+                    sql_limit
+                )
+                .fetch_all(&self.pool),
+            )
+        } else {
+            let fts_query = fts_query(&query.words);
+            self.read(
+                query_as!(
+                    SqlSearchResult,
+                    r#"
+                        SELECT
+                            m.channel_id AS "channel_id!: _",
+                            m.from_id AS "from_id!: _",
+                            m.sent_at AS "arrived_at!"
+                        FROM messages_fts
+                        JOIN messages AS m ON m.id = messages_fts.rowid
+                        WHERE messages_fts MATCH ?1
+                            AND m.sent_at < ?2 AND m.sent_at >= ?3
+                            AND m.edit IS NULL
+                            AND (?4 IS NULL OR hex(m.from_id) IN (SELECT value FROM json_each(?4)))
+                            AND (?5 IS NULL OR hex(m.channel_id) IN (SELECT value FROM json_each(?5)))
+                            AND (NOT ?6 OR (m.attachments IS NOT NULL AND m.attachments != X'00'))
+                        ORDER BY m.sent_at DESC
+                        LIMIT ?7
+                    "#,
+                    fts_query,
+                    before,
+                    after,
+                    from,
+                    channels,
+                    has_attachment,
+                    sql_limit
+                )
+                .fetch_all(&self.pool),
+            )
+        };
+        let stored = results
+            .ok_logged()
+            .into_iter()
+            .flatten()
+            .filter_map(|result| {
+                let arrived_at = result
+                    .arrived_at
+                    .try_into()
+                    .map_err(|_| MessageConvertError::InvalidTimestamp)
+                    .ok_logged()?;
+                let message_id = MessageId::new(result.channel_id, result.from_id, arrived_at);
+                Some((message_id, ()))
+            });
+        let pending = pending
+            .into_iter()
+            .map(|(message_id, message)| (message_id, message.map(|_| ())));
+        let mut results: Vec<MessageId> = overlay(stored, pending)
+            .into_iter()
+            .map(|(message_id, ())| message_id)
+            .collect();
+        results.sort_by_key(|message_id| Reverse(message_id.arrived_at));
+        results.truncate(limit);
+        Box::new(results.into_iter())
+    }
+
+    fn attachment_files(&self) -> Box<dyn Iterator<Item = AttachmentFile> + '_> {
+        let pending = self.writer.pending().messages(|_, _| true);
+        let stored = self.read(
+            query_as!(
+                SqlAttachmentFile,
+                r#"
+                    SELECT
+                        m.channel_id AS "channel_id: _",
+                        m.from_id AS "from_id: _",
+                        m.sent_at AS arrived_at,
+                        m.edit,
+                        a.attachment_id,
+                        a.filename
+                    FROM message_attachments AS a
+                    JOIN messages AS m ON m.id = a.message_id
+                "#
+            )
+            .fetch_all(&self.pool),
+        );
+        let files = overlay_attachment_files(stored, pending, |_| true);
+        Box::new(files.into_iter())
+    }
+
+    fn attachment_copies(
+        &self,
+        attachment_id: &str,
+    ) -> Box<dyn Iterator<Item = AttachmentFile> + '_> {
+        let pending = self.writer.pending().messages(|_, message| {
+            message
+                .attachments
+                .iter()
+                .any(|attachment| attachment.id == attachment_id)
+        });
+        let stored = self.read(
+            query_as!(
+                SqlAttachmentFile,
+                r#"
+                    SELECT
+                        m.channel_id AS "channel_id: _",
+                        m.from_id AS "from_id: _",
+                        m.sent_at AS arrived_at,
+                        m.edit,
+                        a.attachment_id,
+                        a.filename
+                    FROM message_attachments AS a
+                    JOIN messages AS m ON m.id = a.message_id
+                    WHERE a.attachment_id = ?
+                "#,
+                attachment_id
+            )
+            .fetch_all(&self.pool),
+        );
+        let files =
+            overlay_attachment_files(stored, pending, |attachment| attachment.id == attachment_id);
+        Box::new(files.into_iter())
     }
 
     fn names(&self) -> Box<dyn Iterator<Item = (Uuid, Cow<'_, str>)> + '_> {
-        let names = block_async_in_place(
+        let pending = self.writer.pending().names();
+        let names = self.read(
             query_as!(
                 SqlName,
                 r#"SELECT id AS "id: _", name AS "name: _" FROM names"#
             )
             .fetch_all(&self.pool),
         );
-        let names = names
+        let stored = names
             .ok_logged()
             .into_iter()
             .flatten()
-            .map(|SqlName { id, name }| (id, Cow::Owned(name)));
-        Box::new(names)
+            .map(|SqlName { id, name }| (id, name));
+        let pending = pending.into_iter().map(|(id, name)| (id, Some(name)));
+        Box::new(
+            overlay(stored, pending)
+                .into_iter()
+                .map(|(id, name)| (id, Cow::Owned(name))),
+        )
     }
 
     fn name(&self, id: Uuid) -> Option<Cow<'_, str>> {
-        let name = block_async_in_place(
+        let pending = self.writer.pending().name(id);
+        if let Some(name) = pending {
+            return Some(Cow::Owned(name));
+        }
+        let name = self.read(
             query_scalar!(r#"SELECT name AS "name: _" FROM names WHERE id = ?"#, id)
                 .fetch_optional(&self.pool),
         );
@@ -528,15 +1039,16 @@ impl Storage for SqliteStorage {
     }
 
     fn store_name(&mut self, id: Uuid, name: String) -> Cow<'_, str> {
-        block_async_in_place(
-            query!("REPLACE INTO names(id, name) VALUES (?, ?)", id, name).execute(&self.pool),
-        )
-        .ok_logged();
+        self.writer.send(Write::Name(id, name.clone()));
         Cow::Owned(name)
     }
 
     fn metadata(&self) -> Cow<'_, Metadata> {
-        let metadata = block_async_in_place(
+        let pending = self.writer.pending().metadata();
+        if let Some(metadata) = pending {
+            return Cow::Owned(metadata);
+        }
+        let metadata = self.read(
             query_as!(
                 Metadata,
                 r#"
@@ -552,42 +1064,47 @@ impl Storage for SqliteStorage {
     }
 
     fn store_metadata(&mut self, metadata: Metadata) -> Cow<'_, Metadata> {
-        block_async_in_place(
-            query!(
-                "REPLACE INTO metadata(id, contacts_sync_request_at, fully_migrated)
-                     VALUES (?, ?, ?)",
-                METADATA_ID,
-                metadata.contacts_sync_request_at,
-                metadata.fully_migrated
-            )
-            .execute(&self.pool),
-        )
-        .ok_logged();
+        self.writer.send(Write::Metadata(metadata.clone()));
         Cow::Owned(metadata)
     }
 
-    fn save(&mut self) {}
+    fn save(&mut self) {
+        block_async_in_place(self.writer.flush());
+    }
 
-    fn message_channel(&self, arrived_at: u64) -> Option<ChannelId> {
+    fn message_channel(&self, from_id: Uuid, arrived_at: u64) -> Option<ChannelId> {
+        let pending = self.writer.pending().messages(|message_id, _| {
+            message_id.from_id == from_id && message_id.arrived_at == arrived_at
+        });
+        // the other pending messages are deleted
+        if let Some((message_id, _)) = pending.iter().find(|(_, message)| message.is_some()) {
+            return Some(message_id.channel_id);
+        }
         let arrived_at: i64 = arrived_at
             .try_into()
             .map_err(|_| MessageConvertError::InvalidTimestamp)
             .ok_logged()?;
-        block_async_in_place(
+        self.read(
             query_scalar!(
                 r#"
                     SELECT
                         m.channel_id AS "channel_id: _"
                     FROM messages AS m
-                    WHERE m.arrived_at = ?
+                    WHERE m.from_id = ? AND m.sent_at = ?
                     LIMIT 1
                 "#,
+                from_id,
                 arrived_at
             )
             .fetch_optional(&self.pool),
         )
         .ok_logged()
         .flatten()
+        .filter(|&channel_id| {
+            !pending
+                .iter()
+                .any(|(message_id, _)| message_id.channel_id == channel_id)
+        })
     }
 }
 
@@ -605,6 +1122,8 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
+
     use chrono::Utc;
     use tempfile::tempdir;
     use uuid::uuid;
@@ -625,6 +1144,8 @@ mod tests {
             unread_messages: 1,
             muted: false,
             typing: TypingSet::new(false),
+            expire_timer: None,
+            draft: None,
         });
         storage.store_message(
             user_channel,
@@ -634,12 +1155,16 @@ mod tests {
                 arrived_at: 1664832050000,
                 quote: None,
                 attachments: Default::default(),
+                pending_attachments: Default::default(),
                 reactions: Default::default(),
                 receipt: Receipt::Nothing,
                 body_ranges: Default::default(),
-                send_failed: Default::default(),
+                send_state: Default::default(),
                 edit: Default::default(),
                 edited: Default::default(),
+                deleted: Default::default(),
+                expire_timer: Default::default(),
+                expires_at: Default::default(),
             },
         );
 
@@ -654,6 +1179,8 @@ mod tests {
             unread_messages: 2,
             muted: false,
             typing: TypingSet::new(true),
+            expire_timer: None,
+            draft: None,
         });
         storage.store_message(
             group_channel,
@@ -663,12 +1190,16 @@ mod tests {
                 arrived_at: 1664832050001,
                 quote: None,
                 attachments: Default::default(),
+                pending_attachments: Default::default(),
                 reactions: Default::default(),
                 receipt: Receipt::Nothing,
                 body_ranges: Default::default(),
-                send_failed: Default::default(),
+                send_state: Default::default(),
                 edit: Default::default(),
                 edited: Default::default(),
+                deleted: Default::default(),
+                expire_timer: Default::default(),
+                expires_at: Default::default(),
             },
         );
 
@@ -694,6 +1225,22 @@ mod tests {
         assert_eq!(storage.channel(channels[1].id).unwrap().id, channels[1].id);
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_storage_channel_draft() {
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let mut storage = fixtures().await;
+        let channel_id = ChannelId::User(uuid!("966960e0-a8cd-43f1-ac7a-2c986dd470cd"));
+
+        let mut channel = storage.channel(channel_id).unwrap().into_owned();
+        assert_eq!(channel.draft, None);
+        channel.draft = Some("unsent\ntext".to_owned());
+        storage.store_channel(channel);
+        assert_eq!(
+            storage.channel(channel_id).unwrap().draft.as_deref(),
+            Some("unsent\ntext")
+        );
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_sqlite_storage_messages() {
         let _ = tracing_subscriber::fmt().with_test_writer().try_init();
@@ -704,22 +1251,137 @@ mod tests {
         assert_eq!(messages.len(), 1);
         assert_eq!(messages[0].message.as_deref(), Some("hello"));
 
-        let arrived_at = messages[0].arrived_at;
-        let message = storage
-            .message(MessageId::new(id.into(), arrived_at))
-            .unwrap();
-        assert_eq!(message.arrived_at, arrived_at);
+        let message_id = MessageId::of(id.into(), &messages[0]);
+        let message = storage.message(message_id).unwrap();
+        assert_eq!(message.arrived_at, message_id.arrived_at);
         assert_eq!(message.message.as_deref(), Some("hello"));
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_storage_messages_before() {
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let mut storage = fixtures().await;
+        let id: Uuid = "966960e0-a8cd-43f1-ac7a-2c986dd470cd".parse().unwrap();
+        let channel_id = ChannelId::User(id);
+        for arrived_at in 1..=5 {
+            storage.store_message(channel_id, Message::text(id, arrived_at, "hi".to_owned()));
+        }
+        storage.store_edited_message(channel_id, 5, Message::text(id, 10, "edit".to_owned()));
+
+        let page = |arrived_at, limit| -> Vec<u64> {
+            let before = MessagePosition {
+                arrived_at,
+                from_id: Uuid::nil(),
+            };
+            storage
+                .messages_before(channel_id, before, limit)
+                .map(|message| message.arrived_at)
+                .collect()
+        };
+        assert_eq!(page(u64::MAX, 3), [4, 5, 1664832050000]);
+        assert_eq!(page(5, 2), [3, 4]);
+        assert_eq!(page(2, 10), [1]);
+        assert!(page(1, 10).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_storage_messages_before_same_arrived_at() {
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let mut storage = fixtures().await;
+        let channel_id = ChannelId::Group(*b"messages-at-the-same-time-------");
+        let senders = [
+            uuid!("00000000-0000-0000-0000-00000000000a"),
+            uuid!("00000000-0000-0000-0000-00000000000b"),
+            uuid!("00000000-0000-0000-0000-00000000000c"),
+        ];
+        storage.store_message(channel_id, Message::text(senders[0], 1, "hi".to_owned()));
+        for from_id in senders {
+            storage.store_message(channel_id, Message::text(from_id, 2, "hi".to_owned()));
+        }
+        storage.store_message(channel_id, Message::text(senders[1], 3, "hi".to_owned()));
+
+        let pages = |storage: &SqliteStorage| -> Vec<Vec<(u64, Uuid)>> {
+            let mut pages = Vec::new();
+            let mut before = MessagePosition::LATEST;
+            loop {
+                let page: Vec<_> = storage
+                    .messages_before(channel_id, before, 2)
+                    .map(|message| (message.arrived_at, message.from_id))
+                    .collect();
+                let Some(&(arrived_at, from_id)) = page.first() else {
+                    return pages;
+                };
+                before = MessagePosition {
+                    arrived_at,
+                    from_id,
+                };
+                pages.push(page);
+            }
+        };
+        let expected = [
+            vec![(2, senders[2]), (3, senders[1])],
+            vec![(2, senders[0]), (2, senders[1])],
+            vec![(1, senders[0])],
+        ];
+        assert_eq!(pages(&storage), expected);
+
+        storage.save();
+        assert_eq!(pages(&storage), expected);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_storage_reads_pending_writes() {
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let mut storage = fixtures().await;
+        let id = uuid!("966960e0-a8cd-43f1-ac7a-2c986dd470cd");
+        let channel_id = ChannelId::User(id);
+        let query = SearchQuery {
+            words: vec!["hi".to_owned()],
+            ..Default::default()
+        };
+        let read = |storage: &SqliteStorage| -> (Vec<u64>, Vec<u64>, Vec<MessageId>) {
+            let before = MessagePosition {
+                arrived_at: 1000,
+                from_id: Uuid::nil(),
+            };
+            let page = storage
+                .messages_before(channel_id, before, 3)
+                .map(|message| message.arrived_at)
+                .collect();
+            let all = storage
+                .messages(channel_id)
+                .map(|message| message.arrived_at)
+                .collect();
+            (page, all, storage.search(&query, 2).collect())
+        };
+
+        for arrived_at in 1..=1000 {
+            storage.store_message(channel_id, Message::text(id, arrived_at, "hi".to_owned()));
+        }
+        storage.delete_message(MessageId::new(channel_id, id, 999));
+        storage.store_message(channel_id, Message::text(id, 997, "bye".to_owned()));
+
+        let (page, all, found) = read(&storage);
+        assert_eq!(page, [996, 997, 998]);
+        assert_eq!(all.len(), 1000);
+        assert_eq!(
+            found,
+            [1000, 998].map(|at| MessageId::new(channel_id, id, at))
+        );
+
+        storage.save();
+        assert_eq!(read(&storage), (page, all, found));
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_sqlite_storage_store_existing_message() {
         let _ = tracing_subscriber::fmt().with_test_writer().try_init();
         let mut storage = fixtures().await;
         let id: Uuid = "966960e0-a8cd-43f1-ac7a-2c986dd470cd".parse().unwrap();
+        let from_id = uuid!("a955d20f-6b83-4e69-846e-a99b1779ff7a");
         let arrived_at = 1664832050000;
         let mut message = storage
-            .message(MessageId::new(id.into(), arrived_at))
+            .message(MessageId::new(id.into(), from_id, arrived_at))
             .unwrap()
             .into_owned();
         message.message = Some("changed".to_string());
@@ -735,23 +1397,271 @@ mod tests {
         assert_eq!(messages[0].message.as_deref(), Some("changed"));
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_storage_expired_messages() {
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let mut storage = fixtures().await;
+        let user_id = uuid!("a955d20f-6b83-4e69-846e-a99b1779ff7a");
+        let channel_id = ChannelId::User(uuid!("966960e0-a8cd-43f1-ac7a-2c986dd470cd"));
+        let message_id = MessageId::new(channel_id, user_id, 1664832050000);
+
+        let mut message = storage.message(message_id).unwrap().into_owned();
+        message.expire_timer = Some(60);
+        assert!(message.start_expiry(1664832060000));
+        storage.store_message(channel_id, message);
+        storage.store_edited_message(
+            channel_id,
+            message_id.arrived_at,
+            Message::text(user_id, 1664832070000, "edited".into()),
+        );
+        assert_eq!(storage.edits(message_id).count(), 2);
+
+        let stored = storage.message(message_id).unwrap();
+        assert_eq!(stored.expire_timer, Some(60));
+        assert_eq!(stored.expires_at, Some(1664832120000));
+
+        assert_eq!(storage.expired_messages(1664832119999).count(), 0);
+        let expired: Vec<_> = storage.expired_messages(1664832120000).collect();
+        assert_eq!(expired, [message_id]);
+
+        storage.delete_message(message_id);
+        assert!(storage.message(message_id).is_none());
+        assert_eq!(storage.edits(message_id).count(), 0);
+        assert_eq!(storage.expired_messages(1664832120000).count(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_storage_outbox() {
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let mut storage = fixtures().await;
+        let channel_id = ChannelId::User(uuid!("966960e0-a8cd-43f1-ac7a-2c986dd470cd"));
+        let from_id = uuid!("a955d20f-6b83-4e69-846e-a99b1779ff7a");
+        let message_id = MessageId::new(channel_id, from_id, 1664832050000);
+        assert_eq!(storage.outbox().count(), 0);
+
+        let mut message = storage.message(message_id).unwrap().into_owned();
+        message.send_state = Some(SendState::Failed("timeout".to_owned()));
+        storage.store_message(channel_id, message.clone());
+        assert_eq!(storage.outbox().collect::<Vec<_>>(), [message_id]);
+        let stored = storage.message(message_id).unwrap();
+        assert_eq!(stored.send_state, message.send_state);
+        let messages: Vec<_> = storage.messages(channel_id).collect();
+        assert_eq!(messages[0].send_state, message.send_state);
+
+        message.send_state = None;
+        storage.store_message(channel_id, message);
+        assert_eq!(storage.outbox().count(), 0);
+        assert_eq!(storage.message(message_id).unwrap().send_state, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_storage_pending_attachments() {
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let mut storage = fixtures().await;
+        let channel_id = ChannelId::User(uuid!("966960e0-a8cd-43f1-ac7a-2c986dd470cd"));
+        let from_id = uuid!("a955d20f-6b83-4e69-846e-a99b1779ff7a");
+        let message_id = MessageId::new(channel_id, from_id, 1664832050000);
+
+        let mut message = storage.message(message_id).unwrap().into_owned();
+        message.pending_attachments = vec![PendingAttachment {
+            id: "some_attachment".to_owned(),
+            content_type: "video/mp4".to_owned(),
+            size: 12_000_000,
+            pointer: vec![1, 2, 3],
+        }];
+        storage.store_message(channel_id, message.clone());
+        let stored = storage.message(message_id).unwrap();
+        assert_eq!(stored.pending_attachments, message.pending_attachments);
+        let messages: Vec<_> = storage.messages(channel_id).collect();
+        assert_eq!(messages[0].pending_attachments, message.pending_attachments);
+
+        message.pending_attachments.clear();
+        storage.store_message(channel_id, message);
+        let stored = storage.message(message_id).unwrap();
+        assert!(stored.pending_attachments.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_storage_attachment_files() {
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let mut storage = fixtures().await;
+        let channel_id = ChannelId::User(uuid!("966960e0-a8cd-43f1-ac7a-2c986dd470cd"));
+        let from_id = uuid!("a955d20f-6b83-4e69-846e-a99b1779ff7a");
+        let message_id = MessageId::new(channel_id, from_id, 1664832050000);
+        assert_eq!(storage.attachment_files().count(), 0);
+
+        let attachment = |id: &str, filename: &str| Attachment {
+            id: id.to_owned(),
+            content_type: "image/jpeg".to_owned(),
+            filename: filename.into(),
+            size: 1,
+        };
+        let mut message = storage.message(message_id).unwrap().into_owned();
+        message.attachments = vec![
+            attachment("photo", "/files/photo.jpeg"),
+            attachment("other", "/files/other.jpeg"),
+        ];
+        storage.store_message(channel_id, message.clone());
+        storage.save();
+        // the edit is not committed yet
+        let edit = Message {
+            edit: Some(message.arrived_at),
+            attachments: vec![attachment("photo", "/files/photo-1.jpeg")],
+            ..Message::text(from_id, 1664832050002, "edited".to_owned())
+        };
+        storage.store_message(channel_id, edit);
+
+        let file = |filename: &str| AttachmentFile {
+            message_id,
+            attachment_id: "photo".to_owned(),
+            filename: filename.into(),
+        };
+        let mut copies: Vec<_> = storage.attachment_copies("photo").collect();
+        copies.sort();
+        assert_eq!(
+            copies,
+            [file("/files/photo-1.jpeg"), file("/files/photo.jpeg")]
+        );
+        assert_eq!(storage.attachment_files().count(), 3);
+
+        storage.save();
+        assert_eq!(storage.attachment_copies("photo").count(), 2);
+        storage.delete_message(message_id);
+        storage.save();
+        assert_eq!(storage.attachment_files().count(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_storage_indexes_existing_attachments() {
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let mut storage = fixtures().await;
+        let channel_id = ChannelId::User(uuid!("966960e0-a8cd-43f1-ac7a-2c986dd470cd"));
+        let from_id = uuid!("a955d20f-6b83-4e69-846e-a99b1779ff7a");
+        let message_id = MessageId::new(channel_id, from_id, 1664832050000);
+        let mut message = storage.message(message_id).unwrap().into_owned();
+        message.attachments = vec![Attachment {
+            id: "photo".to_owned(),
+            content_type: "image/jpeg".to_owned(),
+            filename: "/files/photo.jpeg".into(),
+            size: 1,
+        }];
+        storage.store_message(channel_id, message);
+        storage.save();
+
+        // as if the message was stored before the index existed
+        query!("DELETE FROM message_attachments")
+            .execute(&storage.pool)
+            .await
+            .unwrap();
+        query!("INSERT INTO message_attachments_backfill(id) VALUES (0)")
+            .execute(&storage.pool)
+            .await
+            .unwrap();
+        assert_eq!(storage.attachment_files().count(), 0);
+
+        index_existing_attachments(&storage.pool).await.unwrap();
+        let files: Vec<_> = storage.attachment_files().collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].message_id, message_id);
+        assert_eq!(files[0].filename, PathBuf::from("/files/photo.jpeg"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_storage_search() {
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let mut storage = fixtures().await;
+        let user_id = uuid!("a955d20f-6b83-4e69-846e-a99b1779ff7a");
+        let channel_id = ChannelId::User(uuid!("966960e0-a8cd-43f1-ac7a-2c986dd470cd"));
+        let message_id = MessageId::new(channel_id, user_id, 1664832050000);
+        let search = |storage: &SqliteStorage, query: SearchQuery| -> Vec<MessageId> {
+            storage.search(&query, usize::MAX).collect()
+        };
+        let words = |words: &[&str]| SearchQuery {
+            words: words.iter().map(|&word| word.to_owned()).collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(search(&storage, words(&["HEL"])), [message_id]);
+        assert!(search(&storage, words(&["hello", "world"])).is_empty());
+        assert!(search(&storage, words(&["\"quoted"])).is_empty());
+
+        // filters without words
+        let from = SearchQuery {
+            from: Some(vec![user_id]),
+            ..Default::default()
+        };
+        assert_eq!(search(&storage, from), [message_id]);
+        let before = SearchQuery {
+            before: Some(1664832050000),
+            ..Default::default()
+        };
+        assert!(search(&storage, before).is_empty());
+        let channels = SearchQuery {
+            channels: Some(vec![channel_id]),
+            ..Default::default()
+        };
+        assert_eq!(search(&storage, channels), [message_id]);
+        let other_channels = SearchQuery {
+            channels: Some(vec![ChannelId::User(Uuid::nil())]),
+            ..Default::default()
+        };
+        assert!(search(&storage, other_channels).is_empty());
+        let has_attachment = SearchQuery {
+            has_attachment: true,
+            ..Default::default()
+        };
+        assert!(search(&storage, has_attachment.clone()).is_empty());
+
+        let photo_id = MessageId::new(channel_id, user_id, 1664832060000);
+        storage.store_message(
+            channel_id,
+            Message {
+                attachments: vec![Attachment {
+                    id: "digest".to_owned(),
+                    content_type: "image/jpeg".to_owned(),
+                    filename: "photo.jpeg".into(),
+                    size: 1,
+                }],
+                ..Message::text(user_id, photo_id.arrived_at, "photo".into())
+            },
+        );
+        assert_eq!(search(&storage, has_attachment), [photo_id]);
+        assert!(storage.search(&words(&["hel"]), 0).next().is_none());
+
+        // edits are found by the latest text
+        storage.store_edited_message(
+            channel_id,
+            message_id.arrived_at,
+            Message::text(user_id, 1664832070000, "goodbye".into()),
+        );
+        assert!(search(&storage, words(&["hello"])).is_empty());
+        assert_eq!(search(&storage, words(&["goodbye"])), [message_id]);
+
+        storage.delete_message(message_id);
+        assert!(search(&storage, words(&["goodbye"])).is_empty());
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_sqlite_storage_store_new_message() {
         let _ = tracing_subscriber::fmt().with_test_writer().try_init();
         let mut storage = fixtures().await;
 
         let id: Uuid = uuid!("966960e0-a8cd-43f1-ac7a-2c986dd470cd");
+        let quote_from_id = uuid!("a955d20f-6b83-4e69-846e-a99b1779ff7a");
 
         // store quote
         let quote_arrived_at = 1664832050000;
         let quote = storage
-            .message(MessageId::new(id.into(), quote_arrived_at))
+            .message(MessageId::new(id.into(), quote_from_id, quote_arrived_at))
             .unwrap()
             .into_owned();
 
         // store message
         let arrived_at = 1664832050001;
-        assert_eq!(storage.message(MessageId::new(id.into(), arrived_at)), None);
+        assert_eq!(
+            storage.message(MessageId::new(id.into(), id, arrived_at)),
+            None
+        );
         let attachments = vec![Attachment {
             id: "some_attachment".to_owned(),
             content_type: "image/png".to_owned(),
@@ -773,12 +1683,16 @@ mod tests {
                 arrived_at,
                 quote: Some(Box::new(quote.clone())),
                 attachments: attachments.clone(),
+                pending_attachments: Default::default(),
                 reactions: reactions.clone(),
                 receipt,
                 body_ranges: body_ranges.clone(),
-                send_failed: Default::default(),
+                send_state: Default::default(),
                 edit: Default::default(),
                 edited: Default::default(),
+                deleted: Default::default(),
+                expire_timer: Default::default(),
+                expires_at: Default::default(),
             },
         );
 
@@ -868,6 +1782,104 @@ mod tests {
             channel_id,
             Message::text(from_id, 1664832050000, "hello".to_owned()),
         );
-        assert_eq!(storage.message_channel(1664832050000), Some(channel_id));
+        assert_eq!(
+            storage.message_channel(from_id, 1664832050000),
+            Some(channel_id)
+        );
+
+        // the message sent at the same time in another channel is kept
+        let other_from_id = uuid!("a955d20f-6b83-4e69-846e-a99b1779ff7a");
+        let other_channel_id = ChannelId::User(from_id);
+        assert_eq!(
+            storage.message_channel(other_from_id, 1664832050000),
+            Some(other_channel_id)
+        );
+        let other_message_id = MessageId::new(other_channel_id, other_from_id, 1664832050000);
+        assert_eq!(
+            storage
+                .message(other_message_id)
+                .unwrap()
+                .message
+                .as_deref(),
+            Some("hello")
+        );
+        assert_eq!(storage.messages(channel_id).count(), 1);
+        assert_eq!(storage.messages(other_channel_id).count(), 1);
+    }
+
+    /// Path of the database written by the crashing child process of
+    /// `test_sqlite_storage_crash_between_flushes`
+    const CRASH_DB_PATH_ENV: &str = "GURK_TEST_CRASH_DB_PATH";
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_storage_crash_between_flushes() {
+        const NUM_FLUSHED: u64 = 100;
+        const NUM_UNFLUSHED: u64 = 1000;
+
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let passphrase = Passphrase::new("secret").unwrap();
+        let channel_id = ChannelId::User(uuid!("966960e0-a8cd-43f1-ac7a-2c986dd470cd"));
+        let from_id = uuid!("a955d20f-6b83-4e69-846e-a99b1779ff7a");
+
+        if let Some(path) = std::env::var_os(CRASH_DB_PATH_ENV).map(PathBuf::from) {
+            // child process: crash while the writes after the flush are still being committed
+            let url: Url = format!("sqlite://{}", path.display()).parse().unwrap();
+            let mut storage = SqliteStorage::open(&url, &passphrase).await.unwrap();
+            storage.store_channel(Channel {
+                id: channel_id,
+                name: "direct-channel".to_owned(),
+                group_data: None,
+                unread_messages: 0,
+                muted: false,
+                typing: TypingSet::new(false),
+                expire_timer: None,
+                draft: None,
+            });
+            for arrived_at in 0..NUM_FLUSHED + NUM_UNFLUSHED {
+                let text = format!("hello {arrived_at}");
+                storage.store_message(channel_id, Message::text(from_id, arrived_at, text));
+                if arrived_at + 1 == NUM_FLUSHED {
+                    storage.save();
+                }
+            }
+            std::process::abort();
+        }
+
+        let tempdir = tempdir().unwrap();
+        let path = tempdir.path().join("data.db");
+        let test_name = concat!(
+            module_path!(),
+            "::test_sqlite_storage_crash_between_flushes"
+        );
+        let (_crate_name, test_name) = test_name.split_once("::").unwrap();
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([test_name, "--exact", "--nocapture"])
+            .env(CRASH_DB_PATH_ENV, &path)
+            .status()
+            .unwrap();
+        assert!(!status.success(), "child process did not crash");
+
+        let url: Url = format!("sqlite://{}", path.display()).parse().unwrap();
+        let storage = SqliteStorage::open(&url, &passphrase).await.unwrap();
+        let integrity = query_scalar::<_, String>("PRAGMA integrity_check")
+            .fetch_one(&storage.pool)
+            .await
+            .unwrap();
+        assert_eq!(integrity, "ok");
+
+        // flushed messages are kept, and the other ones are kept in the order they were written
+        let messages: Vec<u64> = storage
+            .messages(channel_id)
+            .map(|message| message.arrived_at)
+            .collect();
+        assert!(messages.len() >= NUM_FLUSHED as usize);
+        assert!(messages.iter().copied().eq(0..messages.len() as u64));
+
+        // no message is written partially
+        let query = SearchQuery {
+            words: vec!["hello".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(storage.search(&query, usize::MAX).count(), messages.len());
     }
 }
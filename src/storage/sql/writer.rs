@@ -0,0 +1,473 @@
+//! Write-behind queue of the sqlite storage
+//!
+//! Writes are sent to a background task, which commits all writes queued so far in a single
+//! transaction. A crash between two commits loses the not yet committed writes, but never leaves
+//! a partially applied write in the database.
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use sqlx::{Connection, SqliteConnection, SqlitePool, query, query_scalar};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+use crate::data::{Channel, ChannelId, Message};
+use crate::signal::Attachment;
+use crate::storage::{MessageId, Metadata};
+
+use super::encoding::BlobData;
+use super::pending::Pending;
+use super::util::ResultExt as _;
+
+const METADATA_ID: i64 = 0;
+
+/// Maximum number of writes committed in a single transaction
+const MAX_BATCH_SIZE: usize = 1000;
+
+pub(super) enum Write {
+    Channel(Channel),
+    Message(ChannelId, Message),
+    DeleteMessage(MessageId),
+    Name(Uuid, String),
+    Metadata(Metadata),
+}
+
+enum Request {
+    /// Write with its sequence number in [`Pending`]
+    Write(u64, Write),
+    /// Acknowledged as soon as all previously queued writes are committed
+    Flush(oneshot::Sender<()>),
+}
+
+/// Handle to the background task committing the writes
+#[derive(Clone)]
+pub(super) struct Writer {
+    tx: mpsc::UnboundedSender<Request>,
+    /// Queued writes which are not committed yet
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl Writer {
+    /// Spawns the background task committing the writes to the pool
+    ///
+    /// The task stops after all handles are dropped and the remaining writes are committed.
+    pub(super) fn spawn(pool: SqlitePool) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        tokio::spawn(run(pool, rx, pending.clone()));
+        Self { tx, pending }
+    }
+
+    pub(super) fn send(&self, write: Write) {
+        let seq = self.pending().record(&write);
+        if self.tx.send(Request::Write(seq, write)).is_err() {
+            error!("sqlite writer is stopped; dropping write");
+        }
+    }
+
+    /// Queued writes which are not committed yet
+    ///
+    /// The lock must not be held while waiting for a query.
+    pub(super) fn pending(&self) -> MutexGuard<'_, Pending> {
+        lock(&self.pending)
+    }
+
+    /// Waits until all queued writes are committed
+    pub(super) async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self.tx.send(Request::Flush(tx)).is_ok() {
+            // the writer is stopped if the sender is dropped
+            let _ = rx.await;
+        }
+    }
+}
+
+fn lock(pending: &Mutex<Pending>) -> MutexGuard<'_, Pending> {
+    // the pending writes are consistent even if a thread panicked while holding the lock
+    pending.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+async fn run(
+    pool: SqlitePool,
+    mut rx: mpsc::UnboundedReceiver<Request>,
+    pending: Arc<Mutex<Pending>>,
+) {
+    let mut requests = Vec::with_capacity(MAX_BATCH_SIZE);
+    while rx.recv_many(&mut requests, MAX_BATCH_SIZE).await > 0 {
+        let mut writes = Vec::with_capacity(requests.len());
+        let mut flushes = Vec::new();
+        let mut last_seq = None;
+        for request in requests.drain(..) {
+            match request {
+                Request::Write(seq, write) => {
+                    writes.push(write);
+                    last_seq = Some(seq);
+                }
+                Request::Flush(tx) => flushes.push(tx),
+            }
+        }
+
+        if !writes.is_empty() {
+            let num_writes = writes.len();
+            debug!(num_writes, "committing writes");
+            commit(&pool, writes).await.ok_logged();
+        }
+        if let Some(seq) = last_seq {
+            lock(&pending).remove_committed(seq);
+        }
+        for tx in flushes {
+            let _ = tx.send(());
+        }
+    }
+}
+
+async fn commit(pool: &SqlitePool, writes: Vec<Write>) -> sqlx::Result<()> {
+    let mut transaction = pool.begin().await?;
+    for write in writes {
+        // a failed write is rolled back on its own without aborting the whole batch
+        let mut savepoint = Connection::begin(&mut *transaction).await?;
+        match write.execute(&mut *savepoint).await {
+            Ok(()) => savepoint.commit().await?,
+            Err(error) => {
+                error!(%error, "failed to write");
+                savepoint.rollback().await?;
+            }
+        }
+    }
+    transaction.commit().await
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("timestamp out of bounds")]
+struct InvalidTimestamp;
+
+fn to_sql_timestamp(timestamp: u64) -> Result<i64, InvalidTimestamp> {
+    timestamp.try_into().map_err(|_| InvalidTimestamp)
+}
+
+impl Write {
+    async fn execute(self, conn: &mut SqliteConnection) -> anyhow::Result<()> {
+        match self {
+            Self::Channel(channel) => store_channel(conn, channel).await?,
+            Self::Message(channel_id, message) => store_message(conn, channel_id, message).await?,
+            Self::DeleteMessage(message_id) => delete_message(conn, message_id).await?,
+            Self::Name(id, name) => {
+                query!("REPLACE INTO names(id, name) VALUES (?, ?)", id, name)
+                    .execute(conn)
+                    .await?;
+            }
+            Self::Metadata(metadata) => {
+                query!(
+                    "REPLACE INTO metadata(id, contacts_sync_request_at, fully_migrated)
+                     VALUES (?, ?, ?)",
+                    METADATA_ID,
+                    metadata.contacts_sync_request_at,
+                    metadata.fully_migrated
+                )
+                .execute(conn)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn store_channel(conn: &mut SqliteConnection, channel: Channel) -> sqlx::Result<()> {
+    let id = &channel.id;
+    let name = &channel.name;
+    let (group_master_key, group_revision, group_members) = channel
+        .group_data
+        .as_ref()
+        .map(|group_data| {
+            (
+                Some(&group_data.master_key_bytes[..]),
+                Some(group_data.revision),
+                Some(BlobData(group_data.members.as_slice())),
+            )
+        })
+        .unwrap_or_default();
+    let muted = channel.muted;
+    let expire_timer = channel.expire_timer;
+    let draft = &channel.draft;
+    query!(
+        r#"
+            REPLACE INTO channels(id, name, group_master_key, group_revision, group_members, muted, expire_timer, draft)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+        id,
+        name,
+        group_master_key,
+        group_revision,
+        group_members,
+        muted,
+        expire_timer,
+        draft
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+async fn store_message(
+    conn: &mut SqliteConnection,
+    channel_id: ChannelId,
+    message: Message,
+) -> anyhow::Result<()> {
+    let channel_id = &channel_id;
+    let sent_at = to_sql_timestamp(message.arrived_at)?;
+    let from_id = &message.from_id;
+    let message_msg = message.message.as_deref();
+    let quote_from_id = message.quote.as_ref().map(|quote| quote.from_id);
+    let quote_sent_at: Option<i64> = message
+        .quote
+        .as_ref()
+        .and_then(|quote| to_sql_timestamp(quote.arrived_at).ok_logged());
+    let receipt = BlobData(&message.receipt);
+    let body_ranges = BlobData(&message.body_ranges);
+    let attachments = BlobData(&message.attachments);
+    let reactions = BlobData(&message.reactions);
+    let edit: Option<i64> = message
+        .edit
+        .and_then(|edit| to_sql_timestamp(edit).ok_logged());
+    let edited: bool = message.edited;
+    let deleted: bool = message.deleted;
+    let expire_timer = message.expire_timer;
+    let expires_at: Option<i64> = message
+        .expires_at
+        .and_then(|expires_at| to_sql_timestamp(expires_at).ok_logged());
+    // updates an existing message in place, such that its id stays the same
+    let id = query_scalar!(
+        "
+            INSERT INTO messages(
+                channel_id,
+                from_id,
+                sent_at,
+                message,
+                quote_from_id,
+                quote_sent_at,
+                receipt,
+                body_ranges,
+                attachments,
+                reactions,
+                edit,
+                edited,
+                deleted,
+                expire_timer,
+                expires_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (channel_id, from_id, sent_at) DO UPDATE SET
+                message = excluded.message,
+                quote_from_id = excluded.quote_from_id,
+                quote_sent_at = excluded.quote_sent_at,
+                receipt = excluded.receipt,
+                body_ranges = excluded.body_ranges,
+                attachments = excluded.attachments,
+                reactions = excluded.reactions,
+                edit = excluded.edit,
+                edited = excluded.edited,
+                deleted = excluded.deleted,
+                expire_timer = excluded.expire_timer,
+                expires_at = excluded.expires_at
+            RETURNING id
+        ",
+        channel_id,
+        from_id,
+        sent_at,
+        message_msg,
+        quote_from_id,
+        quote_sent_at,
+        receipt,
+        body_ranges,
+        attachments,
+        reactions,
+        edit,
+        edited,
+        deleted,
+        expire_timer,
+        expires_at
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    // edits are found by the text of the original message
+    query!("DELETE FROM messages_fts WHERE rowid = ?", id)
+        .execute(&mut *conn)
+        .await?;
+    if message.edit.is_none()
+        && let Some(text) = message_msg
+    {
+        query!(
+            "INSERT INTO messages_fts(rowid, message) VALUES (?, ?)",
+            id,
+            text
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    if let Some(send_state) = message.send_state.as_ref() {
+        let send_state = BlobData(send_state);
+        query!(
+            "REPLACE INTO outbox(message_id, state) VALUES (?, ?)",
+            id,
+            send_state
+        )
+        .execute(&mut *conn)
+        .await?;
+    } else {
+        query!("DELETE FROM outbox WHERE message_id = ?", id)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    query!("DELETE FROM message_attachments WHERE message_id = ?", id)
+        .execute(&mut *conn)
+        .await?;
+    insert_attachments(&mut *conn, id, &message.attachments).await?;
+
+    if !message.pending_attachments.is_empty() {
+        let pending_attachments = BlobData(&message.pending_attachments);
+        query!(
+            "REPLACE INTO pending_attachments(message_id, attachments) VALUES (?, ?)",
+            id,
+            pending_attachments
+        )
+        .execute(conn)
+        .await?;
+    } else {
+        query!("DELETE FROM pending_attachments WHERE message_id = ?", id)
+            .execute(conn)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn delete_message(conn: &mut SqliteConnection, message_id: MessageId) -> anyhow::Result<()> {
+    let channel_id = &message_id.channel_id;
+    let from_id = &message_id.from_id;
+    let sent_at = to_sql_timestamp(message_id.arrived_at)?;
+    query!(
+        "
+            DELETE FROM outbox WHERE message_id IN (
+                SELECT id FROM messages
+                WHERE channel_id = ?1 AND from_id = ?2 AND (sent_at = ?3 OR edit = ?3)
+            )
+        ",
+        channel_id,
+        from_id,
+        sent_at
+    )
+    .execute(&mut *conn)
+    .await?;
+    query!(
+        "
+            DELETE FROM pending_attachments WHERE message_id IN (
+                SELECT id FROM messages
+                WHERE channel_id = ?1 AND from_id = ?2 AND (sent_at = ?3 OR edit = ?3)
+            )
+        ",
+        channel_id,
+        from_id,
+        sent_at
+    )
+    .execute(&mut *conn)
+    .await?;
+    query!(
+        "
+            DELETE FROM message_attachments WHERE message_id IN (
+                SELECT id FROM messages
+                WHERE channel_id = ?1 AND from_id = ?2 AND (sent_at = ?3 OR edit = ?3)
+            )
+        ",
+        channel_id,
+        from_id,
+        sent_at
+    )
+    .execute(&mut *conn)
+    .await?;
+    query!(
+        "
+            DELETE FROM messages_fts WHERE rowid IN (
+                SELECT id FROM messages
+                WHERE channel_id = ?1 AND from_id = ?2 AND (sent_at = ?3 OR edit = ?3)
+            )
+        ",
+        channel_id,
+        from_id,
+        sent_at
+    )
+    .execute(&mut *conn)
+    .await?;
+    query!(
+        "
+            DELETE FROM messages
+            WHERE channel_id = ?1 AND from_id = ?2 AND (sent_at = ?3 OR edit = ?3)
+        ",
+        channel_id,
+        from_id,
+        sent_at
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Adds the attachments of the message with the given id to the index of attachment files
+async fn insert_attachments(
+    conn: &mut SqliteConnection,
+    message_id: i64,
+    attachments: &[Attachment],
+) -> sqlx::Result<()> {
+    for attachment in attachments {
+        let attachment_id = &attachment.id;
+        // paths are valid unicode, since they are encoded with serde in the messages
+        let Some(filename) = attachment.filename.to_str() else {
+            continue;
+        };
+        query!(
+            "
+                INSERT OR IGNORE INTO message_attachments(attachment_id, message_id, filename)
+                VALUES (?, ?, ?)
+            ",
+            attachment_id,
+            message_id,
+            filename
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Adds the attachments of the messages stored before the index of attachment files existed
+///
+/// Does nothing after the first run.
+pub(super) async fn index_existing_attachments(pool: &SqlitePool) -> sqlx::Result<()> {
+    let mut transaction = pool.begin().await?;
+    let is_pending = query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM message_attachments_backfill) AS "is_pending!: bool""#
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    if !is_pending {
+        return Ok(());
+    }
+    let messages = query!(
+        r#"
+            SELECT id, attachments AS "attachments!: BlobData<Vec<Attachment>>"
+            FROM messages
+            WHERE attachments IS NOT NULL
+        "#
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    info!(num_messages = messages.len(), "indexing attachments");
+    for message in messages {
+        insert_attachments(&mut *transaction, message.id, &message.attachments.0).await?;
+    }
+    query!("DELETE FROM message_attachments_backfill")
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await
+}
@@ -1,14 +1,19 @@
 //! Draw the UI
 
 use std::fmt;
+use std::num::NonZeroU16;
+use std::ops::Range;
+use std::rc::Rc;
 
 use chrono::Datelike;
+use image::Rgb;
 use itertools::Itertools;
 use ratatui::Frame;
+use ratatui::buffer::{Buffer, CellDiffOption};
 use ratatui::text::{Line, Span, Text};
 use ratatui::widgets::{Block, Borders, Clear, List, ListDirection, ListItem, Paragraph};
 use ratatui::{
-    layout::{Constraint, Direction, Layout, Rect},
+    layout::{Constraint, Direction, Layout, Margin, Rect},
     widgets::Padding,
 };
 use ratatui::{
@@ -19,19 +24,24 @@ use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 use uuid::Uuid;
 
 use crate::app::App;
+use crate::attachment_tray::{AddAttachment, AttachmentTray};
 use crate::channels::SelectChannel;
 use crate::command::{Command, WindowMode};
 use crate::cursor::Cursor;
-use crate::data::{AssociatedValue, Message};
-use crate::receipt::{Receipt, ReceiptEvent};
+use crate::data::{self, AssociatedValue, Message, SendState};
+use crate::find::Find;
+use crate::preview::{ImagePreviews, PreviewContent, PreviewSize};
+use crate::receipt::Receipt;
+use crate::signal::PendingAttachment;
 use crate::storage::MessageId;
-use crate::util::utc_timestamp_msec_to_local;
+use crate::util::{utc_now_timestamp_msec, utc_timestamp_msec_to_local};
 
 use super::CHANNEL_VIEW_RATIO;
 use super::name_resolver::NameResolver;
 
 /// The main function drawing the UI for each frame
-pub fn draw(f: &mut Frame, app: &mut App) {
+/// Draws the app and returns the ids of the messages visible on the screen
+pub fn draw(f: &mut Frame, app: &mut App) -> Vec<MessageId> {
     if app.is_help() {
         // Display shortcut panel
         let chunks = Layout::default()
@@ -43,7 +53,7 @@ pub fn draw(f: &mut Frame, app: &mut App) {
             .direction(Direction::Horizontal)
             .split(f.area());
         draw_help(f, app, chunks[1]);
-        return;
+        return Vec::new();
     }
 
     let chunks = if app.is_channel_list_shown() {
@@ -64,16 +74,26 @@ pub fn draw(f: &mut Frame, app: &mut App) {
             .split(f.area())
     };
 
-    if app.is_channel_list_shown() {
+    let shown_messages = if app.is_channel_list_shown() {
         draw_channels(f, app, chunks[0]);
-        draw_chat(f, app, chunks[1]);
+        draw_chat(f, app, chunks[1])
     } else {
-        draw_chat(f, app, chunks[0]);
-    }
+        draw_chat(f, app, chunks[0])
+    };
 
     if app.select_channel.is_shown {
         draw_select_channel_popup(f, &mut app.select_channel);
     }
+    if app.search.is_shown {
+        draw_search_popup(f, app);
+    }
+    if app.attachment_picker.is_shown {
+        draw_attachment_picker_popup(f, app);
+    }
+    if app.add_attachment.is_shown {
+        draw_add_attachment_popup(f, &mut app.add_attachment);
+    }
+    shown_messages
 }
 
 fn draw_select_channel_popup(f: &mut Frame, select_channel: &mut SelectChannel) {
@@ -110,6 +130,153 @@ fn draw_select_channel_popup(f: &mut Frame, select_channel: &mut SelectChannel)
     f.render_stateful_widget(list, chunks[1], &mut select_channel.state);
 }
 
+fn draw_search_popup(f: &mut Frame, app: &mut App) {
+    let area = centered_rect(80, 60, f.area());
+    let chunks = Layout::default()
+        .constraints([Constraint::Length(1 + 2), Constraint::Min(0)].as_ref())
+        .direction(Direction::Vertical)
+        .split(area);
+    f.render_widget(Clear, area);
+    let input = Paragraph::new(Text::from(app.search.input.data.clone())).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Search messages"),
+    );
+    f.render_widget(input, chunks[0]);
+    let cursor = &app.search.input.cursor;
+    f.set_cursor_position((
+        chunks[0].x + cursor.col as u16 + 1,
+        chunks[0].y + cursor.line as u16 + 1,
+    ));
+
+    if let Some(error) = app.search.error() {
+        let error = Paragraph::new(error.to_string())
+            .style(Style::default().fg(Color::Red))
+            .block(Block::default().borders(Borders::ALL));
+        f.render_widget(error, chunks[1]);
+        return;
+    }
+    let items: Vec<_> = app
+        .search
+        .results()
+        .iter()
+        .filter_map(|&message_id| display_search_result(app, message_id))
+        .map(ListItem::new)
+        .collect();
+    let title = if app.search.is_outdated() {
+        "Press enter to search".to_string()
+    } else {
+        format!("{} results", items.len())
+    };
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().reversed());
+    f.render_stateful_widget(list, chunks[1], &mut app.search.state);
+}
+
+fn draw_attachment_picker_popup(f: &mut Frame, app: &mut App) {
+    let area = centered_rect(60, 40, f.area());
+    f.render_widget(Clear, area);
+    let picker = &mut app.attachment_picker;
+    let list_area = if picker.is_saving {
+        let chunks = Layout::default()
+            .constraints([Constraint::Length(1 + 2), Constraint::Min(0)].as_ref())
+            .direction(Direction::Vertical)
+            .split(area);
+        let input = Paragraph::new(Text::from(picker.input.data.clone()))
+            .block(Block::default().borders(Borders::ALL).title("Save to"));
+        f.render_widget(input, chunks[0]);
+        let cursor = &picker.input.cursor;
+        f.set_cursor_position((
+            chunks[0].x + cursor.col as u16 + 1,
+            chunks[0].y + cursor.line as u16 + 1,
+        ));
+        chunks[1]
+    } else {
+        area
+    };
+
+    let items: Vec<_> = picker
+        .attachments()
+        .iter()
+        .map(|attachment| {
+            let name = attachment
+                .filename
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+            ListItem::new(format!(
+                "{name} ({}, {})",
+                attachment.content_type,
+                display_size(attachment.size.into())
+            ))
+        })
+        .collect();
+    let mut block = Block::default().borders(Borders::ALL).title("Attachments");
+    if let Some(status) = picker.status() {
+        let status = match status {
+            Ok(status) => Span::styled(status.to_owned(), Style::default().fg(Color::Green)),
+            Err(error) => Span::styled(error.to_owned(), Style::default().fg(Color::Red)),
+        };
+        block = block.title_bottom(Line::from(status));
+    }
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(Style::default().reversed());
+    f.render_stateful_widget(list, list_area, &mut picker.state);
+}
+
+fn draw_add_attachment_popup(f: &mut Frame, add_attachment: &mut AddAttachment) {
+    let area = centered_rect(60, 60, f.area());
+    let chunks = Layout::default()
+        .constraints([Constraint::Length(1 + 2), Constraint::Min(0)].as_ref())
+        .direction(Direction::Vertical)
+        .split(area);
+    f.render_widget(Clear, area);
+    let input = Paragraph::new(Text::from(add_attachment.input.data.clone()))
+        .block(Block::default().borders(Borders::ALL).title("Attach file"));
+    f.render_widget(input, chunks[0]);
+    let cursor = &add_attachment.input.cursor;
+    f.set_cursor_position((
+        chunks[0].x + cursor.col as u16 + 1,
+        chunks[0].y + cursor.line as u16 + 1,
+    ));
+
+    if let Some(error) = add_attachment.error() {
+        let error = Paragraph::new(error.to_string())
+            .style(Style::default().fg(Color::Red))
+            .wrap(Wrap { trim: false })
+            .block(Block::default().borders(Borders::ALL));
+        f.render_widget(error, chunks[1]);
+        return;
+    }
+    let items: Vec<_> = add_attachment
+        .completions()
+        .iter()
+        .map(|completion| ListItem::new(completion.as_str()))
+        .collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL))
+        .highlight_style(Style::default().reversed());
+    f.render_stateful_widget(list, chunks[1], &mut add_attachment.state);
+}
+
+fn display_search_result(app: &App, message_id: MessageId) -> Option<String> {
+    let message = app.storage.message(message_id)?;
+    let channel = app.storage.channel(message_id.channel_id)?;
+    let date = utc_timestamp_msec_to_local(message.arrived_at).format("%Y-%m-%d %R");
+    let text = message
+        .message
+        .as_deref()
+        .and_then(|text| text.lines().next())
+        .unwrap_or_default();
+    Some(format!(
+        "{date} [{}] {}: {text}",
+        app.channel_name(&channel),
+        app.name_by_id_cached(message.from_id)
+    ))
+}
+
 fn draw_channels(f: &mut Frame, app: &mut App, area: Rect) {
     let channel_list_width = area.width.saturating_sub(2) as usize;
     let channels = app
@@ -124,7 +291,8 @@ fn draw_channels(f: &mut Frame, app: &mut App, area: Rect) {
                 String::new()
             };
             let mute_label = if channel.muted { " [M]" } else { "" };
-            let suffix = format!("{unread_messages_label}{mute_label}");
+            let draft_label = if app.has_draft(&channel) { " [D]" } else { "" };
+            let suffix = format!("{unread_messages_label}{mute_label}{draft_label}");
             let channel_name = app.channel_name(&channel);
             let label = format!("{channel_name}{suffix}");
             let label_width = label.width();
@@ -204,15 +372,31 @@ fn wrap(text: &str, mut cursor: Cursor, width: usize) -> (String, Cursor, usize)
     (res, cursor, line + 1)
 }
 
-fn draw_chat(f: &mut Frame, app: &mut App, area: Rect) {
+/// Draws the messages and the input of the selected channel, and returns the shown messages
+fn draw_chat(f: &mut Frame, app: &mut App, area: Rect) -> Vec<MessageId> {
     let text_width = area.width.saturating_sub(2) as usize;
+    // the find prompt replaces the input box while it is shown
+    let input = if app.find.is_shown {
+        &app.find.input
+    } else {
+        &app.input
+    };
     let (wrapped_input, cursor, num_input_lines) =
-        wrap(&app.input.data, app.input.cursor.clone(), text_width);
+        wrap(&input.data, input.cursor.clone(), text_width);
+
+    let reply_text = app.reply_target().map(|message| {
+        let names = NameResolver::compute(app, std::iter::empty());
+        displayed_reply_target(&names, &message)
+    });
+
+    let tray = display_attachment_tray(&app.attachment_tray);
 
     let chunks = Layout::default()
         .constraints(
             [
                 Constraint::Min(0),
+                Constraint::Length(reply_text.is_some().into()),
+                Constraint::Length(tray.len() as u16),
                 Constraint::Length(num_input_lines as u16 + 2),
             ]
             .as_ref(),
@@ -220,83 +404,126 @@ fn draw_chat(f: &mut Frame, app: &mut App, area: Rect) {
         .direction(Direction::Vertical)
         .split(area);
 
-    draw_messages(f, app, chunks[0]);
+    let shown_messages = draw_messages(f, app, chunks[0]);
 
-    let title = match (app.is_editing(), app.is_multiline_input) {
-        (true, true) => "Input (Editing, Multiline)",
-        (true, false) => "Input (Editing)",
-        (false, true) => "Input (Multiline)",
-        (false, false) => "Input",
+    if let Some(reply_text) = reply_text {
+        let reply = Paragraph::new(reply_text).style(Style::default().fg(Color::Yellow));
+        f.render_widget(reply, chunks[1]);
+    }
+    f.render_widget(Paragraph::new(tray), chunks[2]);
+
+    let title = if app.find.is_shown {
+        if app.find.target().is_some() {
+            format!("Find {}", display_find_status(&app.find))
+        } else {
+            "Find".to_owned()
+        }
+    } else if app.is_deleting() {
+        "Delete the selected message for everyone? Delete again to confirm".to_owned()
+    } else {
+        match (app.is_editing(), app.is_multiline_input) {
+            (true, true) => "Input (Editing, Multiline)",
+            (true, false) => "Input (Editing)",
+            (false, true) => "Input (Multiline)",
+            (false, false) => "Input",
+        }
+        .to_owned()
     };
 
     let input = Paragraph::new(Text::from(wrapped_input))
         .block(Block::default().borders(Borders::ALL).title(title));
-    f.render_widget(input, chunks[1]);
+    f.render_widget(input, chunks[3]);
     if !app.select_channel.is_shown {
         f.set_cursor_position((
-            chunks[1].x + cursor.col as u16 + 1,  // +1 for frame
-            chunks[1].y + cursor.line as u16 + 1, // +1 for frame
+            chunks[3].x + cursor.col as u16 + 1,  // +1 for frame
+            chunks[3].y + cursor.line as u16 + 1, // +1 for frame
         ));
+        if app.mention_completion.is_shown() {
+            draw_mention_completion_popup(f, app, chunks[3]);
+        }
     }
+    shown_messages
 }
 
-fn prepare_receipts(app: &mut App, height: usize) {
-    let user_id = app.user_id;
-    let channel_id = match app.channels.selected_item() {
-        Some(channel_id) => *channel_id,
-        None => return,
-    };
-    let messages = match app.messages.get(&channel_id) {
-        Some(messages) => messages,
-        None => return,
-    };
-    if messages.items.is_empty() {
-        return;
+/// Lines of the pending attachments followed by the error which prevented sending them
+fn display_attachment_tray(tray: &AttachmentTray) -> Vec<Line<'static>> {
+    let attachments = tray
+        .attachments()
+        .iter()
+        .enumerate()
+        .map(|(idx, attachment)| {
+            let text = format!(
+                "+ {} ({}, {})",
+                attachment.file_name(),
+                attachment.content_type,
+                display_size(attachment.size)
+            );
+            let style = if tray.selected() == Some(idx) {
+                Style::default().fg(Color::Cyan).reversed()
+            } else {
+                Style::default().fg(Color::Cyan)
+            };
+            Line::styled(text, style)
+        });
+    let error = tray
+        .error()
+        .map(|error| Line::styled(error.to_owned(), Style::default().fg(Color::Red)));
+    attachments.chain(error).collect()
+}
+
+/// Position of the selected match among the matches found so far, e.g. `[2/5]`
+fn display_find_status(find: &Find) -> String {
+    let pending = if find.is_pending() { "…" } else { "" };
+    match find.selected() {
+        Some(idx) => format!("[{}/{}{pending}]", idx + 1, find.matches().len()),
+        None if find.is_pending() => "[searching…]".to_owned(),
+        None => "[no matches]".to_owned(),
     }
+}
 
-    let offset = if let Some(selected) = messages.state.selected() {
-        messages
-            .rendered
-            .offset
-            .clamp(selected.saturating_sub(height), selected)
-    } else {
-        messages.rendered.offset
+/// Draws the mention completion list right above the input box
+fn draw_mention_completion_popup(f: &mut Frame, app: &mut App, input_area: Rect) {
+    const MAX_VISIBLE_CANDIDATES: usize = 5;
+
+    let candidates = app.mention_completion.candidates();
+    let width = candidates
+        .iter()
+        .map(|mention| mention.name.width() + 1) // +1 for @
+        .max()
+        .unwrap_or(0)
+        + 2; // +2 for frame
+    let height = candidates.len().min(MAX_VISIBLE_CANDIDATES) + 2; // +2 for frame
+    let height = (height as u16).min(input_area.y);
+    let area = Rect {
+        x: input_area.x,
+        y: input_area.y - height,
+        width: (width as u16).min(input_area.width),
+        height,
     };
 
-    let read_messages: Vec<Message> = app
-        .storage
-        .messages(channel_id)
-        .rev()
-        .skip(offset)
-        .filter_map(|message| {
-            if let Receipt::Delivered = message.receipt
-                && message.from_id != user_id
-            {
-                let mut message = message.into_owned();
-                message.receipt = Receipt::Read;
-                return Some(message);
-            }
-            None
-        })
+    let items: Vec<_> = candidates
+        .iter()
+        .map(|mention| ListItem::new(format!("@{}", mention.name)))
         .collect();
-
-    for message in read_messages {
-        let from_id = message.from_id;
-        let arrived_at = message.arrived_at;
-        app.storage.store_message(channel_id, message);
-        app.add_receipt_event(ReceiptEvent::new(from_id, arrived_at, Receipt::Read));
-    }
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL))
+        .highlight_style(Style::default().reversed());
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(list, area, &mut app.mention_completion.state);
 }
 
-fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
+/// Draws the messages of the selected channel and returns the ids of the visible ones
+fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) -> Vec<MessageId> {
     // area without borders
     let height = area.height.saturating_sub(2) as usize;
     if height == 0 {
-        return;
+        return Vec::new();
     }
     let width = area.width.saturating_sub(2) as usize;
 
-    prepare_receipts(app, height);
+    if app.config.image_previews.enabled {
+        app.previews.update_cell_size();
+    }
 
     let Some(&channel_id) = app.channels.selected_item() else {
         f.render_widget(
@@ -309,7 +536,7 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
                 .centered(),
             area,
         );
-        return;
+        return Vec::new();
     };
 
     let channel = app
@@ -339,12 +566,7 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
         .take(height)
         .copied();
 
-    let names = NameResolver::compute(
-        app,
-        messages_to_render
-            .clone()
-            .map(|arrived_at| MessageId::new(channel_id, arrived_at)),
-    );
+    let names = NameResolver::compute(app, messages_to_render.clone());
 
     // message display options
     const TIME_WIDTH: usize = 6; // width of "00:00 "
@@ -355,16 +577,18 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
     let prefix = " ".repeat(prefix_width);
 
     // The day of the message at the bottom of the viewport
-    let first_msg_timestamp = messages_to_render.clone().next().unwrap_or_default();
+    let first_msg_timestamp = messages_to_render
+        .clone()
+        .next()
+        .map(|message_id| message_id.arrived_at)
+        .unwrap_or_default();
     let mut previous_msg_timestamp = first_msg_timestamp;
     let mut previous_msg_day = utc_timestamp_msec_to_local(first_msg_timestamp).num_days_from_ce();
 
     let messages_from_offset = messages_to_render
         .enumerate()
-        .flat_map(|(idx, arrived_at)| {
-            let msg = app
-                .storage
-                .message(MessageId::new(channel_id, arrived_at))?;
+        .flat_map(|(idx, message_id)| {
+            let msg = app.storage.message(message_id)?;
             let date_division = display_date_line(
                 msg.arrived_at,
                 previous_msg_timestamp,
@@ -382,7 +606,9 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
 
             previous_msg_timestamp = msg.arrived_at;
             let show_receipt = ShowReceipt::from_msg(&msg, app.user_id, app.config.show_receipts);
-            display_message(
+            let show_spoilers = app.is_spoiler_revealed(message_id);
+            let find_pattern = app.find.pattern(channel_id);
+            let mut text = display_message(
                 &names,
                 &msg,
                 &prefix,
@@ -392,7 +618,15 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
                 date_division,
                 new_messages_division,
                 app.config.colored_messages,
-            )
+                show_spoilers,
+                find_pattern,
+            )?;
+            let graphics = if app.config.image_previews.enabled && !msg.deleted {
+                add_previews(&app.previews, &msg, &prefix, width, height, &mut text)
+            } else {
+                Vec::new()
+            };
+            Some((ListItem::new(text), graphics))
         });
 
     // counters to accumulate messages as long they fit into the list height,
@@ -400,9 +634,9 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
     let mut items_height = 0;
     let selected = messages.state.selected().unwrap_or(0);
 
-    let mut items: Vec<ListItem<'static>> = messages_from_offset
+    let mut items: Vec<(ListItem<'static>, Vec<Graphic>)> = messages_from_offset
         .enumerate()
-        .take_while(|(idx, item)| {
+        .take_while(|(idx, (item, _))| {
             items_height += item.height();
             items_height <= height || offset + *idx <= selected
         })
@@ -413,7 +647,7 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
     // we known that we either stopped at the last fitting message or at the selected message
     let mut items_height = height;
     let mut first_idx = 0;
-    for (idx, item) in items.iter().enumerate().rev() {
+    for (idx, (item, _)) in items.iter().enumerate().rev() {
         if item.height() <= items_height {
             items_height -= item.height();
             first_idx = idx;
@@ -423,17 +657,28 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
     }
     let offset = offset + first_idx;
     items = items.split_off(first_idx);
+    let num_rendered = items.len();
 
     let title = {
         let channel_name = app.channel_name(&channel);
-        if let Some(writing_people) = writing_people {
+        let mut title = if let Some(writing_people) = writing_people {
             format!("{channel_name} - Messages {writing_people}")
         } else {
             format!("{channel_name} - Messages")
+        };
+        if !app.find.is_shown
+            && let Some(pattern) = app.find.pattern(channel_id)
+        {
+            title += &format!(" - Find: {pattern} {}", display_find_status(&app.find));
         }
+        title
     };
 
-    let list = List::new(items)
+    let item_graphics: Vec<(usize, Vec<Graphic>)> = items
+        .iter_mut()
+        .map(|(item, graphics)| (item.height(), std::mem::take(graphics)))
+        .collect();
+    let list = List::new(items.into_iter().map(|(item, _)| item))
         .block(Block::default().title(title).borders(Borders::ALL))
         .highlight_style(Style::default().reversed())
         .direction(ListDirection::BottomToTop);
@@ -452,10 +697,138 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
     }
 
     f.render_stateful_widget(list, area, state);
+    let first_rendered = state.offset().min(item_graphics.len());
 
     // restore selected state and update offset
     state.select(selected_global);
     messages.rendered.offset = offset;
+
+    let placements = draw_graphics(
+        f.buffer_mut(),
+        area.inner(Margin::new(1, 1)),
+        &item_graphics[first_rendered..],
+    );
+    app.previews.set_placements(placements);
+
+    messages
+        .items
+        .iter()
+        .rev()
+        .skip(offset)
+        .take(num_rendered)
+        .copied()
+        .collect()
+}
+
+/// Image preview drawn with a graphics protocol over blank lines of a message
+struct Graphic {
+    /// Position of the preview in the list item
+    col: u16,
+    row: u16,
+    size: PreviewSize,
+    content: Rc<PreviewContent>,
+}
+
+/// Appends the previews of the image attachments to the text of the message
+///
+/// Half blocks are added as text. Previews drawn with a graphics protocol are reserved as blank
+/// lines and returned.
+fn add_previews(
+    previews: &ImagePreviews,
+    msg: &Message,
+    prefix: &str,
+    width: usize,
+    height: usize,
+    text: &mut Text<'static>,
+) -> Vec<Graphic> {
+    let col = prefix.len().try_into().unwrap_or(u16::MAX);
+    let max_cols = width
+        .saturating_sub(prefix.len())
+        .try_into()
+        .unwrap_or(u16::MAX);
+    let mut graphics = Vec::new();
+    for attachment in &msg.attachments {
+        let Some((size, content)) = previews.preview(attachment, max_cols) else {
+            continue;
+        };
+        if text.lines.len() + usize::from(size.rows) > height {
+            // the preview would not be shown fully
+            break;
+        }
+        match &*content {
+            PreviewContent::HalfBlocks(rows) => {
+                text.lines.extend(rows.iter().map(|row| {
+                    let mut spans = vec![Span::raw(prefix.to_owned())];
+                    spans.extend(row.iter().map(|&(Rgb([r, g, b]), Rgb([r2, g2, b2]))| {
+                        let style = Style::default()
+                            .fg(Color::Rgb(r, g, b))
+                            .bg(Color::Rgb(r2, g2, b2));
+                        Span::styled("▀", style)
+                    }));
+                    Line::from(spans)
+                }));
+            }
+            PreviewContent::Placeholder => {
+                text.lines.push(Line::from(vec![
+                    Span::raw(prefix.to_owned()),
+                    Span::styled("[Loading image]", Style::default().fg(Color::DarkGray)),
+                ]));
+            }
+            PreviewContent::Graphics(_) => {
+                graphics.push(Graphic {
+                    col,
+                    row: text.lines.len().try_into().unwrap_or(u16::MAX),
+                    size,
+                    content: content.clone(),
+                });
+                text.lines
+                    .extend((0..size.rows).map(|_| Line::from(prefix.to_owned())));
+            }
+        }
+    }
+    graphics
+}
+
+/// Draws the graphics of the rendered list items, which are stacked from the bottom of the list
+///
+/// The escape sequence of a graphic is written into its top left cell; the other cells it covers
+/// are skipped, such that the text below does not overwrite it. Returns the areas of the drawn
+/// graphics.
+fn draw_graphics(buf: &mut Buffer, list_area: Rect, items: &[(usize, Vec<Graphic>)]) -> Vec<Rect> {
+    let mut placements = Vec::new();
+    let mut bottom = list_area.bottom();
+    for (item_height, graphics) in items {
+        let Some(top) = u16::try_from(*item_height)
+            .ok()
+            .and_then(|item_height| bottom.checked_sub(item_height))
+            .filter(|&top| top >= list_area.top())
+        else {
+            break;
+        };
+        for graphic in graphics {
+            let PreviewContent::Graphics(escape) = &*graphic.content else {
+                continue;
+            };
+            let area = Rect::new(
+                list_area.x + graphic.col,
+                top + graphic.row,
+                graphic.size.cols,
+                graphic.size.rows,
+            );
+            if area.intersection(list_area) != area {
+                continue;
+            }
+            for position in area.positions() {
+                buf[position].set_diff_option(CellDiffOption::Skip);
+            }
+            buf[(area.x, area.y)]
+                .set_symbol(escape)
+                .set_diff_option(CellDiffOption::ForcedWidth(NonZeroU16::MIN));
+            placements.push(area);
+        }
+        bottom = top;
+    }
+    placements
 }
 
 fn display_time(timestamp: u64) -> String {
@@ -513,7 +886,9 @@ fn display_message(
     date_division: Option<String>,
     unread_messages_division: Option<String>,
     colored_messages: bool,
-) -> Option<ListItem<'static>> {
+    show_spoilers: bool,
+    find_pattern: Option<&str>,
+) -> Option<Text<'static>> {
     let receipt = Span::styled(
         display_receipt(msg.receipt, show_receipt),
         Style::default().fg(Color::Yellow),
@@ -532,13 +907,19 @@ fn display_message(
 
     // collect message text
     let text = strip_ansi_escapes::strip_str(msg.message.as_deref().unwrap_or_default());
-    let mut text = replace_mentions(msg, names, text);
+    let styled = StyledText::new(msg, names, &text);
+    let highlights = find_pattern
+        .map(|pattern| styled.find(pattern, show_spoilers))
+        .unwrap_or_default();
+    let StyledText { mut text, styles } = styled;
     add_attachments(msg, &mut text);
+    add_deleted(msg, &mut text);
     if text.is_empty() {
         return None; // no text => nothing to render
     }
     add_reactions(msg, &mut text);
     add_edited(msg, &mut text);
+    add_expiry(msg, utc_now_timestamp_msec(), &mut text);
 
     let mut spans: Vec<Line> = vec![];
     if let Some(date_division) = date_division {
@@ -597,30 +978,46 @@ fn display_message(
         .subsequent_indent(prefix);
     let mut wrapped_text = textwrap::wrap(&text, &wrap_opts).into_iter();
 
-    if add_time && let Some(first_line) = wrapped_text.next() {
-        let line = first_line
-            .strip_prefix(&first_line_prefix)
-            .expect("logic error")
-            .to_owned();
-        spans.push(Line::from(vec![
-            receipt,
-            time,
-            from,
-            delimiter,
-            Span::styled(line, message_style),
-        ]));
-    }
-    spans.extend(
-        wrapped_text.map(|line| Line::from(Span::styled(line.into_owned(), message_style))),
-    );
+    // Wrapped lines are consecutive slices of `text` (modulo indentation and trimmed
+    // whitespace), which we use to map them back to the styled ranges.
+    let mut text_offset = 0;
+    let mut styled_line = |line: &str, indent: &str| -> Vec<Span<'static>> {
+        let content = line.strip_prefix(indent).unwrap_or(line);
+        match text[text_offset..].find(content) {
+            Some(pos) => {
+                let start = text_offset + pos;
+                text_offset = start + content.len();
+                let range = start..text_offset;
+                style_spans(
+                    &text,
+                    &styles,
+                    &highlights,
+                    range,
+                    message_style,
+                    show_spoilers,
+                )
+            }
+            None => vec![Span::styled(content.to_owned(), message_style)],
+        }
+    };
 
-    if let Some(reason) = msg.send_failed.as_deref() {
-        let error = format!("[Could not send: {reason}]");
-        let error_style = Style::default().fg(Color::Red);
+    if add_time && let Some(first_line) = wrapped_text.next() {
+        let mut line = vec![receipt, time, from, delimiter];
+        line.extend(styled_line(&first_line, &first_line_prefix));
+        spans.push(Line::from(line));
+    }
+    spans.extend(wrapped_text.map(|line| {
+        let line_spans = styled_line(&line, prefix);
+        Line::from(indent_spans(prefix, line_spans, message_style))
+    }));
+
+    if let Some(send_state) = msg.send_state.as_ref() {
+        let (status, status_color) = display_send_state(send_state);
+        let status_style = Style::default().fg(status_color);
         spans.extend(
-            textwrap::wrap(&error, &wrap_opts)
+            textwrap::wrap(&status, &wrap_opts)
                 .into_iter()
-                .map(|line| Span::styled(line.into_owned(), error_style).into()),
+                .map(|line| Span::styled(line.into_owned(), status_style).into()),
         );
     }
 
@@ -629,34 +1026,214 @@ fn display_message(
         spans.resize(height - 1, Line::from(""));
         spans.push(Line::from(format!("{prefix}[...]")));
     }
-    Some(ListItem::new(Text::from(spans)))
+    Some(Text::from(spans))
 }
 
-fn replace_mentions(msg: &Message, names: &NameResolver, text: String) -> String {
-    if msg.body_ranges.is_empty() {
-        return text;
-    }
-
-    let ac = aho_corasick::AhoCorasickBuilder::new()
-        .build(std::iter::repeat_n("￼", msg.body_ranges.len())) // TODO: cache
-        .expect("failed to build obj replacer");
-    let mut buf = String::with_capacity(text.len());
-    let mut ranges = msg.body_ranges.iter();
-    ac.replace_all_with(&text, &mut buf, |_, _, dst| {
-        // TODO: check ranges?
-        for range in &mut ranges {
-            let (name, _color) = match range.value {
-                AssociatedValue::MentionUuid(id) => names.resolve(id),
-                AssociatedValue::Style(_) => continue, // not supported yet
+fn display_send_state(send_state: &SendState) -> (String, Color) {
+    match send_state {
+        SendState::Pending => ("[Waiting to send]".to_owned(), Color::DarkGray),
+        SendState::Retrying { attempts, error } => (
+            format!("[Could not send: {error}; retrying (attempt {attempts})]"),
+            Color::Yellow,
+        ),
+        SendState::Failed(error) => (format!("[Could not send: {error}]"), Color::Red),
+    }
+}
+
+/// Byte ranges of the matches of the pattern in the message text as it is displayed
+///
+/// The text of the message is matched with resolved mentions.
+pub(crate) fn find_in_message(
+    names: &NameResolver,
+    msg: &Message,
+    pattern: &str,
+    show_spoilers: bool,
+) -> Vec<Range<usize>> {
+    let text = strip_ansi_escapes::strip_str(msg.message.as_deref().unwrap_or_default());
+    StyledText::new(msg, names, &text).find(pattern, show_spoilers)
+}
+
+/// Message text with resolved mentions and style ranges as byte ranges into the text
+struct StyledText {
+    text: String,
+    styles: Vec<(Range<usize>, data::Style)>,
+}
+
+impl StyledText {
+    fn new(msg: &Message, names: &NameResolver, text: &str) -> Self {
+        if msg.body_ranges.is_empty() {
+            return Self {
+                text: text.to_owned(),
+                styles: Default::default(),
             };
-            dst.push('@');
-            dst.push_str(&name);
-            return true;
         }
-        false
-    });
 
-    buf
+        // body ranges are given in UTF-16 code units of the original text
+        let mut out = String::with_capacity(text.len());
+        let mut offsets = Vec::with_capacity(text.len() + 1);
+        let mut utf16_pos = 0;
+        for c in text.chars() {
+            let mention = (c == '￼')
+                .then(|| {
+                    msg.body_ranges.iter().find_map(|range| match range.value {
+                        AssociatedValue::MentionUuid(id)
+                            if usize::from(range.start) == utf16_pos =>
+                        {
+                            Some(id)
+                        }
+                        _ => None,
+                    })
+                })
+                .flatten();
+            offsets.extend(std::iter::repeat_n(out.len(), c.len_utf16()));
+            if let Some(id) = mention {
+                let (name, _color) = names.resolve(id);
+                out.push('@');
+                out.push_str(&name);
+            } else {
+                out.push(c);
+            }
+            utf16_pos += c.len_utf16();
+        }
+        offsets.push(out.len());
+
+        let styles = msg
+            .body_ranges
+            .iter()
+            .filter_map(|range| {
+                let AssociatedValue::Style(style) = &range.value else {
+                    return None;
+                };
+                let start = *offsets.get(usize::from(range.start))?;
+                let end = offsets
+                    .get(usize::from(range.end))
+                    .copied()
+                    .unwrap_or(out.len());
+                (start < end && *style != data::Style::None).then(|| (start..end, style.clone()))
+            })
+            .collect();
+
+        Self { text: out, styles }
+    }
+
+    /// Byte ranges of the matches of the pattern in the text
+    ///
+    /// Matches overlapping hidden spoilers are skipped, since they would reveal the spoiler.
+    fn find(&self, pattern: &str, show_spoilers: bool) -> Vec<Range<usize>> {
+        let mut matches = crate::find::match_ranges(&self.text, pattern);
+        if !show_spoilers {
+            matches.retain(|m| {
+                !self.styles.iter().any(|(range, style)| {
+                    *style == data::Style::Spoiler && range.start < m.end && m.start < range.end
+                })
+            });
+        }
+        matches
+    }
+
+    /// Returns the text with hidden spoilers
+    fn into_masked_string(self) -> String {
+        let spoilers: Vec<&Range<usize>> = self
+            .styles
+            .iter()
+            .filter(|(_, style)| *style == data::Style::Spoiler)
+            .map(|(range, _)| range)
+            .collect();
+        if spoilers.is_empty() {
+            return self.text;
+        }
+        let mut masked = String::with_capacity(self.text.len());
+        for (idx, c) in self.text.char_indices() {
+            if spoilers.iter().any(|range| range.contains(&idx)) {
+                masked.extend(std::iter::repeat_n(SPOILER_CHAR, c.width().unwrap_or(0)));
+            } else {
+                masked.push(c);
+            }
+        }
+        masked
+    }
+}
+
+/// Replaces each character of a hidden spoiler while preserving the display width
+const SPOILER_CHAR: char = '▒';
+
+/// Splits the given range of the text into spans styled by the overlapping style ranges
+///
+/// Highlighted ranges (matches of the find prompt) are styled on top of the text styles.
+fn style_spans(
+    text: &str,
+    styles: &[(Range<usize>, data::Style)],
+    highlights: &[Range<usize>],
+    range: Range<usize>,
+    base_style: Style,
+    show_spoilers: bool,
+) -> Vec<Span<'static>> {
+    let mut bounds = vec![range.start, range.end];
+    let style_ranges = styles.iter().map(|(style_range, _)| style_range);
+    for style_range in style_ranges.chain(highlights) {
+        for bound in [style_range.start, style_range.end] {
+            if range.start < bound && bound < range.end {
+                bounds.push(bound);
+            }
+        }
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    for (&start, &end) in bounds.iter().tuple_windows() {
+        let mut style = base_style;
+        let mut hidden = false;
+        let covering = styles
+            .iter()
+            .filter(|(style_range, _)| style_range.start <= start && end <= style_range.end);
+        for (_, text_style) in covering {
+            match text_style {
+                data::Style::None => {}
+                data::Style::Bold => style = style.add_modifier(Modifier::BOLD),
+                data::Style::Italic => style = style.add_modifier(Modifier::ITALIC),
+                data::Style::Strikethrough => style = style.add_modifier(Modifier::CROSSED_OUT),
+                data::Style::Monospace => style = style.bg(Color::Rgb(60, 60, 60)),
+                data::Style::Spoiler => hidden = !show_spoilers,
+            }
+        }
+        if highlights
+            .iter()
+            .any(|highlight| highlight.start <= start && end <= highlight.end)
+        {
+            style = style.bg(Color::Yellow).fg(Color::Black);
+        }
+
+        let segment = &text[start..end];
+        let content: String = if hidden {
+            segment
+                .chars()
+                .flat_map(|c| std::iter::repeat_n(SPOILER_CHAR, c.width().unwrap_or(0)))
+                .collect()
+        } else {
+            segment.to_owned()
+        };
+
+        match spans.last_mut() {
+            Some(last) if last.style == style => last.content.to_mut().push_str(&content),
+            _ => spans.push(Span::styled(content, style)),
+        }
+    }
+    spans
+}
+
+/// Prepends the indentation to the spans of a line
+fn indent_spans(indent: &str, mut spans: Vec<Span<'static>>, style: Style) -> Vec<Span<'static>> {
+    if indent.is_empty() {
+        return spans;
+    }
+    match spans.first_mut() {
+        Some(span) if span.style == style => {
+            span.content = format!("{indent}{}", span.content).into()
+        }
+        _ => spans.insert(0, Span::styled(indent.to_owned(), style)),
+    }
+    spans
 }
 
 fn display_date_line(
@@ -679,26 +1256,52 @@ fn display_date_line(
     }
 }
 
+/// Appends the links to the attachment files and the placeholders of the pending attachments
+///
+/// The linked files are encrypted at rest, so opening a link outside of gurk only shows the
+/// ciphertext. Opening the attachment from gurk, i.e. with `enter` on the message or in the
+/// attachments popup, decrypts it first.
 fn add_attachments(msg: &Message, out: &mut String) {
-    if !msg.attachments.is_empty() {
+    let attachments = msg
+        .attachments
+        .iter()
+        .map(|attachment| format!("<file://{}>", attachment.filename.display()))
+        .chain(
+            msg.pending_attachments
+                .iter()
+                .map(display_pending_attachment),
+        );
+    for attachment in attachments {
         if !out.is_empty() {
             out.push('\n');
         }
+        out.push_str(&attachment);
+    }
+}
 
-        fmt::write(
-            out,
-            format_args!(
-                "{}",
-                msg.attachments
-                    .iter()
-                    .format_with("\n", |attachment, f| f(&format_args!(
-                        "<file://{}>",
-                        attachment.filename.display()
-                    )))
-            ),
-        )
-        .expect("formatting attachments failed");
+/// Placeholder of an attachment which is not downloaded yet, e.g. `[not downloaded: 12 MB video]`
+fn display_pending_attachment(attachment: &PendingAttachment) -> String {
+    let kind = match attachment.content_type.split_once('/') {
+        Some(("image", _)) => "image",
+        Some(("video", _)) => "video",
+        Some(("audio", _)) => "audio",
+        _ => "file",
+    };
+    format!(
+        "[not downloaded: {} {kind}]",
+        display_size(attachment.size.into())
+    )
+}
+
+fn display_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
     }
+    format!("{} {}", size.round(), UNITS[unit])
 }
 
 fn add_reactions(msg: &Message, out: &mut dyn fmt::Write) {
@@ -714,12 +1317,45 @@ fn add_reactions(msg: &Message, out: &mut dyn fmt::Write) {
     }
 }
 
+fn add_deleted(msg: &Message, out: &mut dyn fmt::Write) {
+    if msg.deleted {
+        write!(out, "[deleted]").expect("formatting deleted failed")
+    }
+}
+
 fn add_edited(msg: &Message, out: &mut dyn fmt::Write) {
     if msg.edited {
         write!(out, " [edited]").expect("formatting edited failed")
     }
 }
 
+/// Adds the remaining time of a disappearing message
+///
+/// If the expiration timer is not started yet, the full timer is shown.
+fn add_expiry(msg: &Message, now: u64, out: &mut dyn fmt::Write) {
+    let remaining_secs = match (msg.expire_timer, msg.expires_at) {
+        (_, Some(expires_at)) => expires_at.saturating_sub(now).div_ceil(1000),
+        (Some(timer), None) => timer.into(),
+        (None, None) => return,
+    };
+    write!(out, " [⏱ {}]", display_duration(remaining_secs)).expect("formatting expiry failed")
+}
+
+/// Formats the duration in the largest fitting unit, e.g. `5m`
+fn display_duration(secs: u64) -> String {
+    const UNITS: [(u64, &str); 4] = [
+        (7 * 24 * 3600, "w"),
+        (24 * 3600, "d"),
+        (3600, "h"),
+        (60, "m"),
+    ];
+    UNITS
+        .iter()
+        .find(|&&(unit_secs, _)| secs >= unit_secs)
+        .map(|(unit_secs, unit)| format!("{}{unit}", secs / unit_secs))
+        .unwrap_or_else(|| format!("{secs}s"))
+}
+
 fn help_commands<'a>() -> Vec<Line<'a>> {
     let commands = <Command as strum::IntoEnumIterator>::iter()
         .map(|cmd| {
@@ -751,6 +1387,12 @@ fn bindings(app: &App) -> Vec<Line<'_>> {
         WindowMode::Anywhere,
         WindowMode::Help,
         WindowMode::ChannelModal,
+        WindowMode::Search,
+        WindowMode::Find,
+        WindowMode::Attachments,
+        WindowMode::AddAttachment,
+        WindowMode::TextInput,
+        WindowMode::MentionCompletion,
         WindowMode::Multiline,
         WindowMode::MessageSelected,
     ]
@@ -800,6 +1442,7 @@ fn help_indicators<'a>() -> Vec<Line<'a>> {
     let indicators: &[(&str, &str)] = &[
         ("(N)", "N unread messages in channel"),
         ("[M]", "Channel is muted (notifications silenced)"),
+        ("[D]", "Unsent draft in channel"),
         ("○", "Message sent"),
         ("◉", "Message delivered"),
         ("●", "Message read"),
@@ -830,8 +1473,22 @@ fn draw_help(f: &mut Frame, app: &mut App, area: Rect) {
 
 fn displayed_quote(names: &NameResolver, quote: &Message) -> Option<String> {
     let (name, _) = names.resolve(quote.from_id);
-    let text = format!("({}) {}", name, quote.message.as_ref()?);
-    Some(replace_mentions(quote, names, text))
+    let text = StyledText::new(quote, names, quote.message.as_ref()?).into_masked_string();
+    Some(format!("({name}) {text}"))
+}
+
+/// Single line describing the message which is replied to
+fn displayed_reply_target(names: &NameResolver, message: &Message) -> String {
+    let text = displayed_quote(names, message).unwrap_or_else(|| {
+        let (name, _) = names.resolve(message.from_id);
+        if message.attachments.is_empty() {
+            format!("({name})")
+        } else {
+            format!("({name}) <attachment>")
+        }
+    });
+    // the paragraph is a single line, so newlines would hide the rest of the text
+    format!("Replying to {}", text.replace('\n', " "))
 }
 
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -894,15 +1551,35 @@ mod tests {
             arrived_at: 1642334397421,
             quote: None,
             attachments: Default::default(),
+            pending_attachments: Default::default(),
             reactions: Default::default(),
             receipt: Receipt::Sent,
             body_ranges: Default::default(),
-            send_failed: Default::default(),
+            send_state: Default::default(),
             edit: Default::default(),
             edited: Default::default(),
+            deleted: Default::default(),
+            expire_timer: Default::default(),
+            expires_at: Default::default(),
         }
     }
 
+    #[test]
+    fn test_display_pending_attachment() {
+        let attachment = PendingAttachment {
+            id: "some_attachment".to_string(),
+            content_type: "video/mp4".to_string(),
+            size: 12_345_678,
+            pointer: Vec::new(),
+        };
+        assert_eq!(
+            display_pending_attachment(&attachment),
+            "[not downloaded: 12 MB video]"
+        );
+        assert_eq!(display_size(512), "512 B");
+        assert_eq!(display_size(1_500), "2 KB");
+    }
+
     #[test]
     fn test_display_attachment_only_message() {
         let names = name_resolver();
@@ -920,9 +1597,11 @@ mod tests {
             None,
             None,
             false,
+            false,
+            None,
         );
 
-        let expected = ListItem::new(Text::from(vec![
+        let expected = Text::from(vec![
             Line::from(vec![
                 Span::styled("", Style::default().fg(Color::Yellow)),
                 Span::styled(
@@ -936,7 +1615,7 @@ mod tests {
             Line::from(vec![Span::raw(
                 "                  16T11:59:58.405665+00:00.jpg>",
             )]),
-        ]));
+        ]);
         assert_eq!(rendered, Some(expected));
     }
 
@@ -958,9 +1637,11 @@ mod tests {
             None,
             None,
             false,
+            false,
+            None,
         );
 
-        let expected = ListItem::new(Text::from(vec![
+        let expected = Text::from(vec![
             Line::from(vec![
                 Span::styled("", Style::default().fg(Color::Yellow)),
                 Span::styled(
@@ -977,7 +1658,7 @@ mod tests {
             Line::from(vec![Span::raw(
                 "                  16T11:59:58.405665+00:00.jpg>",
             )]),
-        ]));
+        ]);
         assert_eq!(rendered, Some(expected));
     }
 
@@ -1000,9 +1681,11 @@ mod tests {
             None,
             None,
             false,
+            false,
+            None,
         );
 
-        let expected = ListItem::new(Text::from(vec![Line::from(vec![
+        let expected = Text::from(vec![Line::from(vec![
             Span::styled("○ ", Style::default().fg(Color::Yellow)),
             Span::styled(
                 display_time(msg.arrived_at),
@@ -1011,7 +1694,7 @@ mod tests {
             Span::styled("boxdot", Style::default().fg(Color::Green)),
             Span::raw(": "),
             Span::raw("Hello, World!"),
-        ])]));
+        ])]);
         assert_eq!(rendered, Some(expected));
     }
 
@@ -1034,9 +1717,11 @@ mod tests {
             None,
             None,
             false,
+            false,
+            None,
         );
 
-        let expected = ListItem::new(Text::from(vec![Line::from(vec![
+        let expected = Text::from(vec![Line::from(vec![
             Span::styled("◉ ", Style::default().fg(Color::Yellow)),
             Span::styled(
                 display_time(msg.arrived_at),
@@ -1045,7 +1730,7 @@ mod tests {
             Span::styled("boxdot", Style::default().fg(Color::Green)),
             Span::raw(": "),
             Span::raw("Hello, World!"),
-        ])]));
+        ])]);
         assert_eq!(rendered, Some(expected));
     }
 
@@ -1068,9 +1753,11 @@ mod tests {
             None,
             None,
             false,
+            false,
+            None,
         );
 
-        let expected = ListItem::new(Text::from(vec![Line::from(vec![
+        let expected = Text::from(vec![Line::from(vec![
             Span::styled("● ", Style::default().fg(Color::Yellow)),
             Span::styled(
                 display_time(msg.arrived_at),
@@ -1079,7 +1766,7 @@ mod tests {
             Span::styled("boxdot", Style::default().fg(Color::Green)),
             Span::raw(": "),
             Span::raw("Hello, World!"),
-        ])]));
+        ])]);
         assert_eq!(rendered, Some(expected));
     }
 
@@ -1102,9 +1789,11 @@ mod tests {
             None,
             None,
             false,
+            false,
+            None,
         );
 
-        let expected = ListItem::new(Text::from(vec![Line::from(vec![
+        let expected = Text::from(vec![Line::from(vec![
             Span::styled("", Style::default().fg(Color::Yellow)),
             Span::styled(
                 display_time(msg.arrived_at),
@@ -1113,7 +1802,7 @@ mod tests {
             Span::styled("boxdot", Style::default().fg(Color::Green)),
             Span::raw(": "),
             Span::raw("Hello, World!"),
-        ])]));
+        ])]);
         assert_eq!(rendered, Some(expected));
     }
 
@@ -1138,9 +1827,11 @@ mod tests {
             None,
             None,
             false,
+            false,
+            None,
         );
 
-        let expected = ListItem::new(Text::from(vec![Line::from(vec![
+        let expected = Text::from(vec![Line::from(vec![
             Span::styled("  ", Style::default().fg(Color::Yellow)),
             Span::styled(
                 display_time(msg.arrived_at),
@@ -1149,7 +1840,7 @@ mod tests {
             Span::styled("boxdot", Style::default().fg(Color::Green)),
             Span::raw(": "),
             Span::raw("Hello, World!"),
-        ])]));
+        ])]);
         assert_eq!(rendered, Some(expected));
     }
 
@@ -1186,9 +1877,11 @@ mod tests {
             None,
             None,
             false,
+            false,
+            None,
         );
 
-        let expected = ListItem::new(Text::from(vec![
+        let expected = Text::from(vec![
             Line::from(vec![
                 Span::styled("  ", Style::default().fg(Color::Yellow)),
                 Span::styled(
@@ -1200,7 +1893,7 @@ mod tests {
                 Span::raw("Mention @boxdot  and even more"),
             ]),
             Line::from(vec![Span::raw("                  @boxdot . End")]),
-        ]));
+        ]);
         assert_eq!(rendered, Some(expected));
     }
 
@@ -1224,9 +1917,11 @@ mod tests {
             None,
             None,
             false,
+            false,
+            None,
         );
 
-        let expected = ListItem::new(Text::from(vec![
+        let expected = Text::from(vec![
             Line::from(vec![
                 Span::styled("", Style::default().fg(Color::Yellow)),
                 Span::styled(
@@ -1241,7 +1936,7 @@ mod tests {
                 "                  should wrap across multiple lines in the",
             )]),
             Line::from(vec![Span::raw("                  display")]),
-        ]));
+        ]);
         assert_eq!(rendered, Some(expected));
     }
 
@@ -1263,9 +1958,11 @@ mod tests {
             None,
             Some(division.clone()),
             false,
+            false,
+            None,
         );
 
-        let expected = ListItem::new(Text::from(vec![
+        let expected = Text::from(vec![
             Line::from(division),
             Line::from(vec![
                 Span::styled("", Style::default().fg(Color::Yellow)),
@@ -1277,7 +1974,279 @@ mod tests {
                 Span::raw(": "),
                 Span::raw("Hello, World!"),
             ]),
-        ]));
+        ]);
+        assert_eq!(rendered, Some(expected));
+    }
+
+    #[test]
+    fn test_display_deleted_message() {
+        let names = name_resolver();
+        let msg = Message {
+            deleted: true,
+            ..test_message()
+        };
+        let rendered = display_message(
+            &names,
+            &msg,
+            PREFIX,
+            WIDTH,
+            HEIGHT,
+            ShowReceipt::Never,
+            None,
+            None,
+            false,
+            false,
+            None,
+        );
+
+        let expected = Text::from(vec![Line::from(vec![
+            Span::styled("", Style::default().fg(Color::Yellow)),
+            Span::styled(
+                display_time(msg.arrived_at),
+                Style::default().fg(Color::Yellow),
+            ),
+            Span::styled("boxdot", Style::default().fg(Color::Green)),
+            Span::raw(": "),
+            Span::raw("[deleted]"),
+        ])]);
+        assert_eq!(rendered, Some(expected));
+    }
+
+    #[test]
+    fn test_display_send_state() {
+        assert_eq!(
+            display_send_state(&SendState::Pending),
+            ("[Waiting to send]".to_owned(), Color::DarkGray)
+        );
+        let retrying = SendState::Retrying {
+            attempts: 2,
+            error: "timeout".to_owned(),
+        };
+        assert_eq!(
+            display_send_state(&retrying),
+            (
+                "[Could not send: timeout; retrying (attempt 2)]".to_owned(),
+                Color::Yellow
+            )
+        );
+        assert_eq!(
+            display_send_state(&SendState::Failed("timeout".to_owned())),
+            ("[Could not send: timeout]".to_owned(), Color::Red)
+        );
+    }
+
+    fn style_range(start: u16, end: u16, style: data::Style) -> BodyRange {
+        BodyRange {
+            start,
+            end,
+            value: AssociatedValue::Style(style),
+        }
+    }
+
+    #[test]
+    fn test_display_bold_text() {
+        let names = name_resolver();
+        let msg = Message {
+            message: Some("Hello, World!".into()),
+            body_ranges: vec![style_range(0, 5, data::Style::Bold)],
+            ..test_message()
+        };
+        let rendered = display_message(
+            &names,
+            &msg,
+            PREFIX,
+            WIDTH,
+            HEIGHT,
+            ShowReceipt::Never,
+            None,
+            None,
+            false,
+            false,
+            None,
+        );
+
+        let expected = Text::from(vec![Line::from(vec![
+            Span::styled("", Style::default().fg(Color::Yellow)),
+            Span::styled(
+                display_time(msg.arrived_at),
+                Style::default().fg(Color::Yellow),
+            ),
+            Span::styled("boxdot", Style::default().fg(Color::Green)),
+            Span::raw(": "),
+            Span::styled("Hello", Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(", World!"),
+        ])]);
+        assert_eq!(rendered, Some(expected));
+    }
+
+    #[test]
+    fn test_display_style_survives_wrapping() {
+        let names = name_resolver();
+        let msg = Message {
+            message: Some(
+                "This is a very long message that should wrap across multiple lines in the display"
+                    .into(),
+            ),
+            // "message that should wrap"
+            body_ranges: vec![style_range(20, 44, data::Style::Italic)],
+            ..test_message()
+        };
+        let rendered = display_message(
+            &names,
+            &msg,
+            PREFIX,
+            WIDTH,
+            HEIGHT,
+            ShowReceipt::Never,
+            None,
+            None,
+            false,
+            false,
+            None,
+        );
+
+        let italic = Style::default().add_modifier(Modifier::ITALIC);
+        let expected = Text::from(vec![
+            Line::from(vec![
+                Span::styled("", Style::default().fg(Color::Yellow)),
+                Span::styled(
+                    display_time(msg.arrived_at),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::styled("boxdot", Style::default().fg(Color::Green)),
+                Span::raw(": "),
+                Span::raw("This is a very long "),
+                Span::styled("message that", italic),
+            ]),
+            Line::from(vec![
+                Span::raw(PREFIX),
+                Span::styled("should wrap", italic),
+                Span::raw(" across multiple lines in the"),
+            ]),
+            Line::from(vec![Span::raw("                  display")]),
+        ]);
         assert_eq!(rendered, Some(expected));
     }
+
+    #[test]
+    fn test_display_spoiler() {
+        let names = name_resolver();
+        let msg = Message {
+            message: Some("secret plan".into()),
+            body_ranges: vec![style_range(0, 6, data::Style::Spoiler)],
+            ..test_message()
+        };
+        let display = |show_spoilers| {
+            display_message(
+                &names,
+                &msg,
+                PREFIX,
+                WIDTH,
+                HEIGHT,
+                ShowReceipt::Never,
+                None,
+                None,
+                false,
+                show_spoilers,
+                None,
+            )
+        };
+        let expected = |text| {
+            Text::from(vec![Line::from(vec![
+                Span::styled("", Style::default().fg(Color::Yellow)),
+                Span::styled(
+                    display_time(msg.arrived_at),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::styled("boxdot", Style::default().fg(Color::Green)),
+                Span::raw(": "),
+                Span::raw(text),
+            ])])
+        };
+
+        assert_eq!(display(false), Some(expected("▒▒▒▒▒▒ plan")));
+        assert_eq!(display(true), Some(expected("secret plan")));
+    }
+
+    #[test]
+    fn test_display_find_highlight() {
+        let names = name_resolver();
+        let msg = Message {
+            message: Some("Hello ￼".into()),
+            body_ranges: vec![BodyRange {
+                start: 6,
+                end: 7,
+                value: AssociatedValue::MentionUuid(USER_ID),
+            }],
+            ..test_message()
+        };
+        let rendered = display_message(
+            &names,
+            &msg,
+            PREFIX,
+            WIDTH,
+            HEIGHT,
+            ShowReceipt::Never,
+            None,
+            None,
+            false,
+            false,
+            Some("bOx"),
+        );
+
+        let expected = Text::from(vec![Line::from(vec![
+            Span::styled("", Style::default().fg(Color::Yellow)),
+            Span::styled(
+                display_time(msg.arrived_at),
+                Style::default().fg(Color::Yellow),
+            ),
+            Span::styled("boxdot", Style::default().fg(Color::Green)),
+            Span::raw(": "),
+            Span::raw("Hello @"),
+            Span::styled("box", Style::default().bg(Color::Yellow).fg(Color::Black)),
+            Span::raw("dot"),
+        ])]);
+        assert_eq!(rendered, Some(expected));
+    }
+
+    #[test]
+    fn test_displayed_quote_hides_spoiler() {
+        let names = name_resolver();
+        let quote = Message {
+            message: Some("the end: ￼ did it".into()),
+            body_ranges: vec![
+                BodyRange {
+                    start: 9,
+                    end: 10,
+                    value: AssociatedValue::MentionUuid(USER_ID),
+                },
+                style_range(9, 10, data::Style::Spoiler),
+            ],
+            ..test_message()
+        };
+        assert_eq!(
+            displayed_quote(&names, &quote).as_deref(),
+            Some("(boxdot) the end: ▒▒▒▒▒▒▒ did it")
+        );
+    }
+
+    #[test]
+    fn test_add_expiry() {
+        let expiry = |expire_timer, expires_at, now| {
+            let msg = Message {
+                expire_timer,
+                expires_at,
+                ..test_message()
+            };
+            let mut out = String::new();
+            add_expiry(&msg, now, &mut out);
+            out
+        };
+        assert_eq!(expiry(None, None, 0), "");
+        assert_eq!(expiry(Some(3600), None, 0), " [⏱ 1h]");
+        assert_eq!(expiry(Some(3600), Some(300_000), 0), " [⏱ 5m]");
+        assert_eq!(expiry(Some(3600), Some(300_000), 299_001), " [⏱ 1s]");
+        assert_eq!(expiry(Some(3600), Some(300_000), 400_000), " [⏱ 0s]");
+        assert_eq!(expiry(Some(604_800), None, 0), " [⏱ 1w]");
+    }
 }
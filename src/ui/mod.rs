@@ -8,5 +8,7 @@ mod name_resolver;
 
 pub use coords::coords_within_channels_view;
 pub use draw::draw;
+pub(crate) use draw::find_in_message;
+pub(crate) use name_resolver::NameResolver;
 
 pub const CHANNEL_VIEW_RATIO: u32 = 4;
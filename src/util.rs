@@ -1,6 +1,7 @@
+use std::path::PathBuf;
 use std::sync::LazyLock;
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, NaiveDate, NaiveTime};
 use phonenumber::PhoneNumber;
 use ratatui::widgets::ListState;
 use regex::Regex;
@@ -97,6 +98,12 @@ pub fn utc_timestamp_msec_to_local(timestamp: u64) -> DateTime<Local> {
         .with_timezone(&Local)
 }
 
+/// Local midnight at the beginning of the date in milliseconds since epoch
+pub fn local_start_of_day_timestamp_msec(date: NaiveDate) -> Option<u64> {
+    let start = date.and_time(NaiveTime::MIN).and_local_timezone(Local);
+    start.earliest()?.timestamp_millis().try_into().ok()
+}
+
 pub fn utc_now_timestamp_msec() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -112,6 +119,18 @@ pub fn is_phone_number(s: impl AsRef<str>) -> bool {
     stripped.parse::<PhoneNumber>().is_ok()
 }
 
+/// Path entered by the user, with a leading `~` replaced by the home directory
+pub(crate) fn expand_home(path: &str) -> PathBuf {
+    let home_dir = || dirs::home_dir().unwrap_or_default();
+    if path == "~" {
+        home_dir()
+    } else if let Some(rest) = path.strip_prefix("~/") {
+        home_dir().join(rest)
+    } else {
+        PathBuf::from(path)
+    }
+}
+
 // Based on Alacritty, APACHE-2.0 License
 pub(crate) static URL_REGEX: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(
@@ -134,4 +153,15 @@ mod tests {
     fn test_is_phone_number() {
         assert!(is_phone_number("+1 800-000-0000"));
     }
+
+    #[test]
+    fn test_expand_home() {
+        let home_dir = dirs::home_dir().unwrap();
+        assert_eq!(expand_home("~"), home_dir);
+        assert_eq!(expand_home("~/file.txt"), home_dir.join("file.txt"));
+        assert_eq!(
+            expand_home("/tmp/~/file.txt"),
+            PathBuf::from("/tmp/~/file.txt")
+        );
+    }
 }